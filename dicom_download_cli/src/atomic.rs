@@ -0,0 +1,81 @@
+//! Crash-safe file writes for reports and the state file.
+//!
+//! Content goes to `<name>.tmp` next to the target, is fsynced, and is then renamed over
+//! the target, so a crash or a full disk leaves either the old file or the complete new
+//! one — never a truncated report that downstream parsers choke on.
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Temp file used while writing `path`: `<path>.tmp` in the same directory.
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+/// Writes `path` through `write`, then fsyncs and atomically renames it into place.
+///
+/// On failure the temp file is removed and any existing `path` is left untouched.
+pub fn write_atomic<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let tmp = tmp_path(path);
+    let written = (|| -> Result<()> {
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e.context(format!("Failed to write {}", path.display())));
+    }
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    sync_parent(path);
+    Ok(())
+}
+
+/// Convenience wrapper for content already held in memory.
+pub fn write_bytes_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    write_atomic(path, |w| Ok(w.write_all(bytes)?))
+}
+
+/// Persists the rename itself; best effort, as directories cannot be fsynced on Windows.
+fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        if let Ok(d) = File::open(dir) {
+            let _ = d.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_write_keeps_old_file() {
+        let dir = std::env::temp_dir().join(format!("atomic_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.csv");
+
+        write_bytes_atomic(&path, b"a,b\n").unwrap();
+        let err = write_atomic(&path, |w| {
+            w.write_all(b"partial")?;
+            Err(anyhow::anyhow!("disk full"))
+        });
+        assert!(err.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"a,b\n");
+        assert!(!tmp_path(&path).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! Append-only audit log of destructive file operations.
+//!
+//! `check` moves and deletes DICOM files and removes emptied folders, and `download` can
+//! delete DICOMs after conversion (`delete_dicom_after_conversion`, or move them to
+//! `trash_dir` and later remove expired trash folders) and purge verified
+//! studies from the source Orthanc (`--purge-source`). Every such mutation —
+//! and, for `check --dry-run`, every planned one — is recorded as one JSON line with the
+//! timestamp, run ID, operation, source/target paths, the rule that triggered it, the dry-run
+//! flag, and the outcome. The file is opened in append mode and never rewritten, so entries
+//! from earlier runs are kept for data governance review. It is created on the first entry;
+//! runs that change nothing leave no file behind.
+//!
+//! `check` also mirrors its executed (not planned) operations into a per-run journal
+//! ([`AuditLog::with_journal`]) that `check undo` replays backwards.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::reportfile::run_id;
+
+/// Default audit log location (relative to the working directory).
+pub const DEFAULT_AUDIT_LOG: &str = "dicom_download_cli_audit.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+    /// File moved to another folder (`check` DWI fix).
+    Move,
+    /// File deleted (`check` ADC duplicate, DICOM removed after conversion).
+    Delete,
+    /// Empty folder removed after its files were moved or deleted.
+    RemoveDir,
+    /// Study deleted from the source Orthanc after its download verified
+    /// (`download --purge-source`); `source` is the local study folder.
+    PurgeSource,
+}
+
+/// One line of the audit log.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub run_id: String,
+    /// Subcommand that performed the operation (`check`, `download`).
+    pub command: String,
+    pub operation: AuditOperation,
+    pub source: PathBuf,
+    pub target: Option<PathBuf>,
+    /// Why the operation was done (checker rule, config option).
+    pub rule: String,
+    pub dry_run: bool,
+    /// `ok`, `planned` (dry run), or `failed: <error>`.
+    pub result: String,
+}
+
+/// Audit log shared by all workers of one run.
+pub struct AuditLog {
+    path: PathBuf,
+    command: String,
+    run_id: String,
+    file: Mutex<Option<File>>,
+    /// Per-run journal receiving a copy of every executed entry.
+    journal: Option<Box<AuditLog>>,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path, command: &str) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            command: command.to_string(),
+            run_id: run_id(Utc::now()),
+            file: Mutex::new(None),
+            journal: None,
+        }
+    }
+
+    /// Also writes every executed (non-dry-run) entry to `path`, under the same run ID.
+    pub fn with_journal(mut self, path: &Path) -> Self {
+        self.journal = Some(Box::new(Self {
+            path: path.to_path_buf(),
+            command: self.command.clone(),
+            run_id: self.run_id.clone(),
+            file: Mutex::new(None),
+            journal: None,
+        }));
+        self
+    }
+
+    pub fn journal_path(&self) -> Option<&Path> {
+        self.journal.as_ref().map(|j| j.path())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// ID of this run, shared by all its entries.
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Appends one entry and syncs it to disk.
+    ///
+    /// Unlike the progress log, failures are returned: a mutation that cannot be audited
+    /// must be reported to the caller.
+    pub fn record(
+        &self,
+        operation: AuditOperation,
+        source: &Path,
+        target: Option<&Path>,
+        rule: &str,
+        dry_run: bool,
+        result: &str,
+    ) -> Result<()> {
+        let entry = AuditEntry {
+            timestamp: Utc::now(),
+            run_id: self.run_id.clone(),
+            command: self.command.clone(),
+            operation,
+            source: source.to_path_buf(),
+            target: target.map(Path::to_path_buf),
+            rule: rule.to_string(),
+            dry_run,
+            result: result.to_string(),
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        let mut guard = self
+            .file
+            .lock()
+            .map_err(|_| anyhow::anyhow!("audit log lock poisoned"))?;
+        if guard.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .with_context(|| format!("Failed to open audit log {}", self.path.display()))?;
+            *guard = Some(file);
+        }
+        let file = guard.as_mut().expect("opened above");
+        file.write_all(&line)
+            .and_then(|_| file.sync_data())
+            .with_context(|| format!("Failed to write audit log {}", self.path.display()))?;
+        drop(guard);
+
+        match &self.journal {
+            Some(journal) if !dry_run => {
+                journal.record(operation, source, target, rule, dry_run, result)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Records the outcome of an operation that was just attempted.
+    pub fn record_outcome<T>(
+        &self,
+        operation: AuditOperation,
+        source: &Path,
+        target: Option<&Path>,
+        rule: &str,
+        outcome: &std::result::Result<T, impl std::fmt::Display>,
+    ) -> Result<()> {
+        let result = match outcome {
+            Ok(_) => "ok".to_string(),
+            Err(e) => format!("failed: {}", e),
+        };
+        self.record(operation, source, target, rule, false, &result)
+    }
+}
+
+/// Reads every entry of an audit log (for tests and tooling).
+pub fn read_audit_log(path: &Path) -> Result<Vec<AuditEntry>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    text.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).context("Invalid audit log line"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log_appends_across_runs() {
+        let dir = std::env::temp_dir().join(format!("audit_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+
+        let first = AuditLog::new(&path, "check");
+        assert!(!path.exists());
+        first
+            .record(
+                AuditOperation::Move,
+                Path::new("a/DWI/1.dcm"),
+                Some(Path::new("a/DWI0/1.dcm")),
+                "b=0",
+                true,
+                "planned",
+            )
+            .unwrap();
+        let outcome: std::result::Result<(), String> = Err("denied".into());
+        AuditLog::new(&path, "download")
+            .record_outcome(
+                AuditOperation::Delete,
+                Path::new("b/T1/2.dcm"),
+                None,
+                "delete_dicom_after_conversion",
+                &outcome,
+            )
+            .unwrap();
+
+        let entries = read_audit_log(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].operation, AuditOperation::Move);
+        assert!(entries[0].dry_run);
+        assert_eq!(entries[1].command, "download");
+        assert_eq!(entries[1].result, "failed: denied");
+        assert_eq!(entries[1].target, None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
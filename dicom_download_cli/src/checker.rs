@@ -1,37 +1,72 @@
 //! DICOM file structure checker and fixer.
 //!
 //! This module provides functionality to check and fix common DICOM file organization issues:
-//! - DWI series: Files misplaced between DWI0 and DWI1000 folders based on b-value
+//! - DWI series: Files misplaced between b-value folders (DWI0/DWI1000 by default, or the
+//!   `[check.dwi]` buckets) based on b-value
 //! - ADC series: Duplicate ADC folders that should be removed
+//! - Mixed series: Folders holding files of more than one SeriesInstanceUID, optionally split
+//!   into `<folder>__<uid suffix>` folders
+//! - Slice gaps: InstanceNumber gaps, uneven slice spacing, or duplicated slice positions,
+//!   which truncated downloads leave behind (reported only)
+//! - Folder names: study folders vs. PatientID/StudyDate/Modality/AccessionNumber and series
+//!   folders vs. the `series.json` type, renamed with `fix_names`
+//! - `[[check.rules]]`: folder/tag rules declared in config (see [`crate::checkrules`])
 
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use dicom_object::{open_file, Tag};
-use serde::Serialize;
-use std::collections::HashSet;
-use std::path::{Path, PathBuf};
+use dicom_object::{open_file, InMemDicomObject, OpenFileOptions, Tag};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
 use tokio::fs;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::atomic::{write_atomic, write_bytes_atomic};
+use crate::audit::{AuditLog, AuditOperation};
+use crate::checkrules::CheckRules;
+use crate::checksum::sha256_file;
+use crate::client::DicomStudyInfo;
+use crate::config::CheckConfig;
+use crate::downloader::{generate_study_folder_name, sanitize_segment};
+use crate::layout::OutputLayout;
+use crate::reportfile::run_id;
+use crate::sidecar::{SeriesSidecar, SERIES_SIDECAR};
+use crate::trash::{move_file, DicomTrash};
 
 // ============================================================================
 // Data Structures
 // ============================================================================
 
 /// Type of action to perform on a file
-#[derive(Debug, Clone, Serialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ActionType {
     Move,
     Delete,
+    /// Rename a whole series folder (`source_path` and `target_path` are folders).
+    Rename,
+    /// Reported only, nothing is changed (e.g. a mixed folder without `split_mixed_series`).
+    Flag,
 }
 
 /// Type of check performed
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CheckType {
     DWI,
     ADC,
+    /// A folder with files of more than one SeriesInstanceUID.
+    MixedSeries,
+    /// Missing or duplicated slices in a series folder.
+    SliceGaps,
+    /// A `[[check.rules]]` entry; the action reason names the rule.
+    Rule,
+    /// A study or series folder name that does not match the DICOM tags / series type.
+    Names,
 }
 
 /// A single file action (move or delete)
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileAction {
     pub source_path: PathBuf,
     pub action_type: ActionType,
@@ -40,7 +75,7 @@ pub struct FileAction {
 }
 
 /// Result of checking a single series
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SeriesCheckResult {
     pub series_folder: String,
     pub check_type: CheckType,
@@ -49,7 +84,7 @@ pub struct SeriesCheckResult {
 }
 
 /// Result of checking a single study
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StudyCheckResult {
     pub study_folder: String,
     pub series_results: Vec<SeriesCheckResult>,
@@ -58,7 +93,8 @@ pub struct StudyCheckResult {
 }
 
 /// Summary statistics for the check operation
-#[derive(Debug, Clone, Serialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct CheckSummary {
     pub total_studies: usize,
     pub total_series_checked: usize,
@@ -67,6 +103,61 @@ pub struct CheckSummary {
     pub total_deletes: usize,
     pub dwi_fixes: usize,
     pub adc_duplicates_removed: usize,
+    /// Folders found holding more than one SeriesInstanceUID.
+    pub mixed_series_folders: usize,
+    /// Folders with missing or duplicated slices.
+    pub slice_gap_folders: usize,
+    /// Files moved or deleted and folders renamed by `[[check.rules]]`.
+    pub rule_fixes: usize,
+    /// Study and series folders whose name does not match their tags.
+    pub name_mismatches: usize,
+    /// `check apply` actions skipped because the tree changed since the plan was made.
+    pub stale_actions: usize,
+}
+
+impl CheckSummary {
+    /// Adds the counts of another (per-study) summary.
+    fn add(&mut self, other: &CheckSummary) {
+        self.total_studies += other.total_studies;
+        self.total_series_checked += other.total_series_checked;
+        self.total_files_checked += other.total_files_checked;
+        self.total_moves += other.total_moves;
+        self.total_deletes += other.total_deletes;
+        self.dwi_fixes += other.dwi_fixes;
+        self.adc_duplicates_removed += other.adc_duplicates_removed;
+        self.mixed_series_folders += other.mixed_series_folders;
+        self.slice_gap_folders += other.slice_gap_folders;
+        self.rule_fixes += other.rule_fixes;
+        self.name_mismatches += other.name_mismatches;
+        self.stale_actions += other.stale_actions;
+    }
+}
+
+/// A b-value range whose DWI files belong in `folder` (`[check.dwi]` buckets).
+#[derive(Debug, Clone, PartialEq)]
+pub struct BValueBucket {
+    pub folder: String,
+    /// Inclusive range; a missing b-value counts as 0.
+    pub min: u32,
+    pub max: u32,
+}
+
+impl BValueBucket {
+    pub fn contains(&self, bvalue: u32) -> bool {
+        (self.min..=self.max).contains(&bvalue)
+    }
+}
+
+/// Buckets used without `[check.dwi]`: b=0 (or missing) in DWI0, b=1000 in DWI1000.
+pub fn default_dwi_buckets() -> Vec<BValueBucket> {
+    [("DWI0", 0), ("DWI1000", 1000)]
+        .into_iter()
+        .map(|(folder, b)| BValueBucket {
+            folder: folder.to_string(),
+            min: b,
+            max: b,
+        })
+        .collect()
 }
 
 /// Complete check report
@@ -79,6 +170,120 @@ pub struct CheckReport {
     pub summary: CheckSummary,
 }
 
+impl CheckReport {
+    /// Series folders under `input_path` whose contents an applied fix changed: both sides
+    /// of a file move, the folder of a deleted file, and both names of a renamed series
+    /// folder. Empty for a dry run. Study folder renames leave the series as they were and
+    /// quarantine folders lie outside `input_path`, so neither is listed.
+    pub fn changed_series_dirs(&self) -> BTreeSet<PathBuf> {
+        let mut dirs = BTreeSet::new();
+        if self.dry_run {
+            return dirs;
+        }
+        for study in &self.studies {
+            for series in &study.series_results {
+                for action in &series.actions {
+                    let paths = [Some(&action.source_path), action.target_path.as_ref()];
+                    let paths = paths.into_iter().flatten();
+                    match action.action_type {
+                        ActionType::Flag => {}
+                        ActionType::Rename if series.series_folder.is_empty() => {}
+                        ActionType::Rename => dirs.extend(paths.cloned()),
+                        ActionType::Move | ActionType::Delete => {
+                            dirs.extend(paths.filter_map(|p| p.parent()).map(Path::to_path_buf))
+                        }
+                    }
+                }
+            }
+        }
+        dirs.retain(|dir| dir.starts_with(&self.input_path) && *dir != self.input_path);
+        dirs
+    }
+
+    /// Series folders an applied fix renamed, new path to old path. Like
+    /// [`changed_series_dirs`](Self::changed_series_dirs) this is empty for a dry run and
+    /// leaves study folder renames out.
+    pub fn renamed_series_dirs(&self) -> BTreeMap<PathBuf, PathBuf> {
+        if self.dry_run {
+            return BTreeMap::new();
+        }
+        self.studies
+            .iter()
+            .flat_map(|study| &study.series_results)
+            .filter(|series| !series.series_folder.is_empty())
+            .flat_map(|series| &series.actions)
+            .filter(|action| action.action_type == ActionType::Rename)
+            .filter_map(|action| Some((action.target_path.clone()?, action.source_path.clone())))
+            .filter(|(target, _)| target.starts_with(&self.input_path))
+            .collect()
+    }
+}
+
+/// Limits `check` to some study folders (`--accession`, `--accessions-file`,
+/// `--study-glob`). An empty filter selects every study.
+#[derive(Debug, Clone, Default)]
+pub struct StudyFilter {
+    /// Accession numbers, matched against the end of the study folder name.
+    pub accessions: Vec<String>,
+    /// Globs (`*`, `?`) on the study folder relative to `dicom/`, `/`-separated.
+    pub globs: Vec<String>,
+}
+
+impl StudyFilter {
+    pub fn is_empty(&self) -> bool {
+        self.accessions.is_empty() && self.globs.is_empty()
+    }
+
+    /// Whether the study folder is selected: its name ends in `_<accession>` (or
+    /// `_<accession>_<number>`, `<accession>_unknown`), or the folder matches a glob.
+    pub fn matches(&self, study_folder: &str) -> bool {
+        self.is_empty()
+            || self.accession_of(study_folder).is_some()
+            || self.globs.iter().any(|g| glob_match(g, study_folder))
+    }
+
+    /// The accession of the filter that names the study folder.
+    fn accession_of(&self, study_folder: &str) -> Option<&str> {
+        let leaf = study_folder.rsplit('/').next().unwrap_or(study_folder);
+        // 重複 study 以 _2、_3 結尾（duplicate_studies = "suffix-folders"）
+        let unnumbered = leaf
+            .rsplit_once('_')
+            .filter(|(_, n)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            .map(|(base, _)| base);
+        self.accessions.iter().map(String::as_str).find(|acc| {
+            let acc = sanitize_segment(acc);
+            let suffix = format!("_{}", acc);
+            leaf.ends_with(&suffix)
+                || unnumbered.is_some_and(|base| base.ends_with(&suffix))
+                || leaf == format!("{}_unknown", acc)
+        })
+    }
+}
+
+/// Shell-style glob: `*` matches any run of characters (`/` included), `?` one character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    // 最近一個 * 的位置與其對應的文字位置，比對失敗時回溯
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
 // ============================================================================
 // DICOM Tag Reading
 // ============================================================================
@@ -95,7 +300,11 @@ pub struct CheckReport {
 /// Returns Some(value) for positive b-values.
 fn read_bvalue(path: &Path) -> Result<Option<u32>> {
     let obj = open_file(path).context("Failed to open DICOM file")?;
+    Ok(bvalue_of(&obj))
+}
 
+/// [`read_bvalue`] on an already opened object.
+pub(crate) fn bvalue_of(obj: &InMemDicomObject) -> Option<u32> {
     // Helper macro to convert element to u32
     macro_rules! elem_to_u32 {
         ($elem:expr) => {{
@@ -117,7 +326,7 @@ fn read_bvalue(path: &Path) -> Result<Option<u32>> {
     // Method 1: Try primary tag (0018,9087) DiffusionBValue
     if let Ok(elem) = obj.element_by_name("DiffusionBValue") {
         if let Some(bval) = elem_to_u32!(elem) {
-            return Ok(if bval == 0 { None } else { Some(bval) });
+            return if bval == 0 { None } else { Some(bval) };
         }
     }
 
@@ -127,7 +336,7 @@ fn read_bvalue(path: &Path) -> Result<Option<u32>> {
             if let Some(first_item) = items.first() {
                 if let Ok(bval_elem) = first_item.element_by_name("DiffusionBValue") {
                     if let Some(bval) = elem_to_u32!(bval_elem) {
-                        return Ok(if bval == 0 { None } else { Some(bval) });
+                        return if bval == 0 { None } else { Some(bval) };
                     }
                 }
             }
@@ -143,7 +352,7 @@ fn read_bvalue(path: &Path) -> Result<Option<u32>> {
                         if let Some(diff_item) = diff_items.first() {
                             if let Ok(bval_elem) = diff_item.element_by_name("DiffusionBValue") {
                                 if let Some(bval) = elem_to_u32!(bval_elem) {
-                                    return Ok(if bval == 0 { None } else { Some(bval) });
+                                    return if bval == 0 { None } else { Some(bval) };
                                 }
                             }
                         }
@@ -162,7 +371,7 @@ fn read_bvalue(path: &Path) -> Result<Option<u32>> {
                         if let Some(diff_item) = diff_items.first() {
                             if let Ok(bval_elem) = diff_item.element_by_name("DiffusionBValue") {
                                 if let Some(bval) = elem_to_u32!(bval_elem) {
-                                    return Ok(if bval == 0 { None } else { Some(bval) });
+                                    return if bval == 0 { None } else { Some(bval) };
                                 }
                             }
                         }
@@ -178,25 +387,25 @@ fn read_bvalue(path: &Path) -> Result<Option<u32>> {
             // Parse first number from string like "1000\0\0"
             if let Some(first_part) = val_str.split(['\\', '/', ' ']).next() {
                 if let Ok(bval) = first_part.trim().parse::<u32>() {
-                    return Ok(if bval == 0 { None } else { Some(bval) });
+                    return if bval == 0 { None } else { Some(bval) };
                 }
             }
         }
         if let Some(bval) = elem_to_u32!(elem) {
-            return Ok(if bval == 0 { None } else { Some(bval) });
+            return if bval == 0 { None } else { Some(bval) };
         }
     }
 
     // Method 6: Try Siemens private tag (0019,100c)
     if let Ok(elem) = obj.element(Tag(0x0019, 0x100c)) {
         if let Some(bval) = elem_to_u32!(elem) {
-            return Ok(if bval == 0 { None } else { Some(bval) });
+            return if bval == 0 { None } else { Some(bval) };
         }
     }
 
     // b-value not found - treat as b=0 (DWI0)
     // This is the expected behavior when the tag is missing or null
-    Ok(None)
+    None
 }
 
 /// Read the SOP Instance UID (0008,0018) from a DICOM file.
@@ -208,12 +417,31 @@ fn read_sop_instance_uid(path: &Path) -> Result<String> {
     Ok(elem.to_str()?.trim().to_string())
 }
 
+/// Read the Series Instance UID (0020,000E) from a DICOM file.
+fn read_series_instance_uid(path: &Path) -> Result<String> {
+    let obj = open_file(path).context("Failed to open DICOM file")?;
+    let elem = obj
+        .element_by_name("SeriesInstanceUID")
+        .context("SeriesInstanceUID not found")?;
+    Ok(elem.to_str()?.trim().to_string())
+}
+
+/// Run `read` on each of `files` on the blocking pool, so parsing a folder does not stall
+/// the runtime threads other studies are checked on. Results follow the order of `files`.
+async fn read_files<T: Send + 'static>(
+    files: &[PathBuf],
+    read: fn(&Path) -> Result<T>,
+) -> Result<Vec<Result<T>>> {
+    let files = files.to_vec();
+    Ok(tokio::task::spawn_blocking(move || files.iter().map(|f| read(f)).collect()).await?)
+}
+
 // ============================================================================
 // File System Helpers
 // ============================================================================
 
 /// List all .dcm files in a directory (non-recursive).
-async fn list_dcm_files(dir: &Path) -> Result<Vec<PathBuf>> {
+pub(crate) async fn list_dcm_files(dir: &Path) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
     let mut entries = fs::read_dir(dir).await?;
 
@@ -222,7 +450,7 @@ async fn list_dcm_files(dir: &Path) -> Result<Vec<PathBuf>> {
         if path.is_file()
             && path
                 .extension()
-                .map(|e| e.to_ascii_lowercase() == "dcm")
+                .map(|e| e.eq_ignore_ascii_case("dcm"))
                 .unwrap_or(false)
         {
             files.push(path);
@@ -232,9 +460,8 @@ async fn list_dcm_files(dir: &Path) -> Result<Vec<PathBuf>> {
     Ok(files)
 }
 
-/// Find all DWI-related folders in a study directory.
-/// Matches folders named exactly "DWI0" or "DWI1000".
-async fn find_dwi_folders(study_dir: &Path) -> Result<Vec<PathBuf>> {
+/// Find the DWI folders of a study: folders named after one of the `buckets`.
+async fn find_dwi_folders(study_dir: &Path, buckets: &[BValueBucket]) -> Result<Vec<PathBuf>> {
     let mut folders = Vec::new();
     let mut entries = fs::read_dir(study_dir).await?;
 
@@ -242,7 +469,7 @@ async fn find_dwi_folders(study_dir: &Path) -> Result<Vec<PathBuf>> {
         let path = entry.path();
         if path.is_dir() {
             if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name == "DWI0" || name == "DWI1000" {
+                if buckets.iter().any(|b| b.folder == name) {
                     folders.push(path);
                 }
             }
@@ -295,13 +522,17 @@ async fn remove_if_empty(dir: &Path) -> Result<bool> {
 /// Check DWI series for misplaced files based on b-value.
 ///
 /// Rules:
-/// - b-value is None or 0 → should be in DWI0
-/// - b-value == 1000 → should be in DWI1000
+/// - Each file belongs in the folder of the bucket containing its b-value (a missing
+///   b-value counts as 0); by default b=0 → DWI0 and b=1000 → DWI1000
+/// - Files whose b-value is in no bucket stay where they are
 ///
-/// If only DWI0 exists but contains b=1000 files, they will be moved to a new DWI1000 folder.
-/// If only DWI1000 exists but contains b=0 files, they will be moved to a new DWI0 folder.
-pub async fn check_dwi_series(study_dir: &Path) -> Result<Vec<SeriesCheckResult>> {
-    let dwi_folders = find_dwi_folders(study_dir).await?;
+/// A bucket folder that does not exist yet is created for the files moved into it, e.g.
+/// b=1000 files found in DWI0 when only DWI0 exists.
+pub async fn check_dwi_series(
+    study_dir: &Path,
+    buckets: &[BValueBucket],
+) -> Result<Vec<SeriesCheckResult>> {
+    let dwi_folders = find_dwi_folders(study_dir, buckets).await?;
 
     // Need at least one DWI folder to check
     if dwi_folders.is_empty() {
@@ -315,31 +546,20 @@ pub async fn check_dwi_series(study_dir: &Path) -> Result<Vec<SeriesCheckResult>
             .file_name()
             .and_then(|n| n.to_str())
             .unwrap_or("unknown");
-        let is_dwi0_folder = folder_name == "DWI0";
 
         let dcm_files = list_dcm_files(folder).await?;
         let mut actions = Vec::new();
         let mut files_checked = 0;
+        let bvalues = read_files(&dcm_files, read_bvalue).await?;
 
-        for dcm_file in &dcm_files {
+        for (dcm_file, bvalue) in dcm_files.iter().zip(bvalues) {
             files_checked += 1;
-            match read_bvalue(dcm_file) {
+            match bvalue {
                 Ok(bvalue) => {
                     // Determine where this file should be
-                    let should_be_in_dwi0 = bvalue.is_none() || bvalue == Some(0);
-                    let should_be_in_dwi1000 = bvalue == Some(1000);
-
-                    let needs_move = if is_dwi0_folder {
-                        // File is in DWI0 but should be in DWI1000
-                        should_be_in_dwi1000
-                    } else {
-                        // File is in DWI1000 but should be in DWI0
-                        should_be_in_dwi0
-                    };
-
-                    if needs_move {
-                        let target_folder_name = if should_be_in_dwi0 { "DWI0" } else { "DWI1000" };
-                        let target_folder = study_dir.join(target_folder_name);
+                    let bucket = buckets.iter().find(|b| b.contains(bvalue.unwrap_or(0)));
+                    if let Some(bucket) = bucket.filter(|b| b.folder != folder_name) {
+                        let target_folder = study_dir.join(&bucket.folder);
                         let target_path = target_folder.join(dcm_file.file_name().unwrap());
 
                         actions.push(FileAction {
@@ -348,16 +568,18 @@ pub async fn check_dwi_series(study_dir: &Path) -> Result<Vec<SeriesCheckResult>
                             target_path: Some(target_path),
                             reason: format!(
                                 "b-value={} should be in {}",
-                                bvalue.map(|v| v.to_string()).unwrap_or("0/None".to_string()),
-                                target_folder_name
+                                bvalue
+                                    .map(|v| v.to_string())
+                                    .unwrap_or("0/None".to_string()),
+                                bucket.folder
                             ),
                         });
                     }
                 }
                 Err(e) => {
                     // This only happens if the DICOM file itself cannot be opened/parsed
-                    eprintln!(
-                        "Warning: Failed to read DICOM file {}: {}",
+                    warn!(
+                        "Failed to read DICOM file {}: {}",
                         dcm_file.file_name().unwrap_or_default().to_string_lossy(),
                         e
                     );
@@ -385,15 +607,16 @@ pub async fn check_dwi_series(study_dir: &Path) -> Result<Vec<SeriesCheckResult>
 async fn collect_sop_instance_uids(dir: &Path) -> Result<HashSet<String>> {
     let mut uids = HashSet::new();
     let dcm_files = list_dcm_files(dir).await?;
+    let read = read_files(&dcm_files, read_sop_instance_uid).await?;
 
-    for file in dcm_files {
-        match read_sop_instance_uid(&file) {
+    for (file, uid) in dcm_files.iter().zip(read) {
+        match uid {
             Ok(uid) => {
                 uids.insert(uid);
             }
             Err(e) => {
-                eprintln!(
-                    "Warning: Failed to read SOP Instance UID from {}: {}",
+                warn!(
+                    "Failed to read SOP Instance UID from {}: {}",
                     file.display(),
                     e
                 );
@@ -487,13 +710,601 @@ pub async fn check_adc_series(study_dir: &Path) -> Result<Vec<SeriesCheckResult>
     Ok(results)
 }
 
+// ============================================================================
+// Mixed Series Check Logic
+// ============================================================================
+
+/// Folder suffix of each UID: its last component, or the whole UID (dots replaced) when
+/// two UIDs of the folder share the last component.
+fn uid_suffixes<'a>(uids: impl Iterator<Item = &'a String> + Clone) -> HashMap<String, String> {
+    let last = |uid: &str| uid.rsplit('.').next().unwrap_or(uid).to_string();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for uid in uids.clone() {
+        *counts.entry(last(uid)).or_default() += 1;
+    }
+    uids.map(|uid| {
+        let suffix = last(uid);
+        let suffix = if counts[&suffix] > 1 {
+            uid.replace('.', "_")
+        } else {
+            suffix
+        };
+        (uid.clone(), suffix)
+    })
+    .collect()
+}
+
+/// Check every series folder of a study for files of more than one SeriesInstanceUID, a
+/// sign that per-instance grouping put unrelated series together.
+///
+/// With `split` each file is moved to `<folder>__<uid suffix>` next to the folder (a file
+/// whose target already exists is only flagged); otherwise the folder is flagged once.
+/// Files without a readable SeriesInstanceUID stay where they are.
+pub async fn check_mixed_series(study_dir: &Path, split: bool) -> Result<Vec<SeriesCheckResult>> {
+    let mut results = Vec::new();
+    let mut entries = fs::read_dir(study_dir).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        let folder = entry.path();
+        if !folder.is_dir() {
+            continue;
+        }
+        let folder_name = folder
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let dcm_files = list_dcm_files(&folder).await?;
+        let mut by_series: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        let uids = read_files(&dcm_files, read_series_instance_uid).await?;
+        for (dcm_file, uid) in dcm_files.iter().zip(uids) {
+            match uid {
+                Ok(uid) => by_series.entry(uid).or_default().push(dcm_file.clone()),
+                Err(e) => warn!(
+                    "Failed to read SeriesInstanceUID from {}: {}",
+                    dcm_file.display(),
+                    e
+                ),
+            }
+        }
+        if by_series.len() <= 1 {
+            continue;
+        }
+
+        let reason = format!("folder holds {} SeriesInstanceUIDs", by_series.len());
+        let mut actions = Vec::new();
+        if split {
+            let suffixes = uid_suffixes(by_series.keys());
+            for (uid, files) in &by_series {
+                let target_folder = study_dir.join(format!("{}__{}", folder_name, suffixes[uid]));
+                for file in files {
+                    let target_path = target_folder.join(file.file_name().unwrap());
+                    let exists = target_path.exists();
+                    actions.push(FileAction {
+                        source_path: file.clone(),
+                        action_type: if exists {
+                            ActionType::Flag
+                        } else {
+                            ActionType::Move
+                        },
+                        target_path: Some(target_path),
+                        reason: if exists {
+                            format!("{}; {} not split, target exists", reason, uid)
+                        } else {
+                            format!("{}; SeriesInstanceUID {}", reason, uid)
+                        },
+                    });
+                }
+            }
+        } else {
+            actions.push(FileAction {
+                source_path: folder.clone(),
+                action_type: ActionType::Flag,
+                target_path: None,
+                reason: format!(
+                    "{} ({})",
+                    reason,
+                    by_series
+                        .iter()
+                        .map(|(uid, files)| format!("{}: {} files", uid, files.len()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        }
+
+        results.push(SeriesCheckResult {
+            series_folder: folder_name,
+            check_type: CheckType::MixedSeries,
+            files_checked: dcm_files.len(),
+            actions,
+        });
+    }
+
+    Ok(results)
+}
+
+// ============================================================================
+// Slice Gap Check Logic
+// ============================================================================
+
+/// Geometry of one slice: InstanceNumber and the position along the slice normal (mm).
+#[derive(Debug, Clone, Copy, Default)]
+struct SliceInfo {
+    instance_number: Option<i64>,
+    position: Option<f64>,
+}
+
+/// Read InstanceNumber and the ImagePositionPatient projected on the slice normal (from
+/// ImageOrientationPatient, or the z coordinate without it), header only.
+fn read_slice_info(path: &Path) -> Result<SliceInfo> {
+    let obj = OpenFileOptions::new()
+        .read_until(Tag(0x7FE0, 0x0010))
+        .open_file(path)
+        .context("Failed to open DICOM file")?;
+    let floats = |name: &str| {
+        obj.element_by_name(name)
+            .ok()
+            .and_then(|e| e.to_multi_float64().ok())
+    };
+    let instance_number = obj
+        .element_by_name("InstanceNumber")
+        .ok()
+        .and_then(|e| e.to_int::<i64>().ok());
+    let normal = floats("ImageOrientationPatient")
+        .filter(|o| o.len() == 6)
+        .map(|o| {
+            [
+                o[1] * o[5] - o[2] * o[4],
+                o[2] * o[3] - o[0] * o[5],
+                o[0] * o[4] - o[1] * o[3],
+            ]
+        })
+        .unwrap_or([0.0, 0.0, 1.0]);
+    let position = floats("ImagePositionPatient")
+        .filter(|p| p.len() == 3)
+        .map(|p| p[0] * normal[0] + p[1] * normal[1] + p[2] * normal[2]);
+    Ok(SliceInfo {
+        instance_number,
+        position,
+    })
+}
+
+/// `[1, 2, 5, 6, 7]` → `"1-2, 5-7"`, at most `limit` ranges.
+fn format_ranges(values: &[i64], limit: usize) -> String {
+    let mut ranges: Vec<(i64, i64)> = Vec::new();
+    for &v in values {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == v => *end = v,
+            _ => ranges.push((v, v)),
+        }
+    }
+    let mut parts: Vec<String> = ranges
+        .iter()
+        .take(limit)
+        .map(|&(a, b)| {
+            if a == b {
+                a.to_string()
+            } else {
+                format!("{}-{}", a, b)
+            }
+        })
+        .collect();
+    if ranges.len() > limit {
+        parts.push(format!("... ({} ranges)", ranges.len()));
+    }
+    parts.join(", ")
+}
+
+/// Problems in the slices of one folder. InstanceNumber gaps are skipped with
+/// `contiguous_numbers = false` (folders holding part of a series split per instance).
+/// Positions repeated once per volume (DWI, fMRI) are fine as long as every position
+/// occurs equally often.
+fn slice_issues(slices: &[SliceInfo], contiguous_numbers: bool) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    let mut numbers: Vec<i64> = slices.iter().filter_map(|s| s.instance_number).collect();
+    numbers.sort_unstable();
+    let mut duplicated: Vec<i64> = numbers
+        .windows(2)
+        .filter(|w| w[0] == w[1])
+        .map(|w| w[0])
+        .collect();
+    duplicated.dedup();
+    if !duplicated.is_empty() {
+        issues.push(format!(
+            "duplicate InstanceNumber {}",
+            format_ranges(&duplicated, 10)
+        ));
+    }
+    numbers.dedup();
+    if contiguous_numbers {
+        let missing: Vec<i64> = numbers
+            .windows(2)
+            .flat_map(|w| w[0] + 1..w[1])
+            .take(100_000)
+            .collect();
+        if !missing.is_empty() {
+            issues.push(format!(
+                "{} InstanceNumber(s) missing: {}",
+                missing.len(),
+                format_ranges(&missing, 10)
+            ));
+        }
+    }
+
+    // 以 0.01 mm 為單位統計各位置出現次數
+    let mut counts: BTreeMap<i64, usize> = BTreeMap::new();
+    for position in slices.iter().filter_map(|s| s.position) {
+        *counts.entry((position * 100.0).round() as i64).or_default() += 1;
+    }
+    let per_position = counts.values().copied().max().unwrap_or(0);
+    let uneven: Vec<String> = counts
+        .iter()
+        .filter(|(_, &n)| n != per_position)
+        .map(|(&z, &n)| format!("{:.2} ({}x)", z as f64 / 100.0, n))
+        .collect();
+    if !uneven.is_empty() {
+        issues.push(format!(
+            "slice positions not repeated {} times: {}",
+            per_position,
+            uneven
+                .iter()
+                .take(10)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    let positions: Vec<f64> = counts.keys().map(|&z| z as f64 / 100.0).collect();
+    if positions.len() >= 3 {
+        let steps: Vec<f64> = positions.windows(2).map(|w| w[1] - w[0]).collect();
+        let mut sorted = steps.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = sorted[sorted.len() / 2];
+        let gaps: Vec<String> = steps
+            .iter()
+            .zip(positions.windows(2))
+            .filter(|(&step, _)| step > median * 1.5)
+            .map(|(step, w)| format!("{:.2} mm between {:.2} and {:.2}", step, w[0], w[1]))
+            .collect();
+        if !gaps.is_empty() {
+            issues.push(format!(
+                "slice spacing gap (usual {:.2} mm): {}",
+                median,
+                gaps.iter().take(10).cloned().collect::<Vec<_>>().join(", ")
+            ));
+        }
+    }
+
+    issues
+}
+
+/// Check every series folder of a study for missing or duplicated slices, reporting each
+/// problem as a [`ActionType::Flag`] on the folder. Folders named in `split_folders` (the
+/// DWI buckets) hold part of a series, so only their positions are checked.
+pub async fn check_slice_gaps(
+    study_dir: &Path,
+    split_folders: &[&str],
+) -> Result<Vec<SeriesCheckResult>> {
+    let mut results = Vec::new();
+    let mut entries = fs::read_dir(study_dir).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        let folder = entry.path();
+        if !folder.is_dir() {
+            continue;
+        }
+        let folder_name = folder
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let dcm_files = list_dcm_files(&folder).await?;
+        if dcm_files.len() < 2 {
+            continue;
+        }
+        let mut slices = Vec::with_capacity(dcm_files.len());
+        let infos = read_files(&dcm_files, read_slice_info).await?;
+        for (dcm_file, info) in dcm_files.iter().zip(infos) {
+            match info {
+                Ok(slice) => slices.push(slice),
+                Err(e) => warn!(
+                    "Failed to read slice position from {}: {}",
+                    dcm_file.display(),
+                    e
+                ),
+            }
+        }
+
+        let issues = slice_issues(&slices, !split_folders.contains(&folder_name.as_str()));
+        if issues.is_empty() {
+            continue;
+        }
+        results.push(SeriesCheckResult {
+            series_folder: folder_name,
+            check_type: CheckType::SliceGaps,
+            files_checked: dcm_files.len(),
+            actions: issues
+                .into_iter()
+                .map(|reason| FileAction {
+                    source_path: folder.clone(),
+                    action_type: ActionType::Flag,
+                    target_path: None,
+                    reason,
+                })
+                .collect(),
+        });
+    }
+
+    Ok(results)
+}
+
+// ============================================================================
+// Folder Name Check Logic
+// ============================================================================
+
+/// Study tags of the folder (header of the first readable file of any series folder) and
+/// the modality of each series folder.
+async fn read_study_tags(study_dir: &Path) -> Result<Option<(DicomStudyInfo, Vec<String>)>> {
+    let mut info = None;
+    let mut modalities = Vec::new();
+    let mut entries = fs::read_dir(study_dir).await?;
+    let mut folders = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.path().is_dir() {
+            folders.push(entry.path());
+        }
+    }
+    folders.sort();
+
+    for folder in folders {
+        let mut dcm_files = list_dcm_files(&folder).await?;
+        dcm_files.sort();
+        let obj = tokio::task::spawn_blocking(move || {
+            dcm_files.iter().find_map(|f| {
+                OpenFileOptions::new()
+                    .read_until(Tag(0x7FE0, 0x0010))
+                    .open_file(f)
+                    .ok()
+            })
+        })
+        .await?;
+        let Some(obj) = obj else {
+            continue;
+        };
+        let text = |name: &str| {
+            obj.element_by_name(name)
+                .ok()
+                .and_then(|e| e.to_str().ok())
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        let modality = text("Modality");
+        if !modality.is_empty() && !modalities.contains(&modality) {
+            modalities.push(modality.clone());
+        }
+        info.get_or_insert_with(|| DicomStudyInfo {
+            patient_id: text("PatientID"),
+            study_date: text("StudyDate"),
+            modality,
+            accession_number: text("AccessionNumber"),
+        });
+    }
+
+    Ok(info.map(|info| (info, modalities)))
+}
+
+/// Whether `name` is `expected`, or `expected` plus a `_<number>` suffix (`duplicate_studies
+/// = "suffix-folders"`, series of one type numbered by SeriesNumber).
+fn is_name_or_numbered(name: &str, expected: &str) -> bool {
+    name == expected
+        || name.strip_prefix(expected).is_some_and(|rest| {
+            rest.strip_prefix('_')
+                .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        })
+}
+
+/// First of `candidates`, then `<last candidate>_2`, `_3`, ... that neither exists under
+/// `parent` nor is claimed by an earlier rename of this run.
+fn free_folder(parent: &Path, candidates: &[String], claimed: &HashSet<PathBuf>) -> PathBuf {
+    let free = |path: &PathBuf| !path.exists() && !claimed.contains(path);
+    if let Some(path) = candidates.iter().map(|c| parent.join(c)).find(free) {
+        return path;
+    }
+    let last = candidates.last().map(String::as_str).unwrap_or("unknown");
+    (2..)
+        .map(|n| parent.join(format!("{}_{}", last, n)))
+        .find(free)
+        .unwrap()
+}
+
+/// Flag, or with `fix` rename, a folder whose name does not match.
+fn name_action(source: &Path, target: PathBuf, reason: String, fix: bool) -> FileAction {
+    FileAction {
+        source_path: source.to_path_buf(),
+        action_type: if fix {
+            ActionType::Rename
+        } else {
+            ActionType::Flag
+        },
+        target_path: Some(target),
+        reason,
+    }
+}
+
+/// Check the series folder names of a study against the `series_type` in their
+/// `series.json` (folders without one are skipped). Accepted are the type itself, the type
+/// with a `_<number>` suffix, and `<type>__...` folders split by the mixed series check.
+pub async fn check_series_names(
+    study_dir: &Path,
+    fix: bool,
+    claimed: &mut HashSet<PathBuf>,
+) -> Result<Vec<SeriesCheckResult>> {
+    let mut results = Vec::new();
+    let mut entries = fs::read_dir(study_dir).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        let folder = entry.path();
+        let Ok(json) = fs::read_to_string(folder.join(SERIES_SIDECAR)).await else {
+            continue;
+        };
+        let sidecar: SeriesSidecar = match serde_json::from_str(&json) {
+            Ok(sidecar) => sidecar,
+            Err(e) => {
+                warn!(
+                    "Failed to parse {}: {}",
+                    folder.join(SERIES_SIDECAR).display(),
+                    e
+                );
+                continue;
+            }
+        };
+        let folder_name = entry.file_name().to_string_lossy().to_string();
+        let expected = sanitize_segment(&sidecar.series_type);
+        let split = folder_name
+            .split_once("__")
+            .is_some_and(|(base, _)| is_name_or_numbered(base, &expected));
+        if is_name_or_numbered(&folder_name, &expected) || split {
+            continue;
+        }
+
+        let mut candidates = vec![expected.clone()];
+        if let Some(number) = sidecar
+            .series_number
+            .as_deref()
+            .and_then(|n| n.trim().parse::<u32>().ok())
+        {
+            candidates.push(format!("{}_{:03}", expected, number));
+        }
+        let target = free_folder(study_dir, &candidates, claimed);
+        claimed.insert(target.clone());
+        let reason = format!(
+            "series folder '{}' does not match series type '{}'",
+            folder_name, sidecar.series_type
+        );
+        results.push(SeriesCheckResult {
+            series_folder: folder_name,
+            check_type: CheckType::Names,
+            files_checked: 0,
+            actions: vec![name_action(&folder, target, reason, fix)],
+        });
+    }
+
+    Ok(results)
+}
+
+/// Check a study folder (relative to `base_dir`, `/`-separated) against the PatientID,
+/// StudyDate, Modality, and AccessionNumber inside it. The folder modality may be that of
+/// any of its series; `_<number>` suffixes of duplicate studies are accepted. Folders
+/// without a readable DICOM file are skipped.
+pub async fn check_study_name(
+    base_dir: &Path,
+    study_folder: &str,
+    layout: OutputLayout,
+    fix: bool,
+    claimed: &mut HashSet<PathBuf>,
+) -> Result<Option<SeriesCheckResult>> {
+    let study_dir = base_dir.join(study_folder);
+    let Some((mut info, modalities)) = read_study_tags(&study_dir).await? else {
+        return Ok(None);
+    };
+    let leaf = study_folder.rsplit('/').next().unwrap_or(study_folder);
+    // 資料夾的 modality 若為任一 series 的 modality 即視為正確
+    if let Some(modality) = modalities
+        .iter()
+        .find(|m| leaf.contains(&format!("_{}_", sanitize_segment(m))))
+    {
+        info.modality = modality.clone();
+    }
+    let expected = layout.study_folder(&info);
+    if is_name_or_numbered(study_folder, &expected) {
+        return Ok(None);
+    }
+
+    let expected_leaf = generate_study_folder_name(&info);
+    let mut mismatched = Vec::new();
+    let components = [
+        (
+            "PatientID",
+            &info.patient_id,
+            leaf.starts_with(&format!("{}_", sanitize_segment(&info.patient_id))),
+        ),
+        (
+            "StudyDate",
+            &info.study_date,
+            leaf.contains(&format!("_{}_", sanitize_segment(&info.study_date))),
+        ),
+        (
+            "Modality",
+            &info.modality,
+            leaf.contains(&format!("_{}_", sanitize_segment(&info.modality))),
+        ),
+        (
+            "AccessionNumber",
+            &info.accession_number,
+            is_name_or_numbered(leaf, &expected_leaf)
+                || leaf.ends_with(&format!("_{}", sanitize_segment(&info.accession_number))),
+        ),
+    ];
+    for (tag, value, matches) in components {
+        if !matches {
+            mismatched.push(format!("{}={}", tag, value));
+        }
+    }
+    if mismatched.is_empty() {
+        // 各欄位都對，只有巢狀路徑（病人／日期資料夾）不符
+        mismatched.push("nesting".to_string());
+    }
+
+    let target = free_folder(base_dir, std::slice::from_ref(&expected), claimed);
+    claimed.insert(target.clone());
+    let reason = format!(
+        "study folder does not match tags ({}), expected '{}'",
+        mismatched.join(", "),
+        expected
+    );
+    Ok(Some(SeriesCheckResult {
+        series_folder: String::new(),
+        check_type: CheckType::Names,
+        files_checked: 0,
+        actions: vec![name_action(&study_dir, target, reason, fix)],
+    }))
+}
+
 // ============================================================================
 // Execution Logic
 // ============================================================================
 
-/// Execute file actions (move or delete).
-/// Returns the number of successful operations.
-pub async fn execute_actions(actions: &[FileAction], dry_run: bool) -> Result<(usize, usize)> {
+/// Turns deletions into moves to `quarantine`, keeping the file's path under `base_dir`.
+pub fn quarantine_actions(actions: &mut [FileAction], base_dir: &Path, quarantine: &DicomTrash) {
+    for action in actions
+        .iter_mut()
+        .filter(|a| a.action_type == ActionType::Delete)
+    {
+        let relative = action
+            .source_path
+            .strip_prefix(base_dir)
+            .unwrap_or(&action.source_path);
+        action.action_type = ActionType::Move;
+        action.target_path = Some(quarantine.run_dir().join(relative));
+        action.reason = format!("{} (quarantined)", action.reason);
+    }
+}
+
+/// Execute file actions (move, delete, or folder rename).
+/// Returns the number of successful moves (renames included) and deletes.
+///
+/// With an audit log every move/delete/folder removal is recorded (planned ones in dry-run
+/// mode); a failure to write the audit log stops the run.
+pub async fn execute_actions(
+    actions: &[FileAction],
+    dry_run: bool,
+    audit: Option<&AuditLog>,
+) -> Result<(usize, usize)> {
     let mut moves = 0;
     let mut deletes = 0;
 
@@ -505,34 +1316,52 @@ pub async fn execute_actions(actions: &[FileAction], dry_run: bool) -> Result<(u
             ActionType::Move => {
                 if let Some(target_path) = &action.target_path {
                     if dry_run {
-                        println!(
+                        info!(
                             "[DRY-RUN] Would move: {} -> {}",
                             action.source_path.display(),
                             target_path.display()
                         );
+                        if let Some(audit) = audit {
+                            audit.record(
+                                AuditOperation::Move,
+                                &action.source_path,
+                                Some(target_path),
+                                &action.reason,
+                                true,
+                                "planned",
+                            )?;
+                        }
                     } else {
                         // Ensure target directory exists
                         if let Some(parent) = target_path.parent() {
                             fs::create_dir_all(parent).await?;
                         }
 
-                        // Move file
-                        fs::rename(&action.source_path, target_path)
-                            .await
-                            .with_context(|| {
-                                format!(
-                                    "Failed to move {} to {}",
-                                    action.source_path.display(),
-                                    target_path.display()
-                                )
-                            })?;
+                        // Move file (copied across filesystems, e.g. to the quarantine)
+                        let moved = move_file(&action.source_path, target_path).await;
+                        if let Some(audit) = audit {
+                            audit.record_outcome(
+                                AuditOperation::Move,
+                                &action.source_path,
+                                Some(target_path),
+                                &action.reason,
+                                &moved,
+                            )?;
+                        }
+                        moved.with_context(|| {
+                            format!(
+                                "Failed to move {} to {}",
+                                action.source_path.display(),
+                                target_path.display()
+                            )
+                        })?;
 
                         // Track source folder for cleanup
                         if let Some(parent) = action.source_path.parent() {
                             folders_to_check.insert(parent.to_path_buf());
                         }
 
-                        println!(
+                        info!(
                             "Moved: {} -> {}",
                             action.source_path.display(),
                             target_path.display()
@@ -543,23 +1372,104 @@ pub async fn execute_actions(actions: &[FileAction], dry_run: bool) -> Result<(u
             }
             ActionType::Delete => {
                 if dry_run {
-                    println!("[DRY-RUN] Would delete: {}", action.source_path.display());
+                    info!("[DRY-RUN] Would delete: {}", action.source_path.display());
+                    if let Some(audit) = audit {
+                        audit.record(
+                            AuditOperation::Delete,
+                            &action.source_path,
+                            None,
+                            &action.reason,
+                            true,
+                            "planned",
+                        )?;
+                    }
                 } else {
-                    fs::remove_file(&action.source_path)
-                        .await
-                        .with_context(|| {
-                            format!("Failed to delete {}", action.source_path.display())
-                        })?;
+                    let deleted = fs::remove_file(&action.source_path).await;
+                    if let Some(audit) = audit {
+                        audit.record_outcome(
+                            AuditOperation::Delete,
+                            &action.source_path,
+                            None,
+                            &action.reason,
+                            &deleted,
+                        )?;
+                    }
+                    deleted.with_context(|| {
+                        format!("Failed to delete {}", action.source_path.display())
+                    })?;
 
                     // Track source folder for cleanup
                     if let Some(parent) = action.source_path.parent() {
                         folders_to_check.insert(parent.to_path_buf());
                     }
 
-                    println!("Deleted: {}", action.source_path.display());
+                    info!("Deleted: {}", action.source_path.display());
                 }
                 deletes += 1;
             }
+            ActionType::Flag => {
+                warn!(
+                    "Flagged: {}: {}",
+                    action.source_path.display(),
+                    action.reason
+                );
+            }
+            ActionType::Rename => {
+                if let Some(target_path) = &action.target_path {
+                    if dry_run {
+                        info!(
+                            "[DRY-RUN] Would rename: {} -> {}",
+                            action.source_path.display(),
+                            target_path.display()
+                        );
+                        if let Some(audit) = audit {
+                            audit.record(
+                                AuditOperation::Move,
+                                &action.source_path,
+                                Some(target_path),
+                                &action.reason,
+                                true,
+                                "planned",
+                            )?;
+                        }
+                    } else {
+                        // 目標已存在時不合併資料夾
+                        let renamed = if target_path.exists() {
+                            Err(std::io::Error::new(
+                                std::io::ErrorKind::AlreadyExists,
+                                "target folder exists",
+                            ))
+                        } else {
+                            if let Some(parent) = target_path.parent() {
+                                fs::create_dir_all(parent).await?;
+                            }
+                            fs::rename(&action.source_path, target_path).await
+                        };
+                        if let Some(audit) = audit {
+                            audit.record_outcome(
+                                AuditOperation::Move,
+                                &action.source_path,
+                                Some(target_path),
+                                &action.reason,
+                                &renamed,
+                            )?;
+                        }
+                        renamed.with_context(|| {
+                            format!(
+                                "Failed to rename {} to {}",
+                                action.source_path.display(),
+                                target_path.display()
+                            )
+                        })?;
+                        info!(
+                            "Renamed: {} -> {}",
+                            action.source_path.display(),
+                            target_path.display()
+                        );
+                    }
+                    moves += 1;
+                }
+            }
         }
     }
 
@@ -567,14 +1477,20 @@ pub async fn execute_actions(actions: &[FileAction], dry_run: bool) -> Result<(u
     if !dry_run {
         for folder in folders_to_check {
             if folder.exists() {
-                match remove_if_empty(&folder).await {
-                    Ok(true) => println!("Removed empty folder: {}", folder.display()),
+                let removed = remove_if_empty(&folder).await;
+                match &removed {
+                    Ok(true) => info!("Removed empty folder: {}", folder.display()),
                     Ok(false) => {}
-                    Err(e) => eprintln!(
-                        "Warning: Failed to check/remove folder {}: {}",
-                        folder.display(),
-                        e
-                    ),
+                    Err(e) => warn!("Failed to check/remove folder {}: {}", folder.display(), e),
+                }
+                if let (Some(audit), Ok(true) | Err(_)) = (audit, &removed) {
+                    audit.record_outcome(
+                        AuditOperation::RemoveDir,
+                        &folder,
+                        None,
+                        "empty after check fixes",
+                        &removed,
+                    )?;
                 }
             }
         }
@@ -583,6 +1499,121 @@ pub async fn execute_actions(actions: &[FileAction], dry_run: bool) -> Result<(u
     Ok((moves, deletes))
 }
 
+// ============================================================================
+// Undo Logic
+// ============================================================================
+
+/// Outcome of `check undo`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UndoSummary {
+    /// Moves, renames, and folder removals reversed (quarantined files included).
+    pub restored: usize,
+    /// Hard deletions, which cannot be reversed.
+    pub not_restorable: usize,
+    /// Operations whose reversal failed or was unsafe (source taken, target gone).
+    pub failed: usize,
+}
+
+/// Reverses the executed operations of a `check` journal, newest first: moved files and
+/// renamed folders go back (from the quarantine too), removed empty folders are recreated.
+/// Each reversal is written to `audit`; with `dry_run` nothing changes.
+pub async fn undo_journal(
+    journal: &Path,
+    dry_run: bool,
+    audit: Option<&AuditLog>,
+) -> Result<UndoSummary> {
+    let entries = crate::audit::read_audit_log(journal)?;
+    let mut summary = UndoSummary::default();
+
+    for entry in entries.iter().rev() {
+        if entry.dry_run || entry.result != "ok" {
+            continue;
+        }
+        let rule = format!("undo {} ({})", entry.run_id, entry.rule);
+        match (entry.operation, &entry.target) {
+            (AuditOperation::Move, Some(target)) => {
+                if !target.exists() || entry.source.exists() {
+                    warn!(
+                        "Cannot restore {}: {} is missing or {} exists",
+                        entry.source.display(),
+                        target.display(),
+                        entry.source.display()
+                    );
+                    summary.failed += 1;
+                    continue;
+                }
+                if dry_run {
+                    info!(
+                        "[DRY-RUN] Would restore: {} -> {}",
+                        target.display(),
+                        entry.source.display()
+                    );
+                    if let Some(audit) = audit {
+                        audit.record(
+                            AuditOperation::Move,
+                            target,
+                            Some(&entry.source),
+                            &rule,
+                            true,
+                            "planned",
+                        )?;
+                    }
+                    summary.restored += 1;
+                    continue;
+                }
+                if let Some(parent) = entry.source.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                // 資料夾（改名）直接 rename，檔案可能跨檔案系統（隔離區）
+                let restored = if target.is_dir() {
+                    fs::rename(target, &entry.source).await
+                } else {
+                    move_file(target, &entry.source).await
+                };
+                if let Some(audit) = audit {
+                    audit.record_outcome(
+                        AuditOperation::Move,
+                        target,
+                        Some(&entry.source),
+                        &rule,
+                        &restored,
+                    )?;
+                }
+                match restored {
+                    Ok(()) => {
+                        info!(
+                            "Restored: {} -> {}",
+                            target.display(),
+                            entry.source.display()
+                        );
+                        summary.restored += 1;
+                    }
+                    Err(e) => {
+                        warn!("Failed to restore {}: {}", entry.source.display(), e);
+                        summary.failed += 1;
+                    }
+                }
+            }
+            (AuditOperation::RemoveDir, _) => {
+                if !dry_run {
+                    fs::create_dir_all(&entry.source).await?;
+                }
+                summary.restored += 1;
+            }
+            (AuditOperation::Delete, _) => {
+                warn!(
+                    "Cannot restore deleted file {} (use --quarantine to keep deletions)",
+                    entry.source.display()
+                );
+                summary.not_restorable += 1;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(summary)
+}
+
 // ============================================================================
 // Main Check Function
 // ============================================================================
@@ -590,7 +1621,7 @@ pub async fn execute_actions(actions: &[FileAction], dry_run: bool) -> Result<(u
 /// Run the complete check on a directory structure.
 ///
 /// Expected structure:
-/// ```
+/// ```text
 /// input_dir/
 /// └── dicom/
 ///     └── PatientID_StudyDate_Modality_Accession/
@@ -599,44 +1630,125 @@ pub async fn execute_actions(actions: &[FileAction], dry_run: bool) -> Result<(u
 ///         ├── ADC/
 ///         └── ADC_3/
 /// ```
-pub async fn run_check(input_dir: &Path, dry_run: bool) -> Result<CheckReport> {
+///
+/// With a nested `layout` the study folders sit under patient (and date) folders; `config`
+/// is the `[check]` table. With `quarantine_dir` set, files that would be deleted are moved
+/// to `<quarantine_dir>/<audit run id>/<path under the scanned folder>` instead.
+///
+/// Up to `config.concurrency` studies are checked at once (DICOM headers are parsed on the
+/// blocking pool); the report lists them in folder order either way. Only the study
+/// folders selected by `filter` are checked.
+pub async fn run_check(
+    input_dir: &Path,
+    dry_run: bool,
+    audit: Option<&AuditLog>,
+    layout: OutputLayout,
+    config: &CheckConfig,
+    filter: &StudyFilter,
+) -> Result<CheckReport> {
     let dicom_dir = input_dir.join("dicom");
 
     if !dicom_dir.exists() {
         // Try input_dir directly if no dicom/ subdirectory
-        return run_check_on_dir(input_dir, dry_run).await;
+        return run_check_on_dir(input_dir, dry_run, audit, layout, config, filter).await;
     }
 
-    run_check_on_dir(&dicom_dir, dry_run).await
+    run_check_on_dir(&dicom_dir, dry_run, audit, layout, config, filter).await
 }
 
-async fn run_check_on_dir(base_dir: &Path, dry_run: bool) -> Result<CheckReport> {
+async fn run_check_on_dir(
+    base_dir: &Path,
+    dry_run: bool,
+    audit: Option<&AuditLog>,
+    layout: OutputLayout,
+    config: &CheckConfig,
+    filter: &StudyFilter,
+) -> Result<CheckReport> {
+    // 以稽核紀錄的 run ID 命名隔離區資料夾，與稽核紀錄互相對應
+    let quarantine_run = audit
+        .map(|a| a.run_id().to_string())
+        .unwrap_or_else(|| run_id(Utc::now()));
+    let checker = StudyChecker {
+        base_dir,
+        dry_run,
+        audit,
+        layout,
+        config,
+        dwi_buckets: config.dwi_buckets()?,
+        rules: config.check_rules()?,
+        quarantine: config.quarantine(&quarantine_run),
+        claimed: Mutex::new(HashSet::new()),
+    };
     let mut studies = Vec::new();
     let mut summary = CheckSummary::default();
 
-    // Iterate over study directories
-    let mut entries = fs::read_dir(base_dir).await?;
-
-    while let Some(entry) = entries.next_entry().await? {
-        let study_dir = entry.path();
-        if !study_dir.is_dir() {
-            continue;
+    let mut study_dirs = layout.study_dirs(base_dir)?;
+    if !filter.is_empty() {
+        let total = study_dirs.len();
+        study_dirs.retain(|(folder, _)| filter.matches(folder));
+        info!("{} of {} study folders selected", study_dirs.len(), total);
+        for acc in &filter.accessions {
+            if !study_dirs
+                .iter()
+                .any(|(folder, _)| filter.accession_of(folder) == Some(acc.as_str()))
+            {
+                warn!("No study folder found for accession {}", acc);
+            }
         }
+    }
 
-        let study_folder = study_dir
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown")
-            .to_string();
+    // buffered 保持 study 順序，報告與逐一檢查時相同
+    let mut checked = stream::iter(study_dirs)
+        .map(|(study_folder, study_dir)| checker.check_study(study_folder, study_dir))
+        .buffered(config.get_concurrency());
+    while let Some(result) = checked.next().await {
+        let (study, study_summary) = result?;
+        summary.add(&study_summary);
+        studies.extend(study);
+    }
+
+    Ok(CheckReport {
+        input_path: base_dir.to_path_buf(),
+        timestamp: Utc::now(),
+        dry_run,
+        studies,
+        summary,
+    })
+}
 
-        println!("\nChecking study: {}", study_folder);
+/// Settings and state shared by the studies of one check run.
+struct StudyChecker<'a> {
+    base_dir: &'a Path,
+    dry_run: bool,
+    audit: Option<&'a AuditLog>,
+    layout: OutputLayout,
+    config: &'a CheckConfig,
+    dwi_buckets: Vec<BValueBucket>,
+    rules: CheckRules,
+    quarantine: Option<DicomTrash>,
+    /// Rename targets claimed so far, so two folders are never renamed to the same name.
+    claimed: Mutex<HashSet<PathBuf>>,
+}
 
+impl StudyChecker<'_> {
+    /// Runs every check on one study and executes its actions. Returns the study result
+    /// (`None` when nothing was found) and the study's share of the summary.
+    async fn check_study(
+        &self,
+        study_folder: String,
+        study_dir: PathBuf,
+    ) -> Result<(Option<StudyCheckResult>, CheckSummary)> {
+        let (base_dir, dry_run, audit, config) =
+            (self.base_dir, self.dry_run, self.audit, self.config);
+        info!("Checking study: {}", study_folder);
+
+        let mut summary = CheckSummary::default();
         let mut series_results = Vec::new();
         let mut study_moves = 0;
         let mut study_deletes = 0;
 
         // Check DWI series
-        match check_dwi_series(&study_dir).await {
+        match check_dwi_series(&study_dir, &self.dwi_buckets).await {
             Ok(dwi_results) => {
                 for result in dwi_results {
                     summary.total_files_checked += result.files_checked;
@@ -644,32 +1756,41 @@ async fn run_check_on_dir(base_dir: &Path, dry_run: bool) -> Result<CheckReport>
 
                     if !result.actions.is_empty() {
                         // Execute actions
-                        let (moves, _deletes) = execute_actions(&result.actions, dry_run).await?;
+                        let (moves, _deletes) =
+                            execute_actions(&result.actions, dry_run, audit).await?;
                         study_moves += moves;
                         summary.dwi_fixes += moves;
 
                         series_results.push(result);
                     } else {
-                        println!("  {} - {} files checked, no issues found", result.series_folder, result.files_checked);
+                        debug!(
+                            "{} - {} files checked, no issues found",
+                            result.series_folder, result.files_checked
+                        );
                     }
                 }
             }
             Err(e) => {
-                eprintln!("Warning: DWI check failed for {}: {}", study_folder, e);
+                warn!("DWI check failed for {}: {}", study_folder, e);
             }
         }
 
         // Check ADC series
         match check_adc_series(&study_dir).await {
             Ok(adc_results) => {
-                for result in adc_results {
+                for mut result in adc_results {
                     summary.total_files_checked += result.files_checked;
 
                     if !result.actions.is_empty() {
+                        if let Some(quarantine) = &self.quarantine {
+                            quarantine_actions(&mut result.actions, base_dir, quarantine);
+                        }
                         // Execute actions
-                        let (_moves, deletes) = execute_actions(&result.actions, dry_run).await?;
+                        let (moves, deletes) =
+                            execute_actions(&result.actions, dry_run, audit).await?;
+                        study_moves += moves;
                         study_deletes += deletes;
-                        summary.adc_duplicates_removed += deletes;
+                        summary.adc_duplicates_removed += moves + deletes;
 
                         series_results.push(result);
                         summary.total_series_checked += 1;
@@ -677,27 +1798,363 @@ async fn run_check_on_dir(base_dir: &Path, dry_run: bool) -> Result<CheckReport>
                 }
             }
             Err(e) => {
-                eprintln!("Warning: ADC check failed for {}: {}", study_folder, e);
+                warn!("ADC check failed for {}: {}", study_folder, e);
             }
         }
 
-        if !series_results.is_empty() {
-            studies.push(StudyCheckResult {
+        // Check for mixed series folders
+        match check_mixed_series(&study_dir, config.split_mixed_series.unwrap_or(false)).await {
+            Ok(mixed_results) => {
+                for result in mixed_results {
+                    summary.total_files_checked += result.files_checked;
+                    summary.total_series_checked += 1;
+                    summary.mixed_series_folders += 1;
+
+                    let (moves, _deletes) =
+                        execute_actions(&result.actions, dry_run, audit).await?;
+                    study_moves += moves;
+                    series_results.push(result);
+                }
+            }
+            Err(e) => {
+                warn!("Mixed series check failed for {}: {}", study_folder, e);
+            }
+        }
+
+        // Check for missing or duplicated slices
+        if config.slice_gaps.unwrap_or(true) {
+            let split_folders: Vec<&str> =
+                self.dwi_buckets.iter().map(|b| b.folder.as_str()).collect();
+            match check_slice_gaps(&study_dir, &split_folders).await {
+                Ok(gap_results) => {
+                    for result in gap_results {
+                        summary.total_files_checked += result.files_checked;
+                        summary.total_series_checked += 1;
+                        summary.slice_gap_folders += 1;
+
+                        execute_actions(&result.actions, dry_run, audit).await?;
+                        series_results.push(result);
+                    }
+                }
+                Err(e) => {
+                    warn!("Slice gap check failed for {}: {}", study_folder, e);
+                }
+            }
+        }
+
+        // Check [[check.rules]]
+        match self.rules.check_study(&study_dir).await {
+            Ok(rule_results) => {
+                for mut result in rule_results {
+                    summary.total_files_checked += result.files_checked;
+                    summary.total_series_checked += 1;
+
+                    if !result.actions.is_empty() {
+                        if let Some(quarantine) = &self.quarantine {
+                            quarantine_actions(&mut result.actions, base_dir, quarantine);
+                        }
+                        let (moves, deletes) =
+                            execute_actions(&result.actions, dry_run, audit).await?;
+                        study_moves += moves;
+                        study_deletes += deletes;
+                        summary.rule_fixes += moves + deletes;
+
+                        series_results.push(result);
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Rule checks failed for {}: {}", study_folder, e);
+            }
+        }
+
+        // Check folder names; the study folder is renamed last. Studies check their names
+        // one at a time so concurrent renames cannot claim the same target.
+        if config.name_check.unwrap_or(true) {
+            let fix = config.fix_names.unwrap_or(false);
+            let mut claimed = self.claimed.lock().await;
+            let mut name_results = match check_series_names(&study_dir, fix, &mut claimed).await {
+                Ok(results) => results,
+                Err(e) => {
+                    warn!("Series name check failed for {}: {}", study_folder, e);
+                    Vec::new()
+                }
+            };
+            match check_study_name(base_dir, &study_folder, self.layout, fix, &mut claimed).await {
+                Ok(result) => name_results.extend(result),
+                Err(e) => warn!("Study name check failed for {}: {}", study_folder, e),
+            }
+            for result in name_results {
+                summary.total_series_checked += 1;
+                summary.name_mismatches += 1;
+
+                let (moves, _deletes) = execute_actions(&result.actions, dry_run, audit).await?;
+                study_moves += moves;
+                series_results.push(result);
+            }
+        }
+
+        summary.total_studies = 1;
+        if series_results.is_empty() {
+            return Ok((None, summary));
+        }
+        summary.total_moves = study_moves;
+        summary.total_deletes = study_deletes;
+        Ok((
+            Some(StudyCheckResult {
                 study_folder,
                 series_results,
                 total_moves: study_moves,
                 total_deletes: study_deletes,
-            });
+            }),
+            summary,
+        ))
+    }
+}
+
+// ============================================================================
+// Plan / Apply
+// ============================================================================
 
-            summary.total_moves += study_moves;
-            summary.total_deletes += study_deletes;
+/// Version of the `check plan` file format.
+pub const CHECK_PLAN_VERSION: u32 = 2;
+
+/// Actions found by `check plan`, applied later (possibly on another machine) by `check
+/// apply`. Action paths are relative to the scanned folder, deletions stay deletions (the
+/// applying run decides about quarantine).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckPlan {
+    pub version: u32,
+    pub created: DateTime<Utc>,
+    /// Folder scanned when the plan was made (`dicom/` when the input has one).
+    pub base_dir: PathBuf,
+    pub studies: Vec<StudyCheckResult>,
+    /// Summary of the planning (dry) run.
+    pub summary: CheckSummary,
+    /// Size and SHA-256 of each file a planned action moves or deletes, by relative path.
+    #[serde(default)]
+    pub sources: BTreeMap<PathBuf, FileFingerprint>,
+}
+
+/// Size and SHA-256 of a file when the plan was made; `check apply` leaves a file alone
+/// when it no longer matches.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileFingerprint {
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl FileFingerprint {
+    pub fn of(path: &Path) -> Result<Self> {
+        let size = std::fs::metadata(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .len();
+        Ok(FileFingerprint {
+            size,
+            sha256: sha256_file(path)?,
+        })
+    }
+}
+
+/// Whether a plan path stays inside the folder it is applied to: relative, without `..`.
+fn is_contained(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+impl CheckPlan {
+    /// Plan from a dry-run report; the files of moves and deletions are fingerprinted.
+    /// Fails when an action path lies outside the scanned folder.
+    pub fn from_report(report: CheckReport) -> Result<Self> {
+        let base = report.input_path;
+        let relative = |path: &mut PathBuf| -> Result<()> {
+            let rel = path
+                .strip_prefix(&base)
+                .with_context(|| format!("{} is outside {}", path.display(), base.display()))?;
+            *path = rel.to_path_buf();
+            Ok(())
+        };
+        let mut studies = report.studies;
+        let mut sources = BTreeMap::new();
+        for action in studies
+            .iter_mut()
+            .flat_map(|st| st.series_results.iter_mut())
+            .flat_map(|series| series.actions.iter_mut())
+        {
+            let fingerprint = (action.action_type != ActionType::Flag
+                && action.source_path.is_file())
+            .then(|| FileFingerprint::of(&action.source_path))
+            .transpose()?;
+            relative(&mut action.source_path)?;
+            if let Some(target) = &mut action.target_path {
+                relative(target)?;
+            }
+            if let Some(fingerprint) = fingerprint {
+                sources.insert(action.source_path.clone(), fingerprint);
+            }
         }
+        Ok(CheckPlan {
+            version: CHECK_PLAN_VERSION,
+            created: report.timestamp,
+            base_dir: base,
+            studies,
+            summary: report.summary,
+            sources,
+        })
+    }
 
-        summary.total_studies += 1;
+    /// Refuses a plan with an absolute or `..` action path, which would move or delete
+    /// files outside the folder it is applied to.
+    fn ensure_contained(&self) -> Result<()> {
+        let paths = self
+            .studies
+            .iter()
+            .flat_map(|st| &st.series_results)
+            .flat_map(|series| &series.actions)
+            .flat_map(|action| std::iter::once(&action.source_path).chain(&action.target_path));
+        for path in paths {
+            if !is_contained(path) {
+                anyhow::bail!(
+                    "Check plan path {} leaves the input folder; refusing to apply",
+                    path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let plan: CheckPlan = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid check plan {}", path.display()))?;
+        if plan.version != CHECK_PLAN_VERSION {
+            anyhow::bail!(
+                "Unsupported check plan version {} in {} (expected {})",
+                plan.version,
+                path.display(),
+                CHECK_PLAN_VERSION
+            );
+        }
+        Ok(plan)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_bytes_atomic(path, json.as_bytes())?;
+        info!("Check plan written to: {}", path.display());
+        Ok(())
+    }
+}
+
+/// Why a planned action can no longer be applied as planned, if it cannot. `changed` says
+/// the source file no longer matches its [`FileFingerprint`].
+fn stale_reason(action: &FileAction, changed: bool) -> Option<&'static str> {
+    let target_taken = action.target_path.as_ref().is_some_and(|t| t.exists());
+    match action.action_type {
+        ActionType::Flag => None,
+        _ if !action.source_path.exists() => Some("source missing"),
+        _ if changed => Some("source changed"),
+        ActionType::Move | ActionType::Rename if target_taken => Some("target exists"),
+        _ => None,
+    }
+}
+
+/// Whether the file at `path` differs from `expected`; an unreadable file counts as changed.
+async fn source_changed(path: &Path, expected: &FileFingerprint) -> Result<bool> {
+    let path = path.to_path_buf();
+    let actual = tokio::task::spawn_blocking(move || FileFingerprint::of(&path).ok()).await?;
+    Ok(actual.as_ref() != Some(expected))
+}
+
+/// Executes a [`CheckPlan`] against `input_dir` (its `dicom/` folder when there is one), or
+/// against the folder the plan was made in. Actions run in plan order, series by series;
+/// one whose source has gone or changed since planning, or whose target now exists,
+/// becomes a `Flag` with the reason and is counted in `stale_actions`. Plans with paths
+/// leaving the folder are refused. Deletions go to the `[check]` quarantine when set.
+pub async fn apply_plan(
+    plan: &CheckPlan,
+    input_dir: Option<&Path>,
+    dry_run: bool,
+    audit: Option<&AuditLog>,
+    config: &CheckConfig,
+) -> Result<CheckReport> {
+    plan.ensure_contained()?;
+    let base_dir = match input_dir {
+        Some(dir) if dir.join("dicom").exists() => dir.join("dicom"),
+        Some(dir) => dir.to_path_buf(),
+        None => plan.base_dir.clone(),
+    };
+    let quarantine_run = audit
+        .map(|a| a.run_id().to_string())
+        .unwrap_or_else(|| run_id(Utc::now()));
+    let quarantine = config.quarantine(&quarantine_run);
+    let mut summary = CheckSummary {
+        total_moves: 0,
+        total_deletes: 0,
+        dwi_fixes: 0,
+        adc_duplicates_removed: 0,
+        rule_fixes: 0,
+        stale_actions: 0,
+        ..plan.summary.clone()
+    };
+    let mut studies = Vec::new();
+
+    for planned in &plan.studies {
+        info!("Applying plan for study: {}", planned.study_folder);
+        let mut study = StudyCheckResult {
+            study_folder: planned.study_folder.clone(),
+            series_results: Vec::new(),
+            total_moves: 0,
+            total_deletes: 0,
+        };
+        for series in &planned.series_results {
+            let mut series = series.clone();
+            for action in &mut series.actions {
+                let expected = plan.sources.get(&action.source_path);
+                action.source_path = base_dir.join(&action.source_path);
+                if let Some(target) = &mut action.target_path {
+                    *target = base_dir.join(&*target);
+                }
+                let changed = match expected {
+                    Some(expected) if action.action_type != ActionType::Flag => {
+                        action.source_path.exists()
+                            && source_changed(&action.source_path, expected).await?
+                    }
+                    _ => false,
+                };
+                if let Some(stale) = stale_reason(action, changed) {
+                    warn!(
+                        "Stale plan action for {}: {}",
+                        action.source_path.display(),
+                        stale
+                    );
+                    action.action_type = ActionType::Flag;
+                    action.reason = format!("{} (stale: {})", action.reason, stale);
+                    summary.stale_actions += 1;
+                }
+            }
+            if let Some(quarantine) = &quarantine {
+                quarantine_actions(&mut series.actions, &base_dir, quarantine);
+            }
+            let (moves, deletes) = execute_actions(&series.actions, dry_run, audit).await?;
+            match series.check_type {
+                CheckType::DWI => summary.dwi_fixes += moves,
+                CheckType::ADC => summary.adc_duplicates_removed += moves + deletes,
+                CheckType::Rule => summary.rule_fixes += moves + deletes,
+                _ => {}
+            }
+            study.total_moves += moves;
+            study.total_deletes += deletes;
+            study.series_results.push(series);
+        }
+        summary.total_moves += study.total_moves;
+        summary.total_deletes += study.total_deletes;
+        studies.push(study);
     }
 
     Ok(CheckReport {
-        input_path: base_dir.to_path_buf(),
+        input_path: base_dir,
         timestamp: Utc::now(),
         dry_run,
         studies,
@@ -711,7 +2168,13 @@ async fn run_check_on_dir(base_dir: &Path, dry_run: bool) -> Result<CheckReport>
 
 /// Write check report to CSV file.
 pub fn write_csv_report(report: &CheckReport, path: &Path) -> Result<()> {
-    let mut wtr = csv::Writer::from_path(path)?;
+    write_atomic(path, |w| write_csv_rows(report, w))?;
+    info!("CSV report written to: {}", path.display());
+    Ok(())
+}
+
+fn write_csv_rows(report: &CheckReport, w: &mut impl std::io::Write) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer(w);
 
     // Write header
     wtr.write_record([
@@ -730,12 +2193,18 @@ pub fn write_csv_report(report: &CheckReport, path: &Path) -> Result<()> {
             let check_type = match series.check_type {
                 CheckType::DWI => "DWI",
                 CheckType::ADC => "ADC",
+                CheckType::MixedSeries => "MixedSeries",
+                CheckType::SliceGaps => "SliceGaps",
+                CheckType::Rule => "Rule",
+                CheckType::Names => "Names",
             };
 
             for action in &series.actions {
                 let action_type = match action.action_type {
                     ActionType::Move => "Move",
                     ActionType::Delete => "Delete",
+                    ActionType::Rename => "Rename",
+                    ActionType::Flag => "Flag",
                 };
 
                 wtr.write_record([
@@ -756,15 +2225,14 @@ pub fn write_csv_report(report: &CheckReport, path: &Path) -> Result<()> {
     }
 
     wtr.flush()?;
-    println!("CSV report written to: {}", path.display());
     Ok(())
 }
 
 /// Write check report to JSON file.
 pub fn write_json_report(report: &CheckReport, path: &Path) -> Result<()> {
     let json = serde_json::to_string_pretty(report)?;
-    std::fs::write(path, json)?;
-    println!("JSON report written to: {}", path.display());
+    write_bytes_atomic(path, json.as_bytes())?;
+    info!("JSON report written to: {}", path.display());
     Ok(())
 }
 
@@ -783,4 +2251,377 @@ mod tests {
             "\"Delete\""
         );
     }
+
+    #[test]
+    fn test_changed_series_dirs_cover_applied_fixes() {
+        let base = PathBuf::from("/data/dicom");
+        let study = base.join("P1_20240601_MR_A1");
+        let action = |action_type, source: PathBuf, target: Option<PathBuf>| FileAction {
+            source_path: source,
+            action_type,
+            target_path: target,
+            reason: String::new(),
+        };
+        let series = |folder: &str, actions| SeriesCheckResult {
+            series_folder: folder.into(),
+            check_type: CheckType::DWI,
+            files_checked: 0,
+            actions,
+        };
+        let mut report = CheckReport {
+            input_path: base.clone(),
+            timestamp: Utc::now(),
+            dry_run: false,
+            studies: vec![StudyCheckResult {
+                study_folder: "P1_20240601_MR_A1".into(),
+                series_results: vec![
+                    series(
+                        "DWI0",
+                        vec![action(
+                            ActionType::Move,
+                            study.join("DWI0/a.dcm"),
+                            Some(study.join("DWI1000/a.dcm")),
+                        )],
+                    ),
+                    series(
+                        "ADC",
+                        vec![
+                            action(
+                                ActionType::Move,
+                                study.join("ADC/b.dcm"),
+                                Some(PathBuf::from("/quarantine/run/ADC/b.dcm")),
+                            ),
+                            action(ActionType::Flag, study.join("T2/c.dcm"), None),
+                        ],
+                    ),
+                    series(
+                        "SWAN",
+                        vec![action(
+                            ActionType::Rename,
+                            study.join("SWAN"),
+                            Some(study.join("SWI")),
+                        )],
+                    ),
+                    series(
+                        "",
+                        vec![action(
+                            ActionType::Rename,
+                            study.clone(),
+                            Some(base.join("P1_20240601_MR_A2")),
+                        )],
+                    ),
+                ],
+                total_moves: 2,
+                total_deletes: 0,
+            }],
+            summary: CheckSummary::default(),
+        };
+        let expected: BTreeSet<PathBuf> = ["ADC", "DWI0", "DWI1000", "SWAN", "SWI"]
+            .iter()
+            .map(|folder| study.join(folder))
+            .collect();
+        assert_eq!(report.changed_series_dirs(), expected);
+
+        assert_eq!(
+            report.renamed_series_dirs(),
+            BTreeMap::from([(study.join("SWI"), study.join("SWAN"))])
+        );
+
+        report.dry_run = true;
+        assert!(report.changed_series_dirs().is_empty());
+        assert!(report.renamed_series_dirs().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_series_names_follow_sidecar_type() {
+        let dir = std::env::temp_dir().join(format!("checker_names_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let sidecar = |folder: &str, series_type: &str, number: &str| {
+            std::fs::create_dir_all(dir.join(folder)).unwrap();
+            let json = serde_json::to_string(&SeriesSidecar {
+                series_type: series_type.into(),
+                series_number: Some(number.into()),
+                ..Default::default()
+            })
+            .unwrap();
+            std::fs::write(dir.join(folder).join(SERIES_SIDECAR), json).unwrap();
+        };
+        sidecar("T1", "T1", "3");
+        sidecar("FLAIR_005", "FLAIR", "5");
+        sidecar("DWI0__77", "DWI0", "7");
+        sidecar("T1_old", "T1", "9");
+        sidecar("SWAN", "SWI", "11");
+
+        let mut claimed = HashSet::new();
+        let mut results = check_series_names(&dir, false, &mut claimed).await.unwrap();
+        results.sort_by(|a, b| a.series_folder.cmp(&b.series_folder));
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].series_folder, "SWAN");
+        assert_eq!(results[0].actions[0].target_path, Some(dir.join("SWI")));
+        assert_eq!(results[0].actions[0].action_type, ActionType::Flag);
+        // T1 已存在，改用 SeriesNumber 編號
+        assert_eq!(results[1].actions[0].target_path, Some(dir.join("T1_009")));
+
+        claimed.insert(dir.join("SWI_011"));
+        std::fs::create_dir_all(dir.join("SWI")).unwrap();
+        assert_eq!(
+            free_folder(&dir, &["SWI".into(), "SWI_011".into()], &claimed),
+            dir.join("SWI_011_2")
+        );
+        assert!(is_name_or_numbered(
+            "P1_20240601_MR_A1_2",
+            "P1_20240601_MR_A1"
+        ));
+        assert!(!is_name_or_numbered(
+            "P1_20240601_MR_A12",
+            "P1_20240601_MR_A1"
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_studies_keep_folder_order() {
+        let dir = std::env::temp_dir().join(format!("checker_concurrent_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for study in ["S3", "S1", "S2"] {
+            let series = dir.join(study).join("SWAN");
+            std::fs::create_dir_all(&series).unwrap();
+            let json = serde_json::to_string(&SeriesSidecar {
+                series_type: "SWI".into(),
+                ..Default::default()
+            })
+            .unwrap();
+            std::fs::write(series.join(SERIES_SIDECAR), json).unwrap();
+        }
+
+        let config = CheckConfig {
+            concurrency: Some(2),
+            ..Default::default()
+        };
+        let report = run_check(
+            &dir,
+            true,
+            None,
+            OutputLayout::Flat,
+            &config,
+            &StudyFilter::default(),
+        )
+        .await
+        .unwrap();
+        let folders: Vec<_> = report
+            .studies
+            .iter()
+            .map(|s| s.study_folder.as_str())
+            .collect();
+        assert_eq!(folders, ["S1", "S2", "S3"]);
+        assert_eq!(report.summary.total_studies, 3);
+        assert_eq!(report.summary.name_mismatches, 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_study_filter_matches_accessions_and_globs() {
+        let filter = StudyFilter {
+            accessions: vec!["A1".into(), "B/2".into()],
+            globs: vec!["P9/*".into(), "X?_*".into()],
+        };
+        assert!(filter.matches("P1_20240601_MR_A1"));
+        assert!(filter.matches("P1/20240601/P1_20240601_MR_A1_2"));
+        assert!(filter.matches("A1_unknown"));
+        assert!(filter.matches("P1_20240601_MR_B_2"));
+        assert!(!filter.matches("P1_20240601_MR_A12"));
+        assert!(filter.matches("P9/P9_20240601_CT_C3"));
+        assert!(filter.matches("X1_20240601_CT_C3"));
+        assert!(!filter.matches("XY1_20240601_CT_C3"));
+        assert_eq!(filter.accession_of("P1_20240601_MR_A1_3"), Some("A1"));
+        assert!(StudyFilter::default().matches("anything"));
+        assert!(glob_match("*_MR_*", "P1_20240601_MR_A1"));
+        assert!(!glob_match("*_CT_*", "P1_20240601_MR_A1"));
+    }
+
+    #[tokio::test]
+    async fn test_plan_applies_elsewhere_and_skips_stale_actions() {
+        let root = std::env::temp_dir().join(format!("checker_plan_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let (made, copy) = (root.join("made/dicom"), root.join("copy"));
+        let action = |file: &str, action_type: ActionType| FileAction {
+            source_path: made.join("S1/ADC").join(file),
+            action_type,
+            target_path: None,
+            reason: "duplicate".into(),
+        };
+        let report = CheckReport {
+            input_path: made.clone(),
+            timestamp: Utc::now(),
+            dry_run: true,
+            studies: vec![StudyCheckResult {
+                study_folder: "S1".into(),
+                series_results: vec![SeriesCheckResult {
+                    series_folder: "ADC".into(),
+                    check_type: CheckType::ADC,
+                    files_checked: 2,
+                    actions: vec![
+                        action("a.dcm", ActionType::Delete),
+                        action("b.dcm", ActionType::Delete),
+                        action("c.dcm", ActionType::Delete),
+                    ],
+                }],
+                total_moves: 0,
+                total_deletes: 3,
+            }],
+            summary: CheckSummary::default(),
+        };
+        std::fs::create_dir_all(made.join("S1/ADC")).unwrap();
+        for (file, content) in [("a.dcm", "x"), ("b.dcm", "y"), ("c.dcm", "z")] {
+            std::fs::write(made.join("S1/ADC").join(file), content).unwrap();
+        }
+        let plan = CheckPlan::from_report(report).unwrap();
+        let planned = &plan.studies[0].series_results[0].actions[0];
+        assert_eq!(planned.source_path, PathBuf::from("S1/ADC/a.dcm"));
+        assert_eq!(plan.sources[Path::new("S1/ADC/a.dcm")].size, 1);
+        std::fs::create_dir_all(copy.join("dicom/S1/ADC")).unwrap();
+        let path = root.join("plan.json");
+        plan.write(&path).unwrap();
+        let plan = CheckPlan::read(&path).unwrap();
+
+        // 套用到另一台機器上的副本；b.dcm 在產生計畫後已被移除，c.dcm 被換成別的檔案
+        std::fs::write(copy.join("dicom/S1/ADC/a.dcm"), "x").unwrap();
+        std::fs::write(copy.join("dicom/S1/ADC/c.dcm"), "other").unwrap();
+        let report = apply_plan(&plan, Some(&copy), false, None, &CheckConfig::default())
+            .await
+            .unwrap();
+        assert!(!copy.join("dicom/S1/ADC/a.dcm").exists());
+        assert!(copy.join("dicom/S1/ADC/c.dcm").exists());
+        assert_eq!(report.summary.total_deletes, 1);
+        assert_eq!(report.summary.stale_actions, 2);
+        let actions = &report.studies[0].series_results[0].actions;
+        assert_eq!(actions[1].action_type, ActionType::Flag);
+        assert_eq!(actions[1].reason, "duplicate (stale: source missing)");
+        assert_eq!(actions[2].reason, "duplicate (stale: source changed)");
+
+        // 手動改過、指向輸入資料夾之外的路徑一律拒絕
+        let mut escaping = plan.clone();
+        escaping.studies[0].series_results[0].actions[0].source_path =
+            PathBuf::from("../../outside.dcm");
+        let config = CheckConfig::default();
+        let applied = apply_plan(&escaping, Some(&copy), true, None, &config).await;
+        assert!(applied.is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_quarantine_replaces_deletion() {
+        let dir = std::env::temp_dir().join(format!("checker_quarantine_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let adc = dir.join("dicom/S1/ADC");
+        std::fs::create_dir_all(&adc).unwrap();
+        std::fs::write(adc.join("a.dcm"), b"a").unwrap();
+
+        let quarantine = DicomTrash::new(&dir.join("quarantine"), "run1", 30);
+        let mut actions = vec![FileAction {
+            source_path: adc.join("a.dcm"),
+            action_type: ActionType::Delete,
+            target_path: None,
+            reason: "Duplicate".into(),
+        }];
+        quarantine_actions(&mut actions, &dir.join("dicom"), &quarantine);
+        assert_eq!(actions[0].action_type, ActionType::Move);
+        assert_eq!(actions[0].reason, "Duplicate (quarantined)");
+
+        let (moves, deletes) = execute_actions(&actions, false, None).await.unwrap();
+        assert_eq!((moves, deletes), (1, 0));
+        assert!(dir.join("quarantine/run1/S1/ADC/a.dcm").exists());
+        assert!(!adc.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_undo_journal_restores_moves_and_quarantine() {
+        let dir = std::env::temp_dir().join(format!("checker_undo_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let dicom = dir.join("dicom");
+        std::fs::create_dir_all(dicom.join("S1/DWI0")).unwrap();
+        std::fs::create_dir_all(dicom.join("S1/ADC")).unwrap();
+        std::fs::write(dicom.join("S1/DWI0/a.dcm"), b"a").unwrap();
+        std::fs::write(dicom.join("S1/ADC/b.dcm"), b"b").unwrap();
+
+        let journal = dir.join("journal.jsonl");
+        let audit = AuditLog::new(&dir.join("audit.jsonl"), "check").with_journal(&journal);
+        let quarantine = DicomTrash::new(&dir.join("quarantine"), audit.run_id(), 30);
+        let mut actions = vec![
+            FileAction {
+                source_path: dicom.join("S1/DWI0/a.dcm"),
+                action_type: ActionType::Move,
+                target_path: Some(dicom.join("S1/DWI1000/a.dcm")),
+                reason: "b-value=1000 should be in DWI1000".into(),
+            },
+            FileAction {
+                source_path: dicom.join("S1/ADC/b.dcm"),
+                action_type: ActionType::Delete,
+                target_path: None,
+                reason: "Duplicate".into(),
+            },
+        ];
+        quarantine_actions(&mut actions, &dicom, &quarantine);
+        execute_actions(&actions, false, Some(&audit))
+            .await
+            .unwrap();
+        assert!(!dicom.join("S1/ADC").exists());
+
+        let summary = undo_journal(&journal, false, None).await.unwrap();
+        assert_eq!(summary.restored, 4);
+        assert_eq!(summary.failed, 0);
+        assert!(dicom.join("S1/DWI0/a.dcm").exists());
+        assert!(dicom.join("S1/ADC/b.dcm").exists());
+        assert!(!dicom.join("S1/DWI1000/a.dcm").exists());
+        // 再次 undo 時來源已存在，不會覆蓋
+        assert_eq!(undo_journal(&journal, false, None).await.unwrap().failed, 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_slice_issues_gaps_and_volumes() {
+        let slice = |n: i64, z: f64| SliceInfo {
+            instance_number: Some(n),
+            position: Some(z),
+        };
+        let complete: Vec<SliceInfo> = (1..=5).map(|n| slice(n, n as f64 * 5.0)).collect();
+        assert!(slice_issues(&complete, true).is_empty());
+
+        let truncated = [
+            slice(1, 5.0),
+            slice(2, 10.0),
+            slice(4, 20.0),
+            slice(5, 25.0),
+        ];
+        let issues = slice_issues(&truncated, true);
+        assert_eq!(issues.len(), 2);
+        assert!(issues[0].contains("missing: 3"));
+        assert!(issues[1].contains("5.00 mm") && issues[1].contains("10.00 mm between 10.00"));
+        // 逐 instance 分組的資料夾不檢查 InstanceNumber 連續性
+        assert_eq!(slice_issues(&truncated, false).len(), 1);
+
+        // 兩個 volume，其中一個少了 z=15
+        let mut volumes: Vec<SliceInfo> = (1..=4).map(|n| slice(n, n as f64 * 5.0)).collect();
+        volumes.extend([slice(5, 5.0), slice(6, 10.0), slice(7, 20.0)]);
+        let issues = slice_issues(&volumes, true);
+        assert_eq!(issues, ["slice positions not repeated 2 times: 15.00 (1x)"]);
+        assert_eq!(
+            format_ranges(&[1, 2, 5, 6, 7, 9], 2),
+            "1-2, 5-7, ... (3 ranges)"
+        );
+    }
+
+    #[test]
+    fn test_uid_suffixes_fall_back_to_full_uid() {
+        let uids = [
+            "1.2.840.1.5".to_string(),
+            "1.2.840.2.5".to_string(),
+            "1.2.840.3.77".to_string(),
+        ];
+        let suffixes = uid_suffixes(uids.iter());
+        assert_eq!(suffixes["1.2.840.1.5"], "1_2_840_1_5");
+        assert_eq!(suffixes["1.2.840.2.5"], "1_2_840_2_5");
+        assert_eq!(suffixes["1.2.840.3.77"], "77");
+    }
 }
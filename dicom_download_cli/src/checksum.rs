@@ -0,0 +1,281 @@
+//! SHA-256 checksum manifests for downloaded series and the `verify` subcommand.
+//!
+//! Each series folder gets a `checksums.sha256` in `sha256sum` format (`<hex>  <file>`)
+//! covering its `.dcm` files, so `sha256sum -c` works as well. `verify` re-hashes every
+//! manifest under a tree and, optionally, compares each instance with the MD5 Orthanc
+//! stored for it (`/instances/{id}/attachments/dicom/md5`) for end-to-end integrity.
+
+use anyhow::{Context, Result};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_bytes_atomic;
+use crate::client::OrthancClient;
+
+/// Manifest file written into every series folder.
+pub const MANIFEST_FILE: &str = "checksums.sha256";
+
+/// Lower-case hex digest of a file, read in 64 KiB chunks.
+fn hash_file<D: Digest>(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = D::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    hash_file::<Sha256>(path)
+}
+
+pub fn md5_file(path: &Path) -> Result<String> {
+    hash_file::<Md5>(path)
+}
+
+/// Sorted `.dcm` files directly inside `dir`.
+fn dcm_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.is_file()
+                && p.extension()
+                    .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case("dcm"))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Hashes every `.dcm` file in `series_dir` and (re)writes its manifest.
+/// Returns the number of files listed.
+pub fn write_manifest(series_dir: &Path) -> Result<usize> {
+    let files = dcm_files(series_dir)?;
+    let mut manifest = String::new();
+    for path in &files {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        manifest.push_str(&format!("{}  {}\n", sha256_file(path)?, name));
+    }
+    write_bytes_atomic(&series_dir.join(MANIFEST_FILE), manifest.as_bytes())?;
+    Ok(files.len())
+}
+
+/// Parses `sha256sum` lines into `(file name, hex digest)`; blank and malformed lines are
+/// skipped, and the binary-mode `*` prefix is accepted.
+pub fn parse_manifest(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .filter_map(|line| {
+            let (hash, name) = line.split_once(char::is_whitespace)?;
+            let name = name.trim_start().trim_start_matches('*');
+            (hash.len() == 64 && !name.is_empty())
+                .then(|| (name.to_string(), hash.to_ascii_lowercase()))
+        })
+        .collect()
+}
+
+/// What is wrong with one file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyIssueKind {
+    /// Listed in the manifest but not on disk.
+    Missing,
+    /// SHA-256 differs from the manifest.
+    Corrupted,
+    /// `.dcm` file on disk that the manifest does not list.
+    Unlisted,
+    /// Local MD5 differs from the one Orthanc stored for the instance.
+    OrthancMismatch,
+    /// File could not be read or Orthanc could not be queried.
+    Error(String),
+}
+
+impl std::fmt::Display for VerifyIssueKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing => write!(f, "missing"),
+            Self::Corrupted => write!(f, "checksum mismatch"),
+            Self::Unlisted => write!(f, "not in manifest"),
+            Self::OrthancMismatch => write!(f, "differs from Orthanc MD5"),
+            Self::Error(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyIssue {
+    pub path: PathBuf,
+    pub kind: VerifyIssueKind,
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Series folders with a manifest.
+    pub series: usize,
+    /// Files whose SHA-256 matched.
+    pub verified: usize,
+    /// Files compared against Orthanc's MD5.
+    pub orthanc_checked: usize,
+    pub issues: Vec<VerifyIssue>,
+    /// Folders holding `.dcm` files but no manifest.
+    pub unverified_dirs: Vec<PathBuf>,
+}
+
+/// Checks one series folder against its manifest.
+pub fn verify_series(series_dir: &Path, report: &mut VerifyReport) -> Result<()> {
+    let text = std::fs::read_to_string(series_dir.join(MANIFEST_FILE))?;
+    let entries = parse_manifest(&text);
+    report.series += 1;
+    for (name, expected) in &entries {
+        let path = series_dir.join(name);
+        let kind = if !path.exists() {
+            Some(VerifyIssueKind::Missing)
+        } else {
+            match sha256_file(&path) {
+                Ok(actual) if actual == *expected => None,
+                Ok(_) => Some(VerifyIssueKind::Corrupted),
+                Err(e) => Some(VerifyIssueKind::Error(e.to_string())),
+            }
+        };
+        match kind {
+            None => report.verified += 1,
+            Some(kind) => report.issues.push(VerifyIssue { path, kind }),
+        }
+    }
+    for path in dcm_files(series_dir)? {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if !entries.iter().any(|(n, _)| *n == name) {
+            report.issues.push(VerifyIssue {
+                path,
+                kind: VerifyIssueKind::Unlisted,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Recursively verifies every series folder under `root`.
+pub fn verify_tree(root: &Path) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        if dir.join(MANIFEST_FILE).is_file() {
+            verify_series(&dir, &mut report)?;
+        } else if !dcm_files(&dir)?.is_empty() {
+            report.unverified_dirs.push(dir.clone());
+        }
+        let mut subdirs: Vec<PathBuf> = std::fs::read_dir(&dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_dir())
+            .collect();
+        subdirs.sort_by(|a, b| b.cmp(a));
+        stack.extend(subdirs);
+    }
+    report.unverified_dirs.sort();
+    Ok(report)
+}
+
+/// Compares every `<instance id>.dcm` under `root` with the MD5 Orthanc stored for it.
+///
+/// Instances Orthanc no longer has, or stored without an MD5, are skipped.
+pub async fn verify_against_orthanc(
+    client: &OrthancClient,
+    root: &Path,
+    report: &mut VerifyReport,
+) -> Result<()> {
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            let is_dcm = path
+                .extension()
+                .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case("dcm"));
+            if !is_dcm {
+                continue;
+            }
+            let id = path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            let expected = match client.get_instance_md5(&id).await {
+                Ok(Some(md5)) => md5,
+                Ok(None) => continue,
+                Err(e) => {
+                    report.issues.push(VerifyIssue {
+                        path,
+                        kind: VerifyIssueKind::Error(format!("{:#}", e)),
+                    });
+                    continue;
+                }
+            };
+            let local = tokio::task::spawn_blocking({
+                let path = path.clone();
+                move || md5_file(&path)
+            })
+            .await??;
+            report.orthanc_checked += 1;
+            if !local.eq_ignore_ascii_case(expected.trim()) {
+                report.issues.push(VerifyIssue {
+                    path,
+                    kind: VerifyIssueKind::OrthancMismatch,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let hash = "a".repeat(64);
+        let text = format!("{h}  1.dcm\n\n{h} *2.dcm\nshort  3.dcm\n", h = hash);
+        let entries = parse_manifest(&text);
+        assert_eq!(
+            entries,
+            [
+                ("1.dcm".to_string(), hash.clone()),
+                ("2.dcm".to_string(), hash)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_flags_corrupted_and_missing() {
+        let dir = std::env::temp_dir().join(format!("checksum_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.dcm"), b"one").unwrap();
+        std::fs::write(dir.join("b.dcm"), b"two").unwrap();
+        assert_eq!(write_manifest(&dir).unwrap(), 2);
+
+        std::fs::write(dir.join("a.dcm"), b"0ne").unwrap();
+        std::fs::remove_file(dir.join("b.dcm")).unwrap();
+        let report = verify_tree(&dir).unwrap();
+        let kinds: Vec<_> = report.issues.iter().map(|i| i.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            [VerifyIssueKind::Corrupted, VerifyIssueKind::Missing]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+//! Tag-based series classification used when the Analyze API has no answer.
+//!
+//! The Analyze service is trained on MR protocols; CT series usually come back as
+//! `Unknown`. For those, a series type is derived from the reconstruction kernel and the
+//! contrast phase (contrast agent tag plus description keywords), e.g. `CT_ARTERIAL_FC43`.
+//!
+//! A [`LocalClassifier`] built from the `[classifier]` rules is tried before that, so sites
+//! without (or temporarily without) the Analyze service still get consistent folder names.
+
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+/// Series-level tags read from a sample instance.
+#[derive(Clone, Debug, Default)]
+pub struct SeriesTags {
+    pub modality: String,
+    pub series_description: String,
+    /// ConvolutionKernel (0018,1210), first value only.
+    pub convolution_kernel: Option<String>,
+    /// ContrastBolusAgent (0018,0010).
+    pub contrast_agent: Option<String>,
+    /// ProtocolName (0018,1030).
+    pub protocol_name: Option<String>,
+    /// DiffusionBValue (0018,9087), or the Siemens private b-value (0019,100C).
+    pub b_value: Option<f64>,
+    /// EchoTime (0018,0081) in ms.
+    pub echo_time: Option<f64>,
+}
+
+/// Description keywords mapped to contrast phases, checked in order.
+/// Keywords of up to three letters must match a whole word; longer ones match a word prefix.
+const PHASE_KEYWORDS: &[(&str, &str)] = &[
+    ("PERF", "PERFUSION"),
+    ("CTP", "PERFUSION"),
+    ("ARTER", "ARTERIAL"),
+    ("ART", "ARTERIAL"),
+    ("CTA", "ARTERIAL"),
+    ("PORTAL", "PORTAL"),
+    ("PV", "PORTAL"),
+    ("VENO", "VENOUS"),
+    ("VEN", "VENOUS"),
+    ("DELAY", "DELAYED"),
+    ("PLAIN", "NONCONTRAST"),
+    ("NC", "NONCONTRAST"),
+];
+
+/// Contrast phase of a CT series: description keywords first, then the contrast agent tag.
+pub fn contrast_phase(tags: &SeriesTags) -> &'static str {
+    let desc = tags.series_description.to_uppercase();
+    let tokens: Vec<&str> = desc
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .collect();
+    for (keyword, phase) in PHASE_KEYWORDS {
+        let hit = if keyword.len() <= 3 {
+            tokens.iter().any(|t| t == keyword)
+        } else {
+            tokens.iter().any(|t| t.starts_with(keyword))
+        };
+        if hit {
+            return phase;
+        }
+    }
+
+    match tags.contrast_agent.as_deref().map(str::trim) {
+        Some(agent) if !agent.is_empty() && !agent.eq_ignore_ascii_case("NONE") => "CONTRAST",
+        _ => "NONCONTRAST",
+    }
+}
+
+/// Builds a CT series type such as `CT_ARTERIAL_FC43` (kernel omitted when missing).
+pub fn classify_ct_series(tags: &SeriesTags) -> String {
+    let phase = contrast_phase(tags);
+    let kernel: String = tags
+        .convolution_kernel
+        .as_deref()
+        .and_then(|k| k.split('\\').next())
+        .unwrap_or("")
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_uppercase();
+    if kernel.is_empty() {
+        format!("CT_{}", phase)
+    } else {
+        format!("CT_{}_{}", phase, kernel)
+    }
+}
+
+/// Modality-specific fallback type; `None` for modalities without a tag-based rule.
+pub fn fallback_series_type(tags: &SeriesTags) -> Option<String> {
+    match tags.modality.trim().to_uppercase().as_str() {
+        "CT" => Some(classify_ct_series(tags)),
+        _ => None,
+    }
+}
+
+/// TOML schema of one `[[classifier.rules]]` entry. Every condition that is set must hold;
+/// regexes are case-insensitive and b-value / echo time bounds are inclusive.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ClassifierRuleFile {
+    pub series_type: String,
+    pub modality: Option<String>,
+    pub series_description: Option<String>,
+    pub protocol_name: Option<String>,
+    pub b_value_min: Option<f64>,
+    pub b_value_max: Option<f64>,
+    pub echo_time_min: Option<f64>,
+    pub echo_time_max: Option<f64>,
+}
+
+/// Compiled `[[classifier.rules]]` entry.
+#[derive(Clone, Debug)]
+struct ClassifierRule {
+    series_type: String,
+    modality: Option<String>,
+    series_description: Option<Regex>,
+    protocol_name: Option<Regex>,
+    b_value: (Option<f64>, Option<f64>),
+    echo_time: (Option<f64>, Option<f64>),
+}
+
+/// Whether `value` lies within the optional inclusive bounds; a missing value fails any bound.
+fn within(value: Option<f64>, (min, max): (Option<f64>, Option<f64>)) -> bool {
+    match value {
+        Some(v) => min.is_none_or(|m| v >= m) && max.is_none_or(|m| v <= m),
+        None => min.is_none() && max.is_none(),
+    }
+}
+
+impl ClassifierRule {
+    fn matches(&self, tags: &SeriesTags) -> bool {
+        let text =
+            |re: &Option<Regex>, value: &str| re.as_ref().is_none_or(|re| re.is_match(value));
+        self.modality
+            .as_deref()
+            .is_none_or(|m| m.eq_ignore_ascii_case(tags.modality.trim()))
+            && text(&self.series_description, &tags.series_description)
+            && text(
+                &self.protocol_name,
+                tags.protocol_name.as_deref().unwrap_or_default(),
+            )
+            && within(tags.b_value, self.b_value)
+            && within(tags.echo_time, self.echo_time)
+    }
+}
+
+/// Local rules-based classifier (`[classifier]`); the first matching rule names the series.
+#[derive(Clone, Debug, Default)]
+pub struct LocalClassifier {
+    rules: Vec<ClassifierRule>,
+}
+
+impl LocalClassifier {
+    pub fn new(rules: &[ClassifierRuleFile]) -> Result<Self> {
+        let compile = |key: &str, pattern: &Option<String>| -> Result<Option<Regex>> {
+            pattern
+                .as_deref()
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(|p| {
+                    Regex::new(&format!("(?i){}", p))
+                        .with_context(|| format!("Invalid classifier {} '{}'", key, p))
+                })
+                .transpose()
+        };
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let series_type = rule.series_type.trim().to_string();
+                if series_type.is_empty() {
+                    return Err(anyhow!("Classifier rule without series_type"));
+                }
+                Ok(ClassifierRule {
+                    series_description: compile("series_description", &rule.series_description)?,
+                    protocol_name: compile("protocol_name", &rule.protocol_name)?,
+                    modality: rule.modality.as_deref().map(|m| m.trim().to_string()),
+                    b_value: (rule.b_value_min, rule.b_value_max),
+                    echo_time: (rule.echo_time_min, rule.echo_time_max),
+                    series_type,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Series type of the first rule that matches `tags`.
+    pub fn classify(&self, tags: &SeriesTags) -> Option<String> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(tags))
+            .map(|rule| rule.series_type.clone())
+    }
+
+    /// Local rules first, then the built-in modality fallback ([`fallback_series_type`]).
+    pub fn fallback(&self, tags: &SeriesTags) -> Option<String> {
+        self.classify(tags).or_else(|| fallback_series_type(tags))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ct(desc: &str, kernel: Option<&str>, agent: Option<&str>) -> SeriesTags {
+        SeriesTags {
+            modality: "CT".into(),
+            series_description: desc.into(),
+            convolution_kernel: kernel.map(String::from),
+            contrast_agent: agent.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_classify_ct_series() {
+        assert_eq!(
+            classify_ct_series(&ct("Arterial 1.0", Some("FC43"), None)),
+            "CT_ARTERIAL_FC43"
+        );
+        assert_eq!(
+            classify_ct_series(&ct("Head 5mm", Some("Br40\\3"), None)),
+            "CT_NONCONTRAST_BR40"
+        );
+        assert_eq!(
+            classify_ct_series(&ct("Abdomen", None, Some("Omnipaque"))),
+            "CT_CONTRAST"
+        );
+        assert_eq!(
+            classify_ct_series(&ct("CTP 4D", None, None)),
+            "CT_PERFUSION"
+        );
+        assert_eq!(
+            classify_ct_series(&ct("PVC check", None, None)),
+            "CT_NONCONTRAST"
+        );
+    }
+
+    #[test]
+    fn test_fallback_only_for_ct() {
+        assert!(fallback_series_type(&ct("x", None, None)).is_some());
+        let mr = SeriesTags {
+            modality: "MR".into(),
+            ..Default::default()
+        };
+        assert!(fallback_series_type(&mr).is_none());
+    }
+
+    #[test]
+    fn test_local_classifier_first_matching_rule() {
+        let rule = |series_type: &str| ClassifierRuleFile {
+            series_type: series_type.into(),
+            modality: Some("MR".into()),
+            ..Default::default()
+        };
+        let classifier = LocalClassifier::new(&[
+            ClassifierRuleFile {
+                b_value_min: Some(900.0),
+                b_value_max: Some(1100.0),
+                ..rule("DWI1000")
+            },
+            ClassifierRuleFile {
+                series_description: Some("flair".into()),
+                echo_time_min: Some(80.0),
+                ..rule("T2FLAIR_AXI")
+            },
+            ClassifierRuleFile {
+                protocol_name: Some("^t1.*mprage".into()),
+                ..rule("T1BRAVO_AXI")
+            },
+        ])
+        .unwrap();
+        let mr = |desc: &str| SeriesTags {
+            modality: "mr".into(),
+            series_description: desc.into(),
+            ..Default::default()
+        };
+
+        let dwi = SeriesTags {
+            b_value: Some(1000.0),
+            ..mr("ep2d_diff")
+        };
+        assert_eq!(classifier.classify(&dwi).as_deref(), Some("DWI1000"));
+        let flair = SeriesTags {
+            echo_time: Some(120.0),
+            ..mr("Ax FLAIR")
+        };
+        assert_eq!(classifier.classify(&flair).as_deref(), Some("T2FLAIR_AXI"));
+        // A bound on a tag the instance lacks does not match.
+        assert_eq!(classifier.classify(&mr("Ax FLAIR")), None);
+        let t1 = SeriesTags {
+            protocol_name: Some("T1_MPRAGE_sag".into()),
+            ..mr("")
+        };
+        assert_eq!(classifier.classify(&t1).as_deref(), Some("T1BRAVO_AXI"));
+
+        let ct = ct("Arterial", None, None);
+        assert_eq!(classifier.fallback(&ct).as_deref(), Some("CT_ARTERIAL"));
+        assert!(LocalClassifier::new(&[rule(" ")]).is_err());
+    }
+}
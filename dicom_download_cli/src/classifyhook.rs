@@ -0,0 +1,132 @@
+//! External series classifier (`classifier_command`).
+//!
+//! Research groups can plug in their own model without changing the CLI: the command gets
+//! one DICOM instance on stdin and prints its series type as the first non-empty line of
+//! stdout. It replaces the analysis service wherever the CLI would upload a sample, so
+//! `analyze_timeout`, the circuit breaker, the header fallback, and the report notes apply
+//! unchanged. Printing nothing (or `Unknown`) means "no answer"; a non-zero exit is a
+//! failure whose stderr ends up in the report.
+
+use anyhow::{bail, Context, Result};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::client::AnalyzerBypassed;
+
+/// Program and arguments of `classifier_command`.
+#[derive(Debug, Clone)]
+pub struct ClassifierCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+impl ClassifierCommand {
+    /// Splits the configured command on whitespace (no shell quoting), e.g.
+    /// `"python3 /opt/models/classify.py --gpu"`.
+    pub fn parse(command: &str) -> Result<Self> {
+        let mut words = command.split_whitespace().map(str::to_string);
+        let Some(program) = words.next() else {
+            bail!("classifier_command is empty");
+        };
+        Ok(Self {
+            program,
+            args: words.collect(),
+        })
+    }
+
+    /// Runs the command on one instance; killed with [`AnalyzerBypassed`] after `timeout`.
+    pub async fn classify(&self, dicom: &[u8], timeout: Duration) -> Result<Option<String>> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to run classifier_command {}", self.program))?;
+
+        let mut stdin = child.stdin.take().context("classifier_command stdin")?;
+        let data = dicom.to_vec();
+        // 另開 task 寫入 stdin，避免 stdout 緩衝區滿時互相等待
+        let writer = tokio::spawn(async move {
+            // 指令可能不讀完 stdin 就結束，broken pipe 不視為錯誤
+            let _ = stdin.write_all(&data).await;
+        });
+
+        let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+            Ok(output) => output.context("classifier_command failed")?,
+            Err(_) => {
+                writer.abort();
+                return Err(AnalyzerBypassed {
+                    reason: format!("classifier command timed out after {}s", timeout.as_secs()),
+                }
+                .into());
+            }
+        };
+        let _ = writer.await;
+        if !output.status.success() {
+            bail!(
+                "classifier command exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(parse_output(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+/// First non-empty stdout line; empty output or `Unknown` is no answer.
+fn parse_output(stdout: &str) -> Option<String> {
+    stdout
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .filter(|line| !line.eq_ignore_ascii_case("unknown"))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command_and_output() {
+        let cmd = ClassifierCommand::parse("  python3 classify.py --gpu ").unwrap();
+        assert_eq!(cmd.program, "python3");
+        assert_eq!(cmd.args, ["classify.py", "--gpu"]);
+        assert!(ClassifierCommand::parse("   ").is_err());
+
+        assert_eq!(
+            parse_output("\n DWI1000 \nscore 0.9\n").as_deref(),
+            Some("DWI1000")
+        );
+        assert_eq!(parse_output("unknown\n"), None);
+        assert_eq!(parse_output(""), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_classify_reads_stdin_and_fails_on_exit_status() {
+        let timeout = Duration::from_secs(10);
+        let echo = ClassifierCommand {
+            program: "sh".into(),
+            args: vec!["-c".into(), "head -c 4; echo".into()],
+        };
+        assert_eq!(
+            echo.classify(b"T1W_extra", timeout)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("T1W_")
+        );
+
+        let failing = ClassifierCommand {
+            program: "sh".into(),
+            args: vec!["-c".into(), "echo model missing >&2; exit 3".into()],
+        };
+        let err = failing.classify(b"", timeout).await.unwrap_err();
+        assert!(err.to_string().contains("model missing"));
+    }
+}
@@ -1,24 +1,415 @@
 use anyhow::{anyhow, Context, Result};
 use base64::{engine::general_purpose, Engine as _};
 use indicatif::ProgressBar;
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
-use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, AUTHORIZATION, RETRY_AFTER};
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::collections::HashSet;
 use std::io::Cursor;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, warn};
+
+use crate::classify::{LocalClassifier, SeriesTags};
+use crate::classifyhook::ClassifierCommand;
+use crate::config::{
+    AuthConfig, HttpConfig, ProxyConfig, TimeoutConfig, TlsConfig, DEFAULT_ANALYZE_TYPE_PATH,
+};
+use crate::estimate::format_bytes;
+use crate::retry::{is_retryable_status, CircuitBreaker, Retrier, RetryPolicy, TransientStatus};
+
+/// HTTP 401/403 from Orthanc (expired or insufficient credentials). The analysis service's
+/// 401/403 becomes [`AnalyzerBypassed`] instead, so it never stops a batch.
+#[derive(Debug)]
+pub struct AuthError {
+    pub status: StatusCode,
+    pub url: String,
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Authentication failed ({}) for {}",
+            self.status, self.url
+        )
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Returns true when `err` was caused by an HTTP 401/403 response.
+pub fn is_auth_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| e.is::<AuthError>())
+}
+
+/// The analysis service was skipped (sample too large) or timed out; callers fall back to
+/// header-based classification and note it in the report.
+#[derive(Debug)]
+pub struct AnalyzerBypassed {
+    pub reason: String,
+}
+
+impl std::fmt::Display for AnalyzerBypassed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Analyzer bypassed: {}", self.reason)
+    }
+}
+
+impl std::error::Error for AnalyzerBypassed {}
+
+/// Returns the bypass reason when `err` is an [`AnalyzerBypassed`].
+pub fn analyzer_bypass_reason(err: &anyhow::Error) -> Option<String> {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<AnalyzerBypassed>())
+        .map(|b| b.reason.clone())
+}
+
+/// Series type of a sampled instance, plus why the analyzer was bypassed (if it was).
+#[derive(Clone, Debug, Default)]
+pub struct SampleResult {
+    pub series_type: Option<String>,
+    pub analyzer_bypassed: Option<String>,
+}
+
+/// One step of a [`TypeSelector`] path.
+#[derive(Clone, Debug, PartialEq)]
+enum PathStep {
+    Key(String),
+    Index(usize),
+    Each,
+}
+
+/// JSONPath-style location of the series type in an analysis response (`type_path`).
+///
+/// Supports `$`, `.key`, `['key']`, `[N]`, and `[*]`; every `[*]` fans out over an array,
+/// producing one result per file. The default `$[*].series_type` reads
+/// `[{"series_type": ...}, ...]`; e.g. `$.results[*].prediction.label` reads a nested
+/// answer. A path without `[*]` yields a single result, so it only suits single-file
+/// uploads.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeSelector {
+    steps: Vec<PathStep>,
+}
+
+impl TypeSelector {
+    pub fn parse(path: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid analyze_request.type_path {:?}", path);
+        let mut rest = path.trim();
+        rest = rest.strip_prefix('$').unwrap_or(rest);
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(inner) = rest.strip_prefix('[') {
+                let end = inner.find(']').ok_or_else(invalid)?;
+                let token = inner[..end].trim();
+                steps.push(if token == "*" {
+                    PathStep::Each
+                } else if let Ok(i) = token.parse::<usize>() {
+                    PathStep::Index(i)
+                } else {
+                    let key = token
+                        .strip_prefix('\'')
+                        .and_then(|t| t.strip_suffix('\''))
+                        .or_else(|| token.strip_prefix('"').and_then(|t| t.strip_suffix('"')))
+                        .ok_or_else(invalid)?;
+                    PathStep::Key(key.to_string())
+                });
+                rest = &inner[end + 1..];
+            } else {
+                let key = rest.strip_prefix('.').unwrap_or(rest);
+                let end = key.find(['.', '[']).unwrap_or(key.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                steps.push(PathStep::Key(key[..end].to_string()));
+                rest = &key[end..];
+            }
+        }
+        Ok(Self { steps })
+    }
+
+    /// Series types found at the path, in response order; a missing or non-string value
+    /// is `None`, and a `[*]` over a non-array yields nothing.
+    pub fn select(&self, body: &Value) -> Vec<Option<String>> {
+        let mut out = Vec::new();
+        select_steps(Some(body), &self.steps, &mut out);
+        out
+    }
+}
+
+impl Default for TypeSelector {
+    fn default() -> Self {
+        Self::parse(DEFAULT_ANALYZE_TYPE_PATH).expect("default type_path parses")
+    }
+}
+
+fn select_steps(value: Option<&Value>, steps: &[PathStep], out: &mut Vec<Option<String>>) {
+    let Some((step, rest)) = steps.split_first() else {
+        out.push(value.and_then(|v| v.as_str()).map(|s| s.to_string()));
+        return;
+    };
+    match step {
+        PathStep::Key(key) => select_steps(value.and_then(|v| v.get(key)), rest, out),
+        PathStep::Index(i) => select_steps(value.and_then(|v| v.get(i)), rest, out),
+        PathStep::Each => {
+            for item in value.and_then(|v| v.as_array()).into_iter().flatten() {
+                select_steps(Some(item), rest, out);
+            }
+        }
+    }
+}
+
+/// Longest server-requested pause honoured from a `Retry-After` header.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Parses `Retry-After` (delay in seconds or an HTTP-date), capped at [`MAX_RETRY_AFTER`].
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    let value = value.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => {
+            let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            (at.with_timezone(&chrono::Utc) - now)
+                .to_std()
+                .unwrap_or(Duration::ZERO)
+        }
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+/// Token bucket state; `paused_until` is a global pause requested via `Retry-After`.
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl Bucket {
+    /// Takes a token at `now`, or returns how long to wait before trying again.
+    fn take(&mut self, now: Instant, rate: Option<f64>) -> Option<Duration> {
+        if let Some(until) = self.paused_until {
+            if until > now {
+                return Some(until - now);
+            }
+            self.paused_until = None;
+        }
+        let rate = rate?;
+        let burst = rate.max(1.0);
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+/// Rate limiter shared by every worker using the same client.
+struct RateLimiter {
+    /// Requests per second; `None` only enforces `Retry-After` pauses.
+    rate: Option<f64>,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    fn new(rate: Option<f64>) -> Self {
+        let rate = rate.filter(|r| *r > 0.0);
+        Self {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate.unwrap_or(1.0).max(1.0),
+                last: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Waits until a request may be sent.
+    async fn acquire(&self) {
+        loop {
+            let wait = self.bucket.lock().unwrap().take(Instant::now(), self.rate);
+            match wait {
+                Some(d) => tokio::time::sleep(d).await,
+                None => return,
+            }
+        }
+    }
+
+    /// Pauses all workers for `delay` (extends, never shortens, an existing pause).
+    fn pause_for(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut bucket = self.bucket.lock().unwrap();
+        if bucket.paused_until < Some(until) {
+            bucket.paused_until = Some(until);
+        }
+    }
+}
+
+/// Aggregate download bandwidth cap shared by all concurrent instance streams.
+struct BandwidthLimiter {
+    /// Bytes per second.
+    rate: f64,
+    /// Available bytes (negative while in debt) and last refill time.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl BandwidthLimiter {
+    fn new(bytes_per_second: u64) -> Self {
+        let rate = bytes_per_second as f64;
+        Self {
+            rate,
+            state: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    /// Accounts for `bytes` received and sleeps while the shared budget is overdrawn.
+    async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let (tokens, last) = *state;
+            let refilled =
+                (tokens + now.duration_since(last).as_secs_f64() * self.rate).min(self.rate);
+            let left = refilled - bytes as f64;
+            *state = (left, now);
+            (left < 0.0).then(|| Duration::from_secs_f64(-left / self.rate))
+        };
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Per-client request state shared by all clones: auth abort flag, retry budget, rate limit.
+struct RequestGuard {
+    /// Set once an Orthanc request got 401/403 so a batch can stop early.
+    auth_failed: AtomicBool,
+    retrier: Retrier,
+    /// Analysis uploads: own retry count, and a breaker that skips the service when it is down.
+    analyze_retrier: Retrier,
+    analyze_breaker: CircuitBreaker,
+    limiter: RateLimiter,
+    /// `None` = unlimited download bandwidth.
+    bandwidth: Option<BandwidthLimiter>,
+}
+
+impl RequestGuard {
+    /// Sends an Orthanc request once (see [`Self::send_unlatched`]); a 401/403 also sets
+    /// `auth_failed`.
+    async fn send_once(&self, req: RequestBuilder) -> Result<Response> {
+        let result = self.send_unlatched(req).await;
+        if result.as_ref().is_err_and(is_auth_error) {
+            self.auth_failed.store(true, Ordering::SeqCst);
+        }
+        result
+    }
+
+    /// Sends once; 401/403 become [`AuthError`], 408/429/5xx become [`TransientStatus`].
+    ///
+    /// A 429/503 with `Retry-After` pauses every worker sharing this client, not just this one.
+    async fn send_unlatched(&self, req: RequestBuilder) -> Result<Response> {
+        self.limiter.acquire().await;
+        let resp = req.send().await?;
+        let status = resp.status();
+        debug!(%status, url = %resp.url(), "Orthanc/analysis response");
+        if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+            let delay = resp
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| parse_retry_after(v, chrono::Utc::now()));
+            if let Some(delay) = delay {
+                warn!(
+                    ?delay,
+                    "{} from {}; pausing all requests",
+                    status,
+                    resp.url()
+                );
+                self.limiter.pause_for(delay);
+            }
+        }
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(AuthError {
+                status,
+                url: resp.url().to_string(),
+            }
+            .into());
+        }
+        if is_retryable_status(status) {
+            return Err(TransientStatus {
+                status,
+                url: resp.url().to_string(),
+            }
+            .into());
+        }
+        Ok(resp)
+    }
+}
+
+/// `send()` with transient-failure retries and 401/403 detection.
+trait SendChecked {
+    async fn send_checked(self, guard: &RequestGuard) -> Result<Response>;
+
+    /// Single attempt for requests that must not run twice (C-MOVE, DELETE): a timeout or
+    /// 5xx may come after the server already acted on the first one.
+    async fn send_once_checked(self, guard: &RequestGuard) -> Result<Response>;
+}
+
+impl SendChecked for RequestBuilder {
+    async fn send_once_checked(self, guard: &RequestGuard) -> Result<Response> {
+        guard.send_once(self).await
+    }
+
+    async fn send_checked(self, guard: &RequestGuard) -> Result<Response> {
+        // Streaming bodies (multipart uploads) cannot be replayed: single attempt
+        if self.try_clone().is_none() {
+            return guard.send_once(self).await;
+        }
+        guard
+            .retrier
+            .run(|| async {
+                let req = self
+                    .try_clone()
+                    .ok_or_else(|| anyhow!("Request body cannot be retried"))?;
+                guard.send_once(req).await
+            })
+            .await
+    }
+}
 
 #[derive(Clone)]
 /// HTTP client that orchestrates Orthanc queries, moves, and analysis calls.
 pub struct OrthancClient {
     client: Client,
+    guard: Arc<RequestGuard>,
+    /// Bearer or Basic credentials, added to Orthanc requests only.
+    authorization: Option<HeaderValue>,
+    /// Extra headers sent only to the analysis service (API key, `analyze_request.headers`).
+    analyze_headers: HeaderMap,
+    /// Multipart field of the uploaded files and extra text fields (`analyze_request`).
+    analyze_file_field: String,
+    analyze_fields: Vec<(String, String)>,
+    /// Where the series type lives in an analysis response.
+    type_selector: TypeSelector,
+    /// Samples larger than this are classified from headers instead of being uploaded.
+    max_analyze_upload: Option<u64>,
+    /// `classifier_command` run instead of posting to `analyze_url`.
+    classifier_command: Option<ClassifierCommand>,
+    timeouts: TimeoutConfig,
     pub base_url: String,
     pub analyze_url: String,
     pub target_aet: String,
 }
 
 /// DICOM 標籤資訊，用於產生人類可讀目錄名稱
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct DicomStudyInfo {
     pub patient_id: String,
     pub study_date: String,
@@ -29,6 +420,8 @@ pub struct DicomStudyInfo {
 /// 下載計畫：圍繞資料設計程式碼（Linus 第二原則）
 #[derive(Clone, Debug)]
 pub struct DownloadPlan {
+    /// Orthanc study ID the plan was built from.
+    pub study_id: String,
     pub study_folder: String,
     pub series: Vec<SeriesDownloadPlan>,
 }
@@ -36,52 +429,303 @@ pub struct DownloadPlan {
 /// 單一 Series 的下載計畫
 #[derive(Clone, Debug)]
 pub struct SeriesDownloadPlan {
+    /// Orthanc series ID this plan came from (shared by per-instance groups).
+    pub source_series: String,
     pub series_folder: String,
+    /// Type from the analysis service (or the header/description fallback).
+    pub series_type: String,
+    /// SeriesDescription Orthanc reports for the source series.
+    pub description: Option<String>,
+    pub series_number: Option<String>,
     pub instances: Vec<String>,
+    /// Why the analyzer was bypassed while classifying this series (reported as a note).
+    pub analyzer_note: Option<String>,
+    /// Non-image series (SR, KO, PR, SEG) kept under `<output>/nonimage/` by
+    /// `non_image_series = "separate"`; never QC'd or converted.
+    pub non_image: bool,
+    /// Instance already fetched while planning; written from memory instead of downloaded.
+    pub prefetched: Option<PrefetchedInstance>,
+}
+
+/// 規劃階段為分析下載的第一個 instance，保留到下載階段直接寫檔，省去重複傳輸
+#[derive(Clone)]
+pub struct PrefetchedInstance {
+    pub instance_id: String,
+    pub data: Arc<Vec<u8>>,
+}
+
+impl PrefetchedInstance {
+    /// Larger samples (e.g. enhanced multi-frame) are not kept in memory for the whole
+    /// accession; they are simply downloaded again.
+    pub const MAX_BYTES: usize = 4 * 1024 * 1024;
+
+    /// Keeps `data` for `instance_id` when it is at most [`Self::MAX_BYTES`].
+    pub fn keep(instance_id: &str, data: &[u8]) -> Option<Self> {
+        (data.len() <= Self::MAX_BYTES).then(|| Self {
+            instance_id: instance_id.to_string(),
+            data: Arc::new(data.to_vec()),
+        })
+    }
+}
+
+impl std::fmt::Debug for PrefetchedInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrefetchedInstance")
+            .field("instance_id", &self.instance_id)
+            .field("bytes", &self.data.len())
+            .finish()
+    }
+}
+
+/// Size statistics Orthanc reports for a stored study.
+#[derive(Clone, Debug, Default)]
+pub struct StudyStatistics {
+    pub count_series: usize,
+    pub count_instances: usize,
+    pub disk_size: u64,
+}
+
+/// Tags of a stored study used to choose between studies sharing an accession number.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StudySummary {
+    pub id: String,
+    /// StudyDate (`YYYYMMDD`), empty when missing.
+    pub study_date: String,
+    /// StudyTime (`HHMMSS.frac`), empty when missing.
+    pub study_time: String,
+    pub description: Option<String>,
+    pub series_count: usize,
 }
 
 pub struct SeriesMeta {
     pub description: Option<String>,
     pub series_number: Option<String>,
+    pub modality: Option<String>,
     pub instances: Vec<String>,
 }
 
+/// Naming tags of one instance from `/series/{id}/instances`.
+#[derive(Debug, Clone, Default)]
+pub struct InstanceTags {
+    pub id: String,
+    pub instance_number: Option<String>,
+    pub sop_instance_uid: Option<String>,
+}
+
+/// Temporary path a streamed download is written to: `<dest>.part`.
+pub fn part_path(dest: &Path) -> PathBuf {
+    let mut name = dest.as_os_str().to_os_string();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Applies [`ProxyConfig`] to a reqwest builder; without a proxy URL the builder is unchanged.
+fn apply_proxy(
+    builder: reqwest::ClientBuilder,
+    proxy: &ProxyConfig,
+) -> Result<reqwest::ClientBuilder> {
+    let Some(url) = proxy.url.as_deref() else {
+        return Ok(builder);
+    };
+    let mut p = reqwest::Proxy::all(url).with_context(|| format!("Invalid proxy URL {}", url))?;
+    if let (Some(u), Some(pw)) = (&proxy.username, &proxy.password) {
+        p = p.basic_auth(u, pw);
+    }
+    if !proxy.no_proxy.is_empty() {
+        p = p.no_proxy(reqwest::NoProxy::from_string(&proxy.no_proxy.join(",")));
+    }
+    Ok(builder.proxy(p))
+}
+
+/// Applies [`TlsConfig`] to a reqwest builder.
+fn apply_tls(
+    mut builder: reqwest::ClientBuilder,
+    tls: &TlsConfig,
+) -> Result<reqwest::ClientBuilder> {
+    if tls.is_insecure() {
+        warn!("TLS certificate verification is disabled (--insecure)");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    if let Some(ca_path) = &tls.ca_cert {
+        let pem = std::fs::read(ca_path)
+            .with_context(|| format!("Failed to read CA bundle {}", ca_path.display()))?;
+        for cert in reqwest::Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Invalid CA bundle {}", ca_path.display()))?
+        {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    match (&tls.client_cert, &tls.client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert = std::fs::read(cert_path).with_context(|| {
+                format!("Failed to read client certificate {}", cert_path.display())
+            })?;
+            let key = std::fs::read(key_path)
+                .with_context(|| format!("Failed to read client key {}", key_path.display()))?;
+            let identity = reqwest::Identity::from_pkcs8_pem(&cert, &key)
+                .context("Invalid client certificate/key (expected PEM + PKCS#8 key)")?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(anyhow!(
+                "client_cert and client_key must be configured together"
+            ))
+        }
+    }
+
+    Ok(builder)
+}
+
+/// reqwest builder with the proxy, TLS settings, and connect timeout of `http`, shared by
+/// Orthanc/analysis requests and outbound webhooks (see [`crate::notify`]).
+pub fn http_client_builder(http: &HttpConfig) -> Result<reqwest::ClientBuilder> {
+    let builder = apply_proxy(Client::builder(), &http.proxy)?;
+    Ok(apply_tls(builder, &http.tls)?.connect_timeout(http.timeouts.connect))
+}
+
 impl OrthancClient {
     /// Builds a reqwest client configured for Orthanc + analysis endpoints and optional auth.
     ///
-    /// Accepts invalid TLS certs, sets request timeout, and applies Basic auth headers when
-    /// credentials are provided.
+    /// Verifies TLS certificates unless `tls.insecure` is set, trusts an optional extra CA
+    /// bundle, presents an optional client certificate, routes through an optional proxy,
+    /// applies the connect timeout (query and download timeouts are set per request), and
+    /// applies Bearer or Basic auth headers when configured. Both credentials go to their own
+    /// service only: the `Authorization` header to Orthanc, the API key to analysis requests.
+    /// Transient failures are retried per `http.retry` (analysis uploads per `http.analyzer`).
     pub fn new(
         base_url: &str,
         analyze_url: &str,
         target_aet: &str,
-        username: Option<String>,
-        password: Option<String>,
+        auth: &AuthConfig,
+        http: &HttpConfig,
     ) -> Result<Self> {
-        let mut builder = Client::builder()
-            .danger_accept_invalid_certs(true)
-            .timeout(Duration::from_secs(60));
-
-        if let (Some(u), Some(p)) = (username, password) {
-            let credentials = format!("{}:{}", u, p);
-            let token = general_purpose::STANDARD.encode(credentials);
-            let mut headers = HeaderMap::new();
-            headers.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&format!("Basic {}", token))
-                    .context("Invalid Authorization header")?,
-            );
-            builder = builder.default_headers(headers);
+        let builder = http_client_builder(http)?;
+
+        let authorization = match (&auth.auth_token, &auth.username, &auth.password) {
+            (Some(token), _, _) => Some(format!("Bearer {}", token)),
+            (None, Some(u), Some(p)) => {
+                let credentials = format!("{}:{}", u, p);
+                Some(format!(
+                    "Basic {}",
+                    general_purpose::STANDARD.encode(credentials)
+                ))
+            }
+            _ => None,
+        };
+        let authorization = authorization
+            .map(|value| {
+                let mut value =
+                    HeaderValue::from_str(&value).context("Invalid Authorization header")?;
+                value.set_sensitive(true);
+                Ok::<_, anyhow::Error>(value)
+            })
+            .transpose()?;
+
+        let request = &http.analyzer.request;
+        let mut analyze_headers = HeaderMap::new();
+        for (name, value) in &request.headers {
+            let header = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid analyze_request header name {}", name))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for analyze_request header {}", name))?;
+            analyze_headers.insert(header, value);
+        }
+        if let Some(key) = &auth.api_key {
+            let name = HeaderName::from_bytes(auth.api_key_header.as_bytes())
+                .with_context(|| format!("Invalid API key header name {}", auth.api_key_header))?;
+            let mut value = HeaderValue::from_str(key).context("Invalid API key value")?;
+            value.set_sensitive(true);
+            analyze_headers.insert(name, value);
         }
 
         Ok(Self {
             client: builder.build().context("Failed to build HTTP client")?,
+            guard: Arc::new(RequestGuard {
+                auth_failed: AtomicBool::new(false),
+                retrier: Retrier::new(http.retry.clone()),
+                analyze_retrier: Retrier::new(RetryPolicy {
+                    max_retries: http.analyzer.retries,
+                    ..http.retry.clone()
+                }),
+                analyze_breaker: CircuitBreaker::new(
+                    http.analyzer.breaker_threshold,
+                    http.analyzer.breaker_cooldown,
+                ),
+                limiter: RateLimiter::new(http.requests_per_second),
+                bandwidth: http
+                    .max_bandwidth
+                    .filter(|b| *b > 0)
+                    .map(BandwidthLimiter::new),
+            }),
+            authorization,
+            analyze_headers,
+            analyze_file_field: request.file_field().to_string(),
+            analyze_fields: request
+                .fields
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            type_selector: TypeSelector::parse(request.type_path())?,
+            max_analyze_upload: http.max_analyze_upload,
+            classifier_command: http
+                .analyzer
+                .command
+                .as_deref()
+                .map(ClassifierCommand::parse)
+                .transpose()?,
+            timeouts: http.timeouts.clone(),
             base_url: base_url.trim_end_matches('/').to_string(),
             analyze_url: analyze_url.to_string(),
             target_aet: target_aet.to_string(),
         })
     }
 
+    /// Orthanc request with the query/metadata timeout and the Orthanc credentials.
+    fn orthanc(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        let req = self
+            .client
+            .request(method, url)
+            .timeout(self.timeouts.query);
+        match &self.authorization {
+            Some(value) => req.header(AUTHORIZATION, value.clone()),
+            None => req,
+        }
+    }
+
+    /// GET with the query/metadata timeout.
+    fn get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.orthanc(Method::GET, url)
+    }
+
+    /// POST with the query/metadata timeout.
+    fn post(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.orthanc(Method::POST, url)
+    }
+
+    /// PUT with the query/metadata timeout.
+    fn put(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.orthanc(Method::PUT, url)
+    }
+
+    /// DELETE with the query/metadata timeout.
+    fn delete(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.orthanc(Method::DELETE, url)
+    }
+
+    /// True once any request was rejected with 401/403; callers stop scheduling new work.
+    pub fn auth_failed(&self) -> bool {
+        self.guard.auth_failed.load(Ordering::SeqCst)
+    }
+
+    /// Clears [`Self::auth_failed`] so a long-running caller (`serve`) can try again with
+    /// the next job.
+    pub fn reset_auth_failed(&self) {
+        self.guard.auth_failed.store(false, Ordering::SeqCst);
+    }
+
     /// Uses Orthanc's modality query to turn an accession number into a StudyInstanceUID.
     pub async fn find_study_by_accession(&self, accession: &str, modality: &str) -> Result<String> {
         let payload = json!({
@@ -90,10 +734,9 @@ impl OrthancClient {
         });
 
         let resp = self
-            .client
             .post(format!("{}/modalities/{}/query", self.base_url, modality))
             .json(&payload)
-            .send()
+            .send_checked(&self.guard)
             .await
             .context("Failed to query study by accession")?;
 
@@ -107,9 +750,8 @@ impl OrthancClient {
             .ok_or(anyhow!("No Query ID returned"))?;
 
         let answers: Vec<String> = self
-            .client
             .get(format!("{}/queries/{}/answers", self.base_url, query_id))
-            .send()
+            .send_checked(&self.guard)
             .await?
             .json()
             .await?;
@@ -119,12 +761,11 @@ impl OrthancClient {
         }
 
         let content: Value = self
-            .client
             .get(format!(
                 "{}/queries/{}/answers/{}/content",
                 self.base_url, query_id, answers[0]
             ))
-            .send()
+            .send_checked(&self.guard)
             .await?
             .json()
             .await?;
@@ -143,10 +784,9 @@ impl OrthancClient {
         payload: Value,
     ) -> Result<Vec<Value>> {
         let resp = self
-            .client
             .post(format!("{}/modalities/{}/query", self.base_url, modality))
             .json(&payload)
-            .send()
+            .send_checked(&self.guard)
             .await
             .context("Failed to run modality query")?;
 
@@ -156,9 +796,8 @@ impl OrthancClient {
             .ok_or(anyhow!("No Query ID returned"))?;
 
         let answers: Vec<String> = self
-            .client
             .get(format!("{}/queries/{}/answers", self.base_url, query_id))
-            .send()
+            .send_checked(&self.guard)
             .await?
             .json()
             .await?;
@@ -166,12 +805,11 @@ impl OrthancClient {
         let mut series_list = Vec::new();
         for ans in answers {
             let content: Value = self
-                .client
                 .get(format!(
                     "{}/queries/{}/answers/{}/content",
                     self.base_url, query_id, ans
                 ))
-                .send()
+                .send_checked(&self.guard)
                 .await?
                 .json()
                 .await?;
@@ -185,12 +823,38 @@ impl OrthancClient {
     pub async fn get_remote_series(&self, modality: &str, study_uid: &str) -> Result<Vec<Value>> {
         let payload = json!({
             "Level": "Series",
-            "Query": { "StudyInstanceUID": study_uid },
+            "Query": { "StudyInstanceUID": study_uid, "Modality": "" },
+            "Normalize": true,
+        });
+        self.execute_modality_query(modality, payload).await
+    }
+
+    /// Queries the modality for one series by SeriesInstanceUID (with its study UID,
+    /// description and modality).
+    pub async fn find_remote_series(&self, modality: &str, series_uid: &str) -> Result<Vec<Value>> {
+        let payload = json!({
+            "Level": "Series",
+            "Query": {
+                "SeriesInstanceUID": series_uid,
+                "StudyInstanceUID": "",
+                "SeriesDescription": "",
+                "Modality": "",
+            },
             "Normalize": true,
         });
         self.execute_modality_query(modality, payload).await
     }
 
+    /// Extracts the Modality tag from a normalized series response.
+    pub fn extract_series_modality(&self, series_json: &Value) -> Option<String> {
+        series_json
+            .get("0008,0060")
+            .and_then(|x| x.get("Value"))
+            .and_then(|x| x.as_str())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    }
+
     /// Extracts the SeriesInstanceUID and description tags from a normalized response.
     pub fn extract_series_info(&self, series_json: &Value) -> (String, String) {
         let uid = series_json
@@ -215,10 +879,9 @@ impl OrthancClient {
             "Query": { "StudyInstanceUID": study_uid },
         });
         let studies: Vec<String> = self
-            .client
             .post(format!("{}/tools/find", self.base_url))
             .json(&payload)
-            .send()
+            .send_checked(&self.guard)
             .await?
             .json()
             .await?;
@@ -228,9 +891,8 @@ impl OrthancClient {
         }
 
         let series_arr: Vec<Value> = self
-            .client
             .get(format!("{}/studies/{}/series", self.base_url, studies[0]))
-            .send()
+            .send_checked(&self.guard)
             .await?
             .json()
             .await?;
@@ -250,7 +912,9 @@ impl OrthancClient {
 
     /// Issues an Orthanc C-MOVE request to transfer a study/series/instance to the target AET.
     ///
-    /// Returns the job ID when running in async mode so callers can poll its status.
+    /// Returns the job ID when running in async mode so callers can poll its status. The
+    /// request is not retried, since a repeated one would move the data again; a synchronous
+    /// move waits up to the download timeout rather than the query timeout.
     pub async fn c_move(
         &self,
         modality: &str,
@@ -266,15 +930,16 @@ impl OrthancClient {
         });
 
         let mut req = self
-            .client
             .post(format!("{}/modalities/{}/move", self.base_url, modality))
             .json(&payload);
 
         if async_mode {
             req = req.header("Asynchronous", "true");
+        } else {
+            req = req.timeout(self.timeouts.download);
         }
 
-        let resp = req.send().await?;
+        let resp = req.send_once_checked(&self.guard).await?;
         if !resp.status().is_success() {
             return Err(anyhow!("C-MOVE failed: {}", resp.status()));
         }
@@ -320,42 +985,174 @@ impl OrthancClient {
             "Query": { "SOPInstanceUID": sop_uid },
         });
         let resp = self
-            .client
             .post(format!("{}/tools/find", self.base_url))
             .json(&payload)
-            .send()
+            .send_checked(&self.guard)
             .await?;
         let ids = resp.json::<Vec<String>>().await?;
         Ok(ids.into_iter().next())
     }
 
     /// Downloads the raw DICOM file bytes of a stored instance in Orthanc.
+    ///
+    /// A body read cut off mid-transfer is retried too, not just the initial request. The body
+    /// is read chunk by chunk so `max_bandwidth` can throttle all concurrent downloads together.
     pub async fn download_instance_file(&self, uuid: &str) -> Result<Vec<u8>> {
+        let url = format!("{}/instances/{}/file", self.base_url, uuid);
+        self.guard
+            .retrier
+            .run(|| async {
+                let req = self.get(url.as_str()).timeout(self.timeouts.download);
+                let mut resp = self.guard.send_once(req).await?;
+                let mut data = Vec::with_capacity(resp.content_length().unwrap_or(0) as usize);
+                while let Some(chunk) = resp.chunk().await? {
+                    if let Some(bandwidth) = &self.guard.bandwidth {
+                        bandwidth.consume(chunk.len()).await;
+                    }
+                    data.extend_from_slice(&chunk);
+                }
+                Ok(data)
+            })
+            .await
+    }
+
+    /// Streams an instance to `<dest>.part` chunk by chunk and renames it over `dest` once
+    /// the body is complete, so large multi-frame files are never held in memory.
+    /// Returns the number of bytes written; the partial file is removed on failure.
+    pub async fn download_instance_to_path(&self, uuid: &str, dest: &Path) -> Result<u64> {
+        let url = format!("{}/instances/{}/file", self.base_url, uuid);
+        let part = part_path(dest);
+        let result = self
+            .guard
+            .retrier
+            .run(|| async {
+                let req = self.get(url.as_str()).timeout(self.timeouts.download);
+                let mut resp = self.guard.send_once(req).await?;
+                // 每次嘗試都重新建立暫存檔
+                let mut file = tokio::fs::File::create(&part)
+                    .await
+                    .with_context(|| format!("Failed to create {}", part.display()))?;
+                let mut written = 0u64;
+                while let Some(chunk) = resp.chunk().await? {
+                    if let Some(bandwidth) = &self.guard.bandwidth {
+                        bandwidth.consume(chunk.len()).await;
+                    }
+                    file.write_all(&chunk)
+                        .await
+                        .with_context(|| format!("Failed to write {}", part.display()))?;
+                    written += chunk.len() as u64;
+                }
+                file.flush().await?;
+                Ok(written)
+            })
+            .await;
+        match result {
+            Ok(written) => {
+                tokio::fs::rename(&part, dest)
+                    .await
+                    .with_context(|| format!("Failed to replace {}", dest.display()))?;
+                Ok(written)
+            }
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&part).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Deletes a study (all its series and instances) from Orthanc; not retried.
+    pub async fn delete_study(&self, study_id: &str) -> Result<()> {
+        self.delete(format!("{}/studies/{}", self.base_url, study_id))
+            .send_once_checked(&self.guard)
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Instance IDs of a series in slice order (Orthanc `ordered-slices`).
+    pub async fn get_ordered_instance_ids(&self, series_id: &str) -> Result<Vec<String>> {
+        let body: Value = self
+            .get(format!(
+                "{}/series/{}/ordered-slices",
+                self.base_url, series_id
+            ))
+            .send_checked(&self.guard)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // "Dicom": ["/instances/<id>/file", ...]，多 frame instance 只出現一次
+        Ok(body
+            .get("Dicom")
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_str()?.split('/').nth(2).map(|id| id.to_string()))
+            .collect())
+    }
+
+    /// PNG rendering of an instance (`/instances/{id}/preview`).
+    pub async fn get_instance_preview(&self, instance_id: &str) -> Result<Vec<u8>> {
         let bytes = self
-            .client
-            .get(format!("{}/instances/{}/file", self.base_url, uuid))
-            .send()
+            .get(format!(
+                "{}/instances/{}/preview",
+                self.base_url, instance_id
+            ))
+            .header(ACCEPT, "image/png")
+            .send_checked(&self.guard)
             .await?
+            .error_for_status()?
             .bytes()
             .await?;
         Ok(bytes.to_vec())
     }
 
+    /// Value of a study metadata entry, or `None` when the study does not carry it.
+    pub async fn get_study_metadata(&self, study_id: &str, name: &str) -> Result<Option<String>> {
+        let resp = self
+            .get(format!(
+                "{}/studies/{}/metadata/{}",
+                self.base_url, study_id, name
+            ))
+            .send_checked(&self.guard)
+            .await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(resp.error_for_status()?.text().await?))
+    }
+
+    /// Sets a study metadata entry; the name must be declared in Orthanc's `UserMetadata`.
+    pub async fn set_study_metadata(&self, study_id: &str, name: &str, value: &str) -> Result<()> {
+        self.put(format!(
+            "{}/studies/{}/metadata/{}",
+            self.base_url, study_id, name
+        ))
+        .body(value.to_string())
+        .send_checked(&self.guard)
+        .await?
+        .error_for_status()?;
+        Ok(())
+    }
+
+    /// Deletes one instance from Orthanc; not retried.
     pub async fn delete_instance(&self, uuid: &str) -> Result<()> {
-        self.client
-            .delete(format!("{}/instances/{}", self.base_url, uuid))
-            .send()
+        self.delete(format!("{}/instances/{}", self.base_url, uuid))
+            .send_once_checked(&self.guard)
             .await?
             .error_for_status()?;
         Ok(())
     }
 
+    /// C-MOVEs one instance of a remote series, analyzes it, and deletes it again; without an
+    /// analyzer answer the series is classified by `classifier` and the built-in fallback.
     pub async fn sample_series_type(
         &self,
         modality: &str,
         study_uid: &str,
         series_uid: &str,
-    ) -> Result<Option<String>> {
+        classifier: &LocalClassifier,
+    ) -> Result<SampleResult> {
         if let Some(sop) = self.find_instance_sop(modality, series_uid).await? {
             let identifier = json!({
                 "SOPInstanceUID": sop,
@@ -365,43 +1162,201 @@ impl OrthancClient {
             self.c_move(modality, "Instance", identifier, false).await?;
             if let Some(local_uuid) = self.find_instance_uuid(&sop).await? {
                 let dicom_data = self.download_instance_file(&local_uuid).await?;
+                let fallback = fallback_type_from_dicom(&dicom_data, classifier);
                 let analysis = self.analyze_dicom_data(dicom_data).await;
                 let _ = self.delete_instance(&local_uuid).await;
-                return analysis;
+                return match analysis {
+                    Ok(Some(t)) if t.to_lowercase() != "unknown" => Ok(SampleResult {
+                        series_type: Some(t),
+                        analyzer_bypassed: None,
+                    }),
+                    Ok(_) => Ok(SampleResult {
+                        series_type: fallback,
+                        analyzer_bypassed: None,
+                    }),
+                    Err(e) => match analyzer_bypass_reason(&e) {
+                        Some(reason) => Ok(SampleResult {
+                            series_type: fallback,
+                            analyzer_bypassed: Some(reason),
+                        }),
+                        None => Err(e),
+                    },
+                };
             }
             return Err(anyhow!("Sample moved but local instance UUID missing"));
         }
-        Ok(None)
+        Ok(SampleResult::default())
     }
 
+    /// Uploads a sample to the analysis service and returns its `series_type`.
+    ///
+    /// Fails with [`AnalyzerBypassed`] when the sample exceeds `max_analyze_upload`, the
+    /// service does not answer within the analyze timeout, keeps failing after retries, or
+    /// is skipped by the circuit breaker.
     pub async fn analyze_dicom_data(&self, dicom_data: Vec<u8>) -> Result<Option<String>> {
-        let part = reqwest::multipart::Part::bytes(dicom_data)
-            .file_name("sample.dcm")
-            .mime_str("application/dicom")?;
-        let form = reqwest::multipart::Form::new().part("dicom_file_list", part);
-        let resp = self
+        self.check_analyze_upload(dicom_data.len())?;
+        Ok(self
+            .post_analysis(vec![dicom_data])
+            .await?
+            .into_iter()
+            .next()
+            .flatten())
+    }
+
+    /// Uploads several instances in one analysis request and returns one `series_type` per
+    /// file, in upload order.
+    ///
+    /// Callers check each file with [`check_analyze_upload`](Self::check_analyze_upload)
+    /// first; the analyze timeout applies to the whole request. A response whose array does
+    /// not match the files one-to-one is an error.
+    pub async fn analyze_dicom_batch(&self, files: Vec<Vec<u8>>) -> Result<Vec<Option<String>>> {
+        let count = files.len();
+        let results = self.post_analysis(files).await?;
+        match results.len() {
+            n if n == count => Ok(results),
+            0 => Ok(vec![None; count]),
+            n => Err(anyhow!(
+                "Analyze API returned {} results for {} files",
+                n,
+                count
+            )),
+        }
+    }
+
+    /// Fails with [`AnalyzerBypassed`] when a file of `len` bytes exceeds `max_analyze_upload`.
+    pub fn check_analyze_upload(&self, len: usize) -> Result<()> {
+        match self.max_analyze_upload {
+            Some(limit) if len as u64 > limit => Err(AnalyzerBypassed {
+                reason: format!(
+                    "sample is {}, over max_analyze_upload {}",
+                    format_bytes(len as u64),
+                    format_bytes(limit)
+                ),
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Posts files as one multipart request (`analyze_request.file_field`), retrying connection errors and
+    /// 429/5xx per `http.analyzer` (or runs `classifier_command` on each file instead).
+    ///
+    /// Every failure, 401/403 included, becomes [`AnalyzerBypassed`], so callers fall back
+    /// to header/SeriesDescription classification and report why. After
+    /// `breaker_threshold` consecutive failures the service is not called until the
+    /// breaker's cooldown has passed.
+    async fn post_analysis(&self, files: Vec<Vec<u8>>) -> Result<Vec<Option<String>>> {
+        let breaker = &self.guard.analyze_breaker;
+        if let Err(failures) = breaker.check(Instant::now()) {
+            return Err(AnalyzerBypassed {
+                reason: format!(
+                    "analysis service skipped after {} consecutive failures",
+                    failures
+                ),
+            }
+            .into());
+        }
+        let result = match &self.classifier_command {
+            Some(command) => self.run_classifier_command(command, &files).await,
+            None => {
+                self.guard
+                    .analyze_retrier
+                    .run(|| self.post_analysis_once(&files))
+                    .await
+            }
+        };
+        match &result {
+            Ok(_) => breaker.record_success(),
+            Err(_) => {
+                if breaker.record_failure(Instant::now()) {
+                    warn!(
+                        "Analysis service keeps failing; classifying series from headers \
+                         until it answers again"
+                    );
+                }
+            }
+        }
+        result.map_err(|e| {
+            if analyzer_bypass_reason(&e).is_some() {
+                return e;
+            }
+            AnalyzerBypassed {
+                reason: format!("analyzer failed: {}", e),
+            }
+            .into()
+        })
+    }
+
+    /// Classifies each file with `classifier_command`, one run per file.
+    async fn run_classifier_command(
+        &self,
+        command: &ClassifierCommand,
+        files: &[Vec<u8>],
+    ) -> Result<Vec<Option<String>>> {
+        let mut results = Vec::with_capacity(files.len());
+        for data in files {
+            results.push(command.classify(data, self.timeouts.analyze).await?);
+        }
+        Ok(results)
+    }
+
+    /// One analysis upload; a timeout or 401/403 is final (not retried) and does not stop
+    /// the Orthanc batch.
+    async fn post_analysis_once(&self, files: &[Vec<u8>]) -> Result<Vec<Option<String>>> {
+        let mut form = reqwest::multipart::Form::new();
+        for (name, value) in &self.analyze_fields {
+            form = form.text(name.clone(), value.clone());
+        }
+        let single = files.len() == 1;
+        for (i, data) in files.iter().enumerate() {
+            let file_name = if single {
+                "sample.dcm".to_string()
+            } else {
+                format!("sample_{}.dcm", i)
+            };
+            let part = reqwest::multipart::Part::bytes(data.clone())
+                .file_name(file_name)
+                .mime_str("application/dicom")?;
+            form = form.part(self.analyze_file_field.clone(), part);
+        }
+        let req = self
             .client
             .post(&self.analyze_url)
-            .multipart(form)
-            .send()
-            .await?;
-        if resp.status().is_success() {
-            let json_body: Value = resp.json().await?;
-            if let Some(arr) = json_body.as_array() {
-                if let Some(first) = arr.first() {
-                    return Ok(first
-                        .get("series_type")
-                        .and_then(|s| s.as_str())
-                        .map(|s| s.to_string()));
+            .timeout(self.timeouts.analyze)
+            .headers(self.analyze_headers.clone())
+            .multipart(form);
+        let timed_out = |e: &anyhow::Error| {
+            e.chain()
+                .filter_map(|c| c.downcast_ref::<reqwest::Error>())
+                .any(|re| re.is_timeout())
+        };
+        let resp = match self.guard.send_unlatched(req).await {
+            Ok(resp) => resp,
+            Err(e) if is_auth_error(&e) => {
+                return Err(AnalyzerBypassed {
+                    reason: format!("analysis service rejected the credentials: {}", e),
                 }
+                .into())
             }
-        } else {
-            eprintln!(
-                "Warning: Analyze API returned non-success status: {}",
-                resp.status()
-            );
+            Err(e) if timed_out(&e) => {
+                return Err(AnalyzerBypassed {
+                    reason: format!(
+                        "analyzer timed out after {}s",
+                        self.timeouts.analyze.as_secs()
+                    ),
+                }
+                .into())
+            }
+            Err(e) => return Err(e),
+        };
+        if !resp.status().is_success() {
+            return Err(AnalyzerBypassed {
+                reason: format!("analyzer returned HTTP {}", resp.status()),
+            }
+            .into());
         }
-        Ok(None)
+        let json_body: Value = resp.json().await?;
+        Ok(self.type_selector.select(&json_body))
     }
 
     pub async fn wait_for_job(&self, job_id: &str, pb: &ProgressBar) -> Result<()> {
@@ -411,9 +1366,8 @@ impl OrthancClient {
                 return Err(anyhow!("Job timeout"));
             }
             let info: Value = self
-                .client
                 .get(format!("{}/jobs/{}", self.base_url, job_id))
-                .send()
+                .send_checked(&self.guard)
                 .await?
                 .json()
                 .await?;
@@ -431,6 +1385,56 @@ impl OrthancClient {
         }
     }
 
+    /// Returns the DICOM AET of the Orthanc behind `base_url` (from `/system`).
+    pub async fn local_aet(&self) -> Result<String> {
+        let body: Value = self
+            .get(format!("{}/system", self.base_url))
+            .send_checked(&self.guard)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        body.get("DicomAet")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .ok_or(anyhow!("Missing DicomAet in /system response"))
+    }
+
+    /// Lists registered modalities as `(symbolic name, AET)` pairs via `/modalities?expand`.
+    pub async fn list_modalities(&self) -> Result<Vec<(String, String)>> {
+        let body: Value = self
+            .get(format!("{}/modalities?expand", self.base_url))
+            .send_checked(&self.guard)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let mut modalities = Vec::new();
+        if let Some(obj) = body.as_object() {
+            for (name, info) in obj {
+                // Older Orthanc versions return [AET, host, port] arrays instead of objects
+                let aet = info
+                    .get("AET")
+                    .or_else(|| info.get(0))
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default();
+                modalities.push((name.clone(), aet.to_string()));
+            }
+        }
+        Ok(modalities)
+    }
+
+    /// Sends a C-ECHO to a registered modality; returns whether it answered.
+    pub async fn echo_modality(&self, modality: &str) -> Result<bool> {
+        let resp = self
+            .post(format!("{}/modalities/{}/echo", self.base_url, modality))
+            .json(&json!({}))
+            .send_checked(&self.guard)
+            .await
+            .context("Failed to send C-ECHO")?;
+        Ok(resp.status().is_success())
+    }
+
     /// Queries local Orthanc by AccessionNumber and returns study IDs (Orthanc UUIDs).
     pub async fn find_study_ids_by_accession(&self, accession: &str) -> Result<Vec<String>> {
         let payload = json!({
@@ -438,10 +1442,9 @@ impl OrthancClient {
             "Query": { "AccessionNumber": accession },
         });
         let resp = self
-            .client
             .post(format!("{}/tools/find", self.base_url))
             .json(&payload)
-            .send()
+            .send_checked(&self.guard)
             .await?
             .error_for_status()?;
 
@@ -463,9 +1466,8 @@ impl OrthancClient {
     /// Returns Orthanc series UUIDs under a study UUID.
     pub async fn list_series_ids(&self, study_id: &str) -> Result<Vec<String>> {
         let resp = self
-            .client
             .get(format!("{}/studies/{}/series", self.base_url, study_id))
-            .send()
+            .send_checked(&self.guard)
             .await?
             .error_for_status()?;
 
@@ -484,41 +1486,321 @@ impl OrthancClient {
         Ok(ids)
     }
 
+    /// Returns StudyDate, StudyTime, StudyDescription, and the series count of a stored study.
+    pub async fn get_study_summary(&self, study_id: &str) -> Result<StudySummary> {
+        let body: Value = self
+            .get(format!("{}/studies/{}", self.base_url, study_id))
+            .send_checked(&self.guard)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let tag = |key: &str| {
+            body.get("MainDicomTags")
+                .and_then(|t| t.get(key))
+                .and_then(|v| v.as_str())
+                .map(|s| s.trim().to_string())
+        };
+        Ok(StudySummary {
+            id: study_id.to_string(),
+            study_date: tag("StudyDate").unwrap_or_default(),
+            study_time: tag("StudyTime").unwrap_or_default(),
+            description: tag("StudyDescription").filter(|s| !s.is_empty()),
+            series_count: body
+                .get("Series")
+                .and_then(|v| v.as_array())
+                .map_or(0, |a| a.len()),
+        })
+    }
+
+    /// Returns instance/series counts and on-disk size for a study UUID.
+    ///
+    /// Orthanc encodes `DiskSize` as a string, so both string and numeric forms are accepted.
+    pub async fn get_study_statistics(&self, study_id: &str) -> Result<StudyStatistics> {
+        let body: Value = self
+            .get(format!("{}/studies/{}/statistics", self.base_url, study_id))
+            .send_checked(&self.guard)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let as_u64 = |key: &str| -> u64 {
+            match body.get(key) {
+                Some(Value::String(s)) => s.parse().unwrap_or(0),
+                Some(v) => v.as_u64().unwrap_or(0),
+                None => 0,
+            }
+        };
+        Ok(StudyStatistics {
+            count_series: as_u64("CountSeries") as usize,
+            count_instances: as_u64("CountInstances") as usize,
+            disk_size: as_u64("DiskSize"),
+        })
+    }
+
     /// Returns series metadata plus instance IDs for a series UUID.
     pub async fn get_series_meta(&self, series_id: &str) -> Result<SeriesMeta> {
         let resp = self
-            .client
             .get(format!("{}/series/{}", self.base_url, series_id))
-            .send()
+            .send_checked(&self.guard)
             .await?
             .error_for_status()?;
         let body: Value = resp.json().await?;
-        let tags = body.get("MainDicomTags");
-        let description = tags
-            .and_then(|t| t.get("SeriesDescription"))
+        Ok(series_meta_from_json(&body))
+    }
+
+    /// Study-folder tags of one instance from `/instances/{id}/tags?simplify`, without
+    /// transferring the file or its pixel data.
+    pub async fn get_instance_study_info(&self, instance_id: &str) -> Result<DicomStudyInfo> {
+        Ok(study_info_from_simplified_tags(
+            &self.get_simplified_tags(instance_id).await?,
+        ))
+    }
+
+    /// Series-level classification tags of one instance from `tags?simplify`, without
+    /// downloading its pixel data.
+    pub async fn get_instance_series_tags(&self, instance_id: &str) -> Result<SeriesTags> {
+        Ok(series_tags_from_simplified_tags(
+            &self.get_simplified_tags(instance_id).await?,
+        ))
+    }
+
+    async fn get_simplified_tags(&self, instance_id: &str) -> Result<Value> {
+        let body: Value = self
+            .get(format!(
+                "{}/instances/{}/tags?simplify",
+                self.base_url, instance_id
+            ))
+            .send_checked(&self.guard)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(body)
+    }
+
+    /// Every series of a study with its tags and instance list in one request
+    /// (`/studies/{id}/series?expand`), in the order of [`Self::list_series_ids`].
+    pub async fn list_series_meta(&self, study_id: &str) -> Result<Vec<(String, SeriesMeta)>> {
+        let items: Vec<Value> = self
+            .get(format!(
+                "{}/studies/{}/series?expand",
+                self.base_url, study_id
+            ))
+            .send_checked(&self.guard)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(items
+            .iter()
+            .filter_map(|item| {
+                let id = item.get("ID")?.as_str()?.to_string();
+                Some((id, series_meta_from_json(item)))
+            })
+            .collect())
+    }
+
+    /// StudyInstanceUID and the study-folder tags of a stored study from one `/studies/{id}`
+    /// request. `modality` is left empty: it is a series tag, filled from the first series.
+    pub async fn get_study_tags(&self, study_id: &str) -> Result<(Option<String>, DicomStudyInfo)> {
+        let body: Value = self
+            .get(format!("{}/studies/{}", self.base_url, study_id))
+            .send_checked(&self.guard)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(study_tags_from_json(&body))
+    }
+
+    /// Returns the Orthanc series ID an instance belongs to, or `None` if Orthanc no longer has it.
+    pub async fn get_instance_parent_series(&self, instance_id: &str) -> Result<Option<String>> {
+        let resp = self
+            .get(format!("{}/instances/{}", self.base_url, instance_id))
+            .send_checked(&self.guard)
+            .await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body: Value = resp.error_for_status()?.json().await?;
+        Ok(body
+            .get("ParentSeries")
             .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let series_number = tags
-            .and_then(|t| t.get("SeriesNumber"))
+            .map(|s| s.to_string()))
+    }
+
+    /// SOPInstanceUID of a stored instance, or `None` if Orthanc no longer has it.
+    pub async fn get_instance_sop_uid(&self, instance_id: &str) -> Result<Option<String>> {
+        let resp = self
+            .get(format!("{}/instances/{}", self.base_url, instance_id))
+            .send_checked(&self.guard)
+            .await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body: Value = resp.error_for_status()?.json().await?;
+        Ok(body
+            .get("MainDicomTags")
+            .and_then(|t| t.get("SOPInstanceUID"))
             .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        let instances: Vec<String> = body
-            .get("Instances")
-            .and_then(|arr| arr.as_array())
-            .map(|arr| {
-                arr.iter()
-                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                    .collect()
+            .map(|s| s.to_string()))
+    }
+
+    /// InstanceNumber and SOPInstanceUID of every instance in a series (one request).
+    pub async fn get_series_instance_tags(&self, series_id: &str) -> Result<Vec<InstanceTags>> {
+        let resp = self
+            .get(format!("{}/series/{}/instances", self.base_url, series_id))
+            .send_checked(&self.guard)
+            .await?
+            .error_for_status()?;
+        let items: Vec<Value> = resp.json().await?;
+        Ok(items
+            .iter()
+            .filter_map(|item| {
+                let tag = |name: &str| {
+                    item.get("MainDicomTags")
+                        .and_then(|t| t.get(name))
+                        .and_then(|v| v.as_str())
+                        .map(|s| s.trim().to_string())
+                        .filter(|s| !s.is_empty())
+                };
+                Some(InstanceTags {
+                    id: item.get("ID")?.as_str()?.to_string(),
+                    instance_number: tag("InstanceNumber"),
+                    sop_instance_uid: tag("SOPInstanceUID"),
+                })
             })
-            .unwrap_or_default();
-        Ok(SeriesMeta {
-            description,
-            series_number,
-            instances,
+            .collect())
+    }
+
+    /// MD5 Orthanc recorded for the stored DICOM file; `None` when the instance is gone or
+    /// Orthanc stores attachments without MD5 (`StoreMD5ForAttachments = false`).
+    pub async fn get_instance_md5(&self, instance_id: &str) -> Result<Option<String>> {
+        let resp = self
+            .get(format!(
+                "{}/instances/{}/attachments/dicom/md5",
+                self.base_url, instance_id
+            ))
+            .send_checked(&self.guard)
+            .await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let md5 = resp.error_for_status()?.text().await?;
+        let md5 = md5.trim().trim_matches('"').to_string();
+        Ok((!md5.is_empty()).then_some(md5))
+    }
+
+    /// Resolves a SeriesInstanceUID to the Orthanc series ID via `/tools/lookup`.
+    pub async fn lookup_series(&self, series_uid: &str) -> Result<Option<String>> {
+        let resp = self
+            .post(format!("{}/tools/lookup", self.base_url))
+            .body(series_uid.to_string())
+            .send_checked(&self.guard)
+            .await?
+            .error_for_status()?;
+        let items: Vec<Value> = resp.json().await?;
+        Ok(items
+            .iter()
+            .find(|item| item.get("Type").and_then(|t| t.as_str()) == Some("Series"))
+            .and_then(|item| item.get("ID"))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string()))
+    }
+}
+
+/// Tags and instance list of a series object from `/series/{id}` or an expanded listing.
+fn series_meta_from_json(body: &Value) -> SeriesMeta {
+    let tags = body.get("MainDicomTags");
+    let description = tags
+        .and_then(|t| t.get("SeriesDescription"))
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let series_number = tags
+        .and_then(|t| t.get("SeriesNumber"))
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let modality = tags
+        .and_then(|t| t.get("Modality"))
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let instances: Vec<String> = body
+        .get("Instances")
+        .and_then(|arr| arr.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                .collect()
         })
+        .unwrap_or_default();
+    SeriesMeta {
+        description,
+        series_number,
+        modality,
+        instances,
     }
 }
 
+/// Folder tags from a simplified tag object (`"PatientID": "P1"`, ...), trimmed like
+/// [`parse_dicom_study_info`].
+fn study_info_from_simplified_tags(body: &Value) -> DicomStudyInfo {
+    let tag = |key: &str| {
+        body.get(key)
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    };
+    DicomStudyInfo {
+        patient_id: tag("PatientID"),
+        study_date: tag("StudyDate"),
+        modality: tag("Modality"),
+        accession_number: tag("AccessionNumber"),
+    }
+}
+
+/// [`SeriesTags`] from a `tags?simplify` object (private Siemens b-value as `0019,100c`).
+fn series_tags_from_simplified_tags(body: &Value) -> SeriesTags {
+    let tag = |key: &str| {
+        body.get(key)
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+    let number = |key: &str| tag(key).and_then(|v| parse_first_number(&v));
+    SeriesTags {
+        modality: tag("Modality").unwrap_or_default(),
+        series_description: tag("SeriesDescription").unwrap_or_default(),
+        convolution_kernel: tag("ConvolutionKernel"),
+        contrast_agent: tag("ContrastBolusAgent"),
+        protocol_name: tag("ProtocolName"),
+        b_value: number("DiffusionBValue").or_else(|| number("0019,100c")),
+        echo_time: number("EchoTime"),
+    }
+}
+
+/// First value of a (possibly multi-valued) numeric DICOM string.
+fn parse_first_number(value: &str) -> Option<f64> {
+    value.split('\\').next()?.trim().parse().ok()
+}
+
+/// `(StudyInstanceUID, folder tags)` from a `/studies/{id}` object; missing tags are empty.
+fn study_tags_from_json(body: &Value) -> (Option<String>, DicomStudyInfo) {
+    let tag = |group: &str, key: &str| {
+        body.get(group)
+            .and_then(|t| t.get(key))
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().to_string())
+    };
+    let info = DicomStudyInfo {
+        patient_id: tag("PatientMainDicomTags", "PatientID").unwrap_or_default(),
+        study_date: tag("MainDicomTags", "StudyDate").unwrap_or_default(),
+        modality: String::new(),
+        accession_number: tag("MainDicomTags", "AccessionNumber").unwrap_or_default(),
+    };
+    (tag("MainDicomTags", "StudyInstanceUID"), info)
+}
+
 /// 從 DICOM bytes 解析 Study 資訊（與 Python pydicom 對齊）
 pub fn parse_dicom_study_info(data: &[u8]) -> Result<DicomStudyInfo> {
     use dicom_object::from_reader;
@@ -544,3 +1826,312 @@ pub fn parse_dicom_study_info(data: &[u8]) -> Result<DicomStudyInfo> {
         accession_number: get_tag(Tag(0x0008, 0x0050)), // AccessionNumber
     })
 }
+
+/// 從 DICOM bytes 解析 series 層級標籤（供 CT 等非 MR 分類使用）
+pub fn parse_series_tags(data: &[u8]) -> Result<SeriesTags> {
+    use dicom_object::{from_reader, Tag};
+
+    let obj = from_reader(Cursor::new(data)).context("Failed to parse DICOM")?;
+    let get_tag = |tag: Tag| -> Option<String> {
+        obj.element(tag)
+            .ok()
+            .and_then(|e| e.to_str().ok())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+
+    let number = |tag: Tag| get_tag(tag).and_then(|v| parse_first_number(&v));
+    // DiffusionBValue, else the Siemens private b-value
+    let b_value = number(Tag(0x0018, 0x9087)).or_else(|| number(Tag(0x0019, 0x100C)));
+
+    Ok(SeriesTags {
+        modality: get_tag(Tag(0x0008, 0x0060)).unwrap_or_default(), // Modality
+        series_description: get_tag(Tag(0x0008, 0x103E)).unwrap_or_default(), // SeriesDescription
+        convolution_kernel: get_tag(Tag(0x0018, 0x1210)),           // ConvolutionKernel
+        contrast_agent: get_tag(Tag(0x0018, 0x0010)),               // ContrastBolusAgent
+        protocol_name: get_tag(Tag(0x0018, 0x1030)),                // ProtocolName
+        b_value,
+        echo_time: number(Tag(0x0018, 0x0081)), // EchoTime
+    })
+}
+
+/// Tag-based series type when the Analyze API has no answer: `[classifier]` rules, then
+/// the built-in rules for modalities it does not cover (e.g. CT).
+pub fn fallback_type_from_dicom(data: &[u8], classifier: &LocalClassifier) -> Option<String> {
+    parse_series_tags(data)
+        .ok()
+        .and_then(|tags| classifier.fallback(&tags))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_part_path_appends_suffix() {
+        assert_eq!(
+            part_path(Path::new("out/MR_T1/abc.dcm")),
+            PathBuf::from("out/MR_T1/abc.dcm.part")
+        );
+    }
+
+    #[test]
+    fn test_expanded_series_and_study_tags() {
+        let series = json!({
+            "ID": "se1",
+            "MainDicomTags": {
+                "SeriesDescription": "Ax T1",
+                "SeriesNumber": "3",
+                "Modality": "MR"
+            },
+            "Instances": ["i1", "i2"]
+        });
+        let meta = series_meta_from_json(&series);
+        assert_eq!(meta.description.as_deref(), Some("Ax T1"));
+        assert_eq!(meta.series_number.as_deref(), Some("3"));
+        assert_eq!(meta.instances, ["i1", "i2"]);
+
+        let study = json!({
+            "MainDicomTags": {
+                "StudyDate": "20240301",
+                "AccessionNumber": "A1 ",
+                "StudyInstanceUID": "1.2.3"
+            },
+            "PatientMainDicomTags": { "PatientID": "P1" }
+        });
+        let (uid, info) = study_tags_from_json(&study);
+        assert_eq!(uid.as_deref(), Some("1.2.3"));
+        assert_eq!(
+            (info.patient_id.as_str(), info.study_date.as_str()),
+            ("P1", "20240301")
+        );
+        assert_eq!(info.accession_number, "A1");
+        assert!(info.modality.is_empty());
+
+        let instance = json!({
+            "PatientID": " P1 ",
+            "StudyDate": "20240301",
+            "Modality": "MR",
+            "AccessionNumber": "A1",
+            "SeriesDescription": "Ax T1"
+        });
+        let info = study_info_from_simplified_tags(&instance);
+        assert_eq!(
+            (info.patient_id.as_str(), info.modality.as_str()),
+            ("P1", "MR")
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = chrono::DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse_retry_after("86400", now), Some(MAX_RETRY_AFTER));
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn test_series_tags_from_simplified_tags() {
+        let tags = series_tags_from_simplified_tags(&json!({
+            "Modality": "MR",
+            "ProtocolName": "ep2d_diff ",
+            "EchoTime": "89",
+            "0019,100c": "1000",
+            "ConvolutionKernel": ""
+        }));
+        assert_eq!(tags.protocol_name.as_deref(), Some("ep2d_diff"));
+        assert_eq!(tags.b_value, Some(1000.0));
+        assert_eq!(tags.echo_time, Some(89.0));
+        assert_eq!(tags.convolution_kernel, None);
+        assert_eq!(parse_first_number("2.5\\5.0"), Some(2.5));
+    }
+
+    #[test]
+    fn test_type_selector_keeps_order() {
+        let body = serde_json::json!([
+            {"series_type": "DWI0"},
+            {"error": "unreadable"},
+            {"series_type": "DWI1000"}
+        ]);
+        let default = TypeSelector::default();
+        assert_eq!(
+            default.select(&body),
+            vec![Some("DWI0".to_string()), None, Some("DWI1000".to_string())]
+        );
+        assert!(default
+            .select(&serde_json::json!({"detail": "x"}))
+            .is_empty());
+
+        let nested = TypeSelector::parse("$.results[*].prediction['label']").unwrap();
+        let body = serde_json::json!({"results": [
+            {"prediction": {"label": "T1W"}},
+            {"prediction": {}}
+        ]});
+        assert_eq!(nested.select(&body), vec![Some("T1W".to_string()), None]);
+
+        let single = TypeSelector::parse("data.types[0]").unwrap();
+        let body = serde_json::json!({"data": {"types": ["ADC", "DWI"]}});
+        assert_eq!(single.select(&body), vec![Some("ADC".to_string())]);
+
+        assert!(TypeSelector::parse("$.results[*").is_err());
+        assert!(TypeSelector::parse("$..label").is_err());
+    }
+
+    #[test]
+    fn test_bucket_rate_and_pause() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 2.0,
+            last: start,
+            paused_until: None,
+        };
+        let rate = Some(2.0);
+        assert_eq!(bucket.take(start, rate), None);
+        assert_eq!(bucket.take(start, rate), None);
+        // Empty bucket: one token refills in 1/rate seconds
+        assert_eq!(bucket.take(start, rate), Some(Duration::from_millis(500)));
+        assert_eq!(bucket.take(start + Duration::from_millis(500), rate), None);
+
+        bucket.paused_until = Some(start + Duration::from_secs(10));
+        assert_eq!(
+            bucket.take(start + Duration::from_secs(4), None),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(bucket.take(start + Duration::from_secs(10), None), None);
+    }
+
+    /// Local HTTP server answering every request with `status`; returns its URL and the
+    /// request heads received so far.
+    async fn stub_server(status: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let heads = Arc::new(Mutex::new(Vec::new()));
+        let seen = heads.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let head_end = loop {
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => break None,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                    if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break Some(pos + 4);
+                    }
+                };
+                let Some(head_end) = head_end else { continue };
+                let head = String::from_utf8_lossy(&buf[..head_end]).to_ascii_lowercase();
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .and_then(|v| v.trim().parse().ok())
+                    .unwrap_or(0);
+                while buf.len() < head_end + length {
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+                seen.lock().unwrap().push(head);
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, heads)
+    }
+
+    #[tokio::test]
+    async fn test_orthanc_credentials_stay_off_analysis_requests() {
+        let (url, heads) = stub_server("200 OK").await;
+        let auth = AuthConfig {
+            auth_token: Some("orthanc-secret".into()),
+            api_key_header: "X-API-Key".into(),
+            api_key: Some("analysis-key".into()),
+            ..Default::default()
+        };
+        let client = OrthancClient::new(
+            &url,
+            &format!("{}/analyze", url),
+            "AET",
+            &auth,
+            &HttpConfig::default(),
+        )
+        .unwrap();
+        let _ = client.local_aet().await;
+        let _ = client.analyze_dicom_data(vec![0; 4]).await;
+
+        let heads = heads.lock().unwrap().clone();
+        let orthanc = heads.iter().find(|h| h.starts_with("get /system")).unwrap();
+        let analysis = heads
+            .iter()
+            .find(|h| h.starts_with("post /analyze"))
+            .unwrap();
+        assert!(orthanc.contains("authorization: bearer orthanc-secret"));
+        assert!(!orthanc.contains("analysis-key"));
+        assert!(!analysis.contains("authorization"));
+        assert!(analysis.contains("x-api-key: analysis-key"));
+    }
+
+    #[tokio::test]
+    async fn test_moves_and_deletes_are_not_retried() {
+        let (url, heads) = stub_server("502 Bad Gateway").await;
+        let http = HttpConfig {
+            retry: RetryPolicy {
+                max_retries: 2,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+                budget: None,
+            },
+            ..Default::default()
+        };
+        let client = OrthancClient::new(&url, &url, "AET", &AuthConfig::default(), &http).unwrap();
+        assert!(client
+            .c_move("PACS", "Series", json!({}), false)
+            .await
+            .is_err());
+        assert!(client.delete_study("s1").await.is_err());
+        assert!(client.delete_instance("i1").await.is_err());
+        assert_eq!(heads.lock().unwrap().len(), 3);
+
+        // 查詢仍會重試
+        assert!(client.local_aet().await.is_err());
+        assert_eq!(heads.lock().unwrap().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_analysis_auth_error_does_not_stop_the_batch() {
+        let (url, _) = stub_server("401 Unauthorized").await;
+        let client = OrthancClient::new(
+            &url,
+            &format!("{}/analyze", url),
+            "AET",
+            &AuthConfig::default(),
+            &HttpConfig::default(),
+        )
+        .unwrap();
+
+        let err = client.analyze_dicom_data(vec![0; 4]).await.unwrap_err();
+        assert!(analyzer_bypass_reason(&err).is_some());
+        assert!(!is_auth_error(&err));
+        assert!(!client.auth_failed());
+
+        let err = client.local_aet().await.unwrap_err();
+        assert!(is_auth_error(&err));
+        assert!(client.auth_failed());
+    }
+}
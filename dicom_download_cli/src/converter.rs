@@ -4,12 +4,65 @@
 //! using the external dcm2niix tool. NIfTI files are output to a separate directory
 //! from the DICOM source files.
 
-#![allow(dead_code)] // TODO: 整合至 download subcommand 時移除
-
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::process::Command;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::warn;
+
+use crate::audit::{AuditLog, AuditOperation};
+use crate::fdlimit::FileSlots;
+
+/// Folders holding the b=0 and b=1000 halves of a DWI series split by per-instance analysis
+/// (the layout `check` enforces).
+pub const DWI_SHELL_FOLDERS: [&str; 2] = ["DWI0", "DWI1000"];
+/// Output name of the 4D NIfTI assembled from [`DWI_SHELL_FOLDERS`].
+pub const DWI_4D_NAME: &str = "DWI_4D";
+
+/// How the DWI0/DWI1000 sibling folders of a study are converted (`[conversion] dwi_mode`).
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DwiMode {
+    /// One NIfTI per folder (default).
+    #[default]
+    Separate,
+    /// One 4D [`DWI_4D_NAME`] NIfTI with combined `.bval`/`.bvec` instead.
+    Merged,
+    /// Both the per-folder NIfTIs and the 4D one.
+    Both,
+}
+
+impl DwiMode {
+    /// Whether DWI0 and DWI1000 are still converted on their own.
+    pub fn separate(self) -> bool {
+        self != DwiMode::Merged
+    }
+
+    /// Whether a study with both folders gets the 4D NIfTI.
+    pub fn merged(self) -> bool {
+        self != DwiMode::Separate
+    }
+}
+
+/// Whether `<output_dir>/<series_name>.nii.gz` (or `.nii`) exists, i.e. the series was
+/// converted by an earlier run.
+pub fn has_nifti_output(output_dir: &Path, series_name: &str) -> bool {
+    ["nii.gz", "nii"]
+        .iter()
+        .any(|ext| output_dir.join(format!("{}.{}", series_name, ext)).exists())
+}
+
+/// Whether `folder` is one of the [`DWI_SHELL_FOLDERS`].
+pub fn is_dwi_shell_folder(folder: &str) -> bool {
+    DWI_SHELL_FOLDERS.contains(&folder)
+}
 
 /// Result of a dcm2niix conversion operation.
 #[derive(Debug, Clone)]
@@ -24,6 +77,10 @@ pub struct ConversionResult {
     pub error: Option<String>,
     /// Time taken in milliseconds.
     pub elapsed_ms: u64,
+    /// Tool and version that produced this result (e.g. `dcm2niix v1.0.20240202`).
+    pub converter: String,
+    /// Command line of the run, for reproducing it; empty when it never started.
+    pub command: String,
 }
 
 /// Check if dcm2niix is available at the specified path.
@@ -72,25 +129,34 @@ pub async fn convert_series_to_nifti(
     dcm2niix_path: &str,
     extra_args: &[String],
 ) -> Result<ConversionResult> {
-    let start = std::time::Instant::now();
-
-    // Ensure output directory exists
-    tokio::fs::create_dir_all(output_dir).await?;
-
-    // Build command: dcm2niix [extra_args] -f <series_name> -o <output_dir> <dicom_dir>
-    let output = Command::new(dcm2niix_path)
-        .args(extra_args)
-        .arg("-f")
-        .arg(series_name)
-        .arg("-o")
-        .arg(output_dir)
-        .arg(dicom_dir)
+    let converter = Dcm2niixConverter {
+        path: dcm2niix_path.to_string(),
+        args: extra_args.to_vec(),
+    };
+    converter.convert(dicom_dir, output_dir, series_name).await
+}
+
+/// Runs a converter command line and collects the `series_name` outputs it left in
+/// `output_dir`.
+async fn run_converter_command(
+    argv: &[OsString],
+    output_dir: &Path,
+    series_name: &str,
+    start: std::time::Instant,
+) -> Result<ConversionResult> {
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| anyhow!("empty converter command"))?;
+    let output = Command::new(program)
+        .args(args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
+        .kill_on_drop(true)
         .output()
         .await?;
 
     let elapsed_ms = start.elapsed().as_millis() as u64;
+    let command = command_line(argv);
 
     // dcm2niix returns 0 even when no images are converted (e.g., for SR DICOM)
     // Check if any NIfTI files were actually created
@@ -103,6 +169,8 @@ pub async fn convert_series_to_nifti(
             json_files,
             error: None,
             elapsed_ms,
+            converter: String::new(),
+            command,
         })
     } else {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -118,14 +186,790 @@ pub async fn convert_series_to_nifti(
             json_files: vec![],
             error: Some(error_msg),
             elapsed_ms,
+            converter: String::new(),
+            command,
+        })
+    }
+}
+
+/// Renders `argv` as one shell-style line, quoting arguments that need it.
+fn command_line(argv: &[OsString]) -> String {
+    argv.iter()
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c));
+            if plain {
+                arg.into_owned()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// First non-empty line a tool prints for `args` (stdout, else stderr); `None` when it
+/// cannot be started.
+fn tool_version(program: &str, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let version = [&output.stdout, &output.stderr]
+        .into_iter()
+        .find_map(|text| {
+            String::from_utf8_lossy(text)
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .map(str::to_string)
+        });
+    version
+}
+
+/// Conversion tool selectable with `[conversion] backend` / `fallback_backend`.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConverterBackend {
+    /// Local dcm2niix (default).
+    #[default]
+    Dcm2niix,
+    /// `plastimatch convert`, which copes with some series dcm2niix rejects.
+    Plastimatch,
+    /// dcm2niix from `container_image`, run with docker or podman.
+    Docker,
+    Podman,
+}
+
+/// One DICOM-to-NIfTI tool. [`ConversionRunner`] adds the timeout, retries, and fallback
+/// around it; outputs are `<output_dir>/<series_name>` plus an extension or `_` suffix.
+pub trait Converter: Send + Sync + std::fmt::Debug {
+    /// Tool name for logs and error messages.
+    fn name(&self) -> String;
+
+    /// Whether the tool can be started at all (checked before converting).
+    fn is_available(&self) -> bool;
+
+    /// Version the tool reports (e.g. `v1.0.20240202`); `None` when it cannot be run.
+    fn version(&self) -> Option<String>;
+
+    /// Program and arguments converting `dicom_dir`; `output_dir` already exists.
+    fn command(
+        &self,
+        dicom_dir: &Path,
+        output_dir: &Path,
+        series_name: &str,
+    ) -> Result<Vec<OsString>>;
+
+    /// One attempt at converting `dicom_dir`; the result carries the command line.
+    fn convert<'a>(
+        &'a self,
+        dicom_dir: &'a Path,
+        output_dir: &'a Path,
+        series_name: &'a str,
+    ) -> BoxFuture<'a, Result<ConversionResult>> {
+        Box::pin(async move {
+            let start = std::time::Instant::now();
+            tokio::fs::create_dir_all(output_dir).await?;
+            let argv = self.command(dicom_dir, output_dir, series_name)?;
+            run_converter_command(&argv, output_dir, series_name, start).await
+        })
+    }
+}
+
+/// `dcm2niix [args] -f <name> -o <output_dir> <dicom_dir>`.
+#[derive(Debug, Clone)]
+pub struct Dcm2niixConverter {
+    pub path: String,
+    pub args: Vec<String>,
+}
+
+impl Converter for Dcm2niixConverter {
+    fn name(&self) -> String {
+        "dcm2niix".into()
+    }
+
+    fn is_available(&self) -> bool {
+        check_dcm2niix_available(&self.path)
+    }
+
+    fn version(&self) -> Option<String> {
+        tool_version(&self.path, &["--version"])
+    }
+
+    fn command(
+        &self,
+        dicom_dir: &Path,
+        output_dir: &Path,
+        series_name: &str,
+    ) -> Result<Vec<OsString>> {
+        let mut argv: Vec<OsString> = vec![self.path.clone().into()];
+        argv.extend(self.args.iter().map(OsString::from));
+        argv.extend([
+            "-f".into(),
+            series_name.into(),
+            "-o".into(),
+            output_dir.into(),
+            dicom_dir.into(),
+        ]);
+        Ok(argv)
+    }
+}
+
+/// `plastimatch convert --input <dicom_dir> --output-img <output_dir>/<name>.nii.gz [args]`.
+/// Writes no JSON sidecar.
+#[derive(Debug, Clone)]
+pub struct PlastimatchConverter {
+    pub path: String,
+    pub args: Vec<String>,
+}
+
+impl Converter for PlastimatchConverter {
+    fn name(&self) -> String {
+        "plastimatch".into()
+    }
+
+    fn is_available(&self) -> bool {
+        // plastimatch 沒有可靠的 -h 結束碼，能啟動即可
+        std::process::Command::new(&self.path)
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok()
+    }
+
+    fn version(&self) -> Option<String> {
+        tool_version(&self.path, &["--version"])
+    }
+
+    fn command(
+        &self,
+        dicom_dir: &Path,
+        output_dir: &Path,
+        series_name: &str,
+    ) -> Result<Vec<OsString>> {
+        let mut argv: Vec<OsString> = vec![
+            self.path.clone().into(),
+            "convert".into(),
+            "--input".into(),
+            dicom_dir.into(),
+            "--output-img".into(),
+            output_dir.join(format!("{}.nii.gz", series_name)).into(),
+        ];
+        argv.extend(self.args.iter().map(OsString::from));
+        Ok(argv)
+    }
+}
+
+/// dcm2niix inside a container image (`<runtime> run --rm --init --name <unique>` with the
+/// series mounted read-only at `/input` and the output folder at `/output`), for a pinned or
+/// patched dcm2niix build. The image's entrypoint must be dcm2niix. A run that times out is
+/// stopped with `<runtime> kill <name>`, since killing the client leaves the container going.
+#[derive(Debug, Clone)]
+pub struct ContainerConverter {
+    /// `docker` or `podman`.
+    pub runtime: String,
+    pub image: String,
+    pub args: Vec<String>,
+}
+
+/// Container name unique to this process and run.
+fn container_name() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    format!(
+        "dicom_download_cli-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Kills the named container when its run is dropped before finishing (timeout or
+/// cancellation).
+struct ContainerKillGuard<'a> {
+    runtime: &'a str,
+    name: String,
+    armed: bool,
+}
+
+impl Drop for ContainerKillGuard<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        warn!("Killing container {}", self.name);
+        let killed = std::process::Command::new(self.runtime)
+            .args(["kill", &self.name])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match killed {
+            // 在背景等待，避免留下殭屍行程
+            Ok(mut child) => {
+                std::thread::spawn(move || child.wait());
+            }
+            Err(e) => warn!("Failed to kill container {}: {}", self.name, e),
+        }
+    }
+}
+
+impl ContainerConverter {
+    fn command_named(
+        &self,
+        dicom_dir: &Path,
+        output_dir: &Path,
+        series_name: &str,
+        container: &str,
+    ) -> Result<Vec<OsString>> {
+        // bind mount 需要絕對路徑
+        let input = std::fs::canonicalize(dicom_dir)?;
+        let output = std::fs::canonicalize(output_dir)?;
+        let mut argv: Vec<OsString> = vec![self.runtime.clone().into()];
+        argv.extend(["run", "--rm", "--init", "--name", container].map(OsString::from));
+        // 以目前使用者執行，輸出檔不會屬於 root
+        #[cfg(unix)]
+        argv.extend([
+            "--user".into(),
+            unsafe { format!("{}:{}", libc::getuid(), libc::getgid()) }.into(),
+        ]);
+        argv.extend([
+            "-v".into(),
+            format!("{}:/input:ro", input.display()).into(),
+            "-v".into(),
+            format!("{}:/output", output.display()).into(),
+            self.image.clone().into(),
+        ]);
+        argv.extend(self.args.iter().map(OsString::from));
+        argv.extend(["-f", series_name, "-o", "/output", "/input"].map(OsString::from));
+        Ok(argv)
+    }
+}
+
+impl Converter for ContainerConverter {
+    fn name(&self) -> String {
+        format!("{} {}", self.runtime, self.image)
+    }
+
+    fn is_available(&self) -> bool {
+        std::process::Command::new(&self.runtime)
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false)
+    }
+
+    fn version(&self) -> Option<String> {
+        tool_version(&self.runtime, &["run", "--rm", &self.image, "--version"])
+    }
+
+    fn command(
+        &self,
+        dicom_dir: &Path,
+        output_dir: &Path,
+        series_name: &str,
+    ) -> Result<Vec<OsString>> {
+        self.command_named(dicom_dir, output_dir, series_name, &container_name())
+    }
+
+    fn convert<'a>(
+        &'a self,
+        dicom_dir: &'a Path,
+        output_dir: &'a Path,
+        series_name: &'a str,
+    ) -> BoxFuture<'a, Result<ConversionResult>> {
+        Box::pin(async move {
+            let start = std::time::Instant::now();
+            tokio::fs::create_dir_all(output_dir).await?;
+            let name = container_name();
+            let argv = self.command_named(dicom_dir, output_dir, series_name, &name)?;
+            // 逾時時 future 被丟棄，guard 會 kill 容器（只 kill client 容器仍會繼續執行）
+            let mut guard = ContainerKillGuard {
+                runtime: &self.runtime,
+                name,
+                armed: true,
+            };
+            let result = run_converter_command(&argv, output_dir, series_name, start).await;
+            guard.armed = false;
+            result
+        })
+    }
+}
+
+/// Numbered suffixes dcm2niix appends to `-f <name>` when one series yields several files:
+/// echo `_e2`, coil `_c3`, `_ROI1`, instance `_i00012`, trigger time `_t5000`, `_Eq_1`,
+/// `_Tilt_1`, `_Crop_1`.
+const NUMBERED_SUFFIXES: [&str; 8] = ["e", "c", "ROI", "i", "t", "Eq_", "Tilt_", "Crop_"];
+/// Fixed dcm2niix suffixes: phase, complex parts, derived maps, motion-corrected images.
+const NAMED_SUFFIXES: [&str; 8] = [
+    "ph",
+    "phMag",
+    "real",
+    "imaginary",
+    "ADC",
+    "trace",
+    "MoCo",
+    "fieldmaphz",
+];
+
+/// Whether `filename` is `series_name` followed by dcm2niix suffixes (possibly chained, as
+/// in `_e2_ph`) and an extension. Other names sharing the prefix, like `T1_2.nii.gz` for
+/// `T1`, belong to another series.
+fn is_series_output(filename: &str, series_name: &str) -> bool {
+    let Some(mut rest) = filename.strip_prefix(series_name) else {
+        return false;
+    };
+    while let Some(suffixed) = rest.strip_prefix('_') {
+        match strip_dcm2niix_suffix(suffixed) {
+            Some(after) => rest = after,
+            None => return false,
+        }
+    }
+    rest.starts_with('.')
+}
+
+/// `s` after one leading dcm2niix suffix, when it starts with one.
+fn strip_dcm2niix_suffix(s: &str) -> Option<&str> {
+    let ends = |after: &str| after.starts_with(['_', '.']);
+    if let Some(after) = NAMED_SUFFIXES
+        .iter()
+        .filter_map(|name| s.strip_prefix(name))
+        .find(|after| ends(after))
+    {
+        return Some(after);
+    }
+    NUMBERED_SUFFIXES.iter().find_map(|prefix| {
+        let after = s.strip_prefix(prefix)?;
+        let number = after.trim_start_matches(|c: char| c.is_ascii_digit());
+        (number.len() < after.len() && ends(number)).then_some(number)
+    })
+}
+
+/// Removes whatever a failed or killed dcm2niix run left for `series_name` in `dir`
+/// (half-written `.nii`, `.json`, `.bval`/`.bvec`), so a retry or a later run starts clean.
+async fn remove_series_output(dir: &Path, series_name: &str) {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        if is_series_output(&filename, series_name) {
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                warn!("Failed to remove partial output {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Removes the outputs of `series_name` in `dir` (`<series_name>.nii.gz`, `.json`, `.bval`,
+/// `_e2` and other dcm2niix suffixes) before the series is converted again; outputs of other
+/// names, `<series_name>_2` included, are kept. Each removal is written to the audit log
+/// under `rule` when one is given. Returns the removed files.
+pub async fn remove_nifti_outputs(
+    dir: &Path,
+    series_name: &str,
+    audit: Option<&AuditLog>,
+    rule: &str,
+) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(removed),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        if !is_series_output(&filename, series_name) {
+            continue;
+        }
+        let outcome = tokio::fs::remove_file(&path).await;
+        if let Some(audit) = audit {
+            audit.record_outcome(AuditOperation::Delete, &path, None, rule, &outcome)?;
+        }
+        outcome.with_context(|| format!("Failed to remove {}", path.display()))?;
+        removed.push(path);
+    }
+    removed.sort();
+    Ok(removed)
+}
+
+/// How each series is converted (`[conversion]` settings): the backend, an optional
+/// fallback backend, a per-run timeout, and retries.
+#[derive(Debug, Clone)]
+pub struct ConversionRunner {
+    pub backend: Arc<dyn Converter>,
+    /// Tried for series the backend still fails on after its retries.
+    pub fallback: Option<Arc<dyn Converter>>,
+    /// A run still going after this long is killed; `None` waits indefinitely.
+    pub timeout: Option<Duration>,
+    /// Further attempts after a run that failed, timed out, or could not start.
+    pub retries: u32,
+    /// Versions reported by the backend and fallback, probed once per run by
+    /// [`ConversionRunner::detect_versions`].
+    pub backend_version: Option<String>,
+    pub fallback_version: Option<String>,
+}
+
+impl ConversionRunner {
+    /// `backend` without fallback, timeout, or retries.
+    pub fn new(backend: Arc<dyn Converter>) -> Self {
+        Self {
+            backend,
+            fallback: None,
+            timeout: None,
+            retries: 0,
+            backend_version: None,
+            fallback_version: None,
+        }
+    }
+
+    /// Asks the backend and fallback for their versions, recorded with each result.
+    pub fn detect_versions(&mut self) {
+        self.backend_version = self.backend.version();
+        self.fallback_version = self.fallback.as_ref().and_then(|f| f.version());
+    }
+
+    /// Backend name and version for logs and reports, e.g. `dcm2niix v1.0.20240202`.
+    pub fn describe(&self) -> String {
+        describe(self.backend.as_ref(), self.backend_version.as_deref())
+    }
+
+    /// Whether the main backend can be started.
+    pub fn is_available(&self) -> bool {
+        self.backend.is_available()
+    }
+
+    /// Converts one series with the backend, then with the fallback backend if it failed
+    /// or produced no NIfTI; the error of a series both fail on names both tools.
+    pub async fn convert(
+        &self,
+        dicom_dir: &Path,
+        output_dir: &Path,
+        series_name: &str,
+    ) -> Result<ConversionResult> {
+        let outcome = self
+            .attempt(
+                self.backend.as_ref(),
+                self.backend_version.as_deref(),
+                dicom_dir,
+                output_dir,
+                series_name,
+            )
+            .await;
+        let Some(fallback) = self.fallback.as_ref().filter(|_| !succeeded(&outcome)) else {
+            return outcome;
+        };
+        let first_error = failure_text(&outcome).unwrap_or_else(|| "no NIfTI output".into());
+        warn!(
+            "{} failed for {}, trying {}",
+            self.backend.name(),
+            series_name,
+            fallback.name()
+        );
+        let outcome = self
+            .attempt(
+                fallback.as_ref(),
+                self.fallback_version.as_deref(),
+                dicom_dir,
+                output_dir,
+                series_name,
+            )
+            .await;
+        if succeeded(&outcome) {
+            return outcome;
+        }
+        let prefix = format!(
+            "{}: {}; {}",
+            self.backend.name(),
+            first_error.trim(),
+            fallback.name()
+        );
+        match outcome {
+            Ok(mut result) => {
+                let error = result
+                    .error
+                    .take()
+                    .unwrap_or_else(|| "no NIfTI output".into());
+                result.error = Some(format!("{}: {}", prefix, error.trim()));
+                Ok(result)
+            }
+            Err(e) => Err(anyhow!("{}: {}", prefix, e)),
+        }
+    }
+
+    /// Runs `converter`, killing runs that exceed the timeout and retrying failed ones.
+    /// Output of a failed attempt is removed; the final error says how many attempts were
+    /// made. A run that succeeds without producing NIfTI (e.g. SR DICOM) is not retried.
+    async fn attempt(
+        &self,
+        converter: &dyn Converter,
+        version: Option<&str>,
+        dicom_dir: &Path,
+        output_dir: &Path,
+        series_name: &str,
+    ) -> Result<ConversionResult> {
+        let attempts = self.retries + 1;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let run = converter.convert(dicom_dir, output_dir, series_name);
+            let mut outcome = match self.timeout {
+                Some(limit) => match tokio::time::timeout(limit, run).await {
+                    Ok(outcome) => outcome,
+                    // 逾時：丟棄 future 時 kill_on_drop 會終止子行程
+                    Err(_) => Err(anyhow!(
+                        "{} timed out after {}s",
+                        converter.name(),
+                        limit.as_secs()
+                    )),
+                },
+                None => run.await,
+            };
+            if let Ok(result) = &mut outcome {
+                result.converter = describe(converter, version);
+            }
+            if failure_text(&outcome).is_none() {
+                return outcome;
+            }
+            remove_series_output(output_dir, series_name).await;
+            if attempt >= attempts {
+                return with_attempt_count(outcome, attempt);
+            }
+            warn!(
+                "{} attempt {}/{} failed for {}, retrying",
+                converter.name(),
+                attempt,
+                attempts,
+                series_name
+            );
+        }
+    }
+
+    /// Converts several folders as one input, e.g. DWI0 + DWI1000 into a 4D NIfTI with
+    /// combined `.bval`/`.bvec` (dcm2niix stacks instances of one SeriesInstanceUID).
+    ///
+    /// The folders' DICOM files are hard-linked (copied across filesystems) into a hidden
+    /// staging folder in `output_dir`, which is removed afterwards. The run only succeeds
+    /// with exactly one `<series_name>` NIfTI whose `.bval` lists one b-value per volume and
+    /// whose volume count matches the staged DICOMs (see [`check_merged_output`]); otherwise
+    /// its outputs are removed and the result is a failure.
+    pub async fn convert_dirs(
+        &self,
+        dicom_dirs: &[PathBuf],
+        output_dir: &Path,
+        series_name: &str,
+    ) -> Result<ConversionResult> {
+        if let [single] = dicom_dirs {
+            return self.convert(single, output_dir, series_name).await;
+        }
+        let staging = output_dir.join(format!(".{}.staging", series_name));
+        let dirs = dicom_dirs.to_vec();
+        let target = staging.clone();
+        let staged_volumes = tokio::task::spawn_blocking(move || -> Result<Option<u64>> {
+            let files = stage_dicom_files(&dirs, &target)?;
+            Ok(staged_volume_count(&files))
         })
+        .await
+        .context("staging task failed")??;
+        let mut result = self.convert(&staging, output_dir, series_name).await;
+        if let Err(e) = tokio::fs::remove_dir_all(&staging).await {
+            warn!("Failed to remove {}: {}", staging.display(), e);
+        }
+        if let Ok(converted) = &mut result {
+            if converted.success {
+                let dir = output_dir.to_path_buf();
+                let name = series_name.to_string();
+                let nifti_files = converted.nifti_files.clone();
+                let check = tokio::task::spawn_blocking(move || {
+                    check_merged_output(&dir, &name, &nifti_files, staged_volumes)
+                })
+                .await
+                .context("output check task failed")?;
+                if let Err(e) = check {
+                    remove_series_output(output_dir, series_name).await;
+                    converted.success = false;
+                    converted.nifti_files.clear();
+                    converted.json_files.clear();
+                    converted.error = Some(format!("merged output rejected: {:#}", e));
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Checks the output of a merged conversion: exactly one `<series_name>.nii[.gz]`, a
+/// `<series_name>.bval` with one b-value per volume of it, and, when the staged DICOMs
+/// tell, as many volumes as were staged. dcm2niix writes suffixed files (`_e2`, `_ph`,
+/// ...) or drops the `.bval` when the shells do not stack into one series.
+fn check_merged_output(
+    output_dir: &Path,
+    series_name: &str,
+    nifti_files: &[PathBuf],
+    staged_volumes: Option<u64>,
+) -> Result<()> {
+    let is_merged = |path: &PathBuf| {
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        filename == format!("{}.nii", series_name) || filename == format!("{}.nii.gz", series_name)
+    };
+    let nifti = match nifti_files {
+        [single] if is_merged(single) => single,
+        _ => {
+            let names: Vec<String> = nifti_files
+                .iter()
+                .map(|p| {
+                    p.file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect();
+            return Err(anyhow!(
+                "expected one 4D NIfTI, got {} ({})",
+                names.len(),
+                names.join(", ")
+            ));
+        }
+    };
+    let bval_path = output_dir.join(format!("{}.bval", series_name));
+    let bvals = std::fs::read_to_string(&bval_path)
+        .with_context(|| format!("no b-values in {}", bval_path.display()))?
+        .split_whitespace()
+        .count() as u64;
+    let volumes = crate::nifti::inspect_nifti(nifti)?.volumes;
+    if bvals != volumes {
+        return Err(anyhow!("{} b-value(s) for {} volume(s)", bvals, volumes));
+    }
+    if let Some(staged) = staged_volumes {
+        if staged != volumes {
+            return Err(anyhow!(
+                "{} volume(s) converted, {} staged",
+                volumes,
+                staged
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Number of volumes in staged single-frame DICOMs: files per distinct ImagePositionPatient.
+/// `None` when it cannot be told (unreadable header, no position, multi-frame files, or
+/// slices not evenly repeated).
+fn staged_volume_count(files: &[PathBuf]) -> Option<u64> {
+    let mut positions = std::collections::HashSet::new();
+    for path in files {
+        let obj = dicom_object::OpenFileOptions::new()
+            .read_until(dicom_object::Tag(0x7FE0, 0x0010))
+            .open_file(path)
+            .ok()?;
+        let frames = obj
+            .element_by_name("NumberOfFrames")
+            .ok()
+            .and_then(|e| e.to_int::<u32>().ok())
+            .unwrap_or(1);
+        if frames > 1 {
+            return None;
+        }
+        let position = obj
+            .element_by_name("ImagePositionPatient")
+            .ok()?
+            .to_multi_float64()
+            .ok()?;
+        // 以 0.01 mm 取整，避免浮點誤差把同一位置算成兩個
+        let key: Vec<i64> = position
+            .iter()
+            .map(|v| (v * 100.0).round() as i64)
+            .collect();
+        positions.insert(key);
+    }
+    let slices = positions.len();
+    if slices == 0 || !files.len().is_multiple_of(slices) {
+        return None;
+    }
+    Some((files.len() / slices) as u64)
+}
+
+/// Links every `.dcm` of `dirs` into a fresh `staging` folder, prefixed with its folder name
+/// so equal file names from different folders do not clash. Returns the staged files.
+fn stage_dicom_files(dirs: &[PathBuf], staging: &Path) -> Result<Vec<PathBuf>> {
+    if staging.exists() {
+        std::fs::remove_dir_all(staging)?;
+    }
+    std::fs::create_dir_all(staging)?;
+    let mut staged = Vec::new();
+    for dir in dirs {
+        let prefix = dir.file_name().unwrap_or_default().to_string_lossy();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_dcm = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("dcm"));
+            if !is_dcm {
+                continue;
+            }
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let dest = staging.join(format!("{}_{}", prefix, name));
+            if std::fs::hard_link(&path, &dest).is_err() {
+                std::fs::copy(&path, &dest)
+                    .with_context(|| format!("Failed to stage {}", path.display()))?;
+            }
+            staged.push(dest);
+        }
+    }
+    Ok(staged)
+}
+
+/// `name version`, or just the name when the version is unknown.
+fn describe(converter: &dyn Converter, version: Option<&str>) -> String {
+    match version {
+        Some(version) => format!("{} {}", converter.name(), version),
+        None => converter.name(),
+    }
+}
+
+/// Whether a conversion produced NIfTI output.
+fn succeeded(outcome: &Result<ConversionResult>) -> bool {
+    matches!(outcome, Ok(result) if result.success)
+}
+
+/// Error of a failed run (non-zero exit, timeout, or start failure).
+fn failure_text(outcome: &Result<ConversionResult>) -> Option<String> {
+    match outcome {
+        Ok(result) => result.error.clone(),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+/// Appends the attempt count to the error of a conversion that was retried.
+fn with_attempt_count(
+    outcome: Result<ConversionResult>,
+    attempts: u32,
+) -> Result<ConversionResult> {
+    if attempts < 2 {
+        return outcome;
+    }
+    match outcome {
+        Ok(mut result) => {
+            result.error = result
+                .error
+                .map(|e| format!("{} (after {} attempts)", e.trim(), attempts));
+            Ok(result)
+        }
+        Err(e) => Err(anyhow!("{} (after {} attempts)", e, attempts)),
     }
 }
 
 /// Find NIfTI and JSON files matching the series name pattern in output directory.
 ///
 /// dcm2niix may append suffixes like `_e1`, `_ph` for multi-echo or phase images,
-/// so we search for files starting with the series name.
+/// so we search for files named after the series followed by such suffixes or `.`.
 async fn find_output_files(dir: &Path, series_name: &str) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
     let mut nifti_files = Vec::new();
     let mut json_files = Vec::new();
@@ -135,8 +979,7 @@ async fn find_output_files(dir: &Path, series_name: &str) -> Result<(Vec<PathBuf
         let path = entry.path();
         let filename = path.file_name().unwrap_or_default().to_string_lossy();
 
-        // Check if filename starts with series_name
-        if filename.starts_with(series_name) {
+        if is_series_output(&filename, series_name) {
             if filename.ends_with(".nii.gz") || filename.ends_with(".nii") {
                 nifti_files.push(path);
             } else if filename.ends_with(".json") {
@@ -166,8 +1009,169 @@ pub async fn find_nifti_files(dir: &Path) -> Result<Vec<PathBuf>> {
     Ok(nifti_files)
 }
 
+/// An output filename that had to be changed to avoid a collision.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputRename {
+    pub original: String,
+    pub renamed: String,
+}
+
+impl std::fmt::Display for OutputRename {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", self.original, self.renamed)
+    }
+}
+
+/// Two output names collide when they are equal ignoring case (case-insensitive
+/// filesystems) or when one is the other plus a `_` suffix, because `find_output_files`
+/// also claims dcm2niix's `_e2`/`_ph` style outputs for a series name.
+fn names_collide(a: &str, b: &str) -> bool {
+    let a = a.to_lowercase();
+    let b = b.to_lowercase();
+    a == b || b.starts_with(&format!("{}_", a)) || a.starts_with(&format!("{}_", b))
+}
+
+/// Resolve unique dcm2niix output names for the series of one study.
+///
+/// `names` are the series folder names in plan order. Colliding names get a suffix from
+/// `suffix_for(index)` (typically the SeriesNumber), falling back to the 1-based position.
+/// The first member of an exact-duplicate group keeps its name; otherwise the shorter name
+/// is the one renamed. Returns the final names plus the renames performed.
+pub fn resolve_output_names<F>(
+    names: &[String],
+    mut suffix_for: F,
+) -> (Vec<String>, Vec<OutputRename>)
+where
+    F: FnMut(usize) -> Option<String>,
+{
+    let mut outputs: Vec<String> = names.to_vec();
+    let mut renamed = vec![false; names.len()];
+
+    loop {
+        let victim = (0..outputs.len()).find(|&i| {
+            (0..outputs.len()).any(|j| {
+                j != i
+                    && names_collide(&outputs[i], &outputs[j])
+                    && (outputs[i].len() < outputs[j].len()
+                        || (outputs[i].len() == outputs[j].len() && i > j))
+            })
+        });
+        let Some(i) = victim else { break };
+
+        let suffix = if renamed[i] {
+            (i + 1).to_string()
+        } else {
+            suffix_for(i)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| (i + 1).to_string())
+        };
+        outputs[i] = format!("{}_{}", outputs[i], suffix);
+        renamed[i] = true;
+    }
+
+    let renames = names
+        .iter()
+        .zip(&outputs)
+        .filter(|(a, b)| a != b)
+        .map(|(a, b)| OutputRename {
+            original: a.clone(),
+            renamed: b.clone(),
+        })
+        .collect();
+    (outputs, renames)
+}
+
+/// Read the SeriesNumber of the first DICOM file in a series directory.
+///
+/// Used to disambiguate colliding output names; returns `None` when no file is readable.
+pub fn read_series_number(dir: &Path) -> Option<String> {
+    let entries = std::fs::read_dir(dir).ok()?;
+    let first = entries.filter_map(|e| e.ok()).map(|e| e.path()).find(|p| {
+        p.extension()
+            .map(|e| e.to_string_lossy().to_lowercase() == "dcm")
+            .unwrap_or(false)
+    })?;
+    let obj = dicom_object::open_file(&first).ok()?;
+    let elem = obj.element_by_name("SeriesNumber").ok()?;
+    let value = elem.to_str().ok()?.trim().to_string();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// One series queued on a [`ConversionPool`].
+#[derive(Debug, Clone)]
+pub struct ConversionJob {
+    /// Several folders are converted as one input (see [`Dcm2niix::convert_dirs`]).
+    pub dicom_dirs: Vec<PathBuf>,
+    pub output_dir: PathBuf,
+    pub series_name: String,
+}
+
+type QueuedJob = (ConversionJob, oneshot::Sender<Result<ConversionResult>>);
+
+/// Bounded pool of dcm2niix workers fed by a channel, so `download --convert` keeps
+/// fetching series while earlier ones convert.
+///
+/// `workers` runs happen at once (`[conversion] concurrency`), each holding a
+/// [`FileSlots::conversion`] permit; [`submit`](Self::submit) waits while `workers` further
+/// jobs are already queued, so conversion backlog slows downloads instead of piling up.
+/// Workers stop once the pool and all its clones are dropped.
+#[derive(Clone)]
+pub struct ConversionPool {
+    tx: mpsc::Sender<QueuedJob>,
+    runner: Arc<ConversionRunner>,
+}
+
+impl ConversionPool {
+    pub fn new(workers: usize, runner: ConversionRunner, file_slots: FileSlots) -> Self {
+        let workers = workers.max(1);
+        let (tx, rx) = mpsc::channel::<QueuedJob>(workers);
+        let rx = Arc::new(Mutex::new(rx));
+        let runner = Arc::new(runner);
+        for _ in 0..workers {
+            let rx = rx.clone();
+            let runner = runner.clone();
+            let file_slots = file_slots.clone();
+            tokio::spawn(async move {
+                loop {
+                    let next = rx.lock().await.recv().await;
+                    let Some((job, reply)) = next else { break };
+                    let _slot = file_slots.conversion().await;
+                    let result = runner
+                        .convert_dirs(&job.dicom_dirs, &job.output_dir, &job.series_name)
+                        .await;
+                    // 呼叫端不再等待結果時直接丟棄
+                    let _ = reply.send(result);
+                }
+            });
+        }
+        Self { tx, runner }
+    }
+
+    /// Whether the configured backend can be started.
+    pub fn is_available(&self) -> bool {
+        self.runner.is_available()
+    }
+
+    /// Queues a series; the receiver yields its conversion result.
+    pub async fn submit(&self, job: ConversionJob) -> oneshot::Receiver<Result<ConversionResult>> {
+        let (reply, rx) = oneshot::channel();
+        if let Err(mpsc::error::SendError((_, reply))) = self.tx.send((job, reply)).await {
+            let _ = reply.send(Err(anyhow!("conversion workers stopped")));
+        }
+        rx
+    }
+}
+
 /// Delete all DICOM files (.dcm) in a directory after successful conversion.
-pub async fn delete_dicom_files(dir: &Path) -> Result<usize> {
+///
+/// Each deletion is written to the audit log when one is given; if the log cannot be
+/// written, deletion stops with an error.
+pub async fn delete_dicom_files(dir: &Path, audit: Option<&AuditLog>) -> Result<usize> {
     let mut deleted_count = 0;
     let mut entries = tokio::fs::read_dir(dir).await?;
 
@@ -175,8 +1179,18 @@ pub async fn delete_dicom_files(dir: &Path) -> Result<usize> {
         let path = entry.path();
         if let Some(ext) = path.extension() {
             if ext.to_string_lossy().to_lowercase() == "dcm" {
-                if let Err(e) = tokio::fs::remove_file(&path).await {
-                    eprintln!("Warning: Failed to delete {}: {}", path.display(), e);
+                let removed = tokio::fs::remove_file(&path).await;
+                if let Some(audit) = audit {
+                    audit.record_outcome(
+                        AuditOperation::Delete,
+                        &path,
+                        None,
+                        "delete_dicom_after_conversion",
+                        &removed,
+                    )?;
+                }
+                if let Err(e) = removed {
+                    warn!("Failed to delete {}: {}", path.display(), e);
                 } else {
                     deleted_count += 1;
                 }
@@ -196,4 +1210,372 @@ mod tests {
         // Test with a non-existent path
         assert!(!check_dcm2niix_available("nonexistent_dcm2niix_binary_xyz"));
     }
+
+    #[tokio::test]
+    async fn test_conversion_pool_reports_each_job() {
+        let dir = std::env::temp_dir().join(format!("convpool_test_{}", std::process::id()));
+        let dcm2niix = ConversionRunner {
+            backend: Arc::new(Dcm2niixConverter {
+                path: "nonexistent_dcm2niix_binary_xyz".into(),
+                args: Vec::new(),
+            }),
+            fallback: None,
+            timeout: None,
+            retries: 0,
+            backend_version: None,
+            fallback_version: None,
+        };
+        let pool = ConversionPool::new(2, dcm2niix, FileSlots::default());
+        let mut pending = Vec::new();
+        for name in ["T1", "T2", "FLAIR"] {
+            let job = ConversionJob {
+                dicom_dirs: vec![dir.join(name)],
+                output_dir: dir.join("niix"),
+                series_name: name.to_string(),
+            };
+            pending.push(pool.submit(job).await);
+        }
+        for rx in pending {
+            // 找不到 dcm2niix：每個工作都回報錯誤，而非遺失
+            assert!(rx.await.unwrap().is_err());
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_remove_nifti_outputs_keeps_other_series() {
+        let dir = std::env::temp_dir().join(format!("reconvert_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "DWI0.nii.gz",
+            "DWI0.bval",
+            "DWI0.json",
+            "DWI0_e2.nii.gz",
+            "DWI0_2.nii.gz",
+            "DWI1000.nii",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let removed = remove_nifti_outputs(&dir, "DWI0", None, "test")
+            .await
+            .unwrap();
+        assert_eq!(
+            removed,
+            vec![
+                dir.join("DWI0.bval"),
+                dir.join("DWI0.json"),
+                dir.join("DWI0.nii.gz"),
+                dir.join("DWI0_e2.nii.gz")
+            ]
+        );
+        assert!(dir.join("DWI0_2.nii.gz").exists());
+        assert!(dir.join("DWI1000.nii").exists());
+        // 尚未轉檔的 study 沒有輸出資料夾
+        assert!(
+            remove_nifti_outputs(&dir.join("missing"), "T1", None, "test")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_convert_dirs_stages_dwi_shells_together() {
+        let dir = std::env::temp_dir().join(format!("dwi4d_test_{}", std::process::id()));
+        let out = dir.join("niix");
+        for shell in DWI_SHELL_FOLDERS {
+            std::fs::create_dir_all(dir.join(shell)).unwrap();
+            std::fs::write(dir.join(shell).join("a.dcm"), shell).unwrap();
+            std::fs::write(dir.join(shell).join("checksums.sha256"), "").unwrap();
+        }
+        std::fs::create_dir_all(&out).unwrap();
+        let nifti = dir.join("two_volumes.nii");
+        std::fs::write(
+            &nifti,
+            crate::nifti::tests::nifti1(&[2, 2, 1, 2], 2 * 2 * 2 * 2),
+        )
+        .unwrap();
+        // $1 = name, $3 = out, $4 = input folder
+        let lister = ConversionRunner {
+            backend: Arc::new(Dcm2niixConverter {
+                path: "sh".into(),
+                args: vec![
+                    "-c".into(),
+                    format!(
+                        r#"ls "$4" > "$3/$1.list"; cp "{}" "$3/$1.nii"; echo "0 1000" > "$3/$1.bval""#,
+                        nifti.display()
+                    ),
+                ],
+            }),
+            fallback: None,
+            timeout: None,
+            retries: 0,
+            backend_version: None,
+            fallback_version: None,
+        };
+        let dirs: Vec<PathBuf> = DWI_SHELL_FOLDERS.iter().map(|s| dir.join(s)).collect();
+        let result = lister.convert_dirs(&dirs, &out, DWI_4D_NAME).await.unwrap();
+        assert!(result.success);
+        let staged = std::fs::read_to_string(out.join("DWI_4D.list")).unwrap();
+        assert_eq!(
+            staged.lines().collect::<Vec<_>>(),
+            ["DWI0_a.dcm", "DWI1000_a.dcm"]
+        );
+        assert!(!out.join(".DWI_4D.staging").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_merged_output_needs_one_nifti_matching_bvals() {
+        let dir = std::env::temp_dir().join(format!("dwi4d_check_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let nifti = dir.join("DWI_4D.nii");
+        std::fs::write(
+            &nifti,
+            crate::nifti::tests::nifti1(&[2, 2, 1, 3], 2 * 2 * 3 * 2),
+        )
+        .unwrap();
+        let bval = dir.join("DWI_4D.bval");
+        std::fs::write(&bval, "0 1000 1000\n").unwrap();
+        let single = [nifti.clone()];
+        assert!(check_merged_output(&dir, DWI_4D_NAME, &single, Some(3)).is_ok());
+        assert!(check_merged_output(&dir, DWI_4D_NAME, &single, None).is_ok());
+
+        // 與暫存的 volume 數不符
+        let err = check_merged_output(&dir, DWI_4D_NAME, &single, Some(4)).unwrap_err();
+        assert!(err.to_string().contains("3 volume(s) converted, 4 staged"));
+        // dcm2niix 另外拆出一個檔案
+        let split = [nifti.clone(), dir.join("DWI_4D_e2.nii")];
+        let err = check_merged_output(&dir, DWI_4D_NAME, &split, Some(3)).unwrap_err();
+        assert!(err.to_string().contains("expected one 4D NIfTI, got 2"));
+        // b-value 數與 volume 數不符
+        std::fs::write(&bval, "0 1000\n").unwrap();
+        let err = check_merged_output(&dir, DWI_4D_NAME, &single, None).unwrap_err();
+        assert!(err.to_string().contains("2 b-value(s) for 3 volume(s)"));
+        // 沒有 .bval
+        std::fs::remove_file(&bval).unwrap();
+        assert!(check_merged_output(&dir, DWI_4D_NAME, &single, None).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_dcm2niix_timeout_retry_and_cleanup() {
+        let dir = std::env::temp_dir().join(format!("dcm2niix_retry_test_{}", std::process::id()));
+        let out = dir.join("niix");
+        // sh -c <script> -f <name> -o <out> <dicom>: $1 = name, $3 = out
+        let failing = ConversionRunner {
+            backend: Arc::new(Dcm2niixConverter {
+                path: "sh".into(),
+                args: vec![
+                    "-c".into(),
+                    r#"touch "$3/$1.nii" "$3/T1W_extra.nii"; exit 1"#.into(),
+                ],
+            }),
+            fallback: None,
+            timeout: None,
+            retries: 1,
+            backend_version: None,
+            fallback_version: None,
+        };
+        let result = failing.convert(&dir, &out, "T1").await.unwrap();
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("after 2 attempts"));
+        assert!(!out.join("T1.nii").exists());
+        assert!(out.join("T1W_extra.nii").exists());
+
+        let hung = ConversionRunner {
+            backend: Arc::new(Dcm2niixConverter {
+                path: "sh".into(),
+                args: vec!["-c".into(), "sleep 30".into()],
+            }),
+            fallback: None,
+            timeout: Some(Duration::from_millis(200)),
+            retries: 0,
+            backend_version: None,
+            fallback_version: None,
+        };
+        let err = hung.convert(&dir, &out, "T1").await.unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_container_is_killed_on_timeout() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("container_kill_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("runtime.log");
+        // 假的 docker：記錄參數，run 時停住直到被 kill
+        let runtime = dir.join("docker");
+        std::fs::write(
+            &runtime,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> {}\n[ \"$1\" = run ] && sleep 30\nexit 0\n",
+                log.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&runtime, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let runner = ConversionRunner {
+            timeout: Some(Duration::from_millis(300)),
+            ..ConversionRunner::new(Arc::new(ContainerConverter {
+                runtime: runtime.display().to_string(),
+                image: "dcm2niix:pinned".into(),
+                args: Vec::new(),
+            }))
+        };
+        let err = runner
+            .convert(&dir, &dir.join("niix"), "T1")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+
+        let mut lines = Vec::new();
+        for _ in 0..50 {
+            lines = std::fs::read_to_string(&log)
+                .unwrap_or_default()
+                .lines()
+                .map(String::from)
+                .collect();
+            if lines.len() >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let name = lines[0]
+            .strip_prefix("run --rm --init --name ")
+            .and_then(|rest| rest.split(' ').next())
+            .expect("run line names the container");
+        assert!(name.starts_with("dicom_download_cli-"));
+        assert_eq!(lines[1], format!("kill {}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_fallback_backend_converts_rejected_series() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("converter_fallback_test_{}", std::process::id()));
+        let out = dir.join("niix");
+        std::fs::create_dir_all(&dir).unwrap();
+        // <script> convert --input <dicom> --output-img <file>: $5 = output file
+        let script = dir.join("plastimatch");
+        std::fs::write(&script, "#!/bin/sh\ntouch \"$5\"\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let rejecting = Arc::new(Dcm2niixConverter {
+            path: "sh".into(),
+            args: vec!["-c".into(), "echo unsupported >&2; exit 1".into()],
+        });
+
+        let runner = ConversionRunner {
+            fallback: Some(Arc::new(PlastimatchConverter {
+                path: script.display().to_string(),
+                args: Vec::new(),
+            })),
+            ..ConversionRunner::new(rejecting.clone())
+        };
+        let result = runner.convert(&dir, &out, "T1").await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.nifti_files, vec![out.join("T1.nii.gz")]);
+        assert_eq!(result.converter, "plastimatch");
+        assert!(result.command.ends_with(&format!(
+            "convert --input {} --output-img {}",
+            dir.display(),
+            out.join("T1.nii.gz").display()
+        )));
+
+        let both_fail = ConversionRunner {
+            fallback: Some(rejecting.clone()),
+            ..ConversionRunner::new(rejecting)
+        };
+        let error = both_fail
+            .convert(&dir, &out, "T2")
+            .await
+            .unwrap()
+            .error
+            .unwrap();
+        assert_eq!(error, "dcm2niix: unsupported; dcm2niix: unsupported");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_series_output_matches_dcm2niix_suffixes_only() {
+        for name in [
+            "T1.nii.gz",
+            "T1.json",
+            "T1_e2.nii.gz",
+            "T1_e2_ph.json",
+            "T1_ph.nii",
+            "T1_phMag.nii",
+            "T1_c12.nii",
+            "T1_ROI1.nii.gz",
+            "T1_i00012.nii",
+            "T1_Eq_1.nii.gz",
+            "T1_Tilt_1.nii",
+        ] {
+            assert!(is_series_output(name, "T1"), "{}", name);
+        }
+        // 其他 series 的輸出（例如名稱衝突時加上編號者）不可誤刪
+        for name in [
+            "T1_2.nii.gz",
+            "T1_2_e2.nii.gz",
+            "T1_FLAIR.nii",
+            "T1_e.nii",
+            "T1_ph2.nii",
+            "T1FLAIR.nii",
+            "T1",
+        ] {
+            assert!(!is_series_output(name, "T1"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_has_nifti_output_ignores_suffixed_names() {
+        let dir = std::env::temp_dir().join(format!("nifti_output_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("ADC_7.nii.gz"), b"").unwrap();
+        std::fs::write(dir.join("T1.nii"), b"").unwrap();
+        assert!(!has_nifti_output(&dir, "ADC"));
+        assert!(has_nifti_output(&dir, "ADC_7"));
+        assert!(has_nifti_output(&dir, "T1"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_command_line_quotes_special_arguments() {
+        let argv: Vec<OsString> = ["dcm2niix", "-f", "T1 AX", "-o", "it's"]
+            .map(OsString::from)
+            .to_vec();
+        assert_eq!(command_line(&argv), r#"dcm2niix -f 'T1 AX' -o 'it'\''s'"#);
+    }
+
+    #[test]
+    fn test_resolve_output_names_no_collision() {
+        let names = vec![
+            "T1".to_string(),
+            "T1FLAIR_AXI".to_string(),
+            "DWI0".to_string(),
+        ];
+        let (outputs, renames) = resolve_output_names(&names, |_| None);
+        assert_eq!(outputs, names);
+        assert!(renames.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_output_names_case_and_suffix_collisions() {
+        let names = vec!["ADC".to_string(), "adc".to_string(), "ADC_3".to_string()];
+        let numbers = [Some("7".to_string()), Some("8".to_string()), None];
+        let (outputs, renames) = resolve_output_names(&names, |i| numbers[i].clone());
+        assert_eq!(outputs, vec!["ADC_7", "adc_8", "ADC_3"]);
+        assert_eq!(renames.len(), 2);
+        assert_eq!(renames[0].to_string(), "ADC -> ADC_7");
+    }
 }
@@ -0,0 +1,87 @@
+//! Password resolution for Orthanc Basic auth.
+//!
+//! Keeps plaintext passwords out of the TOML file: when a username is configured without
+//! a password, the OS keyring is consulted (with `--use-keyring`) and otherwise the user
+//! is prompted on the TTY with hidden input.
+
+use anyhow::{anyhow, Context, Result};
+use std::io::IsTerminal;
+use tracing::warn;
+
+/// Service name under which credentials are stored in the OS keyring.
+pub const KEYRING_SERVICE: &str = "dicom_download_cli";
+
+/// Keyring account for a user on a given Orthanc, so different servers do not clash.
+pub fn keyring_account(username: &str, url: &str) -> String {
+    format!("{}@{}", username, url.trim_end_matches('/'))
+}
+
+fn keyring_entry(username: &str, url: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, &keyring_account(username, url))
+        .context("Failed to open OS keyring entry")
+}
+
+/// Reads a cached password from the OS keyring; `Ok(None)` when nothing is stored.
+pub fn keyring_get(username: &str, url: &str) -> Result<Option<String>> {
+    match keyring_entry(username, url)?.get_password() {
+        Ok(p) => Ok(Some(p)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(anyhow!("Failed to read OS keyring: {}", e)),
+    }
+}
+
+/// Stores a password in the OS keyring.
+pub fn keyring_set(username: &str, url: &str, password: &str) -> Result<()> {
+    keyring_entry(username, url)?
+        .set_password(password)
+        .map_err(|e| anyhow!("Failed to write OS keyring: {}", e))
+}
+
+/// Returns the password to use for `username` on `url`.
+///
+/// Order: explicit password (CLI/env/TOML) → OS keyring (when `use_keyring`) → hidden TTY
+/// prompt. A prompted password is cached in the keyring when `use_keyring` is set. Without
+/// a TTY and without a stored password, `Ok(None)` is returned and requests go unauthenticated.
+pub fn resolve_password(
+    url: &str,
+    username: Option<&str>,
+    password: Option<String>,
+    use_keyring: bool,
+) -> Result<Option<String>> {
+    let Some(username) = username else {
+        return Ok(password);
+    };
+    if password.is_some() {
+        return Ok(password);
+    }
+
+    if use_keyring {
+        match keyring_get(username, url) {
+            Ok(Some(p)) => return Ok(Some(p)),
+            Ok(None) => {}
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    if !std::io::stdin().is_terminal() {
+        warn!(
+            "username '{}' configured without a password and no TTY to prompt on",
+            username
+        );
+        return Ok(None);
+    }
+
+    let prompted =
+        rpassword::prompt_password(format!("Password for {}: ", keyring_account(username, url)))
+            .context("Failed to read password from TTY")?;
+    if prompted.is_empty() {
+        return Ok(None);
+    }
+
+    if use_keyring {
+        if let Err(e) = keyring_set(username, url, &prompted) {
+            warn!("{}", e);
+        }
+    }
+    Ok(Some(prompted))
+}
@@ -0,0 +1,493 @@
+//! DICOMDIR generation for downloaded studies (`download --dicomdir`).
+//!
+//! PS3.10 limits Referenced File IDs to components of at most 8 characters from `A-Z0-9_`,
+//! which the `dicom/<study>/<series>/<uuid>.dcm` layout does not satisfy. Each study is
+//! therefore mirrored into `media/<study>/DICOM/Sxxxx/Ixxxxx` (hard links, falling back to
+//! copies) next to a `DICOMDIR` indexing PATIENT → STUDY → SERIES → IMAGE records. The study
+//! folder can be burned to media or imported as-is.
+//!
+//! The file is encoded here directly (Explicit VR Little Endian with explicit lengths) because
+//! directory records carry byte offsets into the file that have to be laid out up front.
+
+use anyhow::{Context, Result};
+use dicom_object::{open_file, Tag};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::warn;
+
+use crate::downloader::PARTIAL_SUFFIX;
+
+/// Name of the index file at the root of each study's media folder.
+pub const DICOMDIR_FILE: &str = "DICOMDIR";
+/// Top-level folder holding the mirrored instance files.
+pub const MEDIA_DIR: &str = "DICOM";
+
+/// Media Storage Directory Storage SOP Class.
+const MEDIA_STORAGE_DIRECTORY_SOP_CLASS: &str = "1.2.840.10008.1.3.10";
+/// Explicit VR Little Endian.
+const EXPLICIT_VR_LE: &str = "1.2.840.10008.1.2.1";
+const IMPLEMENTATION_CLASS_UID: &str = "2.25.221119730452584566091862087364447705905";
+const IMPLEMENTATION_VERSION_NAME: &str = "DICOMDLCLI";
+
+/// SOP class prefixes that need non-IMAGE record types (SR, encapsulated documents); skipped.
+const NON_IMAGE_SOP_PREFIXES: &[&str] = &[
+    "1.2.840.10008.5.1.4.1.1.88.",
+    "1.2.840.10008.5.1.4.1.1.104.",
+];
+
+/// One IMAGE record.
+#[derive(Clone, Debug, Default)]
+pub struct ImageEntry {
+    /// Referenced File ID components relative to the DICOMDIR (e.g. `["DICOM", "S0001", "I00001"]`).
+    pub file_id: Vec<String>,
+    pub sop_class_uid: String,
+    pub sop_instance_uid: String,
+    pub transfer_syntax_uid: String,
+    pub instance_number: String,
+}
+
+/// One SERIES record and its images.
+#[derive(Clone, Debug, Default)]
+pub struct SeriesEntry {
+    pub modality: String,
+    pub series_instance_uid: String,
+    pub series_number: String,
+    pub images: Vec<ImageEntry>,
+}
+
+/// PATIENT + STUDY records of one study folder.
+#[derive(Clone, Debug, Default)]
+pub struct StudyEntry {
+    pub patient_id: String,
+    pub patient_name: String,
+    pub study_instance_uid: String,
+    pub study_date: String,
+    pub study_time: String,
+    pub study_description: String,
+    pub study_id: String,
+    pub accession_number: String,
+    pub series: Vec<SeriesEntry>,
+}
+
+/// A data element ready for Explicit VR Little Endian encoding.
+struct Element {
+    tag: (u16, u16),
+    vr: [u8; 2],
+    value: Vec<u8>,
+}
+
+impl Element {
+    /// Text value padded to even length (UI with NUL, everything else with a space).
+    fn text(tag: (u16, u16), vr: &[u8; 2], value: &str) -> Self {
+        let mut bytes = value.as_bytes().to_vec();
+        if bytes.len() % 2 == 1 {
+            bytes.push(if vr == b"UI" { 0 } else { b' ' });
+        }
+        Self {
+            tag,
+            vr: *vr,
+            value: bytes,
+        }
+    }
+
+    fn ul(tag: (u16, u16), value: u32) -> Self {
+        Self {
+            tag,
+            vr: *b"UL",
+            value: value.to_le_bytes().to_vec(),
+        }
+    }
+
+    fn us(tag: (u16, u16), value: u16) -> Self {
+        Self {
+            tag,
+            vr: *b"US",
+            value: value.to_le_bytes().to_vec(),
+        }
+    }
+
+    fn has_long_header(&self) -> bool {
+        matches!(&self.vr, b"OB" | b"OW" | b"SQ" | b"UN" | b"UT")
+    }
+
+    fn encoded_len(&self) -> usize {
+        (if self.has_long_header() { 12 } else { 8 }) + self.value.len()
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.tag.0.to_le_bytes());
+        out.extend_from_slice(&self.tag.1.to_le_bytes());
+        out.extend_from_slice(&self.vr);
+        if self.has_long_header() {
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(&(self.value.len() as u32).to_le_bytes());
+        } else {
+            out.extend_from_slice(&(self.value.len() as u16).to_le_bytes());
+        }
+        out.extend_from_slice(&self.value);
+    }
+}
+
+/// A directory record before offsets are known; `next`/`child` index into the record list.
+struct Record {
+    kind: &'static str,
+    keys: Vec<Element>,
+    next: Option<usize>,
+    child: Option<usize>,
+}
+
+impl Record {
+    /// Encodes the record as a sequence item, resolving links through `positions`.
+    fn encode(&self, positions: &[u32]) -> Vec<u8> {
+        let link = |i: Option<usize>| i.map(|i| positions[i]).unwrap_or(0);
+        let mut body = Vec::new();
+        Element::ul((0x0004, 0x1400), link(self.next)).encode(&mut body);
+        Element::us((0x0004, 0x1410), 0xFFFF).encode(&mut body);
+        Element::ul((0x0004, 0x1420), link(self.child)).encode(&mut body);
+        Element::text((0x0004, 0x1430), b"CS", self.kind).encode(&mut body);
+        for key in &self.keys {
+            key.encode(&mut body);
+        }
+
+        let mut item = Vec::with_capacity(body.len() + 8);
+        item.extend_from_slice(&0xFFFEu16.to_le_bytes());
+        item.extend_from_slice(&0xE000u16.to_le_bytes());
+        item.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        item.extend_from_slice(&body);
+        item
+    }
+}
+
+/// Flattens the study into directory records in file order (depth first).
+fn build_records(study: &StudyEntry) -> Vec<Record> {
+    let mut records = vec![
+        Record {
+            kind: "PATIENT",
+            keys: vec![
+                Element::text((0x0010, 0x0010), b"PN", &study.patient_name),
+                Element::text((0x0010, 0x0020), b"LO", &study.patient_id),
+            ],
+            next: None,
+            child: Some(1),
+        },
+        Record {
+            kind: "STUDY",
+            keys: vec![
+                Element::text((0x0008, 0x0020), b"DA", &study.study_date),
+                Element::text((0x0008, 0x0030), b"TM", &study.study_time),
+                Element::text((0x0008, 0x0050), b"SH", &study.accession_number),
+                Element::text((0x0008, 0x1030), b"LO", &study.study_description),
+                Element::text((0x0020, 0x000D), b"UI", &study.study_instance_uid),
+                Element::text((0x0020, 0x0010), b"SH", &study.study_id),
+            ],
+            next: None,
+            child: None,
+        },
+    ];
+
+    let mut prev_series: Option<usize> = None;
+    for series in study.series.iter().filter(|s| !s.images.is_empty()) {
+        let idx = records.len();
+        match prev_series {
+            Some(p) => records[p].next = Some(idx),
+            None => records[1].child = Some(idx),
+        }
+        prev_series = Some(idx);
+        records.push(Record {
+            kind: "SERIES",
+            keys: vec![
+                Element::text((0x0008, 0x0060), b"CS", &series.modality),
+                Element::text((0x0020, 0x000E), b"UI", &series.series_instance_uid),
+                Element::text((0x0020, 0x0011), b"IS", &series.series_number),
+            ],
+            next: None,
+            child: Some(idx + 1),
+        });
+
+        let count = series.images.len();
+        for (n, image) in series.images.iter().enumerate() {
+            let image_idx = records.len();
+            records.push(Record {
+                kind: "IMAGE",
+                keys: vec![
+                    Element::text((0x0004, 0x1500), b"CS", &image.file_id.join("\\")),
+                    Element::text((0x0004, 0x1510), b"UI", &image.sop_class_uid),
+                    Element::text((0x0004, 0x1511), b"UI", &image.sop_instance_uid),
+                    Element::text((0x0004, 0x1512), b"UI", &image.transfer_syntax_uid),
+                    Element::text((0x0020, 0x0013), b"IS", &image.instance_number),
+                ],
+                next: (n + 1 < count).then_some(image_idx + 1),
+                child: None,
+            });
+        }
+    }
+    records
+}
+
+/// Encodes a complete DICOMDIR file (preamble, file meta, directory record sequence).
+pub fn encode_dicomdir(study: &StudyEntry, fileset_uid: &str) -> Vec<u8> {
+    let mut meta_body = Vec::new();
+    for element in [
+        Element {
+            tag: (0x0002, 0x0001),
+            vr: *b"OB",
+            value: vec![0, 1],
+        },
+        Element::text((0x0002, 0x0002), b"UI", MEDIA_STORAGE_DIRECTORY_SOP_CLASS),
+        Element::text((0x0002, 0x0003), b"UI", fileset_uid),
+        Element::text((0x0002, 0x0010), b"UI", EXPLICIT_VR_LE),
+        Element::text((0x0002, 0x0012), b"UI", IMPLEMENTATION_CLASS_UID),
+        Element::text((0x0002, 0x0013), b"SH", IMPLEMENTATION_VERSION_NAME),
+    ] {
+        element.encode(&mut meta_body);
+    }
+
+    let mut out = vec![0u8; 128];
+    out.extend_from_slice(b"DICM");
+    Element::ul((0x0002, 0x0000), meta_body.len() as u32).encode(&mut out);
+    out.extend_from_slice(&meta_body);
+
+    let records = build_records(study);
+    // Offsets count from the first byte of the file; sizes do not depend on offset values
+    let fileset_id = Element::text((0x0004, 0x1130), b"CS", "");
+    let header_len = fileset_id.encoded_len() + 12 + 12 + 10 + 12;
+    let mut positions = Vec::with_capacity(records.len());
+    let mut pos = (out.len() + header_len) as u32;
+    let no_links = vec![0u32; records.len()];
+    for record in &records {
+        positions.push(pos);
+        pos += record.encode(&no_links).len() as u32;
+    }
+    let items: Vec<u8> = records.iter().flat_map(|r| r.encode(&positions)).collect();
+
+    fileset_id.encode(&mut out);
+    Element::ul((0x0004, 0x1200), positions[0]).encode(&mut out);
+    Element::ul((0x0004, 0x1202), positions[0]).encode(&mut out);
+    Element::us((0x0004, 0x1212), 0).encode(&mut out);
+    Element {
+        tag: (0x0004, 0x1220),
+        vr: *b"SQ",
+        value: items,
+    }
+    .encode(&mut out);
+    out
+}
+
+/// Random `2.25.<uuid>` UID for the file-set.
+fn generate_uid() -> String {
+    format!("2.25.{}", rand::random::<u128>() >> 1)
+}
+
+/// Instance tags read from one downloaded file.
+struct InstanceTags {
+    tags: BTreeMap<Tag, String>,
+    transfer_syntax: String,
+    sop_class: String,
+}
+
+fn read_instance_tags(path: &Path) -> Result<InstanceTags> {
+    let obj = open_file(path).context("Failed to open DICOM file")?;
+    let clean = |s: &str| {
+        s.trim_matches(|c: char| c == '\0' || c.is_whitespace())
+            .to_string()
+    };
+    let mut tags = BTreeMap::new();
+    for tag in [
+        Tag(0x0008, 0x0018),
+        Tag(0x0008, 0x0020),
+        Tag(0x0008, 0x0030),
+        Tag(0x0008, 0x0050),
+        Tag(0x0008, 0x0060),
+        Tag(0x0008, 0x1030),
+        Tag(0x0010, 0x0010),
+        Tag(0x0010, 0x0020),
+        Tag(0x0020, 0x000D),
+        Tag(0x0020, 0x000E),
+        Tag(0x0020, 0x0010),
+        Tag(0x0020, 0x0011),
+        Tag(0x0020, 0x0013),
+    ] {
+        if let Some(value) = obj.element(tag).ok().and_then(|e| e.to_str().ok()) {
+            tags.insert(tag, clean(&value));
+        }
+    }
+    Ok(InstanceTags {
+        tags,
+        transfer_syntax: clean(obj.meta().transfer_syntax()),
+        sop_class: clean(obj.meta().media_storage_sop_class_uid()),
+    })
+}
+
+/// Mirrors `dicom_study_dir` into `media_study_dir` and writes its DICOMDIR.
+///
+/// Any previous `DICOM/` mirror and DICOMDIR in `media_study_dir` are replaced. Returns the
+/// number of IMAGE records written.
+pub fn write_study_dicomdir(dicom_study_dir: &Path, media_study_dir: &Path) -> Result<usize> {
+    let mirror_root = media_study_dir.join(MEDIA_DIR);
+    if mirror_root.exists() {
+        std::fs::remove_dir_all(&mirror_root)
+            .with_context(|| format!("Failed to clear {}", mirror_root.display()))?;
+    }
+
+    // Incomplete `.partial` series folders are left out of the media
+    let mut series_dirs: Vec<_> = std::fs::read_dir(dicom_study_dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_dir() && !p.to_string_lossy().ends_with(PARTIAL_SUFFIX))
+        .collect();
+    series_dirs.sort();
+
+    let mut study = StudyEntry::default();
+    // Series keyed by SeriesInstanceUID so split folders (DWI0/DWI1000) share one record
+    let mut series_by_uid: BTreeMap<String, (usize, SeriesEntry)> = BTreeMap::new();
+    let mut image_count = 0usize;
+
+    for series_dir in &series_dirs {
+        let mut files: Vec<_> = std::fs::read_dir(series_dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension()
+                    .map(|e| e.to_string_lossy().to_lowercase() == "dcm")
+                    .unwrap_or(false)
+            })
+            .collect();
+        files.sort();
+
+        for file in files {
+            let inst = match read_instance_tags(&file) {
+                Ok(t) => t,
+                Err(e) => {
+                    warn!("{}: {}", file.display(), e);
+                    continue;
+                }
+            };
+            if NON_IMAGE_SOP_PREFIXES
+                .iter()
+                .any(|p| inst.sop_class.starts_with(p))
+            {
+                continue;
+            }
+            let get = |g: u16, e: u16| inst.tags.get(&Tag(g, e)).cloned().unwrap_or_default();
+            if study.study_instance_uid.is_empty() {
+                study.patient_name = get(0x0010, 0x0010);
+                study.patient_id = get(0x0010, 0x0020);
+                study.study_instance_uid = get(0x0020, 0x000D);
+                study.study_date = get(0x0008, 0x0020);
+                study.study_time = get(0x0008, 0x0030);
+                study.study_description = get(0x0008, 0x1030);
+                study.study_id = get(0x0020, 0x0010);
+                study.accession_number = get(0x0008, 0x0050);
+            }
+
+            let next_index = series_by_uid.len();
+            let (series_index, series) =
+                series_by_uid.entry(get(0x0020, 0x000E)).or_insert_with(|| {
+                    (
+                        next_index,
+                        SeriesEntry {
+                            modality: get(0x0008, 0x0060),
+                            series_instance_uid: get(0x0020, 0x000E),
+                            series_number: get(0x0020, 0x0011),
+                            images: Vec::new(),
+                        },
+                    )
+                });
+
+            let series_component = format!("S{:04}", *series_index + 1);
+            let image_component = format!("I{:05}", series.images.len() + 1);
+            let series_mirror = mirror_root.join(&series_component);
+            std::fs::create_dir_all(&series_mirror)?;
+            let target = series_mirror.join(&image_component);
+            if std::fs::hard_link(&file, &target).is_err() {
+                std::fs::copy(&file, &target)
+                    .with_context(|| format!("Failed to copy {}", file.display()))?;
+            }
+
+            series.images.push(ImageEntry {
+                file_id: vec![MEDIA_DIR.to_string(), series_component, image_component],
+                sop_class_uid: inst.sop_class,
+                sop_instance_uid: get(0x0008, 0x0018),
+                transfer_syntax_uid: inst.transfer_syntax,
+                instance_number: get(0x0020, 0x0013),
+            });
+            image_count += 1;
+        }
+    }
+
+    let mut series: Vec<(usize, SeriesEntry)> = series_by_uid.into_values().collect();
+    series.sort_by_key(|(i, _)| *i);
+    study.series = series.into_iter().map(|(_, s)| s).collect();
+
+    std::fs::create_dir_all(media_study_dir)?;
+    std::fs::write(
+        media_study_dir.join(DICOMDIR_FILE),
+        encode_dicomdir(&study, &generate_uid()),
+    )
+    .context("Failed to write DICOMDIR")?;
+    Ok(image_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(buf: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+    }
+
+    /// Offset of the value of the first `tag` element at or after `from` (short VR header).
+    fn find_value(buf: &[u8], from: usize, tag: (u16, u16)) -> usize {
+        let mut needle = tag.0.to_le_bytes().to_vec();
+        needle.extend_from_slice(&tag.1.to_le_bytes());
+        from + buf[from..]
+            .windows(4)
+            .position(|w| w == needle.as_slice())
+            .unwrap()
+            + 8
+    }
+
+    #[test]
+    fn test_encode_dicomdir_offsets() {
+        let image = |n: &str| ImageEntry {
+            file_id: vec!["DICOM".into(), "S0001".into(), format!("I0000{}", n)],
+            sop_class_uid: "1.2.840.10008.5.1.4.1.1.4".into(),
+            sop_instance_uid: format!("1.2.3.4.{}", n),
+            transfer_syntax_uid: EXPLICIT_VR_LE.into(),
+            instance_number: n.into(),
+        };
+        let study = StudyEntry {
+            patient_id: "P001".into(),
+            study_instance_uid: "1.2.3".into(),
+            series: vec![SeriesEntry {
+                modality: "MR".into(),
+                series_instance_uid: "1.2.3.4".into(),
+                series_number: "5".into(),
+                images: vec![image("1"), image("2")],
+            }],
+            ..Default::default()
+        };
+        let buf = encode_dicomdir(&study, "2.25.1");
+        assert_eq!(&buf[128..132], b"DICM");
+
+        // Root record offset points at an item whose record type is PATIENT
+        let root = read_u32(&buf, find_value(&buf, 132, (0x0004, 0x1200))) as usize;
+        assert_eq!(&buf[root..root + 4], &[0xFE, 0xFF, 0x00, 0xE0]);
+        let kind = find_value(&buf, root, (0x0004, 0x1430));
+        assert_eq!(&buf[kind..kind + 7], b"PATIENT");
+
+        // PATIENT → STUDY via the lower-level offset
+        let study_at = read_u32(&buf, find_value(&buf, root, (0x0004, 0x1420))) as usize;
+        let kind = find_value(&buf, study_at, (0x0004, 0x1430));
+        assert_eq!(&buf[kind..kind + 6], b"STUDY ");
+
+        // First IMAGE links to the second; the last IMAGE has no next record
+        let series_at = read_u32(&buf, find_value(&buf, study_at, (0x0004, 0x1420))) as usize;
+        let image1 = read_u32(&buf, find_value(&buf, series_at, (0x0004, 0x1420))) as usize;
+        let image2 = read_u32(&buf, find_value(&buf, image1, (0x0004, 0x1400))) as usize;
+        assert!(image2 > image1);
+        assert_eq!(
+            read_u32(&buf, find_value(&buf, image2, (0x0004, 0x1400))),
+            0
+        );
+        assert_eq!(image2 + 8 + read_u32(&buf, image2 + 4) as usize, buf.len());
+    }
+}
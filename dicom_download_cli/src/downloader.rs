@@ -0,0 +1,2538 @@
+//! Direct download workflow: plan series folders from Orthanc and fetch instances to disk.
+//!
+//! Mirrors `scripts/download_dicom_async.py`: each accession is planned into study/series
+//! folders, instances are downloaded with bounded concurrency and retries, and series are
+//! optionally converted to NIfTI afterwards.
+
+use anyhow::{anyhow, Result};
+use futures::stream::{self, StreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::audit::AuditLog;
+use crate::checksum::{write_manifest, MANIFEST_FILE};
+use crate::classify::LocalClassifier;
+use crate::client::{
+    analyzer_bypass_reason, fallback_type_from_dicom, parse_dicom_study_info, part_path,
+    DicomStudyInfo, DownloadPlan, OrthancClient, PrefetchedInstance, SeriesDownloadPlan,
+    SeriesMeta, StudySummary,
+};
+use crate::config::{ConversionConfig, PerInstanceConfig, SeriesFilter};
+use crate::converter::{
+    delete_dicom_files, has_nifti_output, is_dwi_shell_folder, remove_nifti_outputs,
+    resolve_output_names, ConversionJob, ConversionPool, DwiMode, DWI_4D_NAME, DWI_SHELL_FOLDERS,
+};
+use crate::dicomdir::write_study_dicomdir;
+use crate::duplicates::{DuplicatePolicy, StudyClaims};
+use crate::encrypt::{AgeEncryptor, AGE_SUFFIX};
+use crate::estimate::format_bytes;
+use crate::events::{self, Event};
+use crate::failed::FailedInstance;
+use crate::fdlimit::FileSlots;
+use crate::layout::{instance_number_file_names, InstanceNaming, OutputLayout, NON_IMAGE_DIR};
+use crate::logging::attach_progress;
+use crate::ordering::{is_dynamic_series, write_ordering_file, ORDERING_FILE};
+use crate::package::{package_study, PackageFormat};
+use crate::preview::write_series_preview;
+use crate::processed::ProcessedMarker;
+use crate::processor::{
+    not_attempted, summarize_status, throughput_bps, ProcessResult, SeriesReport,
+    AUTH_FAILED_REASON,
+};
+use crate::progress::{
+    aggregate_bar, hidden_multi_progress, should_collapse, terminal_rows, BatchProgress,
+    ProgressLog,
+};
+use crate::purge::{purge_study, PurgeMode};
+use crate::qc::check_series;
+use crate::sidecar::{record_conversion, write_series_sidecar, SidecarConversion};
+use crate::sopindex::{adoptions, dicom_file_names, index_sop_uids};
+use crate::state::StateStore;
+use crate::studyselect::{prompt_study_choice, StudySelect, StudySelection};
+use crate::trash::DicomTrash;
+use crate::validate::validate_instance;
+
+/// 下載結果狀態
+#[derive(Clone, Debug)]
+pub enum DownloadResult {
+    /// 已下載，附本次傳輸的位元組數
+    Completed(u64),
+    Skipped,
+    Failed(String),
+    /// 寫入後驗證失敗（`--validate`），檔案已移除以便重跑時重新下載
+    Invalid(String),
+    /// 認證失敗（401/403）後不再嘗試
+    NotAttempted,
+}
+
+/// (source series, series_type, series_number, instances, analyzer bypass note)
+type SeriesInfo = (String, String, Option<String>, Vec<String>, Option<String>);
+
+/// (instance ID, 下載結果)
+type InstanceOutcome = (String, DownloadResult);
+
+/// 無效路徑字元集合（與 Python 對齊）
+const INVALID_PATH_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Windows 保留檔名（不區分大小寫）
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// 檢查是否為 Windows 保留檔名
+fn is_windows_reserved_name(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    WINDOWS_RESERVED_NAMES.contains(&upper.as_str())
+}
+
+/// 清理路徑片段，移除無效字元並處理 Windows 保留檔名
+pub fn sanitize_segment(text: &str) -> String {
+    let cleaned: String = text
+        .trim()
+        .chars()
+        .map(|c| {
+            if INVALID_PATH_CHARS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if cleaned.is_empty() {
+        "unknown".to_string()
+    } else if is_windows_reserved_name(&cleaned) {
+        // 為 Windows 保留名稱加上底線前綴
+        format!("_{}", cleaned)
+    } else {
+        cleaned
+    }
+}
+
+/// 產生安全的 DICOM 檔名（處理 Windows 保留名稱）
+pub fn safe_dicom_filename(instance_id: &str) -> String {
+    let base_name = sanitize_segment(instance_id);
+    format!("{}.dcm", base_name)
+}
+
+/// 產生 study 資料夾名稱（與 Python 對齊）
+pub fn generate_study_folder_name(info: &DicomStudyInfo) -> String {
+    format!(
+        "{}_{}_{}_{}",
+        sanitize_segment(&info.patient_id),
+        sanitize_segment(&info.study_date),
+        sanitize_segment(&info.modality),
+        sanitize_segment(&info.accession_number)
+    )
+}
+
+/// 產生 series 資料夾名稱（Linus Good Taste: 統一處理，消除 DWI 特殊情況）
+pub fn generate_series_folder_name(
+    series_type: &str,
+    series_number: Option<&str>,
+    type_counts: &HashMap<String, usize>,
+) -> String {
+    let count = *type_counts.get(series_type).unwrap_or(&1);
+
+    // 統一模式：只要同類型有多個，就加編號
+    if count > 1 {
+        let num = series_number
+            .and_then(|n| n.parse::<u32>().ok())
+            .map(|n| format!("{:03}", n))
+            .unwrap_or_else(|| "000".to_string());
+        format!("{}_{}", series_type, num)
+    } else {
+        series_type.to_string()
+    }
+}
+
+/// 規劃前已通過過濾的 series
+struct SeriesToPlan {
+    series_id: String,
+    meta: SeriesMeta,
+    /// 送分析服務（未分開存放的影像 series 且已啟用分析）
+    analyze: bool,
+    /// non-image series 分開存放，以 modality 命名
+    separated: bool,
+}
+
+/// 單一 series 的規劃結果
+struct PlannedSeries {
+    series_id: String,
+    infos: Vec<SeriesInfo>,
+    /// 取樣 instance 的 study 標籤（`need_study_info` 時）
+    study_info: Option<DicomStudyInfo>,
+    prefetched: Option<PrefetchedInstance>,
+}
+
+/// 分析結果快取：以 SOPInstanceUID 查詢／記錄 state store 中的 series_type，
+/// Orthanc 重建後 instance ID 改變也能沿用
+struct AnalysisCache<'a> {
+    store: Option<&'a StateStore>,
+    /// Orthanc instance ID → SOPInstanceUID
+    sop_uids: HashMap<String, String>,
+    /// 讀取既有結果（`--refresh-analysis` 時只寫不讀）
+    reuse: bool,
+}
+
+impl<'a> AnalysisCache<'a> {
+    /// 有 state store 時以一次 `/series/{id}/instances` 查詢取得 SOPInstanceUID；
+    /// 查詢失敗時不使用快取
+    async fn load(
+        client: &OrthancClient,
+        series_id: &str,
+        store: Option<&'a StateStore>,
+        reuse: bool,
+    ) -> AnalysisCache<'a> {
+        let sop_uids = match store {
+            Some(_) => client
+                .get_series_instance_tags(series_id)
+                .await
+                .map(|tags| {
+                    tags.into_iter()
+                        .filter_map(|t| Some((t.id, t.sop_instance_uid?)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            None => HashMap::new(),
+        };
+        Self {
+            store,
+            sop_uids,
+            reuse,
+        }
+    }
+
+    fn get(&self, instance_id: &str) -> Option<String> {
+        if !self.reuse {
+            return None;
+        }
+        self.store?.analysis_type(self.sop_uids.get(instance_id)?)
+    }
+
+    /// 只記錄分析服務的實際結果，不含 Unknown 與標籤 fallback
+    fn put(&self, instance_id: &str, series_type: &str) {
+        if let (Some(store), Some(uid)) = (self.store, self.sop_uids.get(instance_id)) {
+            store.put_analysis_type(uid, series_type);
+        }
+    }
+}
+
+/// 逐 instance 分析一批 instances：並發下載後以一次 Analyze 請求上傳。
+/// 下載失敗或無結果者為 Unknown；超過上傳上限或逾時者為 Unknown 並附上略過原因。
+async fn analyze_instance_batch(
+    client: &OrthancClient,
+    batch: Vec<String>,
+) -> Vec<(String, String, Option<String>)> {
+    let unknown = || "Unknown".to_string();
+    let downloads =
+        futures::future::join_all(batch.iter().map(|id| client.download_instance_file(id))).await;
+
+    let mut results = Vec::with_capacity(batch.len());
+    let mut ids = Vec::new();
+    let mut files = Vec::new();
+    for (inst_id, download) in batch.into_iter().zip(downloads) {
+        match download.map(|data| (client.check_analyze_upload(data.len()), data)) {
+            Ok((Ok(()), data)) => {
+                ids.push(inst_id);
+                files.push(data);
+            }
+            Ok((Err(e), _)) => results.push((inst_id, unknown(), analyzer_bypass_reason(&e))),
+            Err(_) => results.push((inst_id, unknown(), None)),
+        }
+    }
+    if files.is_empty() {
+        return results;
+    }
+
+    match client.analyze_dicom_batch(files).await {
+        Ok(types) => results.extend(ids.into_iter().zip(types).map(|(inst_id, t)| {
+            let t = t
+                .filter(|t| t.to_lowercase() != "unknown")
+                .unwrap_or_else(unknown);
+            (inst_id, t, None)
+        })),
+        Err(e) => {
+            let bypassed = analyzer_bypass_reason(&e);
+            if bypassed.is_none() {
+                warn!("Analyze API failed for {} instances: {}", ids.len(), e);
+            }
+            results.extend(
+                ids.into_iter()
+                    .map(|inst_id| (inst_id, unknown(), bypassed.clone())),
+            );
+        }
+    }
+    results
+}
+
+/// `len` 個 instance 中平均分布的 `count` 個索引（第一個一定是 0）
+fn sample_indices(len: usize, count: usize) -> Vec<usize> {
+    let count = count.clamp(1, len.max(1));
+    (0..count).map(|k| k * len / count).collect()
+}
+
+/// 票數最多的 series_type 與其票數；同票時取較早取樣者
+fn majority_type(votes: &[String]) -> Option<(String, usize)> {
+    let mut best: Option<(&String, usize)> = None;
+    for vote in votes {
+        let count = votes.iter().filter(|v| *v == vote).count();
+        if best.is_none_or(|(_, c)| count > c) {
+            best = Some((vote, count));
+        }
+    }
+    best.map(|(t, c)| (t.clone(), c))
+}
+
+/// `sample_count` > 1 時分析 series 中平均分布的 instances（第一個以 `first_vote` 計票），
+/// 回傳多數決的 series_type、票數與有效票總數。未啟用或沒有分析結果時回傳 `None`。
+async fn vote_series_type(
+    client: &Arc<OrthancClient>,
+    instances: &[String],
+    first_vote: Option<String>,
+    cache: &AnalysisCache<'_>,
+    ctx: &DownloadContext,
+) -> Option<(String, usize, usize)> {
+    if ctx.sample_count <= 1 || instances.len() <= 1 {
+        return None;
+    }
+    let sampled: Vec<&String> = sample_indices(instances.len(), ctx.sample_count)
+        .into_iter()
+        .skip(1)
+        .map(|i| &instances[i])
+        .collect();
+    let uncached: Vec<String> = sampled
+        .iter()
+        .filter(|id| cache.get(id).is_none())
+        .map(|id| id.to_string())
+        .collect();
+    let batches: Vec<Vec<String>> = uncached
+        .chunks(ctx.per_instance_config.get_analyze_batch_size())
+        .map(<[String]>::to_vec)
+        .collect();
+    let analyzed: HashMap<String, String> = stream::iter(batches)
+        .map(|batch| {
+            let client = client.clone();
+            async move { analyze_instance_batch(&client, batch).await }
+        })
+        .buffer_unordered(ctx.per_instance_config.get_analyze_concurrency())
+        .flat_map(stream::iter)
+        .filter(|(_, t, _)| std::future::ready(t != "Unknown"))
+        .map(|(id, t, _)| (id, t))
+        .collect()
+        .await;
+
+    let mut votes: Vec<String> = first_vote.into_iter().collect();
+    for id in sampled {
+        let vote = match analyzed.get(id) {
+            Some(t) => {
+                cache.put(id, t);
+                Some(t.clone())
+            }
+            None => cache.get(id),
+        };
+        votes.extend(vote);
+    }
+    let (voted, count) = majority_type(&votes)?;
+    Some((voted, count, votes.len()))
+}
+
+/// 規劃單一 series：取樣第一個 instance 分析（`sample_count` > 1 時多數決），
+/// 符合 trigger_prefixes 時逐 instance 分析分組。
+/// `need_study_info` 時（study 標籤查詢失敗）一併由取樣 instance 解析資料夾標籤。
+/// 已快取分析結果（SOPInstanceUID）的 instance 不再下載與分析。
+/// 取樣下載失敗時回傳 `None`，該 series 不列入計畫。
+async fn plan_series(
+    client: &Arc<OrthancClient>,
+    series: SeriesToPlan,
+    need_study_info: bool,
+    ctx: &DownloadContext,
+) -> Option<PlannedSeries> {
+    let SeriesToPlan {
+        series_id,
+        meta,
+        analyze,
+        separated,
+    } = series;
+    let modality = meta.modality.as_deref();
+    let per_instance_config = &ctx.per_instance_config;
+    let cache = AnalysisCache::load(
+        client,
+        &series_id,
+        ctx.state.as_deref().filter(|_| analyze),
+        ctx.reuse_analysis,
+    )
+    .await;
+
+    // 取第一個 instance 的 DICOM bytes（只有分析且無快取結果時才需要）
+    let first_instance = &meta.instances[0];
+    let cached_first = cache.get(first_instance).filter(|_| analyze);
+    let dicom_data = if analyze && cached_first.is_none() {
+        match client.download_instance_file(first_instance).await {
+            Ok(d) => Some(d),
+            Err(e) => {
+                warn!(
+                    "Failed to download first instance {} for series {}: {}",
+                    first_instance, series_id, e
+                );
+                return None;
+            }
+        }
+    } else {
+        None
+    };
+    let study_info = dicom_data
+        .as_deref()
+        .filter(|_| need_study_info)
+        .and_then(|d| parse_dicom_study_info(d).ok());
+    // 分析用的 instance 保留下來，下載時直接寫檔
+    let prefetched = dicom_data
+        .as_deref()
+        .and_then(|d| PrefetchedInstance::keep(first_instance, d));
+
+    // 決定 series_type（支援 per-instance 模式）
+    let mut analyzer_note: Option<String> = None;
+    let mut first_vote = cached_first.clone();
+    let first_series_type = match dicom_data {
+        _ if cached_first.is_some() => cached_first.clone().unwrap_or_default(),
+        // 呼叫 Analyze API 分析第一個 instance
+        Some(data) => {
+            let fallback = fallback_type_from_dicom(&data, &ctx.classifier);
+            match client.analyze_dicom_data(data).await {
+                Ok(Some(t)) if t.to_lowercase() != "unknown" => {
+                    cache.put(first_instance, &t);
+                    first_vote = Some(t.clone());
+                    t
+                }
+                // 分析無結果或被略過（過大/逾時）時，CT 等以標籤分類（kernel / 顯影相位）
+                result => {
+                    analyzer_note = result.err().and_then(|e| analyzer_bypass_reason(&e));
+                    fallback
+                        .or_else(|| meta.description.clone())
+                        .unwrap_or_else(|| "Unknown".to_string())
+                }
+            }
+        }
+        None if separated => modality.unwrap_or_default().trim().to_uppercase(),
+        // 未啟用分析時以 [classifier] 規則分類（只查標籤 JSON，不下載像素資料）
+        None if !ctx.classifier.is_empty() => client
+            .get_instance_series_tags(first_instance)
+            .await
+            .ok()
+            .and_then(|tags| ctx.classifier.classify(&tags))
+            .or_else(|| meta.description.clone())
+            .unwrap_or_else(|| "Unknown".to_string()),
+        None => meta
+            .description
+            .clone()
+            .unwrap_or_else(|| "Unknown".to_string()),
+    };
+
+    // sample_count > 1 時再分析平均分布的其他 instance，以多數決決定 series_type
+    let vote = if analyze {
+        vote_series_type(client, &meta.instances, first_vote, &cache, ctx).await
+    } else {
+        None
+    };
+    let first_series_type = match vote {
+        Some((voted, count, total)) if voted != first_series_type => {
+            let note = format!(
+                "series type by majority of sampled instances ({}/{})",
+                count, total
+            );
+            analyzer_note = Some(match analyzer_note {
+                Some(bypass) => format!("{}; {}", bypass, note),
+                None => note,
+            });
+            voted
+        }
+        _ => first_series_type,
+    };
+
+    let mut infos: Vec<SeriesInfo> = Vec::new();
+    // 檢查是否需要 per-instance 分析
+    if analyze && per_instance_config.should_analyze(&first_series_type) {
+        // Per-instance 模式：分析每個 instance 並按 type 分組
+        let analyze_concurrency = per_instance_config.get_analyze_concurrency();
+
+        // 已快取的 instance 直接沿用，其餘每 analyze_batch_size 個一批並發分析
+        let mut instance_types: Vec<(String, String, Option<String>)> = Vec::new();
+        let mut to_analyze: Vec<String> = Vec::new();
+        for inst_id in &meta.instances {
+            match cache.get(inst_id) {
+                Some(t) => instance_types.push((inst_id.clone(), t, None)),
+                None => to_analyze.push(inst_id.clone()),
+            }
+        }
+        let batches: Vec<Vec<String>> = to_analyze
+            .chunks(per_instance_config.get_analyze_batch_size())
+            .map(<[String]>::to_vec)
+            .collect();
+        let analyzed: Vec<(String, String, Option<String>)> = stream::iter(batches)
+            .map(|batch| {
+                let client = client.clone();
+                async move { analyze_instance_batch(&client, batch).await }
+            })
+            .buffer_unordered(analyze_concurrency)
+            .flat_map(stream::iter)
+            .collect()
+            .await;
+        for (inst_id, inst_type, _) in &analyzed {
+            if inst_type != "Unknown" {
+                cache.put(inst_id, inst_type);
+            }
+        }
+        instance_types.extend(analyzed);
+
+        // 按 series_type 分組 instances
+        let total = instance_types.len();
+        let mut grouped: HashMap<String, Vec<String>> = HashMap::new();
+        let mut bypassed: Vec<String> = Vec::new();
+        for (inst_id, inst_type, bypass) in instance_types {
+            grouped.entry(inst_type).or_default().push(inst_id);
+            bypassed.extend(bypass);
+        }
+        let group_note = bypassed
+            .first()
+            .map(|reason| format!("{} of {} instances, {}", bypassed.len(), total, reason));
+
+        // 為每個分組創建 series_info 條目
+        for (group_type, instances) in grouped {
+            infos.push((
+                series_id.clone(),
+                group_type,
+                meta.series_number.clone(),
+                instances,
+                group_note.clone(),
+            ));
+        }
+    } else {
+        // 標準模式：所有 instances 使用相同 series_type
+        infos.push((
+            series_id.clone(),
+            first_series_type,
+            meta.series_number.clone(),
+            meta.instances.clone(),
+            analyzer_note,
+        ));
+    }
+    Some(PlannedSeries {
+        series_id,
+        infos,
+        study_info,
+        prefetched,
+    })
+}
+
+/// 建立下載計畫（與 Python build_download_plan 對齊）
+/// 支援 per-instance 分析模式：當第一個 instance 的 series_type 匹配 trigger_prefixes 時，
+/// 對所有 instances 進行個別分析並分組到不同資料夾。
+/// 每個 study 只需兩次查詢：`/studies/{id}` 取得資料夾標籤，`/studies/{id}/series?expand`
+/// 取得所有 series 的描述、編號與 instance 清單；未啟用分析時不下載任何 instance
+/// （study 標籤查詢失敗時改查 instance 的標籤 JSON）。
+/// 有 state store 時另以 StudyInstanceUID 快取 study 標籤。
+/// 各 series 的取樣與分析以 `plan_concurrency` 並行，計畫仍依 Orthanc 的 series 順序。
+/// `study_ids` 為 [`select_study_ids`] 選出的 study。
+pub async fn build_download_plan(
+    client: Arc<OrthancClient>,
+    accession: &str,
+    study_ids: Vec<String>,
+    ctx: &DownloadContext,
+) -> Result<Vec<DownloadPlan>> {
+    let analyze_enabled = ctx.analyze_enabled;
+    let state = ctx.state.as_deref();
+    let layout = ctx.layout;
+    let series_filter = &ctx.series_filter;
+    let plan_concurrency = ctx.plan_concurrency.max(1);
+    let mut plans = Vec::new();
+
+    for study_id in study_ids {
+        let series_metas = match client.list_series_meta(&study_id).await {
+            Ok(series) => series,
+            Err(_) => continue,
+        };
+
+        let mut series_info: Vec<SeriesInfo> = Vec::new();
+        let mut descriptions: HashMap<String, Option<String>> = HashMap::new();
+        let mut non_image: HashSet<String> = HashSet::new();
+        let mut prefetched: HashMap<String, PrefetchedInstance> = HashMap::new();
+
+        // study 標籤（含 StudyInstanceUID）一次取得；先查 state store 的快取
+        let (study_uid, study_tags) = match client.get_study_tags(&study_id).await {
+            Ok((uid, tags)) => (uid, Some(tags)),
+            Err(_) => (None, None),
+        };
+        let mut study_folder_name: Option<String> = state
+            .zip(study_uid.as_deref())
+            .and_then(|(store, uid)| store.study_info(uid))
+            .map(|info| layout.study_folder(&info));
+        let remember_study = |info: DicomStudyInfo| {
+            if let (Some(store), Some(uid)) = (state, study_uid.as_deref()) {
+                store.put_study_info(uid, info);
+            }
+        };
+
+        // 先依標籤過濾（不需網路），再並行取樣與分析
+        let mut selected: Vec<SeriesToPlan> = Vec::new();
+        for (series_id, meta) in series_metas {
+            if meta.instances.is_empty() {
+                continue;
+            }
+            // SeriesDescription 的 include/exclude regex、non-image 政策與 instance 數量先過濾，
+            // 不下載也不分析
+            let description = meta.description.as_deref().unwrap_or_default();
+            let modality = meta.modality.as_deref();
+            let rejection = series_filter
+                .rejection(description, modality)
+                .or_else(|| series_filter.count_rejection(meta.instances.len()));
+            if let Some(reason) = rejection {
+                info!(
+                    "{}: series '{}' skipped ({})",
+                    accession, description, reason
+                );
+                continue;
+            }
+            descriptions.insert(series_id.clone(), meta.description.clone());
+            // SR/KO/PR/SEG 分開存放時不送分析，資料夾以 modality 命名
+            let separated = series_filter.separates(modality);
+            if separated {
+                non_image.insert(series_id.clone());
+            }
+
+            // 資料夾名稱取自 study 標籤與第一個 series 的 modality，不必下載 instance
+            if let (None, Some(tags)) = (&study_folder_name, &study_tags) {
+                let info = DicomStudyInfo {
+                    modality: modality.unwrap_or_default().trim().to_string(),
+                    ..tags.clone()
+                };
+                study_folder_name = Some(layout.study_folder(&info));
+                remember_study(info);
+            }
+            selected.push(SeriesToPlan {
+                series_id,
+                meta,
+                analyze: analyze_enabled && !separated,
+                separated,
+            });
+        }
+
+        let need_study_info = study_folder_name.is_none();
+        let planned: Vec<Option<PlannedSeries>> = stream::iter(selected)
+            .map(|series| {
+                let client = client.clone();
+                async move { plan_series(&client, series, need_study_info, ctx).await }
+            })
+            .buffered(plan_concurrency)
+            .collect()
+            .await;
+
+        for planned in planned.into_iter().flatten() {
+            if let (None, Some(info)) = (&study_folder_name, planned.study_info) {
+                study_folder_name = Some(layout.study_folder(&info));
+                remember_study(info);
+            }
+            if let Some(kept) = planned.prefetched {
+                prefetched.insert(planned.series_id, kept);
+            }
+            series_info.extend(planned.infos);
+        }
+
+        // study 標籤查詢失敗且未分析時，只查一個 instance 的標籤 JSON，不下載像素資料
+        if study_folder_name.is_none() {
+            let first = series_info
+                .first()
+                .and_then(|(_, _, _, instances, _)| instances.first());
+            if let Some(instance) = first {
+                if let Ok(info) = client.get_instance_study_info(instance).await {
+                    study_folder_name = Some(layout.study_folder(&info));
+                    remember_study(info);
+                }
+            }
+        }
+
+        // 計算每個 series_type 的出現次數
+        let mut type_counts: HashMap<String, usize> = HashMap::new();
+        for (_, series_type, _, _, _) in &series_info {
+            *type_counts.entry(series_type.clone()).or_insert(0) += 1;
+        }
+
+        // 產生 SeriesDownloadPlan
+        let series_plans: Vec<SeriesDownloadPlan> = series_info
+            .into_iter()
+            .map(
+                |(source_series, series_type, series_number, instances, analyzer_note)| {
+                    let series_folder = generate_series_folder_name(
+                        &series_type,
+                        series_number.as_deref(),
+                        &type_counts,
+                    );
+                    SeriesDownloadPlan {
+                        description: descriptions.get(&source_series).cloned().flatten(),
+                        non_image: non_image.contains(&source_series),
+                        // per-instance 分組時只交給含該 instance 的分組
+                        prefetched: prefetched
+                            .get(&source_series)
+                            .filter(|p| instances.contains(&p.instance_id))
+                            .cloned(),
+                        source_series,
+                        series_folder,
+                        series_type,
+                        series_number,
+                        instances,
+                        analyzer_note,
+                    }
+                },
+            )
+            .collect();
+
+        plans.push(DownloadPlan {
+            study_id,
+            study_folder: study_folder_name
+                .unwrap_or_else(|| layout.unknown_study_folder(accession)),
+            series: series_plans,
+        });
+    }
+
+    Ok(plans)
+}
+
+/// 依 `--study-date` / `--study-select` 選出要下載的 study；多個 study 符合時附上說明。
+pub async fn select_study_ids(
+    client: &OrthancClient,
+    accession: &str,
+    selection: &StudySelection,
+    batch: Option<&BatchProgress>,
+) -> Result<(Vec<String>, Option<String>)> {
+    let study_ids = client.find_study_ids_by_accession(accession).await?;
+    let fetch_tags = selection.needs_summaries(study_ids.len());
+    let mut studies = Vec::with_capacity(study_ids.len());
+    for id in study_ids {
+        studies.push(if fetch_tags {
+            client.get_study_summary(&id).await?
+        } else {
+            StudySummary {
+                id,
+                ..Default::default()
+            }
+        });
+    }
+    if selection.policy != StudySelect::Interactive {
+        return selection.select(accession, studies, |_, _| Ok(Vec::new()));
+    }
+    // 詢問時暫停進度條，stdin 讀取為阻塞操作
+    tokio::task::block_in_place(|| {
+        selection.select(accession, studies, |acc, found| match batch {
+            Some(b) => b.multi().suspend(|| prompt_study_choice(acc, found)),
+            None => prompt_study_choice(acc, found),
+        })
+    })
+}
+
+/// 將來自同一 Orthanc series 的連續計畫（per-instance 分組）合併為一組索引
+pub fn group_by_source_series(series: &[SeriesDownloadPlan]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (i, plan) in series.iter().enumerate() {
+        match groups.last_mut() {
+            Some(group) if series[group[0]].source_series == plan.source_series => group.push(i),
+            _ => groups.push(vec![i]),
+        }
+    }
+    groups
+}
+
+/// `sample_count` 未設定時每個 series 分析的 instance 數（只分析第一個）
+pub const DEFAULT_SAMPLE_COUNT: usize = 1;
+
+/// `plan_concurrency` 未設定時同時規劃的 series 數
+pub const DEFAULT_PLAN_CONCURRENCY: usize = 4;
+
+/// 未完成 series 資料夾的後綴
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+/// 下載中 series 的暫存資料夾：`<series>.partial`
+pub fn partial_dir(series_dir: &Path) -> PathBuf {
+    let mut name = series_dir.as_os_str().to_os_string();
+    name.push(PARTIAL_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// 下載單一 instance 並串流寫入檔案
+///
+/// 內容以 chunk 寫入 `<dest>.part`，完成後才 rename 為正式檔名，避免大型 multi-frame
+/// instance 整檔駐留記憶體，也不會留下截斷的 `.dcm`。
+/// 暫時性 HTTP 錯誤的重試與退避由 `OrthancClient` 統一處理（見 [`crate::retry`]）。
+/// 同時進行的寫入受 `file_slots` 限制（見 [`crate::fdlimit`]）。
+pub async fn download_instance_to_file(
+    client: &OrthancClient,
+    instance_id: &str,
+    dest_path: &Path,
+    file_slots: &FileSlots,
+) -> DownloadResult {
+    if client.auth_failed() {
+        return DownloadResult::NotAttempted;
+    }
+    // 檔案已存在，跳過
+    if fs::try_exists(dest_path).await.unwrap_or(false) {
+        return DownloadResult::Skipped;
+    }
+    // 等待 file descriptor 額度，避免大量併發時 EMFILE
+    let _slot = file_slots.download().await;
+    match client
+        .download_instance_to_path(instance_id, dest_path)
+        .await
+    {
+        Ok(bytes) => DownloadResult::Completed(bytes),
+        Err(e) => DownloadResult::Failed(format!("Download failed: {:#}", e)),
+    }
+}
+
+/// 規劃階段已取得的 instance 直接寫檔（同樣經 `<dest>.part` 再 rename），不再重新下載
+async fn write_prefetched(data: &[u8], dest_path: &Path, file_slots: &FileSlots) -> DownloadResult {
+    if fs::try_exists(dest_path).await.unwrap_or(false) {
+        return DownloadResult::Skipped;
+    }
+    let _slot = file_slots.download().await;
+    let part = part_path(dest_path);
+    let written = match fs::write(&part, data).await {
+        Ok(()) => fs::rename(&part, dest_path).await,
+        Err(e) => Err(e),
+    };
+    match written {
+        Ok(()) => DownloadResult::Completed(data.len() as u64),
+        Err(e) => {
+            let _ = fs::remove_file(&part).await;
+            DownloadResult::Failed(format!("Write failed: {}", e))
+        }
+    }
+}
+
+/// Orthanc 重建後同一 instance 可能換了 ID：資料夾內對不上檔名的既有檔案以 SOPInstanceUID
+/// 比對，改名為本次的檔名，下載時即視為已存在（見 [`crate::sopindex`]）。回傳沿用的檔案數。
+async fn adopt_by_sop_uid(
+    client: &OrthancClient,
+    series_plan: &SeriesDownloadPlan,
+    series_dir: &Path,
+    file_names: &HashMap<String, String>,
+) -> usize {
+    let existing = dicom_file_names(series_dir);
+    if existing.is_empty() {
+        return 0;
+    }
+    let planned: HashMap<&String, String> = series_plan
+        .instances
+        .iter()
+        .map(|id| {
+            let name = file_names
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| safe_dicom_filename(id));
+            (id, name)
+        })
+        .collect();
+    let planned_names: HashSet<&String> = planned.values().collect();
+    let orphans: Vec<String> = existing
+        .iter()
+        .filter(|name| !planned_names.contains(name))
+        .cloned()
+        .collect();
+    if orphans.is_empty() || planned.values().all(|name| existing.contains(name)) {
+        return 0;
+    }
+
+    let tags = match client
+        .get_series_instance_tags(&series_plan.source_series)
+        .await
+    {
+        Ok(tags) => tags,
+        Err(e) => {
+            warn!(
+                "{}: SOPInstanceUID lookup failed, not reusing existing files: {}",
+                series_plan.series_folder, e
+            );
+            return 0;
+        }
+    };
+    let missing: Vec<(String, String)> = tags
+        .into_iter()
+        .filter_map(|t| {
+            let name = planned.get(&t.id).filter(|n| !existing.contains(*n))?;
+            Some((name.clone(), t.sop_instance_uid?))
+        })
+        .collect();
+    let dir = series_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let index = index_sop_uids(&dir, &orphans);
+        adoptions(&dir, &missing, &index)
+            .into_iter()
+            .filter(|(from, to)| std::fs::rename(from, to).is_ok())
+            .count()
+    })
+    .await
+    .unwrap_or(0)
+}
+
+/// 重新解析剛寫入的檔案（`--validate`）；驗證失敗時移除檔案，重跑時會重新下載
+async fn validate_written(dest_path: &Path, instance_id: &str, bytes: u64) -> DownloadResult {
+    let path = dest_path.to_path_buf();
+    let id = instance_id.to_string();
+    let checked = tokio::task::spawn_blocking(move || validate_instance(&path, &id)).await;
+    let err = match checked {
+        Ok(Ok(())) => return DownloadResult::Completed(bytes),
+        Ok(Err(e)) => e,
+        Err(e) => format!("validation task failed: {}", e),
+    };
+    let _ = fs::remove_file(dest_path).await;
+    let name = dest_path.file_name().unwrap_or_default().to_string_lossy();
+    DownloadResult::Invalid(format!("{}: {}", name, err))
+}
+
+/// 系列資料夾內 `.dcm` 檔案的總大小
+fn dicom_bytes(dir: &Path) -> u64 {
+    std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| {
+                    e.path()
+                        .extension()
+                        .is_some_and(|x| x.to_string_lossy().eq_ignore_ascii_case("dcm"))
+                })
+                .filter_map(|e| e.metadata().ok())
+                .map(|m| m.len())
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// 失敗結果的錯誤訊息；成功或已存在時為 `None`
+pub fn failure_text(result: &DownloadResult) -> Option<String> {
+    match result {
+        DownloadResult::Completed(_) | DownloadResult::Skipped => None,
+        DownloadResult::Failed(e) | DownloadResult::Invalid(e) => Some(e.clone()),
+        DownloadResult::NotAttempted => Some("not attempted (authentication failed)".into()),
+    }
+}
+
+/// 由各 instance 的下載結果組出一個 series 的報告列
+pub fn series_row(
+    study_folder: &str,
+    plan: &SeriesDownloadPlan,
+    results: &[DownloadResult],
+    bytes: u64,
+    elapsed: Duration,
+) -> SeriesReport {
+    let mut row = SeriesReport {
+        study_folder: study_folder.to_string(),
+        series_folder: plan.series_folder.clone(),
+        expected_instances: plan.instances.len(),
+        bytes,
+        duration_ms: elapsed.as_millis() as u64,
+        ..Default::default()
+    };
+    let mut errors: Vec<String> = Vec::new();
+    for r in results {
+        match r {
+            DownloadResult::Completed(_) => row.downloaded_instances += 1,
+            DownloadResult::Skipped => row.skipped_instances += 1,
+            _ => {}
+        }
+        let Some(err) = failure_text(r) else {
+            continue;
+        };
+        row.failed_instances += 1;
+        if !errors.contains(&err) {
+            errors.push(err);
+        }
+    }
+    row.status = if row.failed_instances == 0 {
+        "Downloaded"
+    } else if row.failed_instances < results.len() {
+        "Partial"
+    } else {
+        "Failed"
+    }
+    .into();
+    row.error = errors.join("; ");
+    row
+}
+
+/// 進度追蹤器（使用 indicatif）
+///
+/// 收合模式下多個 tracker 共用一條總進度條，完成訊息改寫入 log 檔。
+pub struct DownloadProgressTracker {
+    completed: AtomicUsize,
+    failed: AtomicUsize,
+    skipped: AtomicUsize,
+    bytes: AtomicU64,
+    start_time: Instant,
+    pb: ProgressBar,
+    series_name: String,
+    log: Option<Arc<ProgressLog>>,
+}
+
+impl DownloadProgressTracker {
+    pub fn new(total: usize, mp: &MultiProgress, series_name: &str) -> Self {
+        let pb = mp.add(ProgressBar::new(total as u64));
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
+                .unwrap()
+                .progress_chars("=>-"),
+        );
+        pb.set_message(series_name.to_string());
+
+        Self {
+            completed: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            skipped: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            start_time: Instant::now(),
+            pb,
+            series_name: series_name.to_string(),
+            log: None,
+        }
+    }
+
+    /// Tracker that advances a shared aggregate bar and reports to `log` when finished.
+    pub fn collapsed(shared: &ProgressBar, series_name: &str, log: Arc<ProgressLog>) -> Self {
+        Self {
+            completed: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            skipped: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            start_time: Instant::now(),
+            pb: shared.clone(),
+            series_name: series_name.to_string(),
+            log: Some(log),
+        }
+    }
+
+    pub fn update(&self, result: &DownloadResult) {
+        match result {
+            DownloadResult::Completed(bytes) => {
+                self.completed.fetch_add(1, Ordering::Relaxed);
+                self.bytes.fetch_add(*bytes, Ordering::Relaxed);
+            }
+            DownloadResult::Failed(err) => {
+                match &self.log {
+                    Some(log) => {
+                        log.line(&format!("{}: download failed: {}", self.series_name, err))
+                    }
+                    None => warn!("Download failed: {}", err),
+                }
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            }
+            DownloadResult::Invalid(err) => {
+                match &self.log {
+                    Some(log) => {
+                        log.line(&format!("{}: validation failed: {}", self.series_name, err))
+                    }
+                    None => warn!("Validation failed: {}", err),
+                }
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            }
+            DownloadResult::Skipped => {
+                self.skipped.fetch_add(1, Ordering::Relaxed);
+            }
+            DownloadResult::NotAttempted => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.pb.inc(1);
+    }
+
+    pub fn finish(&self) {
+        let completed = self.completed.load(Ordering::Relaxed);
+        let failed = self.failed.load(Ordering::Relaxed);
+        let skipped = self.skipped.load(Ordering::Relaxed);
+        let elapsed = self.start_time.elapsed();
+        let rate = throughput_bps(self.bytes.load(Ordering::Relaxed), elapsed);
+
+        let msg = format!(
+            "Done: {} ok, {} skip, {} fail ({:.1}s, {}/s)",
+            completed,
+            skipped,
+            failed,
+            elapsed.as_secs_f64(),
+            format_bytes(rate as u64)
+        );
+        match &self.log {
+            Some(log) => log.line(&format!("{}: {}", self.series_name, msg)),
+            None => self.pb.finish_with_message(msg),
+        }
+    }
+
+    /// Takes a finished bar out of a shared `MultiProgress` and prints its final line
+    /// above the bars still running (no-op in collapsed mode).
+    pub fn retire(&self, mp: &MultiProgress) {
+        if self.log.is_some() {
+            return;
+        }
+        mp.remove(&self.pb);
+        let _ = mp.println(format!("  {}: {}", self.series_name, self.pb.message()));
+    }
+}
+
+/// Settings shared by every accession of a direct download run.
+pub struct DownloadContext {
+    pub dicom_root: PathBuf,
+    pub niix_root: PathBuf,
+    pub instance_concurrency: usize,
+    pub analyze_enabled: bool,
+    pub convert_enabled: bool,
+    /// Convert series whose NIfTI already exists (`--reconvert`); skipped otherwise.
+    pub reconvert: bool,
+    pub conversion_config: ConversionConfig,
+    /// Background dcm2niix workers (present with `--convert`).
+    pub conversions: Option<ConversionPool>,
+    pub per_instance_config: PerInstanceConfig,
+    /// Root for DICOMDIR media folders (`<output>/media`); `None` disables DICOMDIR output.
+    pub media_root: Option<PathBuf>,
+    /// Run the pixel-data QC pass on each downloaded series.
+    pub qc_enabled: bool,
+    /// Save Orthanc's rendering of the middle instance as `preview.png` (`--preview`).
+    pub preview_enabled: bool,
+    /// Re-open every written instance and check its UIDs (`--validate`).
+    pub validate_enabled: bool,
+    /// Log that receives per-series lines when progress bars are collapsed.
+    pub progress_log: Option<Arc<ProgressLog>>,
+    /// Cross-run cache (study tags by StudyInstanceUID).
+    pub state: Option<Arc<StateStore>>,
+    /// Open-file budget shared by instance downloads and dcm2niix runs.
+    pub file_slots: FileSlots,
+    /// Records DICOM deletions after conversion.
+    pub audit: Option<Arc<AuditLog>>,
+    /// Where `delete_dicom_after_conversion` moves DICOMs instead of deleting them.
+    pub trash: Option<DicomTrash>,
+    /// Draw no per-series bars (the `--tui` dashboard shows progress instead).
+    pub hide_progress: bool,
+    /// Whole-batch bar of `download`; per-series bars are drawn below it.
+    pub batch: Option<BatchProgress>,
+    /// How study folders are nested under `dicom/` (see [`crate::layout`]).
+    pub layout: OutputLayout,
+    /// `<id>.dcm` or `<InstanceNumber>.dcm` file names.
+    pub instance_naming: InstanceNaming,
+    /// Archive each completed study folder (`--package`).
+    pub package: Option<PackageFormat>,
+    /// Encrypt packaged studies for the `[encryption]` recipients.
+    pub encryption: Option<Arc<AgeEncryptor>>,
+    /// SeriesDescription include/exclude patterns from the analysis config.
+    pub series_filter: SeriesFilter,
+    /// `[classifier]` rules: the fallback when the analyzer has no answer, and the series
+    /// type when analysis is off.
+    pub classifier: LocalClassifier,
+    /// Which studies to take when several share the accession (`--study-select`).
+    pub study_selection: StudySelection,
+    /// What to do when a study folder was already claimed in this batch (`duplicate_studies`).
+    pub duplicate_policy: DuplicatePolicy,
+    /// Delete verified studies from Orthanc afterwards (`--purge-source`).
+    pub purge: Option<PurgeMode>,
+    /// Series sampled and analyzed at the same time while planning a study.
+    pub plan_concurrency: usize,
+    /// Instances analyzed per series for the majority-vote series type (1 = first only).
+    pub sample_count: usize,
+    /// Use analysis results cached by SOPInstanceUID (off with `--refresh-analysis`).
+    pub reuse_analysis: bool,
+    /// Orthanc processed marks to write and/or honour (`--mark-processed`, `--skip-processed`).
+    pub processed: Option<ProcessedMarker>,
+}
+
+/// Packages and encrypts the folders outside `dicom/` that hold readable copies of a
+/// study (NIfTI outputs, separated non-image series, the DICOMDIR media tree of hard
+/// links); a plaintext archive left by an earlier failed run is encrypted as well. Returns
+/// the encrypted archives.
+fn encrypt_companions(
+    age: &AgeEncryptor,
+    dirs: &[PathBuf],
+    format: PackageFormat,
+) -> Result<Vec<PathBuf>> {
+    let mut archives = Vec::new();
+    for dir in dirs {
+        let archive = if dir.is_dir() {
+            package_study(dir, format)?
+        } else {
+            format.archive_path(dir)
+        };
+        if archive.exists() {
+            archives.push(age.encrypt_file(&archive)?);
+        }
+    }
+    Ok(archives)
+}
+
+/// Conversion jobs for the convertible `(index, folder)` series of one download group: each
+/// series on its own, and with `dwi_mode` merged/both the DWI0 + DWI1000 pair as one job.
+fn conversion_jobs(series: &[(usize, &str)], mode: DwiMode) -> Vec<Vec<usize>> {
+    let shells: Vec<usize> = series
+        .iter()
+        .filter(|(_, folder)| is_dwi_shell_folder(folder))
+        .map(|(i, _)| *i)
+        .collect();
+    let merge = mode.merged() && shells.len() == DWI_SHELL_FOLDERS.len();
+    let mut jobs: Vec<Vec<usize>> = series
+        .iter()
+        .filter(|(i, _)| !merge || mode.separate() || !shells.contains(i))
+        .map(|(i, _)| vec![*i])
+        .collect();
+    if merge {
+        jobs.push(shells);
+    }
+    jobs
+}
+
+/// Adds `msg` to a series row's error column, after any download error.
+fn push_series_error(row: &mut SeriesReport, msg: &str) {
+    if !row.error.is_empty() {
+        row.error.push_str("; ");
+    }
+    row.error.push_str(msg);
+}
+
+/// Instance outcomes that leave a series incomplete.
+fn is_failure(result: &DownloadResult) -> bool {
+    matches!(
+        result,
+        DownloadResult::Failed(_) | DownloadResult::Invalid(_) | DownloadResult::NotAttempted
+    )
+}
+
+/// 完整下載的 series 才從 .partial 改名，下游流程不會讀到寫到一半的資料夾；
+/// 沒有任何檔案的暫存資料夾直接移除。
+async fn finalize_series_dir(
+    results: &[DownloadResult],
+    series_dir: &mut PathBuf,
+    final_dir: PathBuf,
+) -> std::result::Result<(), String> {
+    if *series_dir == final_dir {
+        return Ok(());
+    }
+    if !results.iter().any(is_failure) {
+        fs::rename(&*series_dir, &final_dir)
+            .await
+            .map_err(|e| format!("Finalize failed {}: {}", final_dir.display(), e))?;
+        *series_dir = final_dir;
+    } else if !results
+        .iter()
+        .any(|r| matches!(r, DownloadResult::Completed(_) | DownloadResult::Skipped))
+    {
+        let _ = fs::remove_dir(&*series_dir).await;
+    }
+    Ok(())
+}
+
+/// 新版下載函數（對齊 Python download_dicom_async.py）
+///
+/// 結果附上此 accession 的耗時、下載量與平均傳輸速率。
+/// `claims` 為同一批次（一次 download、一個 serve 工作或一個 watch 檔案）共用的 study 資料夾。
+pub async fn download_accession_v2(
+    client: Arc<OrthancClient>,
+    acc: String,
+    ctx: &DownloadContext,
+    claims: &StudyClaims,
+) -> ProcessResult {
+    let started = Instant::now();
+    let mut res = download_accession(client, acc, ctx, claims).await;
+    res.finish_timing(started.elapsed());
+    res
+}
+
+async fn download_accession(
+    client: Arc<OrthancClient>,
+    acc: String,
+    ctx: &DownloadContext,
+    study_claims: &StudyClaims,
+) -> ProcessResult {
+    let DownloadContext {
+        dicom_root,
+        niix_root,
+        instance_concurrency,
+        analyze_enabled: _,
+        convert_enabled,
+        reconvert,
+        conversion_config,
+        conversions,
+        per_instance_config,
+        media_root,
+        qc_enabled,
+        preview_enabled,
+        validate_enabled,
+        progress_log,
+        state: _,
+        file_slots,
+        audit,
+        trash,
+        hide_progress,
+        batch,
+        layout: _,
+        instance_naming,
+        package,
+        encryption,
+        series_filter: _,
+        classifier: _,
+        study_selection,
+        duplicate_policy,
+        purge,
+        plan_concurrency: _,
+        sample_count: _,
+        reuse_analysis: _,
+        processed,
+    } = ctx;
+    let (instance_concurrency, convert_enabled, qc_enabled, validate_enabled) = (
+        *instance_concurrency,
+        *convert_enabled,
+        *qc_enabled,
+        *validate_enabled,
+    );
+
+    if client.auth_failed() {
+        return not_attempted(&acc);
+    }
+
+    let mut res = ProcessResult {
+        accession: acc.clone(),
+        timestamp: chrono::Utc::now(),
+        ..Default::default()
+    };
+
+    let study_ids = match select_study_ids(&client, &acc, study_selection, batch.as_ref()).await {
+        Ok((ids, Some(note))) if ids.is_empty() => {
+            res.reason.push(format!("No study selected: {}", note));
+            res.status = "Failed".into();
+            return res;
+        }
+        Ok((ids, note)) => {
+            res.notes.extend(note);
+            ids
+        }
+        Err(e) => {
+            res.reason.push(format!("Study lookup failed: {}", e));
+            res.status = "Failed".into();
+            return res;
+        }
+    };
+
+    // 先前執行已標記完成的 study 不再下載（--skip-processed）
+    let study_ids = match processed.as_ref().filter(|m| m.skip) {
+        Some(marker) => {
+            let (todo, done) = marker.partition(&client, study_ids).await;
+            for (id, run) in &done {
+                res.notes.push(format!(
+                    "study {}: already processed by run {} ({}), skipped",
+                    id, run, marker.metadata
+                ));
+            }
+            if todo.is_empty() {
+                res.status = "Success".into();
+                return res;
+            }
+            todo
+        }
+        None => study_ids,
+    };
+
+    // 建立下載計畫
+    let plans = match build_download_plan(client.clone(), &acc, study_ids, ctx).await {
+        Ok(p) if !p.is_empty() => p,
+        Ok(_) => {
+            res.reason.push("No studies found".into());
+            res.status = "Failed".into();
+            return res;
+        }
+        Err(e) => {
+            res.reason.push(format!("Build plan failed: {}", e));
+            res.status = "Failed".into();
+            return res;
+        }
+    };
+
+    // 系列數超過終端機高度時收合為單一總進度條，逐系列訊息寫入 log
+    let series_count: usize = plans.iter().map(|p| p.series.len()).sum();
+    let shared_mp = || {
+        batch
+            .as_ref()
+            .map(|b| b.multi().clone())
+            .unwrap_or_else(MultiProgress::new)
+    };
+    let collapse_log = progress_log
+        .clone()
+        .filter(|_| !*hide_progress && should_collapse(series_count, terminal_rows()));
+    let (mp, aggregate) = match &collapse_log {
+        _ if *hide_progress => (hidden_multi_progress(), None),
+        Some(log) => {
+            info!(
+                "{}: {} series exceed terminal height; per-series output goes to {}",
+                acc,
+                series_count,
+                log.path().display()
+            );
+            let total: usize = plans
+                .iter()
+                .flat_map(|p| &p.series)
+                .map(|s| s.instances.len())
+                .sum();
+            let mp = shared_mp();
+            let pb = mp.add(aggregate_bar(total as u64, &acc));
+            (mp, Some(pb))
+        }
+        None => (shared_mp(), None),
+    };
+    let _log_guard = attach_progress(&mp);
+    let mut any_success = false;
+
+    // Check converter availability once
+    let dcm2niix_available = convert_enabled
+        && ctx
+            .conversions
+            .as_ref()
+            .is_some_and(|pool| pool.is_available());
+
+    for mut plan in plans {
+        // 同一批次已有其他 accession 或 study 使用此資料夾時，依 duplicate_studies 處理
+        let placement =
+            study_claims.place(*duplicate_policy, &plan.study_folder, &plan.study_id, &acc);
+        res.notes.extend(placement.note);
+        match placement.folder {
+            Some(folder) => plan.study_folder = folder,
+            None => {
+                any_success = true;
+                continue;
+            }
+        }
+        let dicom_study_dir = dicom_root.join(&plan.study_folder);
+        let niix_study_dir = niix_root.join(&plan.study_folder);
+        let nonimage_study_dir = dicom_root
+            .with_file_name(NON_IMAGE_DIR)
+            .join(&plan.study_folder);
+        // 啟用加密時，NIfTI、非影像 series 與 DICOMDIR（hard link）也須一併打包加密
+        let companion_dirs: Vec<PathBuf> = [
+            Some(niix_study_dir.clone()),
+            Some(nonimage_study_dir.clone()),
+            media_root
+                .as_ref()
+                .map(|root| root.join(&plan.study_folder)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let study_dir_of = |series: &SeriesDownloadPlan| {
+            if series.non_image {
+                &nonimage_study_dir
+            } else {
+                &dicom_study_dir
+            }
+        };
+
+        // 已打包的 study 不再下載（散檔已在打包後刪除）
+        if let Some(format) = package {
+            let archive = format.archive_path(&dicom_study_dir);
+            let mut encrypted = archive.as_os_str().to_os_string();
+            encrypted.push(AGE_SUFFIX);
+            if (archive.exists() || Path::new(&encrypted).exists()) && !dicom_study_dir.exists() {
+                res.notes
+                    .push(format!("{}: already packaged, skipped", plan.study_folder));
+                any_success = true;
+                // 上次加密失敗留下的明文 archive（或未打包的附屬資料夾）在此補加密
+                if let Some(age) = encryption {
+                    let age = Arc::clone(age);
+                    let format = *format;
+                    let companions = companion_dirs.clone();
+                    let encrypted = tokio::task::spawn_blocking(move || {
+                        if archive.exists() {
+                            age.encrypt_file(&archive)?;
+                        }
+                        encrypt_companions(&age, &companions, format)
+                    });
+                    match encrypted.await {
+                        Ok(Ok(_)) => {}
+                        Ok(Err(e)) => res.reason.push(format!(
+                            "Encryption failed for {}: {:#}",
+                            plan.study_folder, e
+                        )),
+                        Err(e) => warn!("encryption task failed: {}", e),
+                    }
+                }
+                continue;
+            }
+        }
+        let reasons_before = res.reason.len();
+
+        let folders: Vec<String> = plan
+            .series
+            .iter()
+            .map(|s| s.series_folder.clone())
+            .collect();
+        let (output_names, renames) =
+            resolve_output_names(&folders, |i| plan.series[i].series_number.clone());
+        if convert_enabled {
+            res.conversion_renames.extend(
+                renames
+                    .iter()
+                    .map(|r| format!("{}/{}", plan.study_folder, r)),
+            );
+        }
+
+        // 建立所有 series 目錄，失敗者不進入下載
+        // 新 series 先下載到 `<series>.partial/`，全部成功才 rename 為正式資料夾
+        let mut ready = vec![true; plan.series.len()];
+        let mut series_dirs: Vec<PathBuf> = Vec::with_capacity(plan.series.len());
+        for (i, series_plan) in plan.series.iter().enumerate() {
+            let final_dir = study_dir_of(series_plan).join(&series_plan.series_folder);
+            let series_dir = if fs::try_exists(&final_dir).await.unwrap_or(false) {
+                final_dir
+            } else {
+                partial_dir(&final_dir)
+            };
+            series_dirs.push(series_dir.clone());
+            if let Err(e) = fs::create_dir_all(&series_dir).await {
+                let err = format!("Create dir failed {}: {}", series_dir.display(), e);
+                res.series.push(SeriesReport {
+                    study_folder: plan.study_folder.clone(),
+                    series_folder: series_plan.series_folder.clone(),
+                    status: "Failed".into(),
+                    expected_instances: series_plan.instances.len(),
+                    error: err.clone(),
+                    ..Default::default()
+                });
+                res.reason.push(err);
+                res.failed_series.push(series_plan.series_folder.clone());
+                ready[i] = false;
+            }
+        }
+
+        // 同一 series 的 per-instance 分組資料夾並行下載，共用一個進度追蹤器
+        let mut downloaded: Vec<(usize, Vec<DownloadResult>)> = Vec::new();
+        let mut durations = vec![Duration::ZERO; plan.series.len()];
+        let mut instance_ids: Vec<Vec<String>> = vec![Vec::new(); plan.series.len()];
+        // instance_number 命名時的 Orthanc instance ID → 檔名；未列入者用 `<id>.dcm`
+        let mut file_names: HashMap<String, String> = HashMap::new();
+        let mut pending_conversions = Vec::new();
+        let mut skipped_conversions = Vec::new();
+        // 本次有新下載 instance 的 series：既有 NIfTI 可能來自不完整的先前下載，須重新轉檔
+        let mut fetched = vec![false; plan.series.len()];
+        for group in group_by_source_series(&plan.series) {
+            if client.auth_failed() {
+                break;
+            }
+            let group: Vec<usize> = group.into_iter().filter(|&i| ready[i]).collect();
+            if group.is_empty() {
+                continue;
+            }
+            let label = group
+                .iter()
+                .map(|&i| plan.series[i].series_folder.as_str())
+                .collect::<Vec<_>>()
+                .join("+");
+            let total: usize = group.iter().map(|&i| plan.series[i].instances.len()).sum();
+            let mut convertible: Vec<(usize, &str)> = Vec::new();
+
+            if *instance_naming == InstanceNaming::InstanceNumber {
+                // 同組共用同一個 Orthanc series，一次查出全部 InstanceNumber
+                let source = &plan.series[group[0]].source_series;
+                match client.get_series_instance_tags(source).await {
+                    Ok(tags) => file_names.extend(instance_number_file_names(&tags)),
+                    Err(e) => warn!(
+                        "{}: InstanceNumber lookup failed, naming files by Orthanc ID: {}",
+                        label, e
+                    ),
+                }
+            }
+            for &i in &group {
+                let series_plan = &plan.series[i];
+                let reused =
+                    adopt_by_sop_uid(&client, series_plan, &series_dirs[i], &file_names).await;
+                if reused > 0 {
+                    res.notes.push(format!(
+                        "{}/{}: {} instance(s) already on disk under another Orthanc ID, reused by SOPInstanceUID",
+                        plan.study_folder, series_plan.series_folder, reused
+                    ));
+                }
+            }
+            for &i in &group {
+                events::emit(Event::SeriesStarted {
+                    accession: &acc,
+                    study_folder: &plan.study_folder,
+                    series_folder: &plan.series[i].series_folder,
+                    instances: plan.series[i].instances.len(),
+                });
+            }
+            let tracker = Arc::new(match (&aggregate, &collapse_log) {
+                (Some(pb), Some(log)) => DownloadProgressTracker::collapsed(
+                    pb,
+                    &format!("{}/{}", plan.study_folder, label),
+                    log.clone(),
+                ),
+                _ => DownloadProgressTracker::new(total, &mp, &label),
+            });
+
+            let group_results: Vec<(usize, Vec<InstanceOutcome>, Duration)> = stream::iter(group)
+                .map(|i| {
+                    let series_dir = series_dirs[i].clone();
+                    let series_folder = plan.series[i].series_folder.clone();
+                    let instances = plan.series[i].instances.clone();
+                    let prefetched = plan.series[i].prefetched.clone();
+                    let tracker = tracker.clone();
+                    let client = client.clone();
+                    let acc = &acc;
+                    let file_names = &file_names;
+                    async move {
+                        let started = Instant::now();
+                        let prefetched = &prefetched;
+                        let results: Vec<InstanceOutcome> = stream::iter(instances)
+                            .map(|inst_id| {
+                                let client = client.clone();
+                                let dir = series_dir.clone();
+                                let tracker = tracker.clone();
+                                let series_folder = &series_folder;
+                                async move {
+                                    let file_name = file_names
+                                        .get(&inst_id)
+                                        .cloned()
+                                        .unwrap_or_else(|| safe_dicom_filename(&inst_id));
+                                    let dest_path = dir.join(file_name);
+                                    let mut result = match prefetched
+                                        .as_ref()
+                                        .filter(|p| p.instance_id == inst_id)
+                                    {
+                                        Some(p) => {
+                                            write_prefetched(&p.data, &dest_path, file_slots).await
+                                        }
+                                        None => {
+                                            download_instance_to_file(
+                                                &client, &inst_id, &dest_path, file_slots,
+                                            )
+                                            .await
+                                        }
+                                    };
+                                    if let (true, DownloadResult::Completed(bytes)) =
+                                        (validate_enabled, &result)
+                                    {
+                                        result =
+                                            validate_written(&dest_path, &inst_id, *bytes).await;
+                                    }
+                                    tracker.update(&result);
+                                    events::emit(Event::instance_finished(
+                                        acc,
+                                        series_folder,
+                                        &inst_id,
+                                        &result,
+                                    ));
+                                    (inst_id, result)
+                                }
+                            })
+                            .buffer_unordered(instance_concurrency)
+                            .collect()
+                            .await;
+                        (i, results, started.elapsed())
+                    }
+                })
+                .buffer_unordered(per_instance_config.get_group_concurrency())
+                .collect()
+                .await;
+
+            tracker.finish();
+            if batch.is_some() {
+                tracker.retire(&mp);
+            }
+            for (i, results, elapsed) in group_results {
+                durations[i] = elapsed;
+                let (ids, results): (Vec<String>, Vec<DownloadResult>) =
+                    results.into_iter().unzip();
+                instance_ids[i] = ids;
+                let series_plan = &plan.series[i];
+                let final_dir = study_dir_of(series_plan).join(&series_plan.series_folder);
+                if let Err(e) = finalize_series_dir(&results, &mut series_dirs[i], final_dir).await
+                {
+                    res.reason.push(e);
+                }
+                fetched[i] = results
+                    .iter()
+                    .any(|r| matches!(r, DownloadResult::Completed(_)));
+                let failures = results.iter().filter(|r| is_failure(r)).count();
+                if failures < results.len() && !series_plan.non_image {
+                    convertible.push((i, series_plan.series_folder.as_str()));
+                }
+                downloaded.push((i, results));
+            }
+            // 轉檔交給背景 worker，與後續 series 的下載重疊；刪除 DICOM 等到結果回來才做
+            if let (Some(pool), true) = (conversions, dcm2niix_available) {
+                for rows in conversion_jobs(&convertible, conversion_config.get_dwi_mode()) {
+                    let (label, series_name) = match rows.as_slice() {
+                        [i] => (
+                            plan.series[*i].series_folder.clone(),
+                            output_names[*i].clone(),
+                        ),
+                        _ => (DWI_4D_NAME.to_string(), DWI_4D_NAME.to_string()),
+                    };
+                    // 重跑批次時不重做已完成的轉檔（--reconvert 強制重做）；
+                    // 本次補抓到 instance 時舊輸出已過時，刪除後重新轉檔
+                    let exists = has_nifti_output(&niix_study_dir, &series_name);
+                    if exists && !reconvert && !rows.iter().any(|&i| fetched[i]) {
+                        info!(
+                            "{}: {}.nii.gz exists, conversion skipped",
+                            label, series_name
+                        );
+                        skipped_conversions.extend(rows);
+                        continue;
+                    }
+                    if exists {
+                        if let Err(e) = remove_nifti_outputs(
+                            &niix_study_dir,
+                            &series_name,
+                            audit.as_deref(),
+                            "reconvert_downloaded",
+                        )
+                        .await
+                        {
+                            res.reason.push(format!(
+                                "Conversion skipped for {}: stale output kept: {:#}",
+                                label, e
+                            ));
+                            continue;
+                        }
+                    }
+                    let job = ConversionJob {
+                        dicom_dirs: rows.iter().map(|&i| series_dirs[i].clone()).collect(),
+                        output_dir: niix_study_dir.clone(),
+                        series_name: series_name.clone(),
+                    };
+                    let rx = pool.submit(job).await;
+                    pending_conversions.push((rows, label, series_name, rx));
+                }
+            }
+        }
+        downloaded.sort_by_key(|(i, _)| *i);
+
+        // DICOMDIR 須在轉檔刪除 DICOM 前建立（media 目錄以 hard link 鏡像檔案）
+        if let Some(media_root) = media_root {
+            let any_file = downloaded
+                .iter()
+                .flat_map(|(_, r)| r)
+                .any(|r| matches!(r, DownloadResult::Completed(_) | DownloadResult::Skipped));
+            if any_file {
+                let src = dicom_study_dir.clone();
+                let dst = media_root.join(&plan.study_folder);
+                match tokio::task::spawn_blocking(move || write_study_dicomdir(&src, &dst)).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => res
+                        .reason
+                        .push(format!("DICOMDIR failed for {}: {}", plan.study_folder, e)),
+                    Err(e) => warn!("DICOMDIR task failed: {}", e),
+                }
+            }
+        }
+
+        let mut study_complete = downloaded.len() == plan.series.len();
+        // 無失敗 instance 且已從 .partial 改名的 series，轉檔後才可刪除其 DICOM
+        let mut series_complete = vec![false; plan.series.len()];
+        let mut series_rows: HashMap<usize, usize> = HashMap::new();
+        for (i, results) in downloaded {
+            let series_plan = &plan.series[i];
+            if let Some(note) = &series_plan.analyzer_note {
+                res.notes.push(format!(
+                    "{}/{}: analyzer bypassed ({}), classified from headers",
+                    plan.study_folder, series_plan.series_folder, note
+                ));
+            }
+            let series_dir = series_dirs[i].clone();
+
+            for r in &results {
+                if let DownloadResult::Invalid(e) = r {
+                    res.validation_failures
+                        .push(format!("{}/{}", series_plan.series_folder, e));
+                }
+            }
+
+            res.bytes_downloaded += results
+                .iter()
+                .map(|r| match r {
+                    DownloadResult::Completed(bytes) => *bytes,
+                    _ => 0,
+                })
+                .sum::<u64>();
+
+            // 重試後仍失敗的 instance 記入 failed_instances.json，供 `retry-instances` 補抓
+            for (inst_id, r) in instance_ids[i].iter().zip(&results) {
+                if let Some(error) = failure_text(r) {
+                    res.failed_instances.push(FailedInstance {
+                        accession: acc.clone(),
+                        study_folder: plan.study_folder.clone(),
+                        series_folder: series_plan.series_folder.clone(),
+                        instance_id: inst_id.clone(),
+                        file_name: file_names.get(inst_id).cloned(),
+                        sop_instance_uid: None,
+                        series_dir: series_dir.clone(),
+                        error,
+                    });
+                }
+            }
+
+            let failures = results
+                .iter()
+                .filter(|r| {
+                    matches!(
+                        r,
+                        DownloadResult::Failed(_)
+                            | DownloadResult::Invalid(_)
+                            | DownloadResult::NotAttempted
+                    )
+                })
+                .count();
+
+            let series_download_success = if failures == 0 {
+                res.matched_series.push(series_plan.series_folder.clone());
+                res.downloaded_series
+                    .push(series_plan.series_folder.clone());
+                any_success = true;
+                true
+            } else if failures < results.len() {
+                res.matched_series.push(series_plan.series_folder.clone());
+                res.downloaded_series
+                    .push(series_plan.series_folder.clone());
+                res.reason.push(format!(
+                    "{} failed out of {} instances for {} (kept in {})",
+                    failures,
+                    results.len(),
+                    series_plan.series_folder,
+                    series_dir
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                ));
+                any_success = true;
+                true
+            } else {
+                res.failed_series.push(series_plan.series_folder.clone());
+                res.reason.push(format!(
+                    "All instances failed for {}",
+                    series_plan.series_folder
+                ));
+                false
+            };
+            study_complete &= series_download_success;
+            series_complete[i] = failures == 0
+                && series_dir == study_dir_of(series_plan).join(&series_plan.series_folder);
+
+            // 逐 series 報告列（`--report-detail series`），大小須在轉檔刪除 DICOM 前計算
+            let bytes = {
+                let dir = series_dir.clone();
+                tokio::task::spawn_blocking(move || dicom_bytes(&dir))
+                    .await
+                    .unwrap_or(0)
+            };
+            let row = res.series.len();
+            res.series.push(series_row(
+                &plan.study_folder,
+                series_plan,
+                &results,
+                bytes,
+                durations[i],
+            ));
+            let report = &res.series[row];
+            events::emit(Event::SeriesFinished {
+                accession: &acc,
+                study_folder: &report.study_folder,
+                series_folder: &report.series_folder,
+                status: &report.status,
+                downloaded: report.downloaded_instances,
+                skipped: report.skipped_instances,
+                failed: report.failed_instances,
+                bytes: report.bytes,
+                duration_ms: report.duration_ms,
+            });
+
+            // 動態序列（DSC/ASL 或標籤顯示多個時間點）輸出時間排序檔，須在轉檔刪除 DICOM 前執行
+            if series_download_success {
+                let dir = series_dir.clone();
+                let folder = series_plan.series_folder.clone();
+                let ordering = tokio::task::spawn_blocking(move || {
+                    if is_dynamic_series(&folder, &dir) {
+                        write_ordering_file(&dir).map(Some)
+                    } else {
+                        Ok(None)
+                    }
+                });
+                match ordering.await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => warn!(
+                        "Failed to write {} for {}: {}",
+                        ORDERING_FILE, series_plan.series_folder, e
+                    ),
+                    Err(e) => warn!("ordering task failed: {}", e),
+                }
+            }
+
+            // 像素資料 QC：取樣一個 instance 解碼，須在轉檔刪除 DICOM 前執行
+            if qc_enabled && series_download_success && !series_plan.non_image {
+                let dir = series_dir.clone();
+                match tokio::task::spawn_blocking(move || check_series(&dir)).await {
+                    Ok(Some(finding)) => res
+                        .qc_issues
+                        .push(format!("{}: {}", series_plan.series_folder, finding)),
+                    Ok(None) => {}
+                    Err(e) => warn!("QC task failed: {}", e),
+                }
+            }
+
+            // preview.png：供人工快速瀏覽，失敗只記 log
+            if *preview_enabled && series_download_success && !series_plan.non_image {
+                if let Err(e) = write_series_preview(&client, series_plan, &series_dir).await {
+                    warn!("{}: {:#}", series_plan.series_folder, e);
+                }
+            }
+
+            // series.json：UID、類型、TE/TR 與 Orthanc ID，須在轉檔刪除 DICOM 前執行
+            if series_download_success {
+                let dir = series_dir.clone();
+                let sidecar_plan = series_plan.clone();
+                match tokio::task::spawn_blocking(move || write_series_sidecar(&dir, &sidecar_plan))
+                    .await
+                {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("{:#}", e),
+                    Err(e) => warn!("sidecar task failed: {}", e),
+                }
+            }
+
+            // 寫入 checksums.sha256，供 `verify` 檢查檔案完整性
+            if series_download_success {
+                let dir = series_dir.clone();
+                match tokio::task::spawn_blocking(move || write_manifest(&dir)).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => res.reason.push(format!(
+                        "Checksum manifest failed for {}: {}",
+                        series_plan.series_folder, e
+                    )),
+                    Err(e) => warn!("checksum task failed: {}", e),
+                }
+            }
+
+            series_rows.insert(i, row);
+        }
+
+        // 等待本 study 的背景轉檔；標記、刪除來源與打包都須在轉檔之後
+        // 每個 series 的所有轉檔（含 4D DWI）都成功才可刪除其 DICOM
+        let mut converted = vec![None::<bool>; plan.series.len()];
+        let mut nifti_outputs = vec![Vec::new(); plan.series.len()];
+        for (rows, label, output, rx) in pending_conversions {
+            let conv_result = rx
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("conversion worker stopped")));
+            let nifti_files = conv_result
+                .as_ref()
+                .map(|result| result.nifti_files.clone())
+                .unwrap_or_default();
+            let provenance = conv_result.as_ref().ok().map(|result| SidecarConversion {
+                output,
+                converter: result.converter.clone(),
+                command: result.command.clone(),
+            });
+            let (ok, error) = match conv_result {
+                Ok(result) if result.success => (true, None),
+                // Conversion ran but produced no NIfTI files (e.g., SR DICOM)
+                Ok(result) => (
+                    false,
+                    result
+                        .error
+                        .map(|err| format!("Conversion produced no output for {}: {}", label, err)),
+                ),
+                Err(e) => (
+                    false,
+                    Some(format!("Conversion failed for {}: {}", label, e)),
+                ),
+            };
+            if ok {
+                res.converted_series.push(label);
+            } else {
+                res.conversion_failed.push(label);
+            }
+            // 4D DWI 與各別轉檔並存時，報告列的狀態以各別轉檔為準
+            let merged = rows.len() > 1;
+            for &i in &rows {
+                converted[i] = Some(converted[i].unwrap_or(true) && ok);
+                nifti_outputs[i].extend(nifti_files.iter().cloned());
+                let Some(&row) = series_rows.get(&i) else {
+                    continue;
+                };
+                let report = &mut res.series[row];
+                if !merged || report.conversion.is_empty() {
+                    report.conversion = if ok { "Converted" } else { "ConversionFailed" }.into();
+                    if let Some(provenance) = &provenance {
+                        report.converter = provenance.converter.clone();
+                        report.conversion_command = provenance.command.clone();
+                    }
+                }
+                if let Some(msg) = &error {
+                    push_series_error(report, msg);
+                }
+            }
+            res.reason.extend(error);
+            // series.json 記錄轉檔工具版本與指令，供重現分析時稽核
+            if let Some(provenance) = provenance.filter(|_| ok) {
+                let dirs: Vec<PathBuf> = rows.iter().map(|&i| series_dirs[i].clone()).collect();
+                let recorded = tokio::task::spawn_blocking(move || {
+                    dirs.iter()
+                        .try_for_each(|dir| record_conversion(dir, provenance.clone()))
+                })
+                .await;
+                match recorded {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("{:#}", e),
+                    Err(e) => warn!("sidecar task failed: {}", e),
+                }
+            }
+        }
+        for i in skipped_conversions {
+            if let Some(&row) = series_rows.get(&i) {
+                let report = &mut res.series[row];
+                if report.conversion.is_empty() {
+                    report.conversion = "Skipped".into();
+                }
+            }
+        }
+        // Optionally delete DICOM files after successful conversion
+        if conversion_config.should_delete_dicom() {
+            let checks = conversion_config.delete_checks();
+            for (i, ok) in converted.into_iter().enumerate() {
+                if ok != Some(true) {
+                    continue;
+                }
+                let series_folder = &plan.series[i].series_folder;
+                // 未下載完整的 series 保留 DICOM，下次執行才能續傳與驗證
+                if !series_complete[i] {
+                    res.notes.push(format!(
+                        "{}/{}: DICOM files kept, series not completely downloaded",
+                        plan.study_folder, series_folder
+                    ));
+                    continue;
+                }
+                // NIfTI 未通過檢查（損毀、volume 不足）時保留 DICOM
+                let files = std::mem::take(&mut nifti_outputs[i]);
+                let verified = tokio::task::spawn_blocking(move || checks.verify(&files))
+                    .await
+                    .unwrap_or_else(|e| Err(anyhow!("NIfTI check task failed: {}", e)));
+                if let Err(e) = verified {
+                    res.reason
+                        .push(format!("DICOM files kept for {}: {:#}", series_folder, e));
+                    continue;
+                }
+                let series_dir = &series_dirs[i];
+                let removed = match trash {
+                    Some(trash) => {
+                        let relative = series_dir
+                            .strip_prefix(dicom_root)
+                            .unwrap_or_else(|_| Path::new(series_folder));
+                        trash
+                            .move_dicom_files(series_dir, relative, audit.as_deref())
+                            .await
+                    }
+                    None => delete_dicom_files(series_dir, audit.as_deref()).await,
+                };
+                if let Err(e) = removed {
+                    res.reason.push(format!(
+                        "Failed to delete DICOM files for {}: {}",
+                        series_folder, e
+                    ));
+                } else {
+                    // manifest 所列檔案已刪除，一併移除避免 verify 誤報
+                    let _ = fs::remove_file(series_dir.join(MANIFEST_FILE)).await;
+                }
+            }
+        }
+
+        // 整個 study 無任何錯誤（含轉檔）才在 Orthanc 標記，下次 --skip-processed 即略過
+        if let Some(marker) = processed.as_ref().filter(|m| m.mark) {
+            if study_complete && res.reason.len() == reasons_before {
+                match marker.mark_study(&client, &plan.study_id).await {
+                    Ok(()) => res.notes.push(format!(
+                        "{}: marked {}={} in Orthanc",
+                        plan.study_folder, marker.metadata, marker.run_id
+                    )),
+                    Err(e) => {
+                        warn!("{}: could not mark as processed: {}", plan.study_folder, e);
+                        res.notes.push(format!(
+                            "{}: not marked as processed: {}",
+                            plan.study_folder, e
+                        ));
+                    }
+                }
+            }
+        }
+
+        // 從 Orthanc 刪除前須確認整個 study 已下載且通過 checksum 驗證；須在打包前（打包會移除資料夾）
+        if let Some(mode) = purge {
+            let outcome = if study_complete {
+                purge_study(
+                    &client,
+                    &plan.study_id,
+                    &dicom_study_dir,
+                    &series_dirs,
+                    *mode,
+                    audit.as_deref(),
+                )
+                .await
+            } else {
+                Err("study has incomplete series".to_string())
+            };
+            match outcome {
+                Ok(note) => res.notes.push(format!("{}: {}", plan.study_folder, note)),
+                Err(e) => {
+                    warn!("{}: kept on Orthanc: {}", plan.study_folder, e);
+                    res.notes.push(format!(
+                        "{}: not purged from Orthanc: {}",
+                        plan.study_folder, e
+                    ));
+                }
+            }
+        }
+
+        // 整個 study 完成（無 .partial series）才打包，須在 DICOMDIR 與轉檔之後
+        if let (Some(format), true) = (package, study_complete) {
+            let dir = dicom_study_dir.clone();
+            let format = *format;
+            let encryption = encryption.clone();
+            let companions = companion_dirs.clone();
+            let packaged = tokio::task::spawn_blocking(move || -> Result<Vec<PathBuf>> {
+                let archive = package_study(&dir, format)?;
+                match encryption {
+                    Some(age) => {
+                        let mut archives = vec![age.encrypt_file(&archive)?];
+                        archives.extend(encrypt_companions(&age, &companions, format)?);
+                        Ok(archives)
+                    }
+                    None => Ok(vec![archive]),
+                }
+            });
+            match packaged.await {
+                Ok(Ok(archives)) => res.notes.push(format!(
+                    "{}: packaged as {}",
+                    plan.study_folder,
+                    archives
+                        .iter()
+                        .map(|a| a.file_name().unwrap_or_default().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+                Ok(Err(e)) => res.reason.push(format!(
+                    "Packaging failed for {}: {:#}",
+                    plan.study_folder, e
+                )),
+                Err(e) => warn!("packaging task failed: {}", e),
+            }
+        }
+    }
+
+    if let Some(pb) = aggregate {
+        pb.finish_with_message(format!("{} done", acc));
+        if batch.is_some() {
+            mp.remove(&pb);
+        }
+    }
+    if client.auth_failed() {
+        res.reason.push(AUTH_FAILED_REASON.into());
+    } else {
+        // 補上 SOPInstanceUID，方便在 PACS 端追查；查詢失敗不影響結果
+        for entry in &mut res.failed_instances {
+            if let Ok(uid) = client.get_instance_sop_uid(&entry.instance_id).await {
+                entry.sop_instance_uid = uid;
+            }
+        }
+    }
+
+    res.status = summarize_status(&res.downloaded_series, &res.reason);
+    if !any_success && res.status == "Success" {
+        res.status = "Failed".into();
+    }
+    res
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::{AuthConfig, HttpConfig};
+    use crate::retry::RetryPolicy;
+    use std::sync::Mutex;
+
+    /// Orthanc stand-in for end-to-end download tests: accession `A1` is study `study-1`
+    /// (patient `P1`, 2024-01-01) whose series are given as `(id, description, instances)`.
+    /// Instances in `failing` answer 500; `downloads` counts the files served.
+    pub(crate) struct FakeOrthanc {
+        pub url: String,
+        pub failing: Arc<Mutex<HashSet<String>>>,
+        pub downloads: Arc<AtomicUsize>,
+    }
+
+    impl FakeOrthanc {
+        pub(crate) async fn start(series: &[(&str, &str, &[&str])]) -> Self {
+            use axum::extract::Path as UrlPath;
+            use axum::http::StatusCode;
+            use axum::routing::{get, post};
+            use axum::Json;
+            use serde_json::json;
+
+            let expanded: Vec<serde_json::Value> = series
+                .iter()
+                .map(|(id, description, instances)| {
+                    json!({
+                        "ID": id,
+                        "MainDicomTags": {
+                            "SeriesDescription": description,
+                            "Modality": "MR",
+                        },
+                        "Instances": instances,
+                    })
+                })
+                .collect();
+            let failing = Arc::new(Mutex::new(HashSet::new()));
+            let downloads = Arc::new(AtomicUsize::new(0));
+            let (fail, count) = (failing.clone(), downloads.clone());
+            let app = axum::Router::new()
+                .route("/tools/find", post(|| async { Json(json!(["study-1"])) }))
+                .route(
+                    "/studies/:id",
+                    get(|| async {
+                        Json(json!({
+                            "MainDicomTags": {
+                                "StudyInstanceUID": "1.2.3",
+                                "StudyDate": "20240101",
+                                "AccessionNumber": "A1",
+                            },
+                            "PatientMainDicomTags": { "PatientID": "P1" },
+                        }))
+                    }),
+                )
+                .route(
+                    "/studies/:id/series",
+                    get(move || async move { Json(json!(expanded)) }),
+                )
+                .route(
+                    "/instances/:id/file",
+                    get(move |UrlPath(id): UrlPath<String>| async move {
+                        if fail.lock().unwrap().contains(&id) {
+                            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                        }
+                        count.fetch_add(1, Ordering::SeqCst);
+                        Ok(format!("DICOM {}", id))
+                    }),
+                );
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            Self {
+                url,
+                failing,
+                downloads,
+            }
+        }
+
+        /// Client without retries, so failing instances fail at once.
+        pub(crate) fn client(&self) -> Arc<OrthancClient> {
+            let http = HttpConfig {
+                retry: RetryPolicy {
+                    max_retries: 0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            Arc::new(OrthancClient::new(&self.url, "", "", &AuthConfig::default(), &http).unwrap())
+        }
+    }
+
+    /// Download settings with every optional step off, writing under `output`.
+    pub(crate) fn test_context(output: &Path) -> DownloadContext {
+        DownloadContext {
+            dicom_root: output.join("dicom"),
+            niix_root: output.join("niix"),
+            instance_concurrency: 2,
+            analyze_enabled: false,
+            convert_enabled: false,
+            reconvert: false,
+            conversion_config: ConversionConfig::default(),
+            conversions: None,
+            per_instance_config: PerInstanceConfig::default(),
+            media_root: None,
+            qc_enabled: false,
+            preview_enabled: false,
+            validate_enabled: false,
+            progress_log: None,
+            state: None,
+            file_slots: FileSlots::new(64),
+            audit: None,
+            trash: None,
+            hide_progress: true,
+            batch: None,
+            layout: OutputLayout::default(),
+            instance_naming: InstanceNaming::default(),
+            package: None,
+            encryption: None,
+            series_filter: SeriesFilter::default(),
+            classifier: LocalClassifier::default(),
+            study_selection: StudySelection::default(),
+            duplicate_policy: DuplicatePolicy::default(),
+            purge: None,
+            plan_concurrency: 1,
+            sample_count: 1,
+            reuse_analysis: false,
+            processed: None,
+        }
+    }
+
+    fn plan(source: &str, folder: &str) -> SeriesDownloadPlan {
+        SeriesDownloadPlan {
+            source_series: source.into(),
+            series_folder: folder.into(),
+            series_type: folder.into(),
+            description: None,
+            series_number: None,
+            instances: vec![],
+            analyzer_note: None,
+            non_image: false,
+            prefetched: None,
+        }
+    }
+
+    #[test]
+    fn test_conversion_jobs_merge_dwi_shells() {
+        let series = [(0, "DWI0"), (1, "DWI1000"), (2, "T1")];
+        assert_eq!(
+            conversion_jobs(&series, DwiMode::Separate),
+            vec![vec![0], vec![1], vec![2]]
+        );
+        assert_eq!(
+            conversion_jobs(&series, DwiMode::Merged),
+            vec![vec![2], vec![0, 1]]
+        );
+        assert_eq!(
+            conversion_jobs(&series, DwiMode::Both),
+            vec![vec![0], vec![1], vec![2], vec![0, 1]]
+        );
+        // 只有一半時照常各別轉檔
+        assert_eq!(
+            conversion_jobs(&[(0, "DWI0")], DwiMode::Merged),
+            vec![vec![0]]
+        );
+    }
+
+    #[test]
+    fn test_sample_indices_spread_from_first() {
+        assert_eq!(sample_indices(10, 3), vec![0, 3, 6]);
+        assert_eq!(sample_indices(2, 5), vec![0, 1]);
+        assert_eq!(sample_indices(7, 1), vec![0]);
+    }
+
+    #[test]
+    fn test_majority_type_prefers_earliest_on_tie() {
+        let votes = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            majority_type(&votes(&["SCOUT", "T1", "T1"])),
+            Some(("T1".to_string(), 2))
+        );
+        assert_eq!(
+            majority_type(&votes(&["T2", "T1"])),
+            Some(("T2".to_string(), 1))
+        );
+        assert_eq!(majority_type(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_write_prefetched_instance() {
+        let dir = std::env::temp_dir().join(format!("prefetch_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dest = dir.join("i1.dcm");
+        let slots = FileSlots::new(8);
+        let kept = PrefetchedInstance::keep("i1", b"DICM").unwrap();
+        assert!(matches!(
+            write_prefetched(&kept.data, &dest, &slots).await,
+            DownloadResult::Completed(4)
+        ));
+        assert_eq!(std::fs::read(&dest).unwrap(), b"DICM");
+        assert!(!part_path(&dest).exists());
+        assert!(matches!(
+            write_prefetched(&kept.data, &dest, &slots).await,
+            DownloadResult::Skipped
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_partial_dir() {
+        assert_eq!(
+            partial_dir(Path::new("dicom/STUDY/T1")),
+            PathBuf::from("dicom/STUDY/T1.partial")
+        );
+    }
+
+    #[test]
+    fn test_group_by_source_series() {
+        let series = vec![
+            plan("a", "T1"),
+            plan("b", "DWI0"),
+            plan("b", "DWI1000"),
+            plan("c", "ADC"),
+        ];
+        assert_eq!(
+            group_by_source_series(&series),
+            vec![vec![0], vec![1, 2], vec![3]]
+        );
+        assert!(group_by_source_series(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_series_row_counts() {
+        let mut p = plan("a", "T1");
+        p.instances = vec!["1".into(), "2".into(), "3".into(), "4".into()];
+        let results = [
+            DownloadResult::Completed(512),
+            DownloadResult::Skipped,
+            DownloadResult::Failed("timeout".into()),
+            DownloadResult::Failed("timeout".into()),
+        ];
+        let row = series_row("STUDY", &p, &results, 1024, Duration::from_millis(1500));
+        assert_eq!(row.status, "Partial");
+        assert_eq!(
+            (
+                row.expected_instances,
+                row.downloaded_instances,
+                row.skipped_instances,
+                row.failed_instances
+            ),
+            (4, 1, 1, 2)
+        );
+        assert_eq!((row.bytes, row.duration_ms), (1024, 1500));
+        assert_eq!(row.error, "timeout");
+    }
+
+    /// Conversion pool running a stand-in for dcm2niix that writes a valid one-volume
+    /// `<name>.nii` and records the number of input `.dcm` files in `<name>.json`.
+    #[cfg(unix)]
+    fn fake_conversions(dir: &Path) -> ConversionPool {
+        use crate::converter::{ConversionRunner, Dcm2niixConverter};
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::create_dir_all(dir).unwrap();
+        let nifti = dir.join("volume.nii");
+        std::fs::write(&nifti, crate::nifti::tests::nifti1(&[2, 2, 1], 2 * 2 * 2)).unwrap();
+        let script = dir.join("fake-dcm2niix");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n[ $# -lt 2 ] && exit 0\nwhile [ $# -gt 1 ]; do case \"$1\" in -f) name=\"$2\";; -o) out=\"$2\";; esac; shift; done\nls \"$1\" | grep -c '\\.dcm$' > \"$out/$name.json\"\ncp \"{}\" \"$out/$name.nii\"\n",
+                nifti.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let runner = ConversionRunner::new(Arc::new(Dcm2niixConverter {
+            path: script.to_string_lossy().to_string(),
+            args: vec![],
+        }));
+        ConversionPool::new(1, runner, FileSlots::new(64))
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_series_completed_later_is_reconverted() {
+        let orthanc = FakeOrthanc::start(&[("series-1", "T1", &["i1", "i2"])]).await;
+        orthanc.failing.lock().unwrap().insert("i2".to_string());
+        let output = std::env::temp_dir().join(format!("reconvert_partial_{}", std::process::id()));
+        let mut ctx = test_context(&output);
+        ctx.convert_enabled = true;
+        ctx.conversions = Some(fake_conversions(&output.join("tools")));
+        let client = orthanc.client();
+        let converted_from = || {
+            std::fs::read_to_string(output.join("niix/P1_20240101_MR_A1/T1.json"))
+                .unwrap()
+                .trim()
+                .to_string()
+        };
+
+        // 第一次只下載到一半，部分 series 仍會轉檔
+        let claims = StudyClaims::default();
+        download_accession_v2(client.clone(), "A1".into(), &ctx, &claims).await;
+        assert_eq!(converted_from(), "1");
+
+        // 補抓到缺少的 instance 後，舊的 NIfTI 須刪除並重新轉檔
+        orthanc.failing.lock().unwrap().clear();
+        let claims = StudyClaims::default();
+        let res = download_accession_v2(client.clone(), "A1".into(), &ctx, &claims).await;
+        assert_eq!(res.status, "Success", "{:?}", res.reason);
+        assert_eq!(converted_from(), "2");
+        assert_eq!(res.series[0].conversion, "Converted");
+
+        // 沒有新下載的 instance 時沿用既有輸出
+        let claims = StudyClaims::default();
+        let res = download_accession_v2(client, "A1".into(), &ctx, &claims).await;
+        assert_eq!(res.series[0].conversion, "Skipped");
+        let _ = std::fs::remove_dir_all(&output);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_partial_series_keeps_dicom_after_conversion() {
+        let orthanc = FakeOrthanc::start(&[("series-1", "T1", &["i1", "i2"])]).await;
+        orthanc.failing.lock().unwrap().insert("i2".to_string());
+        let output = std::env::temp_dir().join(format!("delete_partial_{}", std::process::id()));
+        let mut ctx = test_context(&output);
+        ctx.convert_enabled = true;
+        ctx.conversions = Some(fake_conversions(&output.join("tools")));
+        ctx.conversion_config.delete_dicom_after_conversion = Some(true);
+        let client = orthanc.client();
+        let study = output.join("dicom/P1_20240101_MR_A1");
+
+        // 轉檔成功但 series 仍在 .partial：DICOM 須保留供下次續傳
+        let claims = StudyClaims::default();
+        let res = download_accession_v2(client.clone(), "A1".into(), &ctx, &claims).await;
+        assert_eq!(res.series[0].conversion, "Converted");
+        assert!(study.join("T1.partial/i1.dcm").exists());
+        assert!(res.notes.iter().any(|n| n.contains("DICOM files kept")));
+
+        // 補齊後才刪除
+        orthanc.failing.lock().unwrap().clear();
+        let claims = StudyClaims::default();
+        let res = download_accession_v2(client, "A1".into(), &ctx, &claims).await;
+        assert_eq!(res.status, "Success", "{:?}", res.reason);
+        assert!(study.join("T1").is_dir());
+        assert!(!study.join("T1/i1.dcm").exists());
+        assert!(!study.join("T1/i2.dcm").exists());
+        let _ = std::fs::remove_dir_all(&output);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_companion_folders_are_encrypted() {
+        use crate::config::EncryptionConfig;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("companions_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("niix/S1")).unwrap();
+        std::fs::write(dir.join("niix/S1/T1.nii.gz"), b"nifti").unwrap();
+        // 以 cp 代替 age：只複製 -o 之後的輸出路徑
+        let fake_age = dir.join("fake-age");
+        std::fs::write(
+            &fake_age,
+            "#!/bin/sh\n[ \"$1\" = --version ] && exit 0\nwhile [ $# -gt 1 ]; do [ \"$1\" = -o ] && out=\"$2\"; shift; done\ncp \"$1\" \"$out\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&fake_age, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let age = AgeEncryptor::new(&EncryptionConfig {
+            recipients: Some(vec!["age1test".into()]),
+            age_path: Some(fake_age.to_string_lossy().to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+
+        let companions = [dir.join("niix/S1"), dir.join("nonimage/S1")];
+        let archives = encrypt_companions(&age, &companions, PackageFormat::Zip).unwrap();
+        assert_eq!(archives, vec![dir.join("niix/S1.zip.age")]);
+        assert!(!dir.join("niix/S1").exists());
+        assert!(!dir.join("niix/S1.zip").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
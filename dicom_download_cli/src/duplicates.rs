@@ -0,0 +1,178 @@
+//! What `download` does when two studies of one run land in the same study folder
+//! (`duplicate_studies`, `download --duplicate-studies`).
+//!
+//! That happens when two accessions of the batch resolve to the same StudyInstanceUID
+//! (the same accession listed twice, or an order merged under two numbers), or when one
+//! accession matches several Orthanc studies whose tags give the same folder name.
+//! `merge` (default) keeps the earlier behaviour and writes both into one folder;
+//! `skip-duplicates` downloads only the first claim; `suffix-folders` writes later ones to
+//! `<folder>_2`, `<folder>_3`, ... Every collision is recorded as a note on the accession.
+//!
+//! Claims last for one batch (a `download` run, one `serve` job, or one list dropped into
+//! `watch`), so an accession submitted again later is downloaded again.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    SkipDuplicates,
+    #[default]
+    Merge,
+    SuffixFolders,
+}
+
+impl std::str::FromStr for DuplicatePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "skip-duplicates" | "skip" => Ok(Self::SkipDuplicates),
+            "merge" => Ok(Self::Merge),
+            "suffix-folders" | "suffix" => Ok(Self::SuffixFolders),
+            other => Err(anyhow!(
+                "Invalid duplicate study policy '{}': expected skip-duplicates, merge, or suffix-folders",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for DuplicatePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::SkipDuplicates => "skip-duplicates",
+            Self::Merge => "merge",
+            Self::SuffixFolders => "suffix-folders",
+        })
+    }
+}
+
+/// First claim on a study folder in this run.
+struct Claim {
+    study_id: String,
+    accession: String,
+}
+
+/// Where a planned study is written; `folder` is `None` when it is skipped.
+#[derive(Debug, PartialEq)]
+pub struct Placement {
+    pub folder: Option<String>,
+    /// Report note describing the collision and the decision.
+    pub note: Option<String>,
+}
+
+/// Study folders claimed so far, shared by every accession of one batch.
+#[derive(Default)]
+pub struct StudyClaims {
+    claims: Mutex<HashMap<String, Claim>>,
+}
+
+impl StudyClaims {
+    /// Claims `folder` for Orthanc study `study_id` of `accession` and applies `policy`
+    /// when another study or accession claimed it first.
+    pub fn place(
+        &self,
+        policy: DuplicatePolicy,
+        folder: &str,
+        study_id: &str,
+        accession: &str,
+    ) -> Placement {
+        let mut claims = self.claims.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(first) = claims.get(folder) else {
+            claims.insert(
+                folder.to_string(),
+                Claim {
+                    study_id: study_id.to_string(),
+                    accession: accession.to_string(),
+                },
+            );
+            return Placement {
+                folder: Some(folder.to_string()),
+                note: None,
+            };
+        };
+        let collision = if first.study_id == study_id {
+            format!("same study as accession {}", first.accession)
+        } else {
+            format!(
+                "folder already used by another study (accession {})",
+                first.accession
+            )
+        };
+        let (placed, decision) = match policy {
+            DuplicatePolicy::SkipDuplicates => (None, "skipped".to_string()),
+            DuplicatePolicy::Merge => (
+                Some(folder.to_string()),
+                "merged into the same folder".to_string(),
+            ),
+            DuplicatePolicy::SuffixFolders => {
+                let suffixed = (2..)
+                    .map(|n| format!("{}_{}", folder, n))
+                    .find(|f| !claims.contains_key(f))
+                    .unwrap_or_default();
+                claims.insert(
+                    suffixed.clone(),
+                    Claim {
+                        study_id: study_id.to_string(),
+                        accession: accession.to_string(),
+                    },
+                );
+                let decision = format!("written to {}", suffixed);
+                (Some(suffixed), decision)
+            }
+        };
+        Placement {
+            folder: placed,
+            note: Some(format!(
+                "{}: {}; {} (duplicate_studies = {})",
+                folder, collision, decision, policy
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicate_study_placement() {
+        let claims = StudyClaims::default();
+        let first = claims.place(
+            DuplicatePolicy::SkipDuplicates,
+            "P_20240101_MR_A1",
+            "s1",
+            "A1",
+        );
+        assert_eq!(first.folder.as_deref(), Some("P_20240101_MR_A1"));
+        assert!(first.note.is_none());
+
+        let skipped = claims.place(
+            DuplicatePolicy::SkipDuplicates,
+            "P_20240101_MR_A1",
+            "s1",
+            "A2",
+        );
+        assert!(skipped.folder.is_none());
+        assert_eq!(
+            skipped.note.as_deref(),
+            Some(
+                "P_20240101_MR_A1: same study as accession A1; skipped \
+                 (duplicate_studies = skip-duplicates)"
+            )
+        );
+
+        let merged = claims.place(DuplicatePolicy::Merge, "P_20240101_MR_A1", "s2", "A1");
+        assert_eq!(merged.folder.as_deref(), Some("P_20240101_MR_A1"));
+        assert!(merged.note.unwrap().contains("another study"));
+
+        let policy: DuplicatePolicy = "suffix_folders".parse().unwrap();
+        let second = claims.place(policy, "P_20240101_MR_A1", "s2", "A1");
+        let third = claims.place(policy, "P_20240101_MR_A1", "s3", "A1");
+        assert_eq!(second.folder.as_deref(), Some("P_20240101_MR_A1_2"));
+        assert_eq!(third.folder.as_deref(), Some("P_20240101_MR_A1_3"));
+        assert!("overwrite".parse::<DuplicatePolicy>().is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! End-of-run summary email (`--notify-email`, `[smtp]` table).
+//!
+//! When a run ends, `remote`, `download`, and `import` mail the summary (counts, batch time,
+//! failed accessions with reasons) to the recipients, with the CSV report of that run
+//! attached. Runs with failures get a distinct subject so mail filters can route them. The
+//! SMTP settings are checked before the batch starts; a send failure afterwards is only
+//! logged, since the downloads and reports are already on disk.
+
+use anyhow::{anyhow, bail, Context, Result};
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::SmtpConfig;
+use crate::processor::{batch_summary_line, ProcessResult};
+
+/// Failed accessions listed in the body; the attached CSV has the rest.
+const MAX_LISTED_FAILURES: usize = 50;
+
+/// Subject line; mentions failures up front when any accession did not succeed.
+pub fn subject(command: &str, results: &[ProcessResult]) -> String {
+    let failed = results.iter().filter(|r| r.status != "Success").count();
+    if failed == 0 {
+        format!(
+            "[dicom_download_cli] {} finished: all {} accessions succeeded",
+            command,
+            results.len()
+        )
+    } else {
+        format!(
+            "[dicom_download_cli] FAILURES in {}: {} of {} accessions failed",
+            command,
+            failed,
+            results.len()
+        )
+    }
+}
+
+/// Plain-text body: summary, batch time, and the failed accessions.
+pub fn body(command: &str, results: &[ProcessResult], elapsed: Duration) -> String {
+    let ok = results.iter().filter(|r| r.status == "Success").count();
+    let mut text = format!(
+        "dicom_download_cli {} finished.\n\nSummary: {} Success, {} Failed/Partial.\n",
+        command,
+        ok,
+        results.len() - ok
+    );
+    if !results.is_empty() {
+        text.push_str(&batch_summary_line(results, elapsed));
+        text.push('\n');
+    }
+    let failures: Vec<&ProcessResult> = results.iter().filter(|r| r.status != "Success").collect();
+    if !failures.is_empty() {
+        text.push_str("\nFailed accessions:\n");
+        for res in failures.iter().take(MAX_LISTED_FAILURES) {
+            text.push_str(&format!("- {}: {}", res.accession, res.status));
+            if !res.reason.is_empty() {
+                text.push_str(&format!(" ({})", res.reason.join("; ")));
+            }
+            text.push('\n');
+        }
+        if failures.len() > MAX_LISTED_FAILURES {
+            text.push_str(&format!(
+                "... and {} more (see the attached report)\n",
+                failures.len() - MAX_LISTED_FAILURES
+            ));
+        }
+    }
+    text
+}
+
+/// SMTP transport and recipients, validated before the batch starts.
+pub struct SummaryMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl SummaryMailer {
+    /// `None` when there are no recipients.
+    pub fn new(smtp: &SmtpConfig, to: &[String]) -> Result<Option<Self>> {
+        if to.is_empty() {
+            return Ok(None);
+        }
+        let host = smtp
+            .host
+            .as_deref()
+            .ok_or_else(|| anyhow!("--notify-email needs [smtp] host in the config"))?;
+        let from = smtp
+            .from
+            .as_deref()
+            .ok_or_else(|| anyhow!("--notify-email needs [smtp] from in the config"))?
+            .parse::<Mailbox>()
+            .context("Invalid [smtp] from address")?;
+        let to = to
+            .iter()
+            .map(|addr| {
+                addr.parse::<Mailbox>()
+                    .with_context(|| format!("Invalid email address: {}", addr))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut builder = match smtp.security.as_deref().unwrap_or("starttls") {
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            other => bail!(
+                "Invalid [smtp] security '{}' (expected starttls, tls, or none)",
+                other
+            ),
+        };
+        if let Some(port) = smtp.port {
+            builder = builder.port(port);
+        }
+        if let (Some(user), Some(password)) = (&smtp.username, &smtp.password) {
+            builder = builder.credentials(Credentials::new(user.clone(), password.clone()));
+        }
+        Ok(Some(Self {
+            transport: builder.build(),
+            from,
+            to,
+        }))
+    }
+
+    /// Sends the summary with `csv_report` attached (when it exists).
+    pub async fn send(
+        &self,
+        command: &str,
+        results: &[ProcessResult],
+        elapsed: Duration,
+        csv_report: &Path,
+    ) -> Result<()> {
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .subject(subject(command, results));
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+        let mut parts =
+            MultiPart::mixed().singlepart(SinglePart::plain(body(command, results, elapsed)));
+        if let Ok(csv) = std::fs::read(csv_report) {
+            let name = csv_report
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "report.csv".to_string());
+            parts = parts.singlepart(
+                Attachment::new(name).body(csv, ContentType::parse("text/csv").expect("valid")),
+            );
+        }
+        let message = builder.multipart(parts)?;
+        self.transport
+            .send(message)
+            .await
+            .context("Failed to send summary email")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subject_and_body_flag_failures() {
+        let result = |acc: &str, status: &str, reason: &str| ProcessResult {
+            accession: acc.into(),
+            status: status.into(),
+            reason: vec![reason.to_string()],
+            ..Default::default()
+        };
+        let ok = [result("A1", "Success", "")];
+        assert!(subject("download", &ok).contains("all 1 accessions succeeded"));
+
+        let mixed = [
+            result("A1", "Success", ""),
+            result("A2", "Failed", "No studies found"),
+        ];
+        assert_eq!(
+            subject("download", &mixed),
+            "[dicom_download_cli] FAILURES in download: 1 of 2 accessions failed"
+        );
+        let text = body("download", &mixed, Duration::from_secs(90));
+        assert!(text.contains("Summary: 1 Success, 1 Failed/Partial."));
+        assert!(text.contains("- A2: Failed (No studies found)"));
+        assert!(!text.contains("- A1"));
+    }
+}
@@ -0,0 +1,167 @@
+//! Encryption at rest for downloaded studies with [age](https://age-encryption.org).
+//!
+//! With recipients configured in `[encryption]`, `download` packages every completed study
+//! (as with `--package zip`) and encrypts the archive to `dicom/<study>.zip.age` for those
+//! recipients; the plaintext archive is removed once the encrypted file is in place. The
+//! study's other readable copies — `niix/<study>/` from conversion, `nonimage/<study>/`,
+//! and the DICOMDIR tree `media/<study>/` — are packaged and encrypted the same way. Only
+//! the holders of the matching private keys can read the data (`age -d -i key.txt`), so
+//! the operator machine and shared storage never keep readable copies of finished studies.
+//! Encrypting the folder as one archive rather than file by file keeps resume, `verify`,
+//! and `check` working on studies that are still downloading.
+//!
+//! The external `age` executable does the encryption (like `dcm2niix` for conversion);
+//! it is checked once at startup so a missing binary fails the run before any download.
+
+use anyhow::{bail, Context, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config::EncryptionConfig;
+
+/// Suffix appended to encrypted files.
+pub const AGE_SUFFIX: &str = ".age";
+
+#[derive(Debug, Clone)]
+pub struct AgeEncryptor {
+    age_path: String,
+    recipients: Vec<String>,
+    recipients_file: Option<PathBuf>,
+}
+
+impl AgeEncryptor {
+    /// `None` when no recipients are configured; fails on malformed recipients or when the
+    /// `age` executable cannot be run.
+    pub fn new(config: &EncryptionConfig) -> Result<Option<Self>> {
+        let recipients: Vec<String> = config
+            .recipients
+            .iter()
+            .flatten()
+            .map(|r| r.trim().to_string())
+            .filter(|r| !r.is_empty())
+            .collect();
+        if recipients.is_empty() && config.recipients_file.is_none() {
+            return Ok(None);
+        }
+        for r in &recipients {
+            if !(r.starts_with("age1") || r.starts_with("ssh-")) {
+                bail!(
+                    "Invalid age recipient '{}': expected an age1... or ssh- public key",
+                    r
+                );
+            }
+        }
+        if let Some(file) = &config.recipients_file {
+            if !file.is_file() {
+                bail!("age recipients file {} not found", file.display());
+            }
+        }
+        let encryptor = Self {
+            age_path: config.age_path.clone().unwrap_or_else(|| "age".to_string()),
+            recipients,
+            recipients_file: config.recipients_file.clone(),
+        };
+        let available = Command::new(&encryptor.age_path)
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+        if !available {
+            bail!(
+                "Encryption is configured but '{}' could not be run; install age or set encryption.age_path",
+                encryptor.age_path
+            );
+        }
+        Ok(Some(encryptor))
+    }
+
+    /// Arguments for encrypting `input` into `output`.
+    fn args(&self, input: &Path, output: &Path) -> Vec<OsString> {
+        let mut args = Vec::new();
+        for r in &self.recipients {
+            args.push("-r".into());
+            args.push(r.into());
+        }
+        if let Some(file) = &self.recipients_file {
+            args.push("-R".into());
+            args.push(file.into());
+        }
+        args.push("-o".into());
+        args.push(output.into());
+        args.push(input.into());
+        args
+    }
+
+    /// Encrypts `path` to `<path>.age` and removes the plaintext; returns the encrypted path.
+    pub fn encrypt_file(&self, path: &Path) -> Result<PathBuf> {
+        let mut target = path.as_os_str().to_os_string();
+        target.push(AGE_SUFFIX);
+        let target = PathBuf::from(target);
+        let mut part = target.as_os_str().to_os_string();
+        part.push(".part");
+        let part = PathBuf::from(part);
+
+        let output = Command::new(&self.age_path)
+            .args(self.args(path, &part))
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| format!("Failed to run {}", self.age_path))?;
+        if !output.status.success() {
+            let _ = std::fs::remove_file(&part);
+            bail!(
+                "age failed for {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        std::fs::rename(&part, &target)
+            .with_context(|| format!("Failed to replace {}", target.display()))?;
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_age_args_and_recipient_validation() {
+        let none = AgeEncryptor::new(&EncryptionConfig::default()).unwrap();
+        assert!(none.is_none());
+        let bad = EncryptionConfig {
+            recipients: Some(vec!["not-a-key".into()]),
+            ..Default::default()
+        };
+        assert!(AgeEncryptor::new(&bad).is_err());
+
+        let encryptor = AgeEncryptor {
+            age_path: "age".into(),
+            recipients: vec!["age1abc".into(), "ssh-ed25519 AAAA".into()],
+            recipients_file: Some(PathBuf::from("keys.txt")),
+        };
+        let args: Vec<String> = encryptor
+            .args(Path::new("S1.zip"), Path::new("S1.zip.age.part"))
+            .into_iter()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            args,
+            [
+                "-r",
+                "age1abc",
+                "-r",
+                "ssh-ed25519 AAAA",
+                "-R",
+                "keys.txt",
+                "-o",
+                "S1.zip.age.part",
+                "S1.zip"
+            ]
+        );
+    }
+}
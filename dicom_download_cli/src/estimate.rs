@@ -0,0 +1,284 @@
+//! Pre-flight batch size estimation.
+//!
+//! Queries Orthanc study statistics for every accession and projects the expected
+//! instance count, bytes, and transfer duration before a download batch is started.
+//! `download --confirm` runs the same estimate and asks before starting a batch larger
+//! than `confirm_threshold` ([`confirm_on_terminal`]); `--yes` answers for scripts.
+
+use anyhow::{bail, Result};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::atomic::write_atomic;
+use crate::client::OrthancClient;
+
+/// Failed lookups/downloads after which [`measure_throughput`] gives up, so an unreachable
+/// Orthanc does not walk every series of the batch.
+const MAX_SAMPLE_FAILURES: usize = 5;
+
+/// Expected transfer volume for a single accession.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EstimateRow {
+    pub accession: String,
+    pub studies: usize,
+    pub series: usize,
+    pub instances: usize,
+    pub bytes: u64,
+    pub estimated_seconds: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// Aggregated pre-flight report for a batch.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EstimateReport {
+    pub rows: Vec<EstimateRow>,
+    pub total_instances: usize,
+    pub total_bytes: u64,
+    /// Measured bytes per second, `None` when no sample could be downloaded.
+    pub throughput_bps: Option<f64>,
+    pub estimated_seconds: Option<f64>,
+}
+
+/// Projects a transfer duration from a byte count and a measured throughput.
+pub fn estimate_seconds(bytes: u64, throughput_bps: Option<f64>) -> Option<f64> {
+    throughput_bps
+        .filter(|bps| *bps > 0.0)
+        .map(|bps| bytes as f64 / bps)
+}
+
+/// Formats a byte count using binary units for console output.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Formats seconds as `HhMMmSSs` for console output.
+pub fn format_duration(seconds: f64) -> String {
+    let secs = seconds.round() as u64;
+    format!(
+        "{}h{:02}m{:02}s",
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
+
+/// Collects statistics for one accession across all matching studies.
+async fn estimate_accession(client: &OrthancClient, accession: String) -> EstimateRow {
+    let mut row = EstimateRow {
+        accession: accession.clone(),
+        ..Default::default()
+    };
+
+    let study_ids = match client.find_study_ids_by_accession(&accession).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            row.error = Some(format!("Study lookup failed: {}", e));
+            return row;
+        }
+    };
+    if study_ids.is_empty() {
+        row.error = Some("No studies found".into());
+        return row;
+    }
+
+    for study_id in &study_ids {
+        match client.get_study_statistics(study_id).await {
+            Ok(stats) => {
+                row.studies += 1;
+                row.series += stats.count_series;
+                row.instances += stats.count_instances;
+                row.bytes += stats.disk_size;
+            }
+            Err(e) => row.error = Some(format!("Statistics failed for {}: {}", study_id, e)),
+        }
+    }
+    row
+}
+
+/// Downloads one instance of the first estimable accession to measure throughput.
+///
+/// A failed lookup or download skips to the next study/series/accession; after
+/// [`MAX_SAMPLE_FAILURES`] failures the throughput is left unknown.
+async fn measure_throughput(client: &OrthancClient, accessions: &[String]) -> Option<f64> {
+    let mut failures = 0;
+    let mut failed = |what: String, e: &anyhow::Error| {
+        debug!("Throughput sample: {} failed: {:#}", what, e);
+        failures += 1;
+        failures >= MAX_SAMPLE_FAILURES
+    };
+    for acc in accessions {
+        let study_ids = match client.find_study_ids_by_accession(acc).await {
+            Ok(ids) => ids,
+            Err(e) if failed(format!("accession {}", acc), &e) => return None,
+            Err(_) => continue,
+        };
+        for study_id in study_ids {
+            let series_ids = match client.list_series_ids(&study_id).await {
+                Ok(ids) => ids,
+                Err(e) if failed(format!("study {}", study_id), &e) => return None,
+                Err(_) => continue,
+            };
+            for series_id in series_ids {
+                let meta = match client.get_series_meta(&series_id).await {
+                    Ok(m) => m,
+                    Err(e) if failed(format!("series {}", series_id), &e) => return None,
+                    Err(_) => continue,
+                };
+                let Some(instance) = meta.instances.first() else {
+                    continue;
+                };
+                let start = Instant::now();
+                match client.download_instance_file(instance).await {
+                    Ok(data) => {
+                        let elapsed = start.elapsed().max(Duration::from_millis(1));
+                        return Some(data.len() as f64 / elapsed.as_secs_f64());
+                    }
+                    Err(e) if failed(format!("instance {}", instance), &e) => return None,
+                    Err(_) => continue,
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Builds the pre-flight report for all accessions with bounded concurrency.
+pub async fn run_estimate(
+    client: Arc<OrthancClient>,
+    accessions: Vec<String>,
+    concurrency: usize,
+) -> EstimateReport {
+    let throughput_bps = measure_throughput(&client, &accessions).await;
+
+    let mut rows: Vec<EstimateRow> = stream::iter(accessions)
+        .map(|acc| {
+            let client = client.clone();
+            async move { estimate_accession(&client, acc).await }
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    for row in &mut rows {
+        row.estimated_seconds = estimate_seconds(row.bytes, throughput_bps);
+    }
+
+    let total_instances = rows.iter().map(|r| r.instances).sum();
+    let total_bytes = rows.iter().map(|r| r.bytes).sum();
+    EstimateReport {
+        rows,
+        total_instances,
+        total_bytes,
+        throughput_bps,
+        estimated_seconds: estimate_seconds(total_bytes, throughput_bps),
+    }
+}
+
+/// One-line batch total printed before the confirmation question.
+pub fn summary_line(report: &EstimateReport) -> String {
+    let duration = report
+        .estimated_seconds
+        .map(|s| format!(", about {}", format_duration(s)))
+        .unwrap_or_default();
+    format!(
+        "Estimated batch: {} accessions, {} instances, {}{}",
+        report.rows.len(),
+        report.total_instances,
+        format_bytes(report.total_bytes),
+        duration
+    )
+}
+
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Asks on the terminal whether to start a batch of this size; anything but `y`/`yes`
+/// declines. Fails when stdin is closed before an answer.
+pub fn confirm_on_terminal(report: &EstimateReport, threshold: u64) -> Result<bool> {
+    let mut err = std::io::stderr().lock();
+    write!(
+        err,
+        "{} exceeds confirm_threshold {}. Proceed? [y/N] ",
+        format_bytes(report.total_bytes),
+        format_bytes(threshold)
+    )?;
+    err.flush()?;
+    let mut line = String::new();
+    if std::io::stdin().lock().read_line(&mut line)? == 0 {
+        bail!("No answer to the batch size confirmation (stdin closed); use --yes");
+    }
+    Ok(is_yes(&line))
+}
+
+/// Writes the per-accession estimate rows to CSV.
+pub fn write_estimate_csv(path: &Path, report: &EstimateReport) -> Result<()> {
+    write_atomic(path, |w| write_estimate_rows(w, report))
+}
+
+fn write_estimate_rows(w: &mut impl std::io::Write, report: &EstimateReport) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer(w);
+    wtr.write_record([
+        "AccessionNumber",
+        "Studies",
+        "Series",
+        "Instances",
+        "Bytes",
+        "EstimatedSeconds",
+        "Error",
+    ])?;
+    for r in &report.rows {
+        wtr.write_record([
+            r.accession.as_str(),
+            &r.studies.to_string(),
+            &r.series.to_string(),
+            &r.instances.to_string(),
+            &r.bytes.to_string(),
+            &r.estimated_seconds
+                .map(|s| format!("{:.1}", s))
+                .unwrap_or_default(),
+            r.error.as_deref().unwrap_or(""),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_seconds() {
+        assert_eq!(estimate_seconds(1000, Some(100.0)), Some(10.0));
+        assert_eq!(estimate_seconds(1000, Some(0.0)), None);
+        assert_eq!(estimate_seconds(1000, None), None);
+    }
+
+    #[test]
+    fn test_confirmation_answer() {
+        assert!(is_yes(" Yes\n"));
+        assert!(is_yes("y"));
+        assert!(!is_yes(""));
+        assert!(!is_yes("no"));
+    }
+
+    #[test]
+    fn test_format_helpers() {
+        assert_eq!(format_bytes(512), "512.0 B");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+        assert_eq!(format_duration(3725.0), "1h02m05s");
+    }
+}
@@ -0,0 +1,174 @@
+//! `--events jsonl`: lifecycle events as JSON Lines on stdout.
+//!
+//! Wrapper scripts and log collectors read progress from this stream instead of scraping
+//! progress bars. Every line is one object with `timestamp` and `event`:
+//! `accession_started` / `accession_finished` (`remote` and `download`), and for direct
+//! downloads `series_started`, `instance_finished`, and `series_finished`. While the stream
+//! is on, stdout carries nothing else: progress bars are off and the command's own summary
+//! goes to stderr.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::downloader::DownloadResult;
+use crate::processor::ProcessResult;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Output format of `--events` (only JSON Lines so far).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventFormat {
+    Jsonl,
+}
+
+impl FromStr for EventFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(Self::Jsonl),
+            other => Err(format!("unknown event format '{}' (expected jsonl)", other)),
+        }
+    }
+}
+
+/// Turns the event stream on for the rest of the process.
+pub fn enable(_format: EventFormat) {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    AccessionStarted {
+        accession: &'a str,
+    },
+    AccessionFinished {
+        accession: &'a str,
+        status: &'a str,
+        reason: &'a [String],
+        duration_ms: u64,
+        bytes_downloaded: u64,
+    },
+    SeriesStarted {
+        accession: &'a str,
+        study_folder: &'a str,
+        series_folder: &'a str,
+        instances: usize,
+    },
+    InstanceFinished {
+        accession: &'a str,
+        series_folder: &'a str,
+        instance_id: &'a str,
+        /// `downloaded`, `skipped`, `failed`, `invalid`, or `not_attempted`.
+        result: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bytes: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<&'a str>,
+    },
+    SeriesFinished {
+        accession: &'a str,
+        study_folder: &'a str,
+        series_folder: &'a str,
+        status: &'a str,
+        downloaded: usize,
+        skipped: usize,
+        failed: usize,
+        bytes: u64,
+        duration_ms: u64,
+    },
+}
+
+impl<'a> Event<'a> {
+    pub fn accession_finished(res: &'a ProcessResult) -> Self {
+        Event::AccessionFinished {
+            accession: &res.accession,
+            status: &res.status,
+            reason: &res.reason,
+            duration_ms: res.duration_ms,
+            bytes_downloaded: res.bytes_downloaded,
+        }
+    }
+
+    pub fn instance_finished(
+        accession: &'a str,
+        series_folder: &'a str,
+        instance_id: &'a str,
+        outcome: &'a DownloadResult,
+    ) -> Self {
+        let (result, bytes, error) = match outcome {
+            DownloadResult::Completed(bytes) => ("downloaded", Some(*bytes), None),
+            DownloadResult::Skipped => ("skipped", None, None),
+            DownloadResult::Failed(e) => ("failed", None, Some(e.as_str())),
+            DownloadResult::Invalid(e) => ("invalid", None, Some(e.as_str())),
+            DownloadResult::NotAttempted => ("not_attempted", None, None),
+        };
+        Event::InstanceFinished {
+            accession,
+            series_folder,
+            instance_id,
+            result,
+            bytes,
+            error,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// One JSON line for `event`.
+pub fn event_line(event: &Event, at: DateTime<Utc>) -> String {
+    serde_json::to_string(&Envelope {
+        timestamp: at,
+        event,
+    })
+    .unwrap_or_default()
+}
+
+/// Writes `event` to stdout when `--events` is on; a no-op otherwise.
+pub fn emit(event: Event) {
+    if !enabled() {
+        return;
+    }
+    let mut line = event_line(&event, Utc::now());
+    line.push('\n');
+    // 整行一次寫入並 flush，併發的 worker 不會交錯輸出
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(line.as_bytes());
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_lines_are_tagged_json() {
+        let at = DateTime::parse_from_rfc3339("2024-06-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let failed = DownloadResult::Failed("HTTP 500".into());
+        let line = event_line(&Event::instance_finished("A1", "T1", "abc", &failed), at);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["event"], "instance_finished");
+        assert_eq!(value["timestamp"], "2024-06-01T12:00:00Z");
+        assert_eq!(value["result"], "failed");
+        assert_eq!(value["error"], "HTTP 500");
+        assert!(value.get("bytes").is_none());
+        assert_eq!("jsonl".parse::<EventFormat>(), Ok(EventFormat::Jsonl));
+        assert!("xml".parse::<EventFormat>().is_err());
+    }
+}
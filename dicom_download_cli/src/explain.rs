@@ -0,0 +1,249 @@
+//! `explain` subcommand: shows how each remote series of an accession (or a single series UID)
+//! would be classified and whether `remote` would download it under the current config.
+//!
+//! The decision itself comes from [`match_series`], the same function `remote` uses, so the
+//! output cannot drift from the real behaviour. Analysis samples one instance per series via
+//! C-MOVE exactly like `remote`; `--no-analyze` skips that and only evaluates keywords.
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::client::OrthancClient;
+use crate::config::{match_series, AnalysisConfig, MatchKind};
+
+/// Classification of one remote series as `explain` reports it.
+#[derive(Debug)]
+pub struct SeriesExplanation {
+    pub uid: String,
+    pub description: String,
+    pub modality: Option<String>,
+    /// Already stored on the local Orthanc; `remote` skips it before matching.
+    pub already_local: bool,
+    /// False when the analyzer was not consulted (keyword hit, `--no-analyze`, or local).
+    pub analyzed: bool,
+    pub series_type: Option<String>,
+    /// Why the analyzer was bypassed, if it was.
+    pub analyzer_note: Option<String>,
+    pub kind: MatchKind,
+}
+
+impl SeriesExplanation {
+    pub fn will_download(&self) -> bool {
+        !self.already_local && self.kind.selected()
+    }
+
+    /// One-line reason for the decision, naming the config list that was consulted.
+    pub fn reason(&self, config: &AnalysisConfig) -> String {
+        let modality = self.modality.as_deref();
+        if self.already_local {
+            return "already stored on the local Orthanc; remote skips it".into();
+        }
+        match self.kind {
+            MatchKind::Filtered => format!(
+                "series '{}' {}",
+                self.description,
+                config
+                    .series_filter
+                    .rejection(&self.description, modality)
+                    .unwrap_or_default()
+            ),
+            MatchKind::DownloadAll => "download_all = true".into(),
+            MatchKind::DirectKeyword => format!(
+                "description '{}' is in {}",
+                self.description,
+                list_name(config, modality, "direct_download_keywords")
+            ),
+            MatchKind::Whitelist => format!(
+                "series type '{}' is in {}",
+                self.series_type.as_deref().unwrap_or_default(),
+                list_name(config, modality, "series_whitelist")
+            ),
+            MatchKind::Excluded => {
+                let keyword = if config.enable_direct_keywords {
+                    format!(
+                        "description not in {}",
+                        list_name(config, modality, "direct_download_keywords")
+                    )
+                } else {
+                    "direct keywords disabled".into()
+                };
+                let whitelist = match (&self.series_type, self.analyzed) {
+                    (_, false) => "not analyzed (--no-analyze)".to_string(),
+                    (None, true) => "analyzer and header fallback gave no series type".into(),
+                    (Some(_), true) if !config.enable_whitelist => {
+                        "whitelist disabled (enable_whitelist = false)".into()
+                    }
+                    (Some(t), true) => format!(
+                        "series type '{}' not in {}",
+                        t,
+                        list_name(config, modality, "series_whitelist")
+                    ),
+                };
+                format!("{}; {}", keyword, whitelist)
+            }
+        }
+    }
+}
+
+/// Names the list consulted for a modality: `[whitelist.MR] series_whitelist` when a
+/// per-modality override exists, otherwise the global key.
+fn list_name(config: &AnalysisConfig, modality: Option<&str>, key: &str) -> String {
+    let overridden = modality
+        .map(|m| m.trim().to_uppercase())
+        .and_then(|m| config.modality_rules.get(&m).map(|r| (m, r)))
+        .filter(|(_, r)| match key {
+            "series_whitelist" => r.series_whitelist.is_some(),
+            _ => r.direct_download_keywords.is_some(),
+        });
+    match overridden {
+        Some((m, _)) => format!("[whitelist.{}] {}", m, key),
+        None => key.to_string(),
+    }
+}
+
+/// Classifies one remote series the way `process_series` does.
+async fn explain_series(
+    client: &OrthancClient,
+    modality: &str,
+    study_uid: &str,
+    series_json: &Value,
+    local: &HashSet<String>,
+    config: &AnalysisConfig,
+    analyze: bool,
+) -> Result<SeriesExplanation> {
+    let (uid, description) = client.extract_series_info(series_json);
+    let series_modality = client.extract_series_modality(series_json);
+    let mut e = SeriesExplanation {
+        already_local: local.contains(&uid),
+        kind: match_series(&description, None, series_modality.as_deref(), config),
+        uid,
+        description,
+        modality: series_modality,
+        analyzed: false,
+        series_type: None,
+        analyzer_note: None,
+    };
+    if analyze && !e.already_local && e.kind == MatchKind::Excluded {
+        let sample = client
+            .sample_series_type(modality, study_uid, &e.uid, &config.classifier)
+            .await?;
+        e.analyzed = true;
+        e.series_type = sample.series_type;
+        e.analyzer_note = sample.analyzer_bypassed;
+        e.kind = match_series(
+            &e.description,
+            e.series_type.as_deref(),
+            e.modality.as_deref(),
+            config,
+        );
+    }
+    Ok(e)
+}
+
+/// Explains every remote series of the study with this accession number.
+pub async fn explain_accession(
+    client: &OrthancClient,
+    modality: &str,
+    accession: &str,
+    config: &AnalysisConfig,
+    analyze: bool,
+) -> Result<Vec<SeriesExplanation>> {
+    let study_uid = client.find_study_by_accession(accession, modality).await?;
+    let series = client.get_remote_series(modality, &study_uid).await?;
+    let local = client
+        .get_local_series(&study_uid)
+        .await
+        .unwrap_or_default();
+    let mut out = Vec::with_capacity(series.len());
+    for s in &series {
+        out.push(explain_series(client, modality, &study_uid, s, &local, config, analyze).await?);
+    }
+    Ok(out)
+}
+
+/// Explains a single remote series by SeriesInstanceUID.
+pub async fn explain_series_uid(
+    client: &OrthancClient,
+    modality: &str,
+    series_uid: &str,
+    config: &AnalysisConfig,
+    analyze: bool,
+) -> Result<SeriesExplanation> {
+    let found = client.find_remote_series(modality, series_uid).await?;
+    let series_json = found
+        .first()
+        .ok_or_else(|| anyhow!("No series found for SeriesInstanceUID {}", series_uid))?;
+    let study_uid = series_json
+        .get("0020,000d")
+        .and_then(|v| v.get("Value"))
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("Missing StudyInstanceUID (0020,000d) in response"))?
+        .to_string();
+    let local = client
+        .get_local_series(&study_uid)
+        .await
+        .unwrap_or_default();
+    explain_series(
+        client,
+        modality,
+        &study_uid,
+        series_json,
+        &local,
+        config,
+        analyze,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModalityRules;
+
+    fn explanation(desc: &str, series_type: Option<&str>, kind: MatchKind) -> SeriesExplanation {
+        SeriesExplanation {
+            uid: "1.2.3".into(),
+            description: desc.into(),
+            modality: Some("MR".into()),
+            already_local: false,
+            analyzed: true,
+            series_type: series_type.map(Into::into),
+            analyzer_note: None,
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_reason_names_consulted_list() {
+        let mut config = AnalysisConfig::default();
+        let e = explanation("Ax FLAIR", Some("T2FLAIR_COR"), MatchKind::Excluded);
+        assert_eq!(
+            e.reason(&config),
+            "description not in direct_download_keywords; \
+             series type 'T2FLAIR_COR' not in series_whitelist"
+        );
+
+        config.modality_rules.insert(
+            "MR".into(),
+            ModalityRules {
+                series_whitelist: Some(HashSet::from(["T2FLAIR_AXI".into()])),
+                direct_download_keywords: None,
+            },
+        );
+        let e = explanation("Ax FLAIR", Some("T2FLAIR_AXI"), MatchKind::Whitelist);
+        assert_eq!(
+            e.reason(&config),
+            "series type 'T2FLAIR_AXI' is in [whitelist.MR] series_whitelist"
+        );
+        assert!(e.will_download());
+
+        config.series_filter = crate::config::SeriesFilter::new(&[], &["SCOUT".into()]).unwrap();
+        let e = explanation("3pl SCOUT", None, MatchKind::Filtered);
+        assert_eq!(
+            e.reason(&config),
+            "series '3pl SCOUT' matches series_exclude_patterns 'SCOUT'"
+        );
+        assert!(!e.will_download());
+    }
+}
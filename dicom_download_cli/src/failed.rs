@@ -0,0 +1,230 @@
+//! Instance-level failure artifact and `retry-instances`.
+//!
+//! `download` records every instance that still failed after all retries (or failed
+//! `--validate`, or was skipped after an auth failure) in `<output>/failed_instances.json`.
+//! `retry-instances` fetches exactly those objects into the folders they belong in, finalizes
+//! `.partial` series that become complete, and rewrites the file with whatever is still
+//! missing — no need to re-pull whole series.
+
+use anyhow::{Context, Result};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::atomic::write_atomic;
+use crate::checksum::write_manifest;
+use crate::client::OrthancClient;
+use crate::downloader::{
+    download_instance_to_file, failure_text, safe_dicom_filename, DownloadResult, PARTIAL_SUFFIX,
+};
+use crate::fdlimit::FileSlots;
+use crate::ordering::{is_dynamic_series, write_ordering_file};
+
+/// Artifact written next to `dicom/` in the download output.
+pub const FAILED_INSTANCES_FILE: &str = "failed_instances.json";
+
+/// One instance that could not be downloaded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FailedInstance {
+    pub accession: String,
+    pub study_folder: String,
+    pub series_folder: String,
+    /// Orthanc instance ID (the file is saved as `<id>.dcm` unless `file_name` is set).
+    pub instance_id: String,
+    /// File name under `instance_naming = "instance_number"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    /// Looked up after the run; `None` when Orthanc could not be asked.
+    pub sop_instance_uid: Option<String>,
+    /// Folder the file belongs in (the `.partial` folder when the series is incomplete);
+    /// stored relative to the output directory so the tree can be moved.
+    pub series_dir: PathBuf,
+    pub error: String,
+}
+
+pub fn failed_instances_path(output: &Path) -> PathBuf {
+    output.join(FAILED_INSTANCES_FILE)
+}
+
+/// Writes `<output>/failed_instances.json`, or removes a stale one when nothing failed.
+pub fn write_failed_instances(output: &Path, failed: &[FailedInstance]) -> Result<()> {
+    let path = failed_instances_path(output);
+    if failed.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        return Ok(());
+    }
+    let relative: Vec<FailedInstance> = failed
+        .iter()
+        .map(|f| FailedInstance {
+            series_dir: f
+                .series_dir
+                .strip_prefix(output)
+                .unwrap_or(&f.series_dir)
+                .to_path_buf(),
+            ..f.clone()
+        })
+        .collect();
+    write_atomic(&path, |w| Ok(serde_json::to_writer_pretty(w, &relative)?))
+}
+
+/// Reads `<output>/failed_instances.json` with series folders resolved against `output`.
+pub fn load_failed_instances(output: &Path) -> Result<Vec<FailedInstance>> {
+    let path = failed_instances_path(output);
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut failed: Vec<FailedInstance> =
+        serde_json::from_str(&text).with_context(|| format!("Invalid {}", path.display()))?;
+    for f in &mut failed {
+        f.series_dir = output.join(&f.series_dir);
+    }
+    Ok(failed)
+}
+
+/// Final series folder for a `.partial` one, `None` otherwise.
+fn final_dir_of(dir: &Path) -> Option<PathBuf> {
+    let name = dir.file_name()?.to_string_lossy();
+    let stem = name.strip_suffix(PARTIAL_SUFFIX)?;
+    Some(dir.with_file_name(stem))
+}
+
+/// Where a recorded instance goes now: its recorded folder, or the final folder when a
+/// later run already finalized the `.partial` one.
+pub fn resolve_series_dir(entry: &FailedInstance) -> PathBuf {
+    match final_dir_of(&entry.series_dir) {
+        Some(final_dir) if !entry.series_dir.exists() && final_dir.exists() => final_dir,
+        _ => entry.series_dir.clone(),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RetrySummary {
+    pub recovered: usize,
+    /// Entries that failed again, with the new error.
+    pub remaining: Vec<FailedInstance>,
+    /// `.partial` folders renamed to their final name because they are now complete.
+    pub finalized: Vec<PathBuf>,
+}
+
+/// Re-fetches every recorded instance and tidies up the series folders it touched.
+pub async fn retry_failed_instances(
+    client: &OrthancClient,
+    entries: Vec<FailedInstance>,
+    concurrency: usize,
+    file_slots: &FileSlots,
+) -> RetrySummary {
+    let results: Vec<(FailedInstance, PathBuf, DownloadResult)> = stream::iter(entries)
+        .map(|entry| async move {
+            let dir = resolve_series_dir(&entry);
+            let result = match std::fs::create_dir_all(&dir) {
+                Ok(()) => {
+                    let name = entry
+                        .file_name
+                        .clone()
+                        .unwrap_or_else(|| safe_dicom_filename(&entry.instance_id));
+                    let dest = dir.join(name);
+                    download_instance_to_file(client, &entry.instance_id, &dest, file_slots).await
+                }
+                Err(e) => DownloadResult::Failed(format!("Create dir failed: {}", e)),
+            };
+            (entry, dir, result)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut summary = RetrySummary::default();
+    let mut touched = BTreeSet::new();
+    let mut incomplete = BTreeSet::new();
+    for (mut entry, dir, result) in results {
+        match failure_text(&result) {
+            None => summary.recovered += 1,
+            Some(error) => {
+                entry.error = error;
+                incomplete.insert(dir.clone());
+                summary.remaining.push(entry);
+            }
+        }
+        touched.insert(dir);
+    }
+
+    for mut dir in touched {
+        if !incomplete.contains(&dir) {
+            if let Some(final_dir) = final_dir_of(&dir).filter(|f| !f.exists()) {
+                match std::fs::rename(&dir, &final_dir) {
+                    Ok(()) => {
+                        summary.finalized.push(final_dir.clone());
+                        dir = final_dir;
+                    }
+                    Err(e) => warn!("could not finalize {}: {}", dir.display(), e),
+                }
+            }
+        }
+        let folder = dir
+            .file_name()
+            .map(|n| {
+                n.to_string_lossy()
+                    .trim_end_matches(PARTIAL_SUFFIX)
+                    .to_string()
+            })
+            .unwrap_or_default();
+        if is_dynamic_series(&folder, &dir) {
+            if let Err(e) = write_ordering_file(&dir) {
+                warn!("temporal ordering export failed: {}", e);
+            }
+        }
+        if let Err(e) = write_manifest(&dir) {
+            warn!("checksum manifest failed: {}", e);
+        }
+    }
+    for entry in &mut summary.remaining {
+        entry.series_dir = resolve_series_dir(entry);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(series_dir: PathBuf) -> FailedInstance {
+        FailedInstance {
+            accession: "ACC".into(),
+            study_folder: "S".into(),
+            series_folder: "T1".into(),
+            instance_id: "abc".into(),
+            file_name: None,
+            sop_instance_uid: None,
+            series_dir,
+            error: "timeout".into(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_series_dir_follows_finalized_folder() {
+        let root = std::env::temp_dir().join(format!("failed_test_{}", std::process::id()));
+        let partial = root.join("T1.partial");
+        std::fs::create_dir_all(&partial).unwrap();
+        assert_eq!(resolve_series_dir(&entry(partial.clone())), partial);
+
+        std::fs::rename(&partial, root.join("T1")).unwrap();
+        assert_eq!(resolve_series_dir(&entry(partial)), root.join("T1"));
+
+        // Stored relative to the output, loaded back as full paths
+        write_failed_instances(&root, &[entry(root.join("T1"))]).unwrap();
+        let text = std::fs::read_to_string(failed_instances_path(&root)).unwrap();
+        assert!(text.contains("\"series_dir\": \"T1\""));
+        assert_eq!(
+            load_failed_instances(&root).unwrap(),
+            vec![entry(root.join("T1"))]
+        );
+        write_failed_instances(&root, &[]).unwrap();
+        assert!(!failed_instances_path(&root).exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+//! Open-file budget for large batches.
+//!
+//! Every in-flight instance download holds a socket plus its `.part` file, and each dcm2niix
+//! run opens a series' worth of inputs and outputs, so high concurrency with conversion
+//! enabled used to exhaust the descriptor limit (EMFILE) and fail random requests. Work that
+//! opens files now takes permits from a shared [`FileSlots`] budget and waits when it is
+//! used up. At startup the soft `RLIMIT_NOFILE` is raised toward the budget (up to the hard
+//! limit) and the budget is clamped, with a warning, if the limit is still too low.
+
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Budget used when `max_open_files` is not configured.
+pub const DEFAULT_MAX_OPEN_FILES: usize = 512;
+/// Descriptors left outside the budget for stdio, logs, reports, and pooled connections.
+pub const RESERVED_FDS: usize = 64;
+/// Permits held by one instance download (socket + `.part` file).
+pub const DOWNLOAD_COST: u32 = 2;
+/// Permits held by one dcm2niix run.
+pub const CONVERSION_COST: u32 = 16;
+
+/// Shared budget of open files; cloning shares the same pool.
+#[derive(Clone, Debug)]
+pub struct FileSlots {
+    sem: Arc<Semaphore>,
+    max: usize,
+}
+
+impl FileSlots {
+    /// Budget of `max` descriptors, never below one conversion's cost so it cannot deadlock.
+    pub fn new(max: usize) -> Self {
+        let max = max.max(CONVERSION_COST as usize);
+        Self {
+            sem: Arc::new(Semaphore::new(max)),
+            max,
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    async fn acquire(&self, n: u32) -> OwnedSemaphorePermit {
+        self.sem
+            .clone()
+            .acquire_many_owned(n)
+            .await
+            .expect("file slot semaphore is never closed")
+    }
+
+    /// Waits for room to stream one instance to disk.
+    pub async fn download(&self) -> OwnedSemaphorePermit {
+        self.acquire(DOWNLOAD_COST).await
+    }
+
+    /// Waits for room to run dcm2niix on one series.
+    pub async fn conversion(&self) -> OwnedSemaphorePermit {
+        self.acquire(CONVERSION_COST).await
+    }
+}
+
+impl Default for FileSlots {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_OPEN_FILES)
+    }
+}
+
+/// Budget that fits under `soft_limit`, plus a warning when `requested` had to be clamped.
+pub fn fit_budget(requested: usize, soft_limit: Option<u64>) -> (usize, Option<String>) {
+    let Some(soft) = soft_limit else {
+        return (requested, None);
+    };
+    let available = (soft as usize).saturating_sub(RESERVED_FDS);
+    if requested <= available {
+        return (requested, None);
+    }
+    let budget = available.max(CONVERSION_COST as usize);
+    let warning = format!(
+        "open-file limit is {} (ulimit -n); max_open_files reduced from {} to {}. \
+         Raise the limit for faster large batches.",
+        soft, requested, budget
+    );
+    (budget, Some(warning))
+}
+
+/// Soft and hard `RLIMIT_NOFILE`; `None` where the platform has no such limit.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // rlim_t is not u64 on every unix
+pub fn nofile_limit() -> Option<(u64, u64)> {
+    let mut lim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes into the struct we pass
+    let rc = unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut lim) };
+    (rc == 0).then_some((lim.rlim_cur as u64, lim.rlim_max as u64))
+}
+
+#[cfg(not(unix))]
+pub fn nofile_limit() -> Option<(u64, u64)> {
+    None
+}
+
+/// Raises the soft limit to `wanted` (capped at the hard limit); best effort.
+#[cfg(unix)]
+fn raise_soft_limit(wanted: u64) {
+    let Some((soft, hard)) = nofile_limit() else {
+        return;
+    };
+    if soft >= wanted {
+        return;
+    }
+    let lim = libc::rlimit {
+        rlim_cur: wanted.min(hard) as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+    // SAFETY: setrlimit only reads the struct; failure leaves the old limit in place
+    unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &lim) };
+}
+
+#[cfg(not(unix))]
+fn raise_soft_limit(_wanted: u64) {}
+
+/// Startup check: raises the descriptor limit if needed and returns the budget to use,
+/// with a warning when the limit forced a smaller one.
+pub fn prepare(max_open_files: Option<usize>) -> (FileSlots, Option<String>) {
+    let requested = max_open_files.unwrap_or(DEFAULT_MAX_OPEN_FILES);
+    raise_soft_limit((requested + RESERVED_FDS) as u64);
+    let (budget, warning) = fit_budget(requested, nofile_limit().map(|(soft, _)| soft));
+    (FileSlots::new(budget), warning)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_budget_clamps_to_soft_limit() {
+        assert_eq!(fit_budget(512, Some(4096)), (512, None));
+        assert_eq!(fit_budget(512, None), (512, None));
+
+        let (budget, warning) = fit_budget(512, Some(256));
+        assert_eq!(budget, 256 - RESERVED_FDS);
+        assert!(warning.unwrap().contains("reduced from 512"));
+
+        // A tiny limit still leaves room for one conversion
+        assert_eq!(fit_budget(512, Some(32)).0, CONVERSION_COST as usize);
+    }
+}
@@ -0,0 +1,476 @@
+//! Standalone HTML reports for people who do not open CSV/JSON files.
+//!
+//! One self-contained page per run (inline CSS and SVG charts, no scripts or external
+//! assets), so it can be mailed or opened from a share: a status breakdown, failure reasons
+//! grouped by kind, and the per-accession table for `remote`/`download`/`import`; a fix
+//! breakdown, actions by check type and reason, and per-study drill-down for `check`.
+
+use anyhow::Result;
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::atomic::write_bytes_atomic;
+use crate::checker::{ActionType, CheckReport, CheckType, FileAction};
+use crate::estimate::{format_bytes, format_duration};
+use crate::processor::ProcessResult;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+h1{font-size:1.5em}h2{font-size:1.2em;margin-top:1.5em}\
+table{border-collapse:collapse;font-size:.9em}\
+th,td{border:1px solid #ccc;padding:4px 8px;text-align:left;vertical-align:top}\
+th{background:#f0f0f0}td.num{text-align:right}\
+.Success{color:#2e7d32}.Partial{color:#ef6c00}.Failed,.NotAttempted{color:#c62828}\
+.muted{color:#777}summary{cursor:pointer;margin:.3em 0}details details{margin-left:1.5em}";
+
+/// Bar colours by status; anything unknown is grey.
+fn status_color(status: &str) -> &'static str {
+    match status {
+        "Success" => "#43a047",
+        "Partial" => "#fb8c00",
+        "Failed" => "#e53935",
+        _ => "#9e9e9e",
+    }
+}
+
+/// Minimal HTML escaping for text and attribute values.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Horizontal bar chart as inline SVG; bars are scaled to the largest value.
+pub fn bar_chart(bars: &[(String, usize, &str)]) -> String {
+    const LABEL_W: usize = 180;
+    const BAR_W: usize = 360;
+    const ROW_H: usize = 24;
+    let max = bars.iter().map(|(_, v, _)| *v).max().unwrap_or(0).max(1);
+    let height = bars.len() * ROW_H + 4;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         font-size=\"13\" role=\"img\">",
+        LABEL_W + BAR_W + 60,
+        height
+    );
+    for (i, (label, value, color)) in bars.iter().enumerate() {
+        let y = i * ROW_H + 2;
+        let w = if *value == 0 {
+            0
+        } else {
+            (value * BAR_W / max).max(2)
+        };
+        let _ = write!(
+            svg,
+            "<text x=\"0\" y=\"{ty}\">{label}</text>\
+             <rect x=\"{LABEL_W}\" y=\"{y}\" width=\"{w}\" height=\"{h}\" fill=\"{color}\"/>\
+             <text x=\"{vx}\" y=\"{ty}\">{value}</text>",
+            ty = y + 15,
+            label = escape(label),
+            h = ROW_H - 6,
+            vx = LABEL_W + w + 6,
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{t}</title>\
+         <style>{STYLE}</style></head><body>\n<h1>{t}</h1>\n\
+         <p class=\"muted\">Generated {}</p>\n{body}</body></html>\n",
+        Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+        t = escape(title),
+    )
+}
+
+/// Groups failure reasons that differ only in counts (`3 failed out of 10 ...`).
+fn reason_kind(reason: &str) -> String {
+    let mut out = String::with_capacity(reason.len());
+    let mut in_digits = false;
+    for c in reason.chars() {
+        if c.is_ascii_digit() {
+            if !in_digits {
+                out.push('#');
+            }
+            in_digits = true;
+        } else {
+            out.push(c);
+            in_digits = false;
+        }
+    }
+    out
+}
+
+/// Report for `remote`, `download`, and `import` runs.
+pub fn processor_html(results: &[ProcessResult]) -> String {
+    let mut body = String::new();
+
+    let mut statuses: BTreeMap<&str, usize> = BTreeMap::new();
+    for r in results {
+        *statuses.entry(r.status.as_str()).or_default() += 1;
+    }
+    let bytes: u64 = results.iter().map(|r| r.bytes_downloaded).sum();
+    let wall_ms: u64 = results.iter().map(|r| r.duration_ms).sum();
+    let _ = writeln!(
+        body,
+        "<p>{} accessions; {} series downloaded; {} converted; {} fetched in {} of \
+         accession time.</p>",
+        results.len(),
+        results
+            .iter()
+            .map(|r| r.downloaded_series.len())
+            .sum::<usize>(),
+        results
+            .iter()
+            .map(|r| r.converted_series.len())
+            .sum::<usize>(),
+        format_bytes(bytes),
+        format_duration(wall_ms as f64 / 1000.0)
+    );
+
+    body.push_str("<h2>Status</h2>\n");
+    let bars: Vec<(String, usize, &str)> = statuses
+        .iter()
+        .map(|(s, n)| (s.to_string(), *n, status_color(s)))
+        .collect();
+    body.push_str(&bar_chart(&bars));
+    body.push('\n');
+
+    // reason kind -> (example text, accessions)
+    let mut reasons: BTreeMap<String, (String, Vec<&str>)> = BTreeMap::new();
+    for r in results.iter().filter(|r| r.status != "Success") {
+        for reason in &r.reason {
+            let entry = reasons
+                .entry(reason_kind(reason))
+                .or_insert_with(|| (reason.clone(), Vec::new()));
+            if !entry.1.contains(&r.accession.as_str()) {
+                entry.1.push(&r.accession);
+            }
+        }
+    }
+    if !reasons.is_empty() {
+        let mut grouped: Vec<_> = reasons.into_values().collect();
+        grouped.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(&b.0)));
+        body.push_str("<h2>Failure reasons</h2>\n");
+        let bars: Vec<(String, usize, &str)> = grouped
+            .iter()
+            .take(10)
+            .map(|(text, accs)| {
+                let label: String = text.chars().take(28).collect();
+                (label, accs.len(), "#e53935")
+            })
+            .collect();
+        body.push_str(&bar_chart(&bars));
+        body.push_str(
+            "\n<table><tr><th>Accessions</th><th>Reason (example)</th><th>Affected</th></tr>\n",
+        );
+        for (text, accs) in &grouped {
+            let _ = writeln!(
+                body,
+                "<tr><td class=\"num\">{}</td><td>{}</td><td>{}</td></tr>",
+                accs.len(),
+                escape(text),
+                escape(&accs.join(", "))
+            );
+        }
+        body.push_str("</table>\n");
+    }
+
+    body.push_str(
+        "<h2>Accessions</h2>\n<table><tr><th>Accession</th><th>Status</th>\
+         <th>Downloaded</th><th>Matched</th><th>Failed</th><th>Converted</th>\
+         <th>Size</th><th>Time</th><th>Reason</th></tr>\n",
+    );
+    for r in results {
+        let _ = writeln!(
+            body,
+            "<tr><td>{}</td><td class=\"{}\">{}</td><td class=\"num\">{}</td>\
+             <td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td>\
+             <td class=\"num\">{}</td><td class=\"num\">{}</td><td>{}</td></tr>",
+            escape(&r.accession),
+            escape(&r.status),
+            escape(&r.status),
+            r.downloaded_series.len(),
+            r.matched_series.len(),
+            r.failed_series.len(),
+            r.converted_series.len(),
+            format_bytes(r.bytes_downloaded),
+            format_duration(r.duration_ms as f64 / 1000.0),
+            escape(&r.reason.join("; "))
+        );
+    }
+    body.push_str("</table>\n");
+
+    page("DICOM download report", &body)
+}
+
+fn check_type_label(check_type: &CheckType) -> &'static str {
+    match check_type {
+        CheckType::DWI => "DWI",
+        CheckType::ADC => "ADC",
+        CheckType::MixedSeries => "MixedSeries",
+        CheckType::SliceGaps => "SliceGaps",
+        CheckType::Rule => "Rule",
+        CheckType::Names => "Names",
+    }
+}
+
+/// Move (renames included), delete, and flag counts of some actions.
+fn action_counts<'a>(actions: impl Iterator<Item = &'a FileAction>) -> [usize; 3] {
+    let mut counts = [0; 3];
+    for a in actions {
+        match a.action_type {
+            ActionType::Move | ActionType::Rename => counts[0] += 1,
+            ActionType::Delete => counts[1] += 1,
+            ActionType::Flag => counts[2] += 1,
+        }
+    }
+    counts
+}
+
+/// Report for `check` runs: fix breakdown, actions by check type and reason, and one
+/// collapsible section per study down to the single file actions.
+pub fn checker_html(report: &CheckReport) -> String {
+    let s = &report.summary;
+    let mut body = format!(
+        "<p>{}{}: {} studies scanned, {} series with issues, {} files checked.</p>\n",
+        escape(&report.input_path.display().to_string()),
+        if report.dry_run {
+            " (dry run, nothing changed)"
+        } else {
+            ""
+        },
+        s.total_studies,
+        s.total_series_checked,
+        s.total_files_checked
+    );
+    if report.dry_run {
+        body.push_str(
+            "<p><b>Proposed fixes.</b> Review the actions below, then run <code>check</code> \
+             without <code>--dry-run</code> to apply them.</p>\n",
+        );
+    }
+    body.push_str("<h2>Fixes</h2>\n");
+    body.push_str(&bar_chart(&[
+        ("DWI fixes (moves)".into(), s.dwi_fixes, "#1e88e5"),
+        (
+            "ADC duplicates removed".into(),
+            s.adc_duplicates_removed,
+            "#8e24aa",
+        ),
+        (
+            "Mixed series folders".into(),
+            s.mixed_series_folders,
+            "#fb8c00",
+        ),
+        (
+            "Folders with slice gaps".into(),
+            s.slice_gap_folders,
+            "#6d4c41",
+        ),
+        ("Rule fixes".into(), s.rule_fixes, "#00897b"),
+        ("Name mismatches".into(), s.name_mismatches, "#546e7a"),
+        ("Total moves".into(), s.total_moves, "#43a047"),
+        ("Total deletes".into(), s.total_deletes, "#e53935"),
+    ]));
+    body.push('\n');
+
+    let studies: Vec<_> = report
+        .studies
+        .iter()
+        .filter(|st| !st.series_results.is_empty())
+        .collect();
+    if studies.is_empty() {
+        return page("DICOM structure check report", &body);
+    }
+
+    // (check type, reason kind) -> (example reason, actions, studies)
+    let mut groups: BTreeMap<(&str, String), (&str, usize, Vec<&str>)> = BTreeMap::new();
+    for study in &studies {
+        for series in &study.series_results {
+            let check = check_type_label(&series.check_type);
+            for a in &series.actions {
+                let entry = groups
+                    .entry((check, reason_kind(&a.reason)))
+                    .or_insert_with(|| (&a.reason, 0, Vec::new()));
+                entry.1 += 1;
+                if !entry.2.contains(&study.study_folder.as_str()) {
+                    entry.2.push(&study.study_folder);
+                }
+            }
+        }
+    }
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by(|((ca, _), a), ((cb, _), b)| ca.cmp(cb).then(b.1.cmp(&a.1)).then(a.0.cmp(b.0)));
+    body.push_str(
+        "<h2>By check type</h2>\n<table><tr><th>Check</th><th>Actions</th><th>Studies</th>\
+         <th>Reason (example)</th></tr>\n",
+    );
+    for ((check, _), (reason, actions, in_studies)) in &groups {
+        let _ = writeln!(
+            body,
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td>\
+             <td>{}</td></tr>",
+            check,
+            actions,
+            in_studies.len(),
+            escape(reason)
+        );
+    }
+    body.push_str("</table>\n");
+
+    body.push_str("<h2>Studies</h2>\n");
+    for study in studies {
+        let [moves, deletes, flags] = action_counts(
+            study
+                .series_results
+                .iter()
+                .flat_map(|series| &series.actions),
+        );
+        let _ = writeln!(
+            body,
+            "<details><summary><b>{}</b>: {} moves, {} deletes, {} flags</summary>\n\
+             <table><tr><th>Series</th><th>Check</th><th>Files</th><th>Moves</th>\
+             <th>Deletes</th><th>Flags</th><th>Reasons</th></tr>",
+            escape(&study.study_folder),
+            moves,
+            deletes,
+            flags
+        );
+        for series in &study.series_results {
+            let [moves, deletes, flags] = action_counts(series.actions.iter());
+            let mut reasons: Vec<&str> = Vec::new();
+            for a in &series.actions {
+                if !reasons.contains(&a.reason.as_str()) {
+                    reasons.push(&a.reason);
+                }
+            }
+            let _ = writeln!(
+                body,
+                "<tr><td>{}</td><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td>\
+                 <td class=\"num\">{}</td><td class=\"num\">{}</td><td>{}</td></tr>",
+                escape(&series.series_folder),
+                check_type_label(&series.check_type),
+                series.files_checked,
+                moves,
+                deletes,
+                flags,
+                escape(&reasons.join("; "))
+            );
+        }
+        body.push_str(
+            "</table>\n<details><summary>File actions</summary>\n<table><tr><th>Action</th>\
+             <th>Source</th><th>Target</th><th>Reason</th></tr>\n",
+        );
+        let relative = |path: &Path| {
+            escape(
+                &path
+                    .strip_prefix(&report.input_path)
+                    .unwrap_or(path)
+                    .display()
+                    .to_string(),
+            )
+        };
+        for a in study
+            .series_results
+            .iter()
+            .flat_map(|series| &series.actions)
+        {
+            let _ = writeln!(
+                body,
+                "<tr><td>{:?}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                a.action_type,
+                relative(&a.source_path),
+                a.target_path.as_deref().map(relative).unwrap_or_default(),
+                escape(&a.reason)
+            );
+        }
+        body.push_str("</table></details></details>\n");
+    }
+
+    page("DICOM structure check report", &body)
+}
+
+pub fn write_processor_html(path: &Path, results: &[ProcessResult]) -> Result<()> {
+    write_bytes_atomic(path, processor_html(results).as_bytes())
+}
+
+pub fn write_checker_html(path: &Path, report: &CheckReport) -> Result<()> {
+    write_bytes_atomic(path, checker_html(report).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_processor_html_groups_reasons_and_escapes() {
+        let result = |acc: &str, status: &str, reason: &str| ProcessResult {
+            accession: acc.into(),
+            status: status.into(),
+            reason: vec![reason.into()],
+            ..Default::default()
+        };
+        let html = processor_html(&[
+            result("A1", "Partial", "3 failed out of 10 instances for T1"),
+            result("A2", "Partial", "1 failed out of 4 instances for T1"),
+            result("<b>", "Failed", "No studies found"),
+        ]);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<td>A1, A2</td>"));
+        assert!(html.contains("&lt;b&gt;"));
+        assert!(!html.contains("<b>"));
+        assert_eq!(reason_kind("3 failed out of 10"), "# failed out of #");
+    }
+
+    #[test]
+    fn test_checker_html_groups_by_type_and_study() {
+        use crate::checker::{CheckSummary, SeriesCheckResult, StudyCheckResult};
+        use std::path::PathBuf;
+
+        let input = PathBuf::from("/data/dicom");
+        let action = |file: &str, target: &str, b: u32| FileAction {
+            source_path: input.join("S1/DWI0").join(file),
+            action_type: ActionType::Move,
+            target_path: Some(input.join("S1").join(target).join(file)),
+            reason: format!("b-value={} should be in {}", b, target),
+        };
+        let report = CheckReport {
+            input_path: input.clone(),
+            timestamp: Utc::now(),
+            dry_run: true,
+            studies: vec![StudyCheckResult {
+                study_folder: "S1".into(),
+                series_results: vec![SeriesCheckResult {
+                    series_folder: "DWI0".into(),
+                    check_type: CheckType::DWI,
+                    files_checked: 3,
+                    actions: vec![
+                        action("a.dcm", "DWI1000", 1000),
+                        action("b.dcm", "DWI1000", 1000),
+                    ],
+                }],
+                total_moves: 2,
+                total_deletes: 0,
+            }],
+            summary: CheckSummary::default(),
+        };
+        let html = checker_html(&report);
+        assert!(html.contains("Proposed fixes"));
+        assert!(html.contains("<tr><td>DWI</td><td class=\"num\">2</td><td class=\"num\">1</td>"));
+        assert!(html.contains("<summary><b>S1</b>: 2 moves, 0 deletes, 0 flags</summary>"));
+        assert!(html.contains("<td>S1/DWI0/a.dcm</td><td>S1/DWI1000/a.dcm</td>"));
+    }
+}
@@ -0,0 +1,449 @@
+//! `import` subcommand: offline study ZIPs (e.g. PACS portal exports) into the standard layout.
+//!
+//! Each ZIP is unpacked into a scratch folder under the output, its DICOM files are grouped by
+//! study and series from their headers, and every series is classified with the same
+//! [`match_series`] rules (and analyzer, when configured) that `remote` and `download` use.
+//! Matching series land in `dicom/<study>/<series>/<orthanc id>.dcm` — the instance ID Orthanc
+//! would assign, so `verify`, `redownload`, and `check` treat imported and downloaded trees
+//! alike — with the usual `.partial` staging, `temporal_order.csv`, and checksum manifest.
+//! Study folders follow `output_layout` like `download` (see [`crate::layout`]).
+//! One [`ProcessResult`] is produced per study. Per-instance grouping and NIfTI conversion
+//! are not applied here; run `convert` on the output afterwards.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use dicom_object::{OpenFileOptions, Tag};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::warn;
+
+use crate::checksum::write_manifest;
+use crate::classify::LocalClassifier;
+use crate::client::{fallback_type_from_dicom, DicomStudyInfo, OrthancClient};
+use crate::config::{match_series, AnalysisConfig};
+use crate::downloader::{generate_series_folder_name, partial_dir, safe_dicom_filename};
+use crate::layout::{OutputLayout, NON_IMAGE_DIR};
+use crate::ordering::{is_dynamic_series, write_ordering_file};
+use crate::processor::{summarize_status, ProcessResult, SeriesReport};
+use crate::validate::orthanc_instance_id;
+
+/// Scratch folder (under the output directory) ZIPs are unpacked into.
+pub const IMPORT_SCRATCH_DIR: &str = ".import";
+
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// One series found in an unpacked ZIP.
+#[derive(Debug, Default)]
+pub struct ImportedSeries {
+    pub description: String,
+    pub series_number: Option<String>,
+    pub modality: Option<String>,
+    /// `(unpacked file, Orthanc instance ID)`.
+    pub files: Vec<(PathBuf, String)>,
+}
+
+/// One study found in an unpacked ZIP, series keyed by SeriesInstanceUID.
+#[derive(Debug, Default)]
+pub struct ImportedStudy {
+    pub info: DicomStudyInfo,
+    pub series: BTreeMap<String, ImportedSeries>,
+}
+
+/// ZIP files given directly, plus every `*.zip` inside given directories (sorted).
+pub fn collect_zip_files(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut zips = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            let mut found: Vec<PathBuf> = std::fs::read_dir(input)?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| {
+                    p.is_file()
+                        && p.extension()
+                            .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case("zip"))
+                })
+                .collect();
+            found.sort();
+            zips.extend(found);
+        } else if input.is_file() {
+            zips.push(input.clone());
+        } else {
+            return Err(anyhow!("{} does not exist", input.display()));
+        }
+    }
+    Ok(zips)
+}
+
+/// Extracts `zip_path` into `dest`; entries escaping `dest` (`../`) are skipped.
+/// Returns the number of files written.
+pub fn unpack_zip(zip_path: &Path, dest: &Path) -> Result<usize> {
+    let file =
+        File::open(zip_path).with_context(|| format!("Failed to open {}", zip_path.display()))?;
+    let mut archive = zip::ZipArchive::new(file)
+        .with_context(|| format!("{} is not a readable ZIP", zip_path.display()))?;
+    let mut count = 0;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let Some(relative) = entry.enclosed_name().map(Path::to_path_buf) else {
+            continue;
+        };
+        if entry.is_dir() {
+            continue;
+        }
+        let out = dest.join(relative);
+        if let Some(parent) = out.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut writer = File::create(&out)?;
+        std::io::copy(&mut entry, &mut writer)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Groups every parsable DICOM file under `dir` by study and series.
+///
+/// Files that are not DICOM, or lack the study/series/SOP UIDs (README, DICOMDIR, reports
+/// bundled in the export), are counted in the second return value and left out.
+pub fn scan_unpacked(dir: &Path) -> Result<(BTreeMap<String, ImportedStudy>, usize)> {
+    let mut studies: BTreeMap<String, ImportedStudy> = BTreeMap::new();
+    let mut ignored = 0;
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            let Ok(obj) = OpenFileOptions::new()
+                .read_until(PIXEL_DATA)
+                .open_file(&path)
+            else {
+                ignored += 1;
+                continue;
+            };
+            let get_tag = |tag: Tag| -> Option<String> {
+                obj.element(tag)
+                    .ok()
+                    .and_then(|e| e.to_str().ok())
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+            };
+            let (Some(study_uid), Some(series_uid), Some(sop_uid)) = (
+                get_tag(Tag(0x0020, 0x000D)), // StudyInstanceUID
+                get_tag(Tag(0x0020, 0x000E)), // SeriesInstanceUID
+                get_tag(Tag(0x0008, 0x0018)), // SOPInstanceUID
+            ) else {
+                ignored += 1;
+                continue;
+            };
+            let patient_id = get_tag(Tag(0x0010, 0x0020)).unwrap_or_default(); // PatientID
+            let instance_id = orthanc_instance_id(&patient_id, &study_uid, &series_uid, &sop_uid);
+
+            let study = studies.entry(study_uid).or_insert_with(|| ImportedStudy {
+                info: DicomStudyInfo {
+                    patient_id,
+                    study_date: get_tag(Tag(0x0008, 0x0020)).unwrap_or_default(), // StudyDate
+                    modality: get_tag(Tag(0x0008, 0x0060)).unwrap_or_default(),   // Modality
+                    accession_number: get_tag(Tag(0x0008, 0x0050)).unwrap_or_default(), // AccessionNumber
+                },
+                series: BTreeMap::new(),
+            });
+            let series = study
+                .series
+                .entry(series_uid)
+                .or_insert_with(|| ImportedSeries {
+                    description: get_tag(Tag(0x0008, 0x103E)).unwrap_or_default(), // SeriesDescription
+                    series_number: get_tag(Tag(0x0020, 0x0011)),                   // SeriesNumber
+                    modality: get_tag(Tag(0x0008, 0x0060)),                        // Modality
+                    files: Vec::new(),
+                });
+            series.files.push((path, instance_id));
+        }
+    }
+    for study in studies.values_mut() {
+        for series in study.series.values_mut() {
+            series.files.sort();
+        }
+    }
+    Ok((studies, ignored))
+}
+
+/// Series type used for the folder name and whitelist, as `download` derives it: the
+/// analyzer result, then `[classifier]` rules and tag-based fallback, then the description.
+async fn classify(
+    series: &ImportedSeries,
+    analyzer: Option<&OrthancClient>,
+    classifier: &LocalClassifier,
+) -> (String, Option<String>, Option<String>) {
+    let Some((sample, _)) = series.files.first() else {
+        return ("Unknown".into(), None, None);
+    };
+    let data = match tokio::fs::read(sample).await {
+        Ok(d) => d,
+        Err(_) => return ("Unknown".into(), None, None),
+    };
+    let fallback = fallback_type_from_dicom(&data, classifier);
+    let mut note = None;
+    let analyzed = match analyzer {
+        Some(client) => match client.analyze_dicom_data(data).await {
+            Ok(Some(t)) if t.to_lowercase() != "unknown" => Some(t),
+            result => {
+                note = result
+                    .err()
+                    .and_then(|e| crate::client::analyzer_bypass_reason(&e));
+                None
+            }
+        },
+        None => None,
+    };
+    let match_type = analyzed.clone().or_else(|| fallback.clone());
+    let folder_type = match_type
+        .clone()
+        .or_else(|| Some(series.description.clone()).filter(|d| !d.is_empty()))
+        .unwrap_or_else(|| "Unknown".to_string());
+    (folder_type, match_type, note)
+}
+
+/// Moves one series' files into `series_dir`; existing files are kept (counted as skipped).
+fn place_files(
+    files: &[(PathBuf, String)],
+    series_dir: &Path,
+    row: &mut SeriesReport,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (src, instance_id) in files {
+        let dest = series_dir.join(safe_dicom_filename(instance_id));
+        if dest.exists() {
+            row.skipped_instances += 1;
+            continue;
+        }
+        // 同一檔案系統直接 rename，否則改為複製
+        let placed = std::fs::rename(src, &dest).or_else(|_| std::fs::copy(src, &dest).map(|_| ()));
+        match placed {
+            Ok(()) => {
+                row.downloaded_instances += 1;
+                row.bytes += dest.metadata().map(|m| m.len()).unwrap_or(0);
+            }
+            Err(e) => {
+                row.failed_instances += 1;
+                errors.push(format!("{}: {}", src.display(), e));
+            }
+        }
+    }
+    errors
+}
+
+/// Lays out one study and returns its result.
+async fn import_study(
+    study: ImportedStudy,
+    source: &str,
+    dicom_root: &Path,
+    config: &AnalysisConfig,
+    analyzer: Option<&OrthancClient>,
+    layout: OutputLayout,
+) -> ProcessResult {
+    let mut res = ProcessResult {
+        accession: study.info.accession_number.clone(),
+        timestamp: Utc::now(),
+        ..Default::default()
+    };
+    res.notes.push(format!("imported from {}", source));
+    let study_folder = layout.study_folder(&study.info);
+
+    // 先分類全部 series，再依類型數量決定資料夾編號（與 download 相同規則）
+    let mut selected = Vec::new();
+    for (_, series) in study.series {
+        let (folder_type, match_type, note) = classify(&series, analyzer, &config.classifier).await;
+        let kind = match_series(
+            &series.description,
+            match_type.as_deref(),
+            series.modality.as_deref(),
+            config,
+        );
+        res.match_stats.record(kind, match_type.as_deref());
+        if let Some(note) = note {
+            res.notes.push(format!(
+                "{}: analyzer bypassed ({}), classified from headers",
+                series.description, note
+            ));
+        }
+        if kind.selected() {
+            selected.push((folder_type, series));
+        }
+    }
+    let mut type_counts: HashMap<String, usize> = HashMap::new();
+    for (t, _) in &selected {
+        *type_counts.entry(t.clone()).or_insert(0) += 1;
+    }
+
+    let study_dir = dicom_root.join(&study_folder);
+    let nonimage_study_dir = dicom_root.with_file_name(NON_IMAGE_DIR).join(&study_folder);
+    for (series_type, series) in selected {
+        let started = Instant::now();
+        let series_folder = generate_series_folder_name(
+            &series_type,
+            series.series_number.as_deref(),
+            &type_counts,
+        );
+        res.matched_series.push(series_folder.clone());
+        let mut row = SeriesReport {
+            study_folder: study_folder.clone(),
+            series_folder: series_folder.clone(),
+            expected_instances: series.files.len(),
+            ..Default::default()
+        };
+
+        // non_image_series = "separate" 時 SR/KO/PR/SEG 放在 nonimage/ 下
+        let final_dir = if config.series_filter.separates(series.modality.as_deref()) {
+            nonimage_study_dir.join(&series_folder)
+        } else {
+            study_dir.join(&series_folder)
+        };
+        let series_dir = if final_dir.exists() {
+            final_dir.clone()
+        } else {
+            partial_dir(&final_dir)
+        };
+        let placed = std::fs::create_dir_all(&series_dir)
+            .map_err(|e| vec![format!("Create dir failed {}: {}", series_dir.display(), e)])
+            .map(|_| place_files(&series.files, &series_dir, &mut row));
+        let errors = match placed {
+            Ok(errors) => errors,
+            Err(errors) => {
+                row.failed_instances = series.files.len();
+                errors
+            }
+        };
+
+        let mut series_dir = series_dir;
+        if errors.is_empty() && series_dir != final_dir {
+            match std::fs::rename(&series_dir, &final_dir) {
+                Ok(()) => series_dir = final_dir,
+                Err(e) => {
+                    res.reason
+                        .push(format!("Finalize failed {}: {}", final_dir.display(), e))
+                }
+            }
+        }
+        if row.failed_instances < row.expected_instances {
+            if is_dynamic_series(&series_folder, &series_dir) {
+                if let Err(e) = write_ordering_file(&series_dir) {
+                    warn!("ordering file failed for {}: {}", series_folder, e);
+                }
+            }
+            if let Err(e) = write_manifest(&series_dir) {
+                res.reason.push(format!(
+                    "Checksum manifest failed for {}: {}",
+                    series_folder, e
+                ));
+            }
+        }
+
+        row.status = if row.failed_instances == 0 {
+            "Downloaded"
+        } else if row.failed_instances < row.expected_instances {
+            "Partial"
+        } else {
+            "Failed"
+        }
+        .into();
+        if row.status == "Failed" {
+            res.failed_series.push(series_folder.clone());
+        } else {
+            res.downloaded_series.push(series_folder.clone());
+        }
+        if row.failed_instances > 0 {
+            res.reason.push(format!(
+                "{} of {} files could not be placed for {}",
+                row.failed_instances, row.expected_instances, series_folder
+            ));
+        }
+        row.error = errors.join("; ");
+        row.duration_ms = started.elapsed().as_millis() as u64;
+        res.series.push(row);
+    }
+
+    res.status = summarize_status(&res.downloaded_series, &res.reason);
+    res
+}
+
+/// Imports one ZIP: one result per study inside it, or a single `Failed` result named after
+/// the ZIP when it cannot be unpacked or holds no DICOM study.
+pub async fn import_zip(
+    zip_path: &Path,
+    output: &Path,
+    config: &AnalysisConfig,
+    analyzer: Option<&OrthancClient>,
+    layout: OutputLayout,
+) -> Vec<ProcessResult> {
+    let source = zip_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let scratch = output
+        .join(IMPORT_SCRATCH_DIR)
+        .join(zip_path.file_stem().unwrap_or_default());
+    let failed = |err: String| ProcessResult {
+        accession: source.clone(),
+        status: "Failed".into(),
+        reason: vec![err],
+        timestamp: Utc::now(),
+        ..Default::default()
+    };
+
+    let unpacked = {
+        let (zip_path, scratch) = (zip_path.to_path_buf(), scratch.clone());
+        tokio::task::spawn_blocking(move || {
+            let _ = std::fs::remove_dir_all(&scratch);
+            unpack_zip(&zip_path, &scratch)?;
+            scan_unpacked(&scratch)
+        })
+        .await
+    };
+    let (studies, ignored) = match unpacked {
+        Ok(Ok(found)) => found,
+        Ok(Err(e)) => return vec![failed(format!("Unpack failed: {:#}", e))],
+        Err(e) => return vec![failed(format!("Unpack task failed: {}", e))],
+    };
+
+    let results = if studies.is_empty() {
+        vec![failed("No DICOM study found in ZIP".into())]
+    } else {
+        let dicom_root = output.join("dicom");
+        let mut results = Vec::with_capacity(studies.len());
+        for (_, study) in studies {
+            let mut res = import_study(study, &source, &dicom_root, config, analyzer, layout).await;
+            if ignored > 0 {
+                res.notes
+                    .push(format!("{} non-DICOM files in {} ignored", ignored, source));
+            }
+            results.push(res);
+        }
+        results
+    };
+    let _ = tokio::fs::remove_dir_all(&scratch).await;
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_zip_files() {
+        let dir = std::env::temp_dir().join(format!("import_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["b.zip", "a.ZIP", "notes.txt"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let zips = collect_zip_files(std::slice::from_ref(&dir)).unwrap();
+        assert_eq!(zips, [dir.join("a.ZIP"), dir.join("b.zip")]);
+        assert!(collect_zip_files(&[dir.join("missing.zip")]).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
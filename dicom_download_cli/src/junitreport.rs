@@ -0,0 +1,129 @@
+//! JUnit XML reports for CI-driven pulls (`--report-junit`).
+//!
+//! Each accession is a test case in one `dicom_download` suite: `Success` passes, accessions
+//! skipped after an authentication failure are `<skipped/>`, and everything else is a
+//! `<failure>` whose message is the joined reasons, so CI UIs list failed accessions next to
+//! ordinary test failures. Notes and QC findings go to the case's `<system-out>`.
+
+use anyhow::Result;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::atomic::write_bytes_atomic;
+use crate::htmlreport::escape;
+use crate::processor::{ProcessResult, STATUS_NOT_ATTEMPTED};
+
+const SUITE: &str = "dicom_download";
+
+fn seconds(ms: u64) -> String {
+    format!("{:.3}", ms as f64 / 1000.0)
+}
+
+/// Renders the suite; `escape` also covers the XML special characters.
+pub fn junit_xml(results: &[ProcessResult]) -> String {
+    let failures = results
+        .iter()
+        .filter(|r| r.status != "Success" && r.status != STATUS_NOT_ATTEMPTED)
+        .count();
+    let skipped = results
+        .iter()
+        .filter(|r| r.status == STATUS_NOT_ATTEMPTED)
+        .count();
+    let total_ms: u64 = results.iter().map(|r| r.duration_ms).sum();
+    let timestamp = results
+        .iter()
+        .map(|r| r.timestamp)
+        .min()
+        .unwrap_or_else(chrono::Utc::now)
+        .format("%Y-%m-%dT%H:%M:%S");
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"{SUITE}\" tests=\"{}\" failures=\"{failures}\" errors=\"0\" \
+         skipped=\"{skipped}\" time=\"{}\">",
+        results.len(),
+        seconds(total_ms)
+    );
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{SUITE}\" tests=\"{}\" failures=\"{failures}\" errors=\"0\" \
+         skipped=\"{skipped}\" time=\"{}\" timestamp=\"{timestamp}\">",
+        results.len(),
+        seconds(total_ms)
+    );
+    for r in results {
+        let _ = write!(
+            xml,
+            "    <testcase classname=\"{SUITE}.accession\" name=\"{}\" time=\"{}\">",
+            escape(&r.accession),
+            seconds(r.duration_ms)
+        );
+        let mut children = String::new();
+        let reasons = escape(&r.reason.join("; "));
+        if r.status == STATUS_NOT_ATTEMPTED {
+            let _ = writeln!(children, "      <skipped message=\"{}\"/>", reasons);
+        } else if r.status != "Success" {
+            let _ = writeln!(
+                children,
+                "      <failure message=\"{}\" type=\"{}\">{}</failure>",
+                reasons,
+                escape(&r.status),
+                escape(&r.reason.join("\n"))
+            );
+        }
+        let output: Vec<String> = r
+            .notes
+            .iter()
+            .chain(&r.qc_issues)
+            .map(|s| escape(s))
+            .collect();
+        if !output.is_empty() {
+            let _ = writeln!(
+                children,
+                "      <system-out>{}</system-out>",
+                output.join("\n")
+            );
+        }
+        if children.is_empty() {
+            xml.push_str("</testcase>\n");
+        } else {
+            let _ = write!(xml, "\n{children}    </testcase>\n");
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+pub fn write_junit_report(path: &Path, results: &[ProcessResult]) -> Result<()> {
+    write_bytes_atomic(path, junit_xml(results).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_junit_xml_marks_failures_and_skips() {
+        let result = |acc: &str, status: &str, reason: &[&str]| ProcessResult {
+            accession: acc.into(),
+            status: status.into(),
+            reason: reason.iter().map(|s| s.to_string()).collect(),
+            duration_ms: 1500,
+            ..Default::default()
+        };
+        let xml = junit_xml(&[
+            result("A1", "Success", &[]),
+            result("A2", "Failed", &["No studies found", "x < y"]),
+            result(
+                "A3",
+                STATUS_NOT_ATTEMPTED,
+                &["Skipped after authentication failure"],
+            ),
+        ]);
+        assert!(xml.contains("tests=\"3\" failures=\"1\" errors=\"0\" skipped=\"1\""));
+        assert!(xml.contains("name=\"A1\" time=\"1.500\"></testcase>"));
+        assert!(xml.contains("<failure message=\"No studies found; x &lt; y\" type=\"Failed\">"));
+        assert!(xml.contains("<skipped message=\"Skipped after authentication failure\"/>"));
+    }
+}
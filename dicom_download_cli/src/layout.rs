@@ -0,0 +1,245 @@
+//! Output folder layout under `dicom/` (and the matching `niix/` and `media/` trees).
+//!
+//! `flat` (default) keeps one folder per study directly under the root:
+//! `dicom/<PatientID>_<StudyDate>_<Modality>_<Accession>/<series>/`. For cohorts where one
+//! patient has many studies, `patient` nests the study folders under a patient folder
+//! (`dicom/<PatientID>/<study>/<series>/`) and `patient_date` adds the study date
+//! (`dicom/<PatientID>/<StudyDate>/<study>/<series>/`). The study folder keeps its full
+//! name in every layout, so a study moved out of the tree still identifies itself.
+//!
+//! Instance files are named `<Orthanc instance ID>.dcm` by default. `instance_naming =
+//! "instance_number"` names them `<InstanceNumber:04>.dcm` instead, so slices sort in
+//! acquisition order for people and for tools that order by file name; instances whose
+//! number is missing or shared with another instance of the series fall back to
+//! `<SOPInstanceUID>.dcm`.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::client::{DicomStudyInfo, InstanceTags};
+use crate::downloader::{generate_study_folder_name, safe_dicom_filename, sanitize_segment};
+
+/// Placeholder for a missing patient ID or study date in nested layouts.
+const UNKNOWN: &str = "unknown";
+
+/// Output folder next to `dicom/` for non-image series under `non_image_series = "separate"`;
+/// it uses the same study layout and is left out of conversion, DICOMDIR, and packaging.
+pub const NON_IMAGE_DIR: &str = "nonimage";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputLayout {
+    #[default]
+    Flat,
+    Patient,
+    PatientDate,
+}
+
+impl std::str::FromStr for OutputLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().replace('-', "_").as_str() {
+            "flat" => Ok(Self::Flat),
+            "patient" => Ok(Self::Patient),
+            "patient_date" => Ok(Self::PatientDate),
+            other => Err(anyhow!(
+                "Invalid output layout '{}': expected flat, patient, or patient_date",
+                other
+            )),
+        }
+    }
+}
+
+impl OutputLayout {
+    /// Study folder relative to the output root, `/`-separated.
+    pub fn study_folder(&self, info: &DicomStudyInfo) -> String {
+        self.nest(
+            &info.patient_id,
+            &info.study_date,
+            generate_study_folder_name(info),
+        )
+    }
+
+    /// Study folder for an accession whose DICOM tags could not be read.
+    pub fn unknown_study_folder(&self, accession: &str) -> String {
+        self.nest("", "", format!("{}_unknown", accession))
+    }
+
+    fn nest(&self, patient_id: &str, study_date: &str, leaf: String) -> String {
+        let segment = |value: &str| {
+            if value.trim().is_empty() {
+                UNKNOWN.to_string()
+            } else {
+                sanitize_segment(value)
+            }
+        };
+        match self {
+            Self::Flat => leaf,
+            Self::Patient => format!("{}/{}", segment(patient_id), leaf),
+            Self::PatientDate => {
+                format!("{}/{}/{}", segment(patient_id), segment(study_date), leaf)
+            }
+        }
+    }
+
+    /// Directory levels between the output root and a study folder.
+    pub fn depth(&self) -> usize {
+        match self {
+            Self::Flat => 1,
+            Self::Patient => 2,
+            Self::PatientDate => 3,
+        }
+    }
+
+    /// Study folders under `root` as `(relative folder, path)`, sorted by folder.
+    pub fn study_dirs(&self, root: &Path) -> std::io::Result<Vec<(String, PathBuf)>> {
+        let mut level = vec![(String::new(), root.to_path_buf())];
+        for _ in 0..self.depth() {
+            let mut next = Vec::new();
+            for (prefix, dir) in level {
+                for entry in std::fs::read_dir(&dir)? {
+                    let entry = entry?;
+                    if !entry.path().is_dir() {
+                        continue;
+                    }
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    let folder = if prefix.is_empty() {
+                        name
+                    } else {
+                        format!("{}/{}", prefix, name)
+                    };
+                    next.push((folder, entry.path()));
+                }
+            }
+            level = next;
+        }
+        level.sort();
+        Ok(level)
+    }
+}
+
+/// How instance files inside a series folder are named.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InstanceNaming {
+    #[default]
+    OrthancId,
+    InstanceNumber,
+}
+
+impl std::str::FromStr for InstanceNaming {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().replace('-', "_").as_str() {
+            "orthanc_id" => Ok(Self::OrthancId),
+            "instance_number" => Ok(Self::InstanceNumber),
+            other => Err(anyhow!(
+                "Invalid instance naming '{}': expected orthanc_id or instance_number",
+                other
+            )),
+        }
+    }
+}
+
+/// File name per Orthanc instance ID for `instance_number` naming: `<InstanceNumber:04>.dcm`,
+/// or `<SOPInstanceUID>.dcm` (then `<id>.dcm`) when the number is missing or not unique.
+pub fn instance_number_file_names(instances: &[InstanceTags]) -> HashMap<String, String> {
+    let number = |tags: &InstanceTags| {
+        tags.instance_number
+            .as_deref()
+            .and_then(|n| n.trim().parse::<u32>().ok())
+    };
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+    for n in instances.iter().filter_map(number) {
+        *counts.entry(n).or_default() += 1;
+    }
+    instances
+        .iter()
+        .map(|tags| {
+            let name = match number(tags) {
+                Some(n) if counts[&n] == 1 => format!("{:04}.dcm", n),
+                _ => match &tags.sop_instance_uid {
+                    Some(uid) => safe_dicom_filename(uid),
+                    None => safe_dicom_filename(&tags.id),
+                },
+            };
+            (tags.id.clone(), name)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layouts_nest_study_folders() {
+        let info = DicomStudyInfo {
+            patient_id: "P1".into(),
+            study_date: "20240601".into(),
+            modality: "MR".into(),
+            accession_number: "A1".into(),
+        };
+        assert_eq!(OutputLayout::Flat.study_folder(&info), "P1_20240601_MR_A1");
+        assert_eq!(
+            OutputLayout::Patient.study_folder(&info),
+            "P1/P1_20240601_MR_A1"
+        );
+        assert_eq!(
+            OutputLayout::PatientDate.study_folder(&info),
+            "P1/20240601/P1_20240601_MR_A1"
+        );
+        assert_eq!(
+            OutputLayout::Patient.unknown_study_folder("A2"),
+            "unknown/A2_unknown"
+        );
+        assert_eq!(
+            "patient-date".parse::<OutputLayout>().unwrap(),
+            OutputLayout::PatientDate
+        );
+        assert!("by_year".parse::<OutputLayout>().is_err());
+
+        let root = std::env::temp_dir().join(format!("layout_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for folder in ["P1/20240601/S1", "P1/20240702/S2", "P2/20230101/S3"] {
+            std::fs::create_dir_all(root.join(folder).join("T1")).unwrap();
+        }
+        std::fs::write(root.join("P1").join("notes.txt"), "x").unwrap();
+        let found: Vec<String> = OutputLayout::PatientDate
+            .study_dirs(&root)
+            .unwrap()
+            .into_iter()
+            .map(|(folder, _)| folder)
+            .collect();
+        assert_eq!(
+            found,
+            vec!["P1/20240601/S1", "P1/20240702/S2", "P2/20230101/S3"]
+        );
+        assert_eq!(OutputLayout::Flat.study_dirs(&root).unwrap().len(), 2);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_instance_number_names_fall_back_on_collision() {
+        let tags = |id: &str, number: Option<&str>, uid: Option<&str>| InstanceTags {
+            id: id.into(),
+            instance_number: number.map(String::from),
+            sop_instance_uid: uid.map(String::from),
+        };
+        let names = instance_number_file_names(&[
+            tags("a", Some("7"), Some("1.2.7")),
+            tags("b", Some("12"), Some("1.2.12a")),
+            tags("c", Some("12"), Some("1.2.12b")),
+            tags("d", None, None),
+        ]);
+        assert_eq!(names["a"], "0007.dcm");
+        assert_eq!(names["b"], "1.2.12a.dcm");
+        assert_eq!(names["c"], "1.2.12b.dcm");
+        assert_eq!(names["d"], "d.dcm");
+        assert_eq!(
+            "instance-number".parse::<InstanceNaming>().unwrap(),
+            InstanceNaming::InstanceNumber
+        );
+    }
+}
@@ -0,0 +1,116 @@
+//! Library API behind the `dicom_download_cli` binary.
+//!
+//! Other Rust services can embed the download, remote C-MOVE, check, and conversion
+//! workflows directly instead of shelling out to the CLI:
+//!
+//! - [`client::OrthancClient`]: HTTP client for Orthanc and the analysis service.
+//! - [`downloader`]: direct download flow ([`DownloadPlan`] → files on disk).
+//! - [`preview`]: `download --preview` per-series `preview.png` from Orthanc's renderer.
+//! - [`processed`]: `download --mark-processed` / `--skip-processed` study marks in Orthanc.
+//! - [`processor`]: remote C-MOVE flow and [`ProcessResult`] reporting.
+//! - [`atomic`]: crash-safe (temp file + fsync + rename) report and state writes.
+//! - [`audit`]: append-only JSON-lines log of destructive file operations.
+//! - [`checker`]: DWI/ADC structure checks producing a [`CheckReport`].
+//! - [`checkrules`]: `[[check.rules]]` folder/tag rules run by `check`.
+//! - [`checksum`]: per-series SHA-256 manifests and `verify`.
+//! - [`classify`]: tag-based series types for modalities the Analyze API does not cover.
+//! - [`classifyhook`]: `classifier_command`, an external series classifier used instead of
+//!   the analysis service.
+//! - [`converter`]: dcm2niix integration.
+//! - [`credentials`]: password prompt and OS keyring lookup.
+//! - [`dicomdir`]: DICOMDIR media folders for downloaded studies.
+//! - [`config`]: runtime configuration and input file parsing.
+//! - [`duplicates`]: `duplicate_studies` policy for study folders claimed twice in a run.
+//! - [`email`]: end-of-run summary email with the CSV report attached.
+//! - [`encrypt`]: age encryption of packaged studies (`[encryption]`).
+//! - [`estimate`]: pre-flight batch size estimation.
+//! - [`fdlimit`]: open-file budget and descriptor limit check for large batches.
+//! - [`failed`]: `failed_instances.json` and `retry-instances` for instance-level retries.
+//! - [`events`]: `--events jsonl` lifecycle event stream on stdout.
+//! - [`explain`]: per-series download decisions for debugging whitelist config.
+//! - [`htmlreport`]: standalone HTML run and check reports with inline charts.
+//! - [`import`]: offline study ZIPs laid out like downloaded studies.
+//! - [`package`]: `download --package zip` study archives.
+//! - [`parquetreport`]: Parquet tables of run and check results for analytics pipelines.
+//! - [`junitreport`]: JUnit XML run reports for CI systems.
+//! - [`listing`]: `list` inventory of stored studies/series with classification.
+//! - [`layout`]: patient/study nesting of output folders (`output_layout`).
+//! - [`logging`]: tracing subscriber setup (verbosity, JSON logs, log file) that spares progress bars.
+//! - [`nifti`]: NIfTI header and data-length checks before converted DICOMs are removed.
+//! - [`notify`]: webhook events for batch start, per-accession results, and batch end.
+//! - [`ordering`]: temporal order export for dynamic (DSC/ASL) series.
+//! - [`progress`]: terminal progress layout and log routing.
+//! - [`purge`]: `download --purge-source` deletion of verified studies from Orthanc.
+//! - [`qc`]: post-download pixel-data sanity checks.
+//! - [`redownload`]: re-fetch a single downloaded series folder from Orthanc.
+//! - [`reportdiff`]: accession-level comparison of two JSON reports (`report diff`).
+//! - [`reportfile`]: timestamped/appended report files and report locking.
+//! - [`retry`]: backoff, jitter, and retry budget for transient HTTP failures.
+//! - [`server`]: `serve` HTTP API that queues download jobs and serves their reports.
+//! - [`sidecar`]: per-series `series.json` metadata written during download.
+//! - [`sopindex`]: reuse of files already on disk by SOPInstanceUID when Orthanc IDs change.
+//! - [`state`]: persistent cross-run cache stored next to the output.
+//! - [`studyselect`]: choosing among studies that share an accession (`--study-select`).
+//! - [`trash`]: trash folder with retention for DICOMs removed after conversion.
+//! - [`tui`]: `--tui` full-screen dashboard with pause/resume of accession scheduling.
+//! - [`validate`]: post-write parse and UID check of downloaded instances.
+//! - [`verify`]: local tree vs. Orthanc series/instance comparison.
+//! - [`watch`]: drop folder polling and archiving for `watch`.
+
+pub mod atomic;
+pub mod audit;
+pub mod checker;
+pub mod checkrules;
+pub mod checksum;
+pub mod classify;
+pub mod classifyhook;
+pub mod client;
+pub mod config;
+pub mod converter;
+pub mod credentials;
+pub mod dicomdir;
+pub mod downloader;
+pub mod duplicates;
+pub mod email;
+pub mod encrypt;
+pub mod estimate;
+pub mod events;
+pub mod explain;
+pub mod failed;
+pub mod fdlimit;
+pub mod htmlreport;
+pub mod import;
+pub mod junitreport;
+pub mod layout;
+pub mod listing;
+pub mod logging;
+pub mod nifti;
+pub mod notify;
+pub mod ordering;
+pub mod package;
+pub mod parquetreport;
+pub mod preview;
+pub mod processed;
+pub mod processor;
+pub mod progress;
+pub mod purge;
+pub mod qc;
+pub mod redownload;
+pub mod reportdiff;
+pub mod reportfile;
+pub mod retry;
+pub mod server;
+pub mod sidecar;
+pub mod sopindex;
+pub mod state;
+pub mod studyselect;
+pub mod trash;
+pub mod tui;
+pub mod validate;
+pub mod verify;
+pub mod watch;
+
+pub use checker::{CheckReport, CheckSummary};
+pub use client::{DownloadPlan, OrthancClient, SeriesDownloadPlan};
+pub use config::{AnalysisConfig, EffectiveConfig, RuntimeConfigFile};
+pub use processor::{FailOn, ProcessResult};
@@ -0,0 +1,272 @@
+//! `list` subcommand: enumerates the studies and series Orthanc holds for each accession,
+//! with instance counts and classification, without downloading anything.
+//!
+//! Meant for triage before building a cohort: every series is listed, including the ones
+//! the series patterns, instance bounds, or whitelist would leave out, together with the
+//! rule that decided it. With an analysis service configured, the first instance of each
+//! series is fetched and classified exactly as `download` does; `--no-analyze` lists tags
+//! only.
+
+use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::atomic::write_atomic;
+use crate::client::{analyzer_bypass_reason, fallback_type_from_dicom, OrthancClient, SeriesMeta};
+use crate::config::{match_series, AnalysisConfig, MatchKind};
+
+/// One series (or one accession-level error) in the listing.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ListRow {
+    pub accession: String,
+    /// Orthanc study ID; empty on accession-level errors.
+    pub study_id: String,
+    pub study_date: String,
+    pub study_description: String,
+    pub series_id: String,
+    pub series_number: String,
+    pub series_description: String,
+    pub modality: String,
+    pub instances: usize,
+    /// Type from the analysis service (or the header fallback); empty when not analyzed.
+    pub series_type: String,
+    /// Rule that selected or rejected the series (`direct_keyword`, `whitelist`, ...).
+    pub decision: String,
+    pub selected: bool,
+    /// Why the analyzer was bypassed, or why the series was filtered.
+    pub note: String,
+    pub error: String,
+}
+
+fn decision_label(kind: MatchKind) -> &'static str {
+    match kind {
+        MatchKind::DownloadAll => "download_all",
+        MatchKind::DirectKeyword => "direct_keyword",
+        MatchKind::Whitelist => "whitelist",
+        MatchKind::Excluded => "excluded",
+        MatchKind::Filtered => "filtered",
+    }
+}
+
+/// Classifies one stored series; the analyzer is only consulted when the keyword rules
+/// and filters leave the series undecided.
+async fn classify_series(
+    client: &OrthancClient,
+    meta: &SeriesMeta,
+    config: &AnalysisConfig,
+    analyze: bool,
+    row: &mut ListRow,
+) {
+    let description = meta.description.as_deref().unwrap_or_default();
+    let modality = meta.modality.as_deref();
+    let filter = &config.series_filter;
+    if let Some(reason) = filter
+        .rejection(description, modality)
+        .or_else(|| filter.count_rejection(meta.instances.len()))
+    {
+        row.decision = decision_label(MatchKind::Filtered).into();
+        row.note = reason;
+        return;
+    }
+
+    let mut kind = match_series(description, None, modality, config);
+    let first = meta.instances.first();
+    if let (true, MatchKind::Excluded, Some(instance)) = (analyze, kind, first) {
+        match client.download_instance_file(instance).await {
+            Ok(data) => {
+                let fallback = fallback_type_from_dicom(&data, &config.classifier);
+                let series_type = match client.analyze_dicom_data(data).await {
+                    Ok(Some(t)) if !t.eq_ignore_ascii_case("unknown") => Some(t),
+                    result => {
+                        if let Some(reason) = result.err().and_then(|e| analyzer_bypass_reason(&e))
+                        {
+                            row.note =
+                                format!("analyzer bypassed ({}), classified from headers", reason);
+                        }
+                        fallback
+                    }
+                };
+                kind = match_series(description, series_type.as_deref(), modality, config);
+                row.series_type = series_type.unwrap_or_default();
+            }
+            Err(e) => row.error = format!("Sample instance download failed: {}", e),
+        }
+    }
+    row.decision = decision_label(kind).into();
+    row.selected = kind.selected();
+}
+
+/// Lists every study and series stored for one accession.
+async fn list_accession(
+    client: &OrthancClient,
+    accession: String,
+    config: &AnalysisConfig,
+    analyze: bool,
+) -> Vec<ListRow> {
+    let error_row = |error: String| ListRow {
+        accession: accession.clone(),
+        error,
+        ..Default::default()
+    };
+    let study_ids = match client.find_study_ids_by_accession(&accession).await {
+        Ok(ids) if ids.is_empty() => return vec![error_row("No studies found".into())],
+        Ok(ids) => ids,
+        Err(e) => return vec![error_row(format!("Study lookup failed: {}", e))],
+    };
+
+    let mut rows = Vec::new();
+    for study_id in study_ids {
+        let study = match client.get_study_summary(&study_id).await {
+            Ok(s) => s,
+            Err(e) => {
+                rows.push(ListRow {
+                    study_id,
+                    ..error_row(format!("Study query failed: {}", e))
+                });
+                continue;
+            }
+        };
+        let series = match client.list_series_meta(&study_id).await {
+            Ok(series) => series,
+            Err(e) => {
+                rows.push(ListRow {
+                    study_id,
+                    ..error_row(format!("Series query failed: {}", e))
+                });
+                continue;
+            }
+        };
+        for (series_id, meta) in series {
+            let mut row = ListRow {
+                accession: accession.clone(),
+                study_id: study_id.clone(),
+                study_date: study.study_date.clone(),
+                study_description: study.description.clone().unwrap_or_default(),
+                series_id,
+                series_number: meta.series_number.clone().unwrap_or_default(),
+                series_description: meta.description.clone().unwrap_or_default(),
+                modality: meta.modality.clone().unwrap_or_default(),
+                instances: meta.instances.len(),
+                ..Default::default()
+            };
+            classify_series(client, &meta, config, analyze, &mut row).await;
+            rows.push(row);
+        }
+    }
+    rows
+}
+
+/// Lists all accessions with bounded concurrency, keeping the input order.
+pub async fn run_list(
+    client: Arc<OrthancClient>,
+    accessions: Vec<String>,
+    config: Arc<AnalysisConfig>,
+    analyze: bool,
+    concurrency: usize,
+) -> Vec<ListRow> {
+    let per_accession: Vec<Vec<ListRow>> = stream::iter(accessions)
+        .map(|acc| {
+            let client = client.clone();
+            let config = config.clone();
+            async move { list_accession(&client, acc, &config, analyze).await }
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+    per_accession.into_iter().flatten().collect()
+}
+
+/// Writes the listing as CSV, one row per series.
+pub fn write_list_csv(path: &Path, rows: &[ListRow]) -> Result<()> {
+    write_atomic(path, |w| write_list_rows(w, rows))
+}
+
+fn write_list_rows(w: &mut impl std::io::Write, rows: &[ListRow]) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer(w);
+    wtr.write_record([
+        "AccessionNumber",
+        "StudyId",
+        "StudyDate",
+        "StudyDescription",
+        "SeriesId",
+        "SeriesNumber",
+        "SeriesDescription",
+        "Modality",
+        "Instances",
+        "SeriesType",
+        "Decision",
+        "Selected",
+        "Note",
+        "Error",
+    ])?;
+    for r in rows {
+        wtr.write_record([
+            r.accession.as_str(),
+            &r.study_id,
+            &r.study_date,
+            &r.study_description,
+            &r.series_id,
+            &r.series_number,
+            &r.series_description,
+            &r.modality,
+            &r.instances.to_string(),
+            &r.series_type,
+            &r.decision,
+            if r.selected { "true" } else { "false" },
+            &r.note,
+            &r.error,
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes the listing as a JSON array.
+pub fn write_list_json(path: &Path, rows: &[ListRow]) -> Result<()> {
+    write_atomic(path, |w| {
+        serde_json::to_writer_pretty(w, rows)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_csv_rows() {
+        let rows = vec![
+            ListRow {
+                accession: "A1".into(),
+                study_id: "st1".into(),
+                study_date: "20240301".into(),
+                series_id: "se1".into(),
+                series_description: "Ax T1".into(),
+                modality: "MR".into(),
+                instances: 24,
+                series_type: "T1".into(),
+                decision: decision_label(MatchKind::Whitelist).into(),
+                selected: true,
+                ..Default::default()
+            },
+            ListRow {
+                accession: "A2".into(),
+                error: "No studies found".into(),
+                ..Default::default()
+            },
+        ];
+        let mut out = Vec::new();
+        write_list_rows(&mut out, &rows).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("AccessionNumber,StudyId,StudyDate"));
+        assert_eq!(
+            lines[1],
+            "A1,st1,20240301,,se1,,Ax T1,MR,24,T1,whitelist,true,,"
+        );
+        assert_eq!(lines[2], "A2,,,,,,,,0,,,false,,No studies found");
+    }
+}
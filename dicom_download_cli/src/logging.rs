@@ -0,0 +1,238 @@
+//! Log output: `tracing` subscriber setup for `-v`/`-q`, `--log-json`, and `--log-file`.
+//!
+//! Diagnostics (status lines, warnings, errors) are `tracing` events written to stderr;
+//! command results such as summaries and tables stay on stdout. indicatif redraws its bars
+//! in place, so a plain `eprintln!` while bars are visible tears them; console events are
+//! therefore written through [`MultiProgress::suspend`] of the bars registered with
+//! [`attach_progress`]. `--log-file` gets the same events with timestamps and no colours,
+//! at `info` or more detailed even under `-q`. `DICOM_CLI_LOG` takes a full filter directive
+//! (e.g. `dicom_download_cli::client=trace`) when the flags are not fine-grained enough.
+
+use anyhow::{Context, Result};
+use indicatif::MultiProgress;
+use std::fs::OpenOptions;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+/// Environment variable holding a filter directive that overrides `-v`/`-q`.
+pub const LOG_FILTER_ENV: &str = "DICOM_CLI_LOG";
+
+/// Bars that console log lines must not tear (the innermost registration wins).
+static PROGRESS: Mutex<Option<MultiProgress>> = Mutex::new(None);
+/// `--log-file`, also used for per-item lines when progress bars are collapsed.
+static LOG_FILE: OnceLock<PathBuf> = OnceLock::new();
+/// Receives console lines instead of stderr while a full-screen dashboard owns the terminal.
+static CAPTURE: Mutex<Option<ConsoleSink>> = Mutex::new(None);
+
+/// Callback for captured console lines (see [`capture_console`]).
+pub type ConsoleSink = Arc<dyn Fn(&str) + Send + Sync>;
+
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    /// Number of `-v` flags.
+    pub verbose: u8,
+    /// Number of `-q` flags.
+    pub quiet: u8,
+    pub json: bool,
+    pub file: Option<PathBuf>,
+}
+
+/// `-qq` error, `-q` warn, default info, `-v` debug, `-vv` trace.
+pub fn level(verbose: u8, quiet: u8) -> LevelFilter {
+    match i16::from(verbose) - i16::from(quiet) {
+        i16::MIN..=-2 => LevelFilter::ERROR,
+        -1 => LevelFilter::WARN,
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Filter directive for a level: dependencies stay at `warn` unless tracing everything.
+pub fn directive(level: LevelFilter) -> String {
+    if level <= LevelFilter::WARN {
+        level.to_string().to_lowercase()
+    } else {
+        let deps = if level == LevelFilter::TRACE {
+            "info"
+        } else {
+            "warn"
+        };
+        format!(
+            "{},dicom_download_cli={}",
+            deps,
+            level.to_string().to_lowercase()
+        )
+    }
+}
+
+fn filter(level: LevelFilter) -> EnvFilter {
+    let directive = std::env::var(LOG_FILTER_ENV)
+        .ok()
+        .filter(|d| !d.trim().is_empty())
+        .unwrap_or_else(|| self::directive(level));
+    EnvFilter::new(directive)
+}
+
+/// Installs the global subscriber; call once at startup.
+pub fn init(options: &LogOptions) -> Result<()> {
+    let console_level = level(options.verbose, options.quiet);
+    let mut layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> = Vec::new();
+
+    let console = tracing_subscriber::fmt::layer()
+        .with_writer(ConsoleWriter)
+        .with_target(false);
+    let console = if options.json {
+        console.json().with_filter(filter(console_level)).boxed()
+    } else {
+        console
+            .without_time()
+            .with_ansi(std::io::stderr().is_terminal())
+            .with_filter(filter(console_level))
+            .boxed()
+    };
+    layers.push(console);
+
+    if let Some(path) = &options.file {
+        let handle = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open log file {}", path.display()))?;
+        let _ = LOG_FILE.set(path.clone());
+        let file = tracing_subscriber::fmt::layer()
+            .with_writer(Mutex::new(handle))
+            .with_ansi(false);
+        let file_filter = filter(console_level.max(LevelFilter::INFO));
+        layers.push(if options.json {
+            file.json().with_filter(file_filter).boxed()
+        } else {
+            file.with_filter(file_filter).boxed()
+        });
+    }
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .try_init()
+        .context("Failed to initialize logging")
+}
+
+/// The `--log-file` path, when one was given.
+pub fn log_file() -> Option<&'static Path> {
+    LOG_FILE.get().map(PathBuf::as_path)
+}
+
+/// Registers bars for console logging until the guard is dropped.
+pub fn attach_progress(mp: &MultiProgress) -> ProgressGuard {
+    let previous = PROGRESS
+        .lock()
+        .ok()
+        .and_then(|mut active| active.replace(mp.clone()));
+    ProgressGuard { previous }
+}
+
+/// Sends console log lines to `sink` instead of stderr until the guard is dropped
+/// (`--log-file` is unaffected).
+pub fn capture_console(sink: ConsoleSink) -> CaptureGuard {
+    if let Ok(mut capture) = CAPTURE.lock() {
+        *capture = Some(sink);
+    }
+    CaptureGuard
+}
+
+/// Stops console capture on drop.
+pub struct CaptureGuard;
+
+impl Drop for CaptureGuard {
+    fn drop(&mut self) {
+        if let Ok(mut capture) = CAPTURE.lock() {
+            *capture = None;
+        }
+    }
+}
+
+/// Restores the previously registered bars on drop.
+pub struct ProgressGuard {
+    previous: Option<MultiProgress>,
+}
+
+impl Drop for ProgressGuard {
+    fn drop(&mut self) {
+        if let Ok(mut active) = PROGRESS.lock() {
+            *active = self.previous.take();
+        }
+    }
+}
+
+/// Stderr writer that hides the registered bars while an event is printed.
+#[derive(Clone, Copy)]
+struct ConsoleWriter;
+
+impl<'a> MakeWriter<'a> for ConsoleWriter {
+    type Writer = ConsoleLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        ConsoleLine(Vec::new())
+    }
+}
+
+/// One formatted event, flushed to stderr as a whole when dropped.
+struct ConsoleLine(Vec<u8>);
+
+impl Write for ConsoleLine {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ConsoleLine {
+    fn drop(&mut self) {
+        if self.0.is_empty() {
+            return;
+        }
+        let sink = CAPTURE.lock().ok().and_then(|capture| capture.clone());
+        if let Some(sink) = sink {
+            sink(String::from_utf8_lossy(&self.0).trim_end());
+            return;
+        }
+        let write = || {
+            let _ = std::io::stderr().lock().write_all(&self.0);
+        };
+        let active = PROGRESS.lock().ok().and_then(|active| active.clone());
+        match active {
+            Some(mp) => mp.suspend(write),
+            None => write(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_and_directive() {
+        assert_eq!(level(0, 0), LevelFilter::INFO);
+        assert_eq!(level(1, 0), LevelFilter::DEBUG);
+        assert_eq!(level(3, 0), LevelFilter::TRACE);
+        assert_eq!(level(0, 1), LevelFilter::WARN);
+        assert_eq!(level(0, 5), LevelFilter::ERROR);
+        assert_eq!(level(1, 1), LevelFilter::INFO);
+        assert_eq!(directive(LevelFilter::WARN), "warn");
+        assert_eq!(
+            directive(LevelFilter::DEBUG),
+            "warn,dicom_download_cli=debug"
+        );
+    }
+}
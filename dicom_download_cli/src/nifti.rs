@@ -0,0 +1,229 @@
+//! NIfTI output checks run before `delete_dicom_after_conversion` removes the source DICOMs.
+//!
+//! Only the header and the data length are examined: the file must be NIfTI-1 or NIfTI-2
+//! (either byte order, `.nii` or `.nii.gz`), have sane dimensions and a positive `bitpix`,
+//! and hold at least the voxel bytes the header promises. Decompressing a `.nii.gz` to the
+//! end also verifies its CRC, so truncated archives are caught.
+
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// What a valid NIfTI file holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NiftiInfo {
+    /// `dim[1..=dim[0]]`.
+    pub dims: Vec<u64>,
+    /// Product of the dimensions past the third (1 for a 3D image).
+    pub volumes: u64,
+}
+
+/// Reads and checks one `.nii` / `.nii.gz` file.
+pub fn inspect_nifti(path: &Path) -> Result<NiftiInfo> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let gzipped = path.to_string_lossy().to_ascii_lowercase().ends_with(".gz");
+    let mut reader: Box<dyn Read> = if gzipped {
+        Box::new(GzDecoder::new(BufReader::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    inspect_stream(&mut reader).with_context(|| format!("{} is not a valid NIfTI", path.display()))
+}
+
+/// Checks a converted series must pass before its DICOMs are removed (`[conversion]`
+/// `delete_require_valid_nifti`, `delete_min_volumes`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeleteChecks {
+    /// Every NIfTI output must pass [`inspect_nifti`].
+    pub require_valid: bool,
+    /// The outputs together must hold at least this many volumes.
+    pub min_volumes: u64,
+}
+
+impl DeleteChecks {
+    /// Checks the NIfTI outputs of one series; the error is the reason to keep its DICOMs.
+    pub fn verify(&self, nifti_files: &[PathBuf]) -> Result<()> {
+        if nifti_files.is_empty() {
+            return Err(anyhow!("no NIfTI output"));
+        }
+        let mut volumes = 0;
+        for path in nifti_files {
+            match inspect_nifti(path) {
+                Ok(info) => volumes += info.volumes,
+                Err(e) if self.require_valid => return Err(e),
+                // 不要求驗證時，無法解析的檔案不計入 volume 數
+                Err(_) => {}
+            }
+        }
+        if volumes < self.min_volumes {
+            return Err(anyhow!(
+                "{} volume(s) converted, delete_min_volumes is {}",
+                volumes,
+                self.min_volumes
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn inspect_stream(reader: &mut dyn Read) -> Result<NiftiInfo> {
+    let mut header = vec![0u8; 348];
+    reader
+        .read_exact(&mut header)
+        .context("header is truncated")?;
+    let size = |big: bool| {
+        let bytes = [header[0], header[1], header[2], header[3]];
+        if big {
+            i32::from_be_bytes(bytes)
+        } else {
+            i32::from_le_bytes(bytes)
+        }
+    };
+    let (big, version) = match (size(false), size(true)) {
+        (348, _) => (false, 1),
+        (_, 348) => (true, 1),
+        (540, _) => (false, 2),
+        (_, 540) => (true, 2),
+        (n, _) => return Err(anyhow!("sizeof_hdr is {}", n)),
+    };
+    if version == 2 {
+        header.resize(540, 0);
+        reader
+            .read_exact(&mut header[348..])
+            .context("header is truncated")?;
+    }
+    let int = |offset: usize, width: usize| -> i64 {
+        let mut bytes = header[offset..offset + width].to_vec();
+        if big {
+            bytes.reverse();
+        }
+        match width {
+            2 => i16::from_le_bytes([bytes[0], bytes[1]]) as i64,
+            _ => i64::from_le_bytes(bytes.try_into().unwrap_or_default()),
+        }
+    };
+    let float = |offset: usize| -> f64 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&header[offset..offset + 4]);
+        if big {
+            bytes.reverse();
+        }
+        f32::from_le_bytes(bytes) as f64
+    };
+    // NIfTI-1 與 NIfTI-2 的欄位位置不同
+    let (magic, bitpix, dims, vox_offset) = if version == 1 {
+        let dims: Vec<i64> = (0..8).map(|k| int(40 + 2 * k, 2)).collect();
+        (&header[344..347], int(72, 2), dims, float(108) as i64)
+    } else {
+        let dims: Vec<i64> = (0..8).map(|k| int(16 + 8 * k, 8)).collect();
+        (&header[4..7], int(14, 2), dims, int(168, 8))
+    };
+    let expected_magic: &[u8] = if version == 1 { b"n+1" } else { b"n+2" };
+    if magic != expected_magic {
+        return Err(anyhow!(
+            "magic is {:?}, expected single-file {:?}",
+            String::from_utf8_lossy(magic),
+            String::from_utf8_lossy(expected_magic)
+        ));
+    }
+    let rank = dims[0];
+    if !(1..=7).contains(&rank) {
+        return Err(anyhow!("dim[0] is {}", rank));
+    }
+    let dims: Vec<u64> = dims[1..=rank as usize]
+        .iter()
+        .map(|&d| u64::try_from(d).ok().filter(|&d| d > 0))
+        .collect::<Option<_>>()
+        .ok_or_else(|| anyhow!("non-positive dimension in {:?}", &dims[1..=rank as usize]))?;
+    if bitpix <= 0 {
+        return Err(anyhow!("bitpix is {}", bitpix));
+    }
+    let voxels = dims.iter().try_fold(1u64, |n, &d| n.checked_mul(d));
+    let data_bytes = voxels
+        .and_then(|v| v.checked_mul(bitpix as u64))
+        .map(|bits| bits.div_ceil(8))
+        .ok_or_else(|| anyhow!("dimensions {:?} overflow", dims))?;
+    let needed = (vox_offset.max(header.len() as i64) as u64).saturating_add(data_bytes);
+    let actual =
+        header.len() as u64 + io::copy(reader, &mut io::sink()).context("data is unreadable")?;
+    if actual < needed {
+        return Err(anyhow!(
+            "{} bytes, header describes {} (dims {:?}, bitpix {})",
+            actual,
+            needed,
+            dims,
+            bitpix
+        ));
+    }
+    let volumes = dims.iter().skip(3).product();
+    Ok(NiftiInfo { dims, volumes })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Little-endian single-file NIfTI-1 with `dims` of 16-bit voxels.
+    pub(crate) fn nifti1(dims: &[i16], data_bytes: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; 352];
+        bytes[0..4].copy_from_slice(&348i32.to_le_bytes());
+        bytes[40..42].copy_from_slice(&(dims.len() as i16).to_le_bytes());
+        for (k, d) in dims.iter().enumerate() {
+            bytes[42 + 2 * k..44 + 2 * k].copy_from_slice(&d.to_le_bytes());
+        }
+        bytes[70..72].copy_from_slice(&4i16.to_le_bytes());
+        bytes[72..74].copy_from_slice(&16i16.to_le_bytes());
+        bytes[108..112].copy_from_slice(&352f32.to_le_bytes());
+        bytes[344..348].copy_from_slice(b"n+1\0");
+        bytes.resize(352 + data_bytes, 0);
+        bytes
+    }
+
+    #[test]
+    fn test_inspect_counts_volumes_and_rejects_truncation() {
+        let full = nifti1(&[4, 4, 2, 3], 4 * 4 * 2 * 3 * 2);
+        let info = inspect_stream(&mut full.as_slice()).unwrap();
+        assert_eq!(info.dims, [4, 4, 2, 3]);
+        assert_eq!(info.volumes, 3);
+
+        let truncated = &full[..full.len() - 1];
+        let err = inspect_stream(&mut &truncated[..]).unwrap_err();
+        assert!(err.to_string().contains("header describes"));
+
+        let zero_dim = nifti1(&[4, 0, 2], 0);
+        let err = inspect_stream(&mut zero_dim.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("non-positive dimension"));
+    }
+
+    #[test]
+    fn test_inspect_reads_gzip_files() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("nifti_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("T1.nii.gz");
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&nifti1(&[2, 2, 2], 16)).unwrap();
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+        assert_eq!(inspect_nifti(&path).unwrap().volumes, 1);
+
+        let broken = dir.join("T1_ph.nii");
+        std::fs::write(&broken, b"not nifti").unwrap();
+        let checks = DeleteChecks {
+            require_valid: true,
+            min_volumes: 1,
+        };
+        assert!(checks.verify(std::slice::from_ref(&path)).is_ok());
+        assert!(checks.verify(&[path.clone(), broken.clone()]).is_err());
+        let lenient = DeleteChecks {
+            require_valid: false,
+            min_volumes: 2,
+        };
+        let err = lenient.verify(&[path, broken]).unwrap_err();
+        assert!(err.to_string().contains("1 volume(s)"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
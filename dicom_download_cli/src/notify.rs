@@ -0,0 +1,255 @@
+//! Webhook notifications for batch lifecycle events (`[notifications]`).
+//!
+//! `remote` and `download` post JSON events to `webhook_url`: `batch_started`,
+//! `accession_finished` (unless `per_accession = false`), a single `failure_threshold` alert
+//! once more than `failure_threshold` percent of the finished accessions failed, and
+//! `batch_finished` with the summary. Every event has a `text` field, which is what Slack and
+//! Teams incoming webhooks display; the other fields are for custom receivers. Delivery is
+//! best-effort: a webhook that is down is logged as a warning and never fails the batch.
+//! Requests go through the same proxy and TLS settings as Orthanc (`[tls]`, `proxy_url`).
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::client::http_client_builder;
+use crate::config::{HttpConfig, NotificationConfig};
+use crate::estimate::{format_bytes, format_duration};
+use crate::processor::{ProcessResult, STATUS_NOT_ATTEMPTED};
+use crate::reportfile::run_id;
+
+/// Per-request timeout; a slow webhook must not hold up the batch.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Accessions that must finish before the failure rate is judged (fewer in small batches).
+const MIN_SAMPLE: usize = 5;
+
+/// True once more than `threshold` percent of `finished` accessions failed, judged only
+/// after a minimal sample so the first failure of a large batch does not alert.
+pub fn threshold_crossed(failed: usize, finished: usize, total: usize, threshold: f64) -> bool {
+    finished > 0
+        && finished >= MIN_SAMPLE.min(total.max(1))
+        && failed as f64 * 100.0 > threshold * finished as f64
+}
+
+fn failure_rate(failed: usize, finished: usize) -> f64 {
+    if finished == 0 {
+        0.0
+    } else {
+        failed as f64 * 100.0 / finished as f64
+    }
+}
+
+/// Posts lifecycle events for one batch.
+pub struct Notifier {
+    client: reqwest::Client,
+    url: String,
+    per_accession: bool,
+    threshold: Option<f64>,
+    command: String,
+    run_id: String,
+    total: usize,
+    finished: AtomicUsize,
+    failed: AtomicUsize,
+    alerted: AtomicBool,
+}
+
+impl Notifier {
+    /// `None` when no webhook is configured.
+    pub fn new(
+        config: &NotificationConfig,
+        http: &HttpConfig,
+        command: &str,
+        total: usize,
+    ) -> Result<Option<Self>> {
+        let Some(url) = config.webhook_url.clone() else {
+            return Ok(None);
+        };
+        let client = http_client_builder(http)?
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .context("Failed to build webhook HTTP client")?;
+        Ok(Some(Self {
+            client,
+            url,
+            per_accession: config.per_accession(),
+            threshold: config.failure_threshold,
+            command: command.to_string(),
+            run_id: run_id(Utc::now()),
+            total,
+            finished: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            alerted: AtomicBool::new(false),
+        }))
+    }
+
+    /// Common fields plus `fields`.
+    fn event(&self, event: &str, text: String, fields: Value) -> Value {
+        let mut body = json!({
+            "event": event,
+            "command": self.command,
+            "run_id": self.run_id,
+            "timestamp": Utc::now().to_rfc3339(),
+            "text": text,
+        });
+        if let (Value::Object(body), Value::Object(fields)) = (&mut body, fields) {
+            body.extend(fields);
+        }
+        body
+    }
+
+    async fn post(&self, body: Value) {
+        let sent = self.client.post(&self.url).json(&body).send().await;
+        match sent {
+            Ok(resp) if resp.status().is_success() => {}
+            Ok(resp) => warn!("Webhook {} returned {}", self.url, resp.status()),
+            Err(e) => warn!("Webhook {} failed: {}", self.url, e),
+        }
+    }
+
+    pub async fn batch_started(&self) {
+        let text = format!(
+            "dicom_download_cli {}: starting batch of {} accessions",
+            self.command, self.total
+        );
+        self.post(self.event("batch_started", text, json!({ "total": self.total })))
+            .await;
+    }
+
+    /// Counts the result and posts it; also posts the threshold alert when crossed.
+    pub async fn accession_finished(&self, res: &ProcessResult) {
+        let finished = self.finished.fetch_add(1, Ordering::SeqCst) + 1;
+        let failed = if res.status == "Success" {
+            self.failed.load(Ordering::SeqCst)
+        } else {
+            self.failed.fetch_add(1, Ordering::SeqCst) + 1
+        };
+
+        if self.per_accession {
+            let text = format!(
+                "[{}/{}] {}: {}{}",
+                finished,
+                self.total,
+                res.accession,
+                res.status,
+                if res.reason.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", res.reason.join("; "))
+                }
+            );
+            let fields = json!({
+                "accession": res.accession,
+                "status": res.status,
+                "reason": res.reason,
+                "downloaded_series": res.downloaded_series.len(),
+                "duration_ms": res.duration_ms,
+                "bytes_downloaded": res.bytes_downloaded,
+                "finished": finished,
+                "failed": failed,
+                "total": self.total,
+            });
+            self.post(self.event("accession_finished", text, fields))
+                .await;
+        }
+
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        if threshold_crossed(failed, finished, self.total, threshold)
+            && !self.alerted.swap(true, Ordering::SeqCst)
+        {
+            let rate = failure_rate(failed, finished);
+            let text = format!(
+                ":warning: dicom_download_cli {}: {:.0}% of accessions failed so far \
+                 ({} of {} finished, {} in batch; threshold {}%)",
+                self.command, rate, failed, finished, self.total, threshold
+            );
+            let fields = json!({
+                "failed": failed,
+                "finished": finished,
+                "total": self.total,
+                "failure_rate": rate,
+                "threshold": threshold,
+            });
+            self.post(self.event("failure_threshold", text, fields))
+                .await;
+        }
+    }
+
+    pub async fn batch_finished(&self, results: &[ProcessResult], elapsed: Duration) {
+        let count = |status: &str| results.iter().filter(|r| r.status == status).count();
+        let success = count("Success");
+        let failed = results.len() - success;
+        let rate = failure_rate(failed, results.len());
+        let bytes: u64 = results.iter().map(|r| r.bytes_downloaded).sum();
+        let exceeded = self.threshold.is_some_and(|t| rate > t);
+        let text = format!(
+            "{}dicom_download_cli {} finished in {}: {} Success, {} Failed/Partial of {} \
+             ({} downloaded)",
+            if exceeded { ":warning: " } else { "" },
+            self.command,
+            format_duration(elapsed.as_secs_f64()),
+            success,
+            failed,
+            results.len(),
+            format_bytes(bytes)
+        );
+        let fields = json!({
+            "summary": {
+                "total": results.len(),
+                "success": success,
+                "partial": count("Partial"),
+                "failed": count("Failed"),
+                "not_attempted": count(STATUS_NOT_ATTEMPTED),
+                "failure_rate": rate,
+                "threshold_exceeded": exceeded,
+                "duration_ms": elapsed.as_millis() as u64,
+                "bytes_downloaded": bytes,
+            },
+        });
+        self.post(self.event("batch_finished", text, fields)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold_crossed_waits_for_sample() {
+        // first failure of a large batch: 100% but too early to judge
+        assert!(!threshold_crossed(1, 1, 100, 20.0));
+        assert!(!threshold_crossed(1, 5, 100, 20.0));
+        assert!(threshold_crossed(2, 5, 100, 20.0));
+        // small batches are judged once they are done
+        assert!(threshold_crossed(1, 2, 2, 20.0));
+        assert!(!threshold_crossed(0, 0, 0, 0.0));
+    }
+
+    #[test]
+    fn test_webhook_client_uses_http_config() {
+        let config = NotificationConfig {
+            webhook_url: Some("https://hooks.example.org/x".into()),
+            ..Default::default()
+        };
+        let mut http = HttpConfig::default();
+        assert!(
+            Notifier::new(&NotificationConfig::default(), &http, "download", 1)
+                .unwrap()
+                .is_none()
+        );
+        assert!(Notifier::new(&config, &http, "download", 1)
+            .unwrap()
+            .is_some());
+
+        // 代理與 TLS 設定和 Orthanc 相同；設定錯誤時直接失敗而非繞過代理
+        http.proxy.url = Some("http://[bad".into());
+        assert!(Notifier::new(&config, &http, "download", 1).is_err());
+        http.proxy.url = None;
+        http.tls.client_cert = Some("client.pem".into());
+        assert!(Notifier::new(&config, &http, "download", 1).is_err());
+    }
+}
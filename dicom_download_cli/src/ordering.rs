@@ -0,0 +1,234 @@
+//! Temporal ordering export for dynamic (4D) series.
+//!
+//! DSC/ASL series are time series whose instance filenames (Orthanc UUIDs) carry no order.
+//! After download, a `temporal_order.csv` is written next to the DICOMs listing each file
+//! with its acquisition time, trigger time, and temporal position, sorted in time order.
+//! A series counts as dynamic by folder name ([`DYNAMIC_SERIES_PREFIXES`]) or, whatever the
+//! analyzer called it, when its headers report more than one temporal position.
+
+use anyhow::{Context, Result};
+use dicom_object::{open_file, OpenFileOptions, Tag};
+use std::cmp::Ordering;
+use std::path::Path;
+use tracing::warn;
+
+/// File name of the ordering export inside a series folder.
+pub const ORDERING_FILE: &str = "temporal_order.csv";
+
+/// Series folder prefixes treated as dynamic series.
+pub const DYNAMIC_SERIES_PREFIXES: &[&str] = &["DSC", "ASL"];
+
+/// Returns true when the series folder `series_folder` (at `series_dir`) holds a dynamic
+/// (4D) series: its name starts with a [`DYNAMIC_SERIES_PREFIXES`] entry, or one of its
+/// instances reports several temporal positions (see [`has_temporal_positions`]).
+pub fn is_dynamic_series(series_folder: &str, series_dir: &Path) -> bool {
+    let upper = series_folder.to_uppercase();
+    DYNAMIC_SERIES_PREFIXES
+        .iter()
+        .any(|prefix| upper.starts_with(prefix))
+        || has_temporal_positions(series_dir)
+}
+
+/// True when NumberOfTemporalPositions (0020,0105) or TemporalPositionIdentifier
+/// (0020,0100) is above 1.
+fn temporal_tags_say_dynamic(positions: Option<i64>, position: Option<i64>) -> bool {
+    positions.is_some_and(|n| n > 1) || position.is_some_and(|n| n > 1)
+}
+
+/// Reads the headers of one `.dcm` file in `series_dir` and checks its temporal tags, so
+/// perfusion or dynamic-contrast series are ordered even when named e.g. `T1_DCE`.
+pub fn has_temporal_positions(series_dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(series_dir) else {
+        return false;
+    };
+    let Some(path) = entries.filter_map(|e| e.ok()).map(|e| e.path()).find(|p| {
+        p.extension()
+            .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case("dcm"))
+    }) else {
+        return false;
+    };
+    let Ok(obj) = OpenFileOptions::new()
+        .read_until(Tag(0x7FE0, 0x0010))
+        .open_file(&path)
+    else {
+        return false;
+    };
+    let int = |tag: Tag| -> Option<i64> {
+        obj.element(tag)
+            .ok()
+            .and_then(|e| e.to_str().ok())
+            .and_then(|s| s.trim().parse().ok())
+    };
+    temporal_tags_say_dynamic(int(Tag(0x0020, 0x0105)), int(Tag(0x0020, 0x0100)))
+}
+
+/// Timing tags of one instance.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InstanceTiming {
+    pub file: String,
+    pub instance_number: Option<i64>,
+    /// TemporalPositionIdentifier (0020,0100).
+    pub temporal_position: Option<i64>,
+    /// AcquisitionTime (0008,0032) as written in the file (DICOM TM).
+    pub acquisition_time: Option<String>,
+    /// TriggerTime (0018,1060) in milliseconds.
+    pub trigger_time: Option<f64>,
+}
+
+/// Converts a DICOM TM value (`HHMMSS.FFFFFF`, optionally `HH:MM:SS`) to seconds since midnight.
+pub fn parse_dicom_time(value: &str) -> Option<f64> {
+    let digits: String = value.trim().chars().filter(|c| *c != ':').collect();
+    let (whole, frac) = match digits.split_once('.') {
+        Some((w, f)) => (w, f),
+        None => (digits.as_str(), ""),
+    };
+    if whole.len() < 2 || !whole.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let part = |range: std::ops::Range<usize>| -> f64 {
+        whole.get(range).and_then(|s| s.parse().ok()).unwrap_or(0.0)
+    };
+    let frac: f64 = if frac.is_empty() {
+        0.0
+    } else {
+        format!("0.{}", frac).parse().ok()?
+    };
+    Some(part(0..2) * 3600.0 + part(2..4) * 60.0 + part(4..6) + frac)
+}
+
+/// Compares optional keys with missing values ordered last.
+fn cmp_missing_last<T: PartialOrd>(a: &Option<T>, b: &Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(x), Some(y)) => x.partial_cmp(y).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Sorts instances by temporal position, acquisition time, trigger time, then instance number.
+pub fn sort_by_time(timings: &mut [InstanceTiming]) {
+    timings.sort_by(|a, b| {
+        cmp_missing_last(&a.temporal_position, &b.temporal_position)
+            .then_with(|| {
+                cmp_missing_last(
+                    &a.acquisition_time.as_deref().and_then(parse_dicom_time),
+                    &b.acquisition_time.as_deref().and_then(parse_dicom_time),
+                )
+            })
+            .then_with(|| cmp_missing_last(&a.trigger_time, &b.trigger_time))
+            .then_with(|| cmp_missing_last(&a.instance_number, &b.instance_number))
+            .then_with(|| a.file.cmp(&b.file))
+    });
+}
+
+/// Reads the timing tags of one DICOM file.
+fn read_instance_timing(path: &Path) -> Result<InstanceTiming> {
+    let obj = open_file(path).context("Failed to open DICOM file")?;
+    let get_str = |tag: Tag| -> Option<String> {
+        obj.element(tag)
+            .ok()
+            .and_then(|e| e.to_str().ok())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+
+    Ok(InstanceTiming {
+        file: path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        instance_number: get_str(Tag(0x0020, 0x0013)).and_then(|s| s.parse().ok()),
+        temporal_position: get_str(Tag(0x0020, 0x0100)).and_then(|s| s.parse().ok()),
+        acquisition_time: get_str(Tag(0x0008, 0x0032)),
+        trigger_time: get_str(Tag(0x0018, 0x1060)).and_then(|s| s.parse().ok()),
+    })
+}
+
+/// Writes [`ORDERING_FILE`] for all `.dcm` files in `series_dir`; returns the row count.
+///
+/// Files that cannot be parsed are skipped with a warning.
+pub fn write_ordering_file(series_dir: &Path) -> Result<usize> {
+    let mut timings = Vec::new();
+    for entry in std::fs::read_dir(series_dir)? {
+        let path = entry?.path();
+        let is_dcm = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase() == "dcm")
+            .unwrap_or(false);
+        if !is_dcm {
+            continue;
+        }
+        match read_instance_timing(&path) {
+            Ok(t) => timings.push(t),
+            Err(e) => warn!("{}: {}", path.display(), e),
+        }
+    }
+    sort_by_time(&mut timings);
+
+    let mut wtr = csv::Writer::from_path(series_dir.join(ORDERING_FILE))?;
+    wtr.write_record([
+        "Order",
+        "File",
+        "InstanceNumber",
+        "TemporalPositionIdentifier",
+        "AcquisitionTime",
+        "TriggerTime",
+    ])?;
+    for (i, t) in timings.iter().enumerate() {
+        let opt = |v: Option<String>| v.unwrap_or_default();
+        wtr.write_record([
+            (i + 1).to_string(),
+            t.file.clone(),
+            opt(t.instance_number.map(|n| n.to_string())),
+            opt(t.temporal_position.map(|n| n.to_string())),
+            opt(t.acquisition_time.clone()),
+            opt(t.trigger_time.map(|n| n.to_string())),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(timings.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dicom_time() {
+        assert_eq!(parse_dicom_time("101502.5"), Some(36902.5));
+        assert_eq!(parse_dicom_time("10:15:02"), Some(36902.0));
+        assert_eq!(parse_dicom_time("10"), Some(36000.0));
+        assert_eq!(parse_dicom_time(""), None);
+    }
+
+    #[test]
+    fn test_sort_by_time() {
+        let timing = |file: &str, acq: Option<&str>, inst: Option<i64>| InstanceTiming {
+            file: file.into(),
+            instance_number: inst,
+            acquisition_time: acq.map(String::from),
+            ..Default::default()
+        };
+        let mut timings = vec![
+            timing("c.dcm", None, Some(1)),
+            timing("b.dcm", Some("101503"), Some(5)),
+            timing("a.dcm", Some("101502.5"), Some(9)),
+        ];
+        sort_by_time(&mut timings);
+        let files: Vec<&str> = timings.iter().map(|t| t.file.as_str()).collect();
+        assert_eq!(files, ["a.dcm", "b.dcm", "c.dcm"]);
+    }
+
+    #[test]
+    fn test_dynamic_series_detection() {
+        let missing = Path::new("no_such_series_dir");
+        assert!(is_dynamic_series("DSC_2", missing));
+        assert!(!is_dynamic_series("T1BRAVO_AXI", missing));
+        // 分析服務未命名為 DSC/ASL 的動態序列（例如 DCE）依標籤判斷
+        assert!(temporal_tags_say_dynamic(Some(40), None));
+        assert!(temporal_tags_say_dynamic(None, Some(3)));
+        assert!(!temporal_tags_say_dynamic(Some(1), Some(1)));
+        assert!(!temporal_tags_say_dynamic(None, None));
+    }
+}
@@ -0,0 +1,168 @@
+//! `download --package zip`: one archive per completed study.
+//!
+//! Once every series of a study downloaded completely (and DICOMDIR, QC, and conversion are
+//! done with it), `dicom/<study>/` is compressed into `dicom/<study>.zip` and the loose
+//! files are removed, which keeps inode counts on network storage down and gives external
+//! collaborators a single file per study. Studies with `.partial` series stay unpacked so
+//! the next run can resume them; a study whose archive already exists is not downloaded
+//! again. The archive is written to `<study>.zip.part`, re-opened to check every file is
+//! listed, and only then renamed into place before the folder is deleted.
+//!
+//! Archives are not password-protected: the `zip` writer in use cannot produce AES entries
+//! and legacy ZipCrypto is trivially broken, so there is no `[package] password`. Age
+//! encryption of the whole archive (`[encryption]`, see [`crate::encrypt`]) replaces it.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::downloader::PARTIAL_SUFFIX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageFormat {
+    Zip,
+}
+
+impl std::str::FromStr for PackageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "zip" => Ok(Self::Zip),
+            other => Err(anyhow!("Invalid package format '{}': expected zip", other)),
+        }
+    }
+}
+
+impl PackageFormat {
+    /// `<study_dir>.zip`, next to the study folder.
+    pub fn archive_path(&self, study_dir: &Path) -> PathBuf {
+        let mut name = study_dir.as_os_str().to_os_string();
+        name.push(".zip");
+        PathBuf::from(name)
+    }
+}
+
+/// Files under `dir` as `(archive entry name, path)`, sorted; entry names use `/`.
+fn collect_files(dir: &Path, prefix: &str, out: &mut Vec<(String, PathBuf)>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = format!(
+            "{}/{}",
+            prefix,
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+        if path.is_dir() {
+            collect_files(&path, &name, out)?;
+        } else {
+            out.push((name, path));
+        }
+    }
+    Ok(())
+}
+
+/// Compresses `study_dir` into its archive and removes the folder; returns the archive path.
+pub fn package_study(study_dir: &Path, format: PackageFormat) -> Result<PathBuf> {
+    let study_name = study_dir
+        .file_name()
+        .context("Study folder has no name")?
+        .to_string_lossy()
+        .to_string();
+    let mut files = Vec::new();
+    collect_files(study_dir, &study_name, &mut files)
+        .with_context(|| format!("Failed to read {}", study_dir.display()))?;
+    if files
+        .iter()
+        .any(|(name, _)| name.split('/').any(|part| part.ends_with(PARTIAL_SUFFIX)))
+    {
+        bail!("{} still has incomplete series", study_dir.display());
+    }
+    files.sort();
+
+    let archive = format.archive_path(study_dir);
+    let mut part = archive.as_os_str().to_os_string();
+    part.push(".part");
+    let part = PathBuf::from(part);
+    let written = (|| -> Result<()> {
+        let mut zip = ZipWriter::new(BufWriter::new(File::create(&part)?));
+        for (name, path) in &files {
+            let size = std::fs::metadata(path)?.len();
+            let options = FileOptions::default()
+                .compression_method(CompressionMethod::Deflated)
+                .large_file(size >= u32::MAX as u64);
+            zip.start_file(name.as_str(), options)?;
+            std::io::copy(&mut File::open(path)?, &mut zip)?;
+        }
+        let mut writer = zip.finish()?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+
+        // 重新開啟確認每個檔案都在 archive 中，才刪除原始資料夾
+        let listed = ZipArchive::new(File::open(&part)?)?.len();
+        if listed != files.len() {
+            bail!("archive lists {} of {} files", listed, files.len());
+        }
+        Ok(())
+    })();
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&part);
+        return Err(e.context(format!("Failed to write {}", archive.display())));
+    }
+    std::fs::rename(&part, &archive)
+        .with_context(|| format!("Failed to replace {}", archive.display()))?;
+    std::fs::remove_dir_all(study_dir)
+        .with_context(|| format!("Failed to remove {}", study_dir.display()))?;
+    Ok(archive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_study_zips_and_removes_folder() {
+        let root = std::env::temp_dir().join(format!("package_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let study = root.join("P1_20240601_MR_A1");
+        std::fs::create_dir_all(study.join("T1")).unwrap();
+        std::fs::create_dir_all(study.join("T2")).unwrap();
+        std::fs::write(study.join("T1").join("a.dcm"), b"one").unwrap();
+        std::fs::write(study.join("T2").join("b.dcm"), b"two").unwrap();
+
+        let archive = package_study(&study, PackageFormat::Zip).unwrap();
+        assert_eq!(archive, root.join("P1_20240601_MR_A1.zip"));
+        assert!(!study.exists());
+        let mut zip = ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let mut names: Vec<String> = zip.file_names().map(String::from).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["P1_20240601_MR_A1/T1/a.dcm", "P1_20240601_MR_A1/T2/b.dcm"]
+        );
+        let mut text = String::new();
+        std::io::Read::read_to_string(
+            &mut zip.by_name("P1_20240601_MR_A1/T2/b.dcm").unwrap(),
+            &mut text,
+        )
+        .unwrap();
+        assert_eq!(text, "two");
+
+        // 有未完成的 series 時不打包
+        let pending = root.join("P2_20240601_MR_A2");
+        std::fs::create_dir_all(pending.join(format!("T1{}", PARTIAL_SUFFIX))).unwrap();
+        std::fs::write(
+            pending.join(format!("T1{}", PARTIAL_SUFFIX)).join("c.dcm"),
+            b"x",
+        )
+        .unwrap();
+        assert!(package_study(&pending, PackageFormat::Zip).is_err());
+        assert!(pending.exists());
+        assert!(!PackageFormat::Zip.archive_path(&pending).exists());
+        assert!("tar".parse::<PackageFormat>().is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
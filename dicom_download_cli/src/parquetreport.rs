@@ -0,0 +1,328 @@
+//! Parquet reports for analytics pipelines (`--report-parquet`).
+//!
+//! Large batches produce JSON reports that are slow to load into Spark; the same results are
+//! written here as one flat Parquet table (snappy-compressed, a single row group), using
+//! plain string/int64/double columns so any reader can consume them without schema
+//! negotiation. Every row carries the run ID, so files from many runs can sit side by side
+//! in a data-lake folder.
+
+use anyhow::{Context, Result};
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::atomic::write_atomic;
+use crate::checker::{ActionType, CheckReport, CheckType};
+use crate::processor::{ProcessResult, ReportDetail};
+
+/// Values of one column, all rows.
+pub enum Column {
+    Text(Vec<String>),
+    Int(Vec<i64>),
+    Float(Vec<f64>),
+}
+
+impl Column {
+    fn physical_type(&self) -> &'static str {
+        match self {
+            Column::Text(_) => "BYTE_ARRAY",
+            Column::Int(_) => "INT64",
+            Column::Float(_) => "DOUBLE",
+        }
+    }
+}
+
+/// Flat table written as one Parquet file.
+pub struct Table {
+    name: &'static str,
+    columns: Vec<(&'static str, Column)>,
+}
+
+impl Table {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            columns: Vec::new(),
+        }
+    }
+
+    pub fn text(mut self, name: &'static str, values: Vec<String>) -> Self {
+        self.columns.push((name, Column::Text(values)));
+        self
+    }
+
+    pub fn int(mut self, name: &'static str, values: Vec<i64>) -> Self {
+        self.columns.push((name, Column::Int(values)));
+        self
+    }
+
+    pub fn float(mut self, name: &'static str, values: Vec<f64>) -> Self {
+        self.columns.push((name, Column::Float(values)));
+        self
+    }
+
+    fn schema(&self) -> String {
+        let fields: String = self
+            .columns
+            .iter()
+            .map(|(name, col)| match col {
+                Column::Text(_) => format!("REQUIRED BYTE_ARRAY {} (UTF8);", name),
+                _ => format!("REQUIRED {} {};", col.physical_type(), name),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!("message {} {{ {} }}", self.name, fields)
+    }
+
+    /// Writes the table (crash-safe, see [`crate::atomic`]).
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let schema = Arc::new(parse_message_type(&self.schema())?);
+        let props = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build(),
+        );
+        write_atomic(path, |w| {
+            let mut writer = SerializedFileWriter::new(w, schema, props)?;
+            let mut row_group = writer.next_row_group()?;
+            for (name, values) in &self.columns {
+                let mut column = row_group
+                    .next_column()?
+                    .with_context(|| format!("schema has no column {}", name))?;
+                match values {
+                    Column::Text(v) => {
+                        let v: Vec<ByteArray> = v.iter().map(|s| s.as_str().into()).collect();
+                        column
+                            .typed::<ByteArrayType>()
+                            .write_batch(&v, None, None)?;
+                    }
+                    Column::Int(v) => {
+                        column.typed::<Int64Type>().write_batch(v, None, None)?;
+                    }
+                    Column::Float(v) => {
+                        column.typed::<DoubleType>().write_batch(v, None, None)?;
+                    }
+                }
+                column.close()?;
+            }
+            row_group.close()?;
+            writer.close()?;
+            Ok(())
+        })
+    }
+}
+
+fn texts<T>(rows: &[T], f: impl Fn(&T) -> String) -> Vec<String> {
+    rows.iter().map(f).collect()
+}
+
+fn ints<T>(rows: &[T], f: impl Fn(&T) -> u64) -> Vec<i64> {
+    rows.iter().map(|r| f(r) as i64).collect()
+}
+
+/// Processor results: one row per accession, or per series with `--report-detail series`
+/// (same granularity as the CSV).
+pub fn processor_table(results: &[ProcessResult], detail: ReportDetail, run_id: &str) -> Table {
+    match detail {
+        ReportDetail::Accession => {
+            let r = results;
+            Table::new("accession_report")
+                .text("run_id", texts(r, |_| run_id.to_string()))
+                .text("accession", texts(r, |r| r.accession.clone()))
+                .text("status", texts(r, |r| r.status.clone()))
+                .text("reason", texts(r, |r| r.reason.join("; ")))
+                .int(
+                    "downloaded_series",
+                    ints(r, |r| r.downloaded_series.len() as u64),
+                )
+                .int("matched_series", ints(r, |r| r.matched_series.len() as u64))
+                .int("failed_series", ints(r, |r| r.failed_series.len() as u64))
+                .int(
+                    "converted_series",
+                    ints(r, |r| r.converted_series.len() as u64),
+                )
+                .int(
+                    "conversion_failed",
+                    ints(r, |r| r.conversion_failed.len() as u64),
+                )
+                .text("timestamp", texts(r, |r| r.timestamp.to_rfc3339()))
+                .text("notes", texts(r, |r| r.notes.join("; ")))
+                .text("qc_issues", texts(r, |r| r.qc_issues.join("; ")))
+                .text(
+                    "validation_failures",
+                    texts(r, |r| r.validation_failures.join("; ")),
+                )
+                .int("duration_ms", ints(r, |r| r.duration_ms))
+                .int("bytes_downloaded", ints(r, |r| r.bytes_downloaded))
+                .float(
+                    "throughput_bps",
+                    r.iter().map(|r| r.throughput_bps).collect(),
+                )
+        }
+        ReportDetail::Series => {
+            // (accession result, series row); accessions without series keep one empty row
+            let rows: Vec<_> = results
+                .iter()
+                .flat_map(|r| {
+                    let series: Vec<_> = if r.series.is_empty() {
+                        vec![None]
+                    } else {
+                        r.series.iter().map(Some).collect()
+                    };
+                    series.into_iter().map(move |s| (r, s))
+                })
+                .collect();
+            let series_text = |f: fn(&crate::processor::SeriesReport) -> String| {
+                texts(&rows, |(_, s)| s.map(f).unwrap_or_default())
+            };
+            let series_int = |f: fn(&crate::processor::SeriesReport) -> u64| {
+                ints(&rows, |(_, s)| s.map(f).unwrap_or(0))
+            };
+            Table::new("series_report")
+                .text("run_id", texts(&rows, |_| run_id.to_string()))
+                .text("accession", texts(&rows, |(r, _)| r.accession.clone()))
+                .text("accession_status", texts(&rows, |(r, _)| r.status.clone()))
+                .text("study_folder", series_text(|s| s.study_folder.clone()))
+                .text("series_folder", series_text(|s| s.series_folder.clone()))
+                .text("status", series_text(|s| s.status.clone()))
+                .text("conversion", series_text(|s| s.conversion.clone()))
+                .int(
+                    "expected_instances",
+                    series_int(|s| s.expected_instances as u64),
+                )
+                .int(
+                    "downloaded_instances",
+                    series_int(|s| s.downloaded_instances as u64),
+                )
+                .int(
+                    "skipped_instances",
+                    series_int(|s| s.skipped_instances as u64),
+                )
+                .int(
+                    "failed_instances",
+                    series_int(|s| s.failed_instances as u64),
+                )
+                .int("bytes", series_int(|s| s.bytes))
+                .int("duration_ms", series_int(|s| s.duration_ms))
+                .text(
+                    "error",
+                    texts(&rows, |(r, s)| match s {
+                        Some(s) => s.error.clone(),
+                        None => r.reason.join("; "),
+                    }),
+                )
+        }
+    }
+}
+
+/// Checker report: one row per planned/applied file action, like the CSV.
+pub fn checker_table(report: &CheckReport, run_id: &str) -> Table {
+    let rows: Vec<_> = report
+        .studies
+        .iter()
+        .flat_map(|st| {
+            st.series_results
+                .iter()
+                .flat_map(move |se| se.actions.iter().map(move |a| (st, se, a)))
+        })
+        .collect();
+    Table::new("check_report")
+        .text("run_id", texts(&rows, |_| run_id.to_string()))
+        .text("timestamp", texts(&rows, |_| report.timestamp.to_rfc3339()))
+        .int("dry_run", ints(&rows, |_| report.dry_run as u64))
+        .text(
+            "study_folder",
+            texts(&rows, |(st, _, _)| st.study_folder.clone()),
+        )
+        .text(
+            "series_folder",
+            texts(&rows, |(_, se, _)| se.series_folder.clone()),
+        )
+        .text(
+            "check_type",
+            texts(&rows, |(_, se, _)| {
+                match se.check_type {
+                    CheckType::DWI => "DWI",
+                    CheckType::ADC => "ADC",
+                    CheckType::MixedSeries => "MixedSeries",
+                    CheckType::SliceGaps => "SliceGaps",
+                    CheckType::Rule => "Rule",
+                    CheckType::Names => "Names",
+                }
+                .to_string()
+            }),
+        )
+        .text(
+            "action",
+            texts(&rows, |(_, _, a)| {
+                match a.action_type {
+                    ActionType::Move => "Move",
+                    ActionType::Delete => "Delete",
+                    ActionType::Rename => "Rename",
+                    ActionType::Flag => "Flag",
+                }
+                .to_string()
+            }),
+        )
+        .text(
+            "source_path",
+            texts(&rows, |(_, _, a)| a.source_path.display().to_string()),
+        )
+        .text(
+            "target_path",
+            texts(&rows, |(_, _, a)| {
+                a.target_path
+                    .as_ref()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_default()
+            }),
+        )
+        .text("reason", texts(&rows, |(_, _, a)| a.reason.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[test]
+    fn test_processor_table_round_trip() {
+        let dir = std::env::temp_dir().join(format!("parquet_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.parquet");
+        let results = [
+            ProcessResult {
+                accession: "A1".into(),
+                status: "Success".into(),
+                bytes_downloaded: 42,
+                ..Default::default()
+            },
+            ProcessResult {
+                accession: "A2".into(),
+                status: "Failed".into(),
+                ..Default::default()
+            },
+        ];
+        processor_table(&results, ReportDetail::Accession, "run1")
+            .write(&path)
+            .unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let meta = reader.metadata().file_metadata();
+        assert_eq!(meta.num_rows(), 2);
+        let names: Vec<&str> = meta
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|c| c.name())
+            .collect();
+        assert_eq!(&names[..3], ["run_id", "accession", "status"]);
+        assert!(names.contains(&"bytes_downloaded"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
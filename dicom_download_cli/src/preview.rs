@@ -0,0 +1,66 @@
+//! `download --preview`: saves Orthanc's PNG rendering of the middle instance of each series
+//! as `preview.png` in the series folder, so a cohort can be eyeballed in a file browser
+//! without a DICOM viewer.
+//!
+//! The middle instance is taken in slice order (Orthanc `ordered-slices`); when that query
+//! fails the planned instance order is used. A failed preview is only logged — it never
+//! fails the series.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::atomic::write_bytes_atomic;
+use crate::client::{OrthancClient, SeriesDownloadPlan};
+
+pub const PREVIEW_FILE: &str = "preview.png";
+
+/// Middle instance of `planned`, ordered by `ordered` when it covers any planned instance.
+/// Per-instance groups only plan part of a series, so other instances are left out.
+pub fn middle_instance<'a>(ordered: &'a [String], planned: &'a [String]) -> Option<&'a String> {
+    let in_order: Vec<&String> = ordered.iter().filter(|id| planned.contains(id)).collect();
+    if in_order.is_empty() {
+        planned.get(planned.len() / 2)
+    } else {
+        in_order.get(in_order.len() / 2).copied()
+    }
+}
+
+/// Fetches the preview of the series' middle instance and writes `<series_dir>/preview.png`.
+pub async fn write_series_preview(
+    client: &OrthancClient,
+    series: &SeriesDownloadPlan,
+    series_dir: &Path,
+) -> Result<()> {
+    let ordered = client
+        .get_ordered_instance_ids(&series.source_series)
+        .await
+        .unwrap_or_default();
+    let Some(instance) = middle_instance(&ordered, &series.instances) else {
+        return Ok(());
+    };
+    let png = client
+        .get_instance_preview(instance)
+        .await
+        .with_context(|| format!("Preview of instance {} failed", instance))?;
+    let path = series_dir.join(PREVIEW_FILE);
+    tokio::task::spawn_blocking(move || write_bytes_atomic(&path, &png)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_middle_instance() {
+        let planned = ids(&["c", "a", "e", "b", "d"]);
+        let ordered = ids(&["a", "b", "c", "d", "e", "x"]);
+        assert_eq!(middle_instance(&ordered, &planned).unwrap(), "c");
+        assert_eq!(middle_instance(&[], &planned).unwrap(), "e");
+        assert_eq!(middle_instance(&ordered, &ids(&["d", "e"])).unwrap(), "e");
+        assert!(middle_instance(&ordered, &[]).is_none());
+    }
+}
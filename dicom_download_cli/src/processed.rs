@@ -0,0 +1,106 @@
+//! Marking studies in Orthanc once `download` has processed them (`--mark-processed`), and
+//! skipping marked studies on later runs (`--skip-processed`).
+//!
+//! The mark is a user-defined Orthanc metadata entry on the study whose value is the run ID
+//! (see [`crate::reportfile::run_id`]), so overlapping cohorts can be run repeatedly without
+//! redoing studies and the report of the run that processed a study can be found again.
+//! Orthanc only accepts metadata declared in its `UserMetadata` configuration, e.g.
+//! `"UserMetadata": { "ProcessedByCli": 1024 }`; the name is set with `processed_metadata`.
+//! A study is marked only when every series completed without errors, conversion included.
+
+use anyhow::{bail, Result};
+use chrono::Utc;
+use tracing::warn;
+
+use crate::client::OrthancClient;
+use crate::reportfile::run_id;
+
+/// Metadata name used when `processed_metadata` is not configured.
+pub const DEFAULT_PROCESSED_METADATA: &str = "ProcessedByCli";
+
+#[derive(Debug, Clone)]
+pub struct ProcessedMarker {
+    /// Orthanc user metadata name holding the run ID.
+    pub metadata: String,
+    /// Write the mark after a study completes (`--mark-processed`).
+    pub mark: bool,
+    /// Leave out studies that already carry the mark (`--skip-processed`).
+    pub skip: bool,
+    /// Value written by this run.
+    pub run_id: String,
+}
+
+impl ProcessedMarker {
+    /// `None` when neither flag is set; fails on a metadata name Orthanc would reject.
+    pub fn new(metadata: Option<&str>, mark: bool, skip: bool) -> Result<Option<Self>> {
+        if !mark && !skip {
+            return Ok(None);
+        }
+        let metadata = metadata.unwrap_or(DEFAULT_PROCESSED_METADATA).trim();
+        if metadata.is_empty()
+            || !metadata
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            bail!(
+                "Invalid processed_metadata '{}': use letters, digits, and underscores",
+                metadata
+            );
+        }
+        Ok(Some(Self {
+            metadata: metadata.to_string(),
+            mark,
+            skip,
+            run_id: run_id(Utc::now()),
+        }))
+    }
+
+    /// Splits `study_ids` into studies still to download and `(study, run ID)` pairs that
+    /// are already marked. A study whose mark cannot be read is downloaded.
+    pub async fn partition(
+        &self,
+        client: &OrthancClient,
+        study_ids: Vec<String>,
+    ) -> (Vec<String>, Vec<(String, String)>) {
+        let mut todo = Vec::new();
+        let mut done = Vec::new();
+        for id in study_ids {
+            match client.get_study_metadata(&id, &self.metadata).await {
+                Ok(Some(run)) => done.push((id, run)),
+                Ok(None) => todo.push(id),
+                Err(e) => {
+                    warn!(
+                        "Study {}: could not read metadata {} ({}); downloading it",
+                        id, self.metadata, e
+                    );
+                    todo.push(id);
+                }
+            }
+        }
+        (todo, done)
+    }
+
+    /// Marks a study as processed by this run.
+    pub async fn mark_study(&self, client: &OrthancClient, study_id: &str) -> Result<()> {
+        client
+            .set_study_metadata(study_id, &self.metadata, &self.run_id)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker_flags_and_name() {
+        assert!(ProcessedMarker::new(None, false, false).unwrap().is_none());
+        let marker = ProcessedMarker::new(None, true, false).unwrap().unwrap();
+        assert_eq!(marker.metadata, DEFAULT_PROCESSED_METADATA);
+        let custom = ProcessedMarker::new(Some(" Cohort_2024 "), false, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(custom.metadata, "Cohort_2024");
+        assert!(ProcessedMarker::new(Some("processed by cli"), true, true).is_err());
+    }
+}
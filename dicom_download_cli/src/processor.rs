@@ -1,15 +1,21 @@
+use crate::atomic::write_atomic;
 use crate::client::OrthancClient;
-use crate::config::{should_download, AnalysisConfig};
+use crate::config::{match_series, AnalysisConfig, MatchKind};
+use crate::estimate::{format_bytes, format_duration};
+use crate::failed::FailedInstance;
+use crate::reportfile::{
+    append_csv, append_json, run_id, timestamped_path, ReportLock, ReportMode,
+};
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
 use colored::*;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use serde::Serialize;
 use serde_json::json;
-use std::fs::File;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[derive(Serialize, Default)]
 pub struct ProcessResult {
@@ -23,16 +29,319 @@ pub struct ProcessResult {
     pub converted_series: Vec<String>,
     /// Series that failed NIfTI conversion.
     pub conversion_failed: Vec<String>,
+    /// NIfTI outputs renamed to avoid filename collisions (`study/original -> renamed`).
+    pub conversion_renames: Vec<String>,
+    /// Informational per-series notes that do not affect status (e.g. analyzer bypassed).
+    pub notes: Vec<String>,
+    /// Pixel-data QC findings (`series: file: problem`); do not affect status.
+    pub qc_issues: Vec<String>,
+    /// Instances that failed post-write validation (`series/file: problem`); removed so a
+    /// rerun fetches them again.
+    pub validation_failures: Vec<String>,
+    /// How each remote series was matched or excluded.
+    pub match_stats: MatchStats,
+    /// Per-series outcomes, the rows of `--report-detail series`.
+    pub series: Vec<SeriesReport>,
+    /// Instances still missing after all retries; written to `failed_instances.json`.
+    #[serde(skip)]
+    pub failed_instances: Vec<FailedInstance>,
+    /// Wall time spent on this accession.
+    pub duration_ms: u64,
+    /// Bytes fetched from Orthanc in this run; files already on disk are not counted, and
+    /// `remote` (C-MOVE) transfers nothing through this tool.
+    pub bytes_downloaded: u64,
+    /// `bytes_downloaded` over the accession's wall time, in bytes per second.
+    pub throughput_bps: f64,
     pub timestamp: DateTime<Utc>,
 }
 
+impl ProcessResult {
+    /// Records the accession's wall time and the resulting average throughput.
+    pub fn finish_timing(&mut self, elapsed: Duration) {
+        self.duration_ms = elapsed.as_millis() as u64;
+        self.throughput_bps = throughput_bps(self.bytes_downloaded, elapsed);
+    }
+}
+
+/// Average rate in bytes per second; zero for an empty interval.
+pub fn throughput_bps(bytes: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        bytes as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+/// End-of-run line for capacity planning: batch wall time, volume, and average rate.
+pub fn batch_summary_line(results: &[ProcessResult], batch: Duration) -> String {
+    let bytes: u64 = results.iter().map(|r| r.bytes_downloaded).sum();
+    let mut line = format!(
+        "Batch time: {}; {} downloaded, {}/s average",
+        format_duration(batch.as_secs_f64()),
+        format_bytes(bytes),
+        format_bytes(throughput_bps(bytes, batch) as u64)
+    );
+    if let Some(slowest) = results.iter().max_by_key(|r| r.duration_ms) {
+        let mean_ms = results.iter().map(|r| r.duration_ms).sum::<u64>() / results.len() as u64;
+        line.push_str(&format!(
+            "; per accession {} mean, slowest {} ({})",
+            format_duration(mean_ms as f64 / 1000.0),
+            slowest.accession,
+            format_duration(slowest.duration_ms as f64 / 1000.0)
+        ));
+    }
+    line
+}
+
+/// Outcome of one downloaded (or attempted) series folder.
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct SeriesReport {
+    /// Study folder (`download`) or StudyInstanceUID (`remote`).
+    pub study_folder: String,
+    /// Series folder (`download`) or SeriesDescription (`remote`).
+    pub series_folder: String,
+    /// `Downloaded`, `Partial`, or `Failed`; `WouldMove` in `remote --dry-run`.
+    pub status: String,
+    /// `Converted`, `ConversionFailed`, or `Skipped` (NIfTI already there); empty when
+    /// conversion did not run.
+    pub conversion: String,
+    /// Converter and version of the conversion (e.g. `dcm2niix v1.0.20240202`).
+    pub converter: String,
+    /// Converter command line run for this series; empty when conversion did not run.
+    pub conversion_command: String,
+    pub expected_instances: usize,
+    pub downloaded_instances: usize,
+    /// Instances already on disk from an earlier run.
+    pub skipped_instances: usize,
+    pub failed_instances: usize,
+    /// Size of the series' DICOM files on disk after download.
+    pub bytes: u64,
+    pub duration_ms: u64,
+    pub error: String,
+}
+
+/// Series status for a selected series that `remote --dry-run` did not move.
+pub const DRY_RUN_STATUS: &str = "WouldMove";
+
+/// Granularity of the CSV report: one row per accession (default) or per series.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportDetail {
+    Accession,
+    Series,
+}
+
+impl std::str::FromStr for ReportDetail {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "accession" => Ok(ReportDetail::Accession),
+            "series" => Ok(ReportDetail::Series),
+            other => Err(format!(
+                "invalid --report-detail '{}': use accession or series",
+                other
+            )),
+        }
+    }
+}
+
+/// Whitelist hit/miss counts, used to tune `series_whitelist` from evidence.
+#[derive(Serialize, Default, Clone, Debug, PartialEq)]
+pub struct MatchStats {
+    pub download_all: usize,
+    pub direct_keyword: usize,
+    /// Analyzer series type found in the whitelist.
+    pub whitelist: usize,
+    /// Analyzer series type not in the whitelist.
+    pub excluded: usize,
+    /// No series type available (analyzer and header fallback gave nothing).
+    pub unclassified: usize,
+    /// Rejected by the series patterns or `non_image_series = "skip"`.
+    pub filtered: usize,
+    /// Series types that hit the whitelist, with counts.
+    pub whitelist_types: BTreeMap<String, usize>,
+    /// Series types that were excluded, with counts.
+    pub excluded_types: BTreeMap<String, usize>,
+}
+
+impl MatchStats {
+    /// Records one series decision; `series_type` is the analyzer result, if any.
+    pub fn record(&mut self, kind: MatchKind, series_type: Option<&str>) {
+        match (kind, series_type) {
+            (MatchKind::DownloadAll, _) => self.download_all += 1,
+            (MatchKind::DirectKeyword, _) => self.direct_keyword += 1,
+            (MatchKind::Whitelist, t) => {
+                self.whitelist += 1;
+                *self
+                    .whitelist_types
+                    .entry(t.unwrap_or_default().to_string())
+                    .or_default() += 1;
+            }
+            (MatchKind::Excluded, Some(t)) => {
+                self.excluded += 1;
+                *self.excluded_types.entry(t.to_string()).or_default() += 1;
+            }
+            (MatchKind::Excluded, None) => self.unclassified += 1,
+            (MatchKind::Filtered, _) => self.filtered += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: &MatchStats) {
+        self.download_all += other.download_all;
+        self.direct_keyword += other.direct_keyword;
+        self.whitelist += other.whitelist;
+        self.excluded += other.excluded;
+        self.unclassified += other.unclassified;
+        self.filtered += other.filtered;
+        for (t, n) in &other.whitelist_types {
+            *self.whitelist_types.entry(t.clone()).or_default() += n;
+        }
+        for (t, n) in &other.excluded_types {
+            *self.excluded_types.entry(t.clone()).or_default() += n;
+        }
+    }
+
+    /// Sum of the per-accession stats.
+    pub fn total(results: &[ProcessResult]) -> MatchStats {
+        let mut total = MatchStats::default();
+        for r in results {
+            total.merge(&r.match_stats);
+        }
+        total
+    }
+
+    /// Human-readable breakdown for the end-of-run summary.
+    pub fn summary_lines(&self) -> Vec<String> {
+        let list = |m: &BTreeMap<String, usize>| {
+            m.iter()
+                .map(|(t, n)| format!("{} x{}", t, n))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut lines = vec![format!(
+            "Series matching: {} direct keyword, {} whitelist, {} excluded, {} unclassified{}{}",
+            self.direct_keyword,
+            self.whitelist,
+            self.excluded,
+            self.unclassified,
+            if self.download_all > 0 {
+                format!(", {} download-all", self.download_all)
+            } else {
+                String::new()
+            },
+            if self.filtered > 0 {
+                format!(", {} filtered", self.filtered)
+            } else {
+                String::new()
+            }
+        )];
+        if !self.whitelist_types.is_empty() {
+            lines.push(format!("  Whitelist hits: {}", list(&self.whitelist_types)));
+        }
+        if !self.excluded_types.is_empty() {
+            lines.push(format!("  Excluded types: {}", list(&self.excluded_types)));
+        }
+        lines
+    }
+}
+
+/// Checks that `target` names an AET Orthanc can resolve for C-MOVE.
+///
+/// Valid targets are the local Orthanc's own AET or any registered modality (by symbolic
+/// name or AET). Case-only mismatches are reported with a hint, since AETs are compared
+/// exactly by most PACS.
+pub fn check_target_aet(
+    target: &str,
+    local_aet: Option<&str>,
+    modalities: &[(String, String)],
+) -> Result<()> {
+    let known = local_aet
+        .into_iter()
+        .chain(
+            modalities
+                .iter()
+                .flat_map(|(n, a)| [n.as_str(), a.as_str()]),
+        )
+        .filter(|s| !s.is_empty());
+    let mut case_hint = None;
+    for aet in known {
+        if aet == target {
+            return Ok(());
+        }
+        if aet.eq_ignore_ascii_case(target) {
+            case_hint = Some(aet.to_string());
+        }
+    }
+
+    let mut msg = format!(
+        "Target AET '{}' is neither the local Orthanc AET{} nor a registered modality",
+        target,
+        local_aet.map(|a| format!(" ({})", a)).unwrap_or_default()
+    );
+    if let Some(hint) = case_hint {
+        msg.push_str(&format!("; did you mean '{}'?", hint));
+    } else if !modalities.is_empty() {
+        let names: Vec<&str> = modalities.iter().map(|(n, _)| n.as_str()).collect();
+        msg.push_str(&format!(" (known modalities: {})", names.join(", ")));
+    }
+    Err(anyhow!(msg))
+}
+
+/// Fails fast when the query modality or the C-MOVE target AET is misconfigured.
+///
+/// Verifies `modality` is registered and answers C-ECHO, then checks `target` against
+/// `/system` and `/modalities` so a typo does not surface as hundreds of job failures.
+pub async fn verify_remote_setup(
+    client: &OrthancClient,
+    modality: &str,
+    target: &str,
+) -> Result<()> {
+    let modalities = client
+        .list_modalities()
+        .await
+        .map_err(|e| anyhow!("Cannot list Orthanc modalities: {}", e))?;
+
+    if !modalities.iter().any(|(name, _)| name == modality) {
+        return Err(anyhow!(
+            "Modality '{}' is not registered in Orthanc /modalities",
+            modality
+        ));
+    }
+    if !client.echo_modality(modality).await.unwrap_or(false) {
+        return Err(anyhow!("C-ECHO to modality '{}' failed", modality));
+    }
+
+    let local_aet = client.local_aet().await.ok();
+    check_target_aet(target, local_aet.as_deref(), &modalities)
+}
+
+/// Remote C-MOVE flow for one accession; the result carries its wall time.
 pub async fn process_single_accession(
     client: Arc<OrthancClient>,
     acc: String,
     modality: String,
     mp: Arc<MultiProgress>,
     config: Arc<AnalysisConfig>,
+    dry_run: bool,
 ) -> ProcessResult {
+    let started = Instant::now();
+    let mut res = process_accession(client, acc, modality, mp, config, dry_run).await;
+    res.finish_timing(started.elapsed());
+    res
+}
+
+async fn process_accession(
+    client: Arc<OrthancClient>,
+    acc: String,
+    modality: String,
+    mp: Arc<MultiProgress>,
+    config: Arc<AnalysisConfig>,
+    dry_run: bool,
+) -> ProcessResult {
+    if client.auth_failed() {
+        return not_attempted(&acc);
+    }
     let pb = setup_progress_bar(&mp, &acc);
     let mut res = ProcessResult {
         accession: acc.clone(),
@@ -57,9 +366,14 @@ pub async fn process_single_accession(
 
     for (idx, series_json) in remote_series.into_iter().enumerate() {
         let (uid, desc) = client.extract_series_info(&series_json);
+        let series_modality = client.extract_series_modality(&series_json);
         if local_uids.contains(&uid) {
             continue;
         }
+        if client.auth_failed() {
+            res.reason.push(AUTH_FAILED_REASON.into());
+            break;
+        }
 
         pb.set_message(format!(
             " [{}/{}] {}",
@@ -68,10 +382,14 @@ pub async fn process_single_accession(
             desc
         ));
 
-        if let Err(e) = process_series(
-            &client, &modality, &study_uid, &uid, &desc, &config, &pb, &mut res,
-        )
-        .await
+        let series = RemoteSeries {
+            study_uid: &study_uid,
+            uid: &uid,
+            description: &desc,
+            modality: series_modality.as_deref(),
+        };
+        if let Err(e) =
+            process_series(&client, &modality, &series, &config, &pb, &mut res, dry_run).await
         {
             res.reason.push(e.to_string());
         }
@@ -82,50 +400,88 @@ pub async fn process_single_accession(
     res
 }
 
+/// Identifying tags of one remote series from the C-FIND response.
+struct RemoteSeries<'a> {
+    study_uid: &'a str,
+    uid: &'a str,
+    description: &'a str,
+    modality: Option<&'a str>,
+}
+
 async fn process_series(
     client: &OrthancClient,
     modality: &str,
-    study_uid: &str,
-    series_uid: &str,
-    desc: &str,
+    series: &RemoteSeries<'_>,
     config: &AnalysisConfig,
     pb: &ProgressBar,
     res: &mut ProcessResult,
+    dry_run: bool,
 ) -> Result<()> {
-    let should_dl = if config.download_all || should_download(desc, None, config) {
-        true
-    } else {
-        match client
-            .sample_series_type(modality, study_uid, series_uid)
-            .await?
-        {
-            Some(t) => should_download(desc, Some(&t), config),
-            None => false,
+    let (study_uid, series_uid, desc) = (series.study_uid, series.uid, series.description);
+    let mut kind = match_series(desc, None, series.modality, config);
+    let mut series_type = None;
+    if kind == MatchKind::Excluded {
+        let sample = client
+            .sample_series_type(modality, study_uid, series_uid, &config.classifier)
+            .await?;
+        if let Some(reason) = sample.analyzer_bypassed {
+            res.notes.push(format!(
+                "{}: analyzer bypassed ({}), classified from headers",
+                desc, reason
+            ));
         }
-    };
+        series_type = sample.series_type;
+        kind = match_series(desc, series_type.as_deref(), series.modality, config);
+    }
+    res.match_stats.record(kind, series_type.as_deref());
 
-    if !should_dl {
+    if !kind.selected() {
         return Ok(());
     }
 
     res.matched_series.push(desc.to_string());
+    if dry_run {
+        // 只記錄會被 C-MOVE 的 series，不送出移動
+        res.series.push(SeriesReport {
+            study_folder: study_uid.to_string(),
+            series_folder: desc.to_string(),
+            status: DRY_RUN_STATUS.into(),
+            ..Default::default()
+        });
+        return Ok(());
+    }
     pb.set_message(format!("Downloading {}...", desc));
 
+    let started = Instant::now();
     let move_payload = json!({ "SeriesInstanceUID": series_uid, "StudyInstanceUID": study_uid });
-    match client
-        .c_move(modality, "Series", move_payload, true)
-        .await?
-    {
-        Some(job_id) => {
-            client.wait_for_job(&job_id, pb).await?;
+    let moved = match client.c_move(modality, "Series", move_payload, true).await {
+        Ok(Some(job_id)) => client.wait_for_job(&job_id, pb).await,
+        Ok(None) => {
+            res.failed_series.push(desc.to_string());
+            Err(anyhow!("Sync move not supported for {}", desc))
+        }
+        Err(e) => Err(e),
+    };
+    let mut row = SeriesReport {
+        study_folder: study_uid.to_string(),
+        series_folder: desc.to_string(),
+        status: "Downloaded".into(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        ..Default::default()
+    };
+    match moved {
+        Ok(()) => {
             res.downloaded_series.push(desc.to_string());
+            res.series.push(row);
+            Ok(())
         }
-        None => {
-            res.failed_series.push(desc.to_string());
-            return Err(anyhow!("Sync move not supported for {}", desc));
+        Err(e) => {
+            row.status = "Failed".into();
+            row.error = e.to_string();
+            res.series.push(row);
+            Err(e)
         }
     }
-    Ok(())
 }
 
 fn setup_progress_bar(mp: &MultiProgress, prefix: &str) -> ProgressBar {
@@ -147,6 +503,23 @@ fn finish_with_error(pb: ProgressBar, res: &mut ProcessResult, err: String) -> P
     std::mem::take(res)
 }
 
+/// Status of accessions skipped because an earlier request hit HTTP 401/403.
+pub const STATUS_NOT_ATTEMPTED: &str = "NotAttempted";
+/// Reason recorded on the accession during which credentials were rejected.
+pub const AUTH_FAILED_REASON: &str =
+    "Authentication failed (HTTP 401/403); batch stopped, check credentials";
+
+/// Result for an accession that was never started because the batch was stopped.
+pub fn not_attempted(accession: &str) -> ProcessResult {
+    ProcessResult {
+        accession: accession.to_string(),
+        status: STATUS_NOT_ATTEMPTED.into(),
+        reason: vec!["Skipped after authentication failure".into()],
+        timestamp: Utc::now(),
+        ..Default::default()
+    }
+}
+
 pub fn summarize_status(downloaded: &[String], reasons: &[String]) -> String {
     if reasons.is_empty() {
         "Success".into()
@@ -157,25 +530,132 @@ pub fn summarize_status(downloaded: &[String], reasons: &[String]) -> String {
     }
 }
 
+/// Process exit code when every accession succeeded (or there was nothing to do).
+pub const EXIT_SUCCESS: u8 = 0;
+/// Process exit code when some accessions failed or were only partially downloaded.
+pub const EXIT_PARTIAL: u8 = 2;
+/// Process exit code when no accession succeeded at all.
+pub const EXIT_ALL_FAILED: u8 = 3;
+
+/// Policy deciding when a run with problems should yield a non-zero exit code.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FailOn {
+    /// Any Failed/Partial accession fails the run.
+    Any,
+    /// Only fail when every accession failed.
+    All,
+    /// Fail when the Failed/Partial share reaches the given percentage.
+    Threshold(f64),
+}
+
+impl std::str::FromStr for FailOn {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        match s.as_str() {
+            "any" => return Ok(FailOn::Any),
+            "all" => return Ok(FailOn::All),
+            _ => {}
+        }
+        let pct = s
+            .strip_prefix("threshold=")
+            .map(|v| v.trim_end_matches('%'))
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| (0.0..=100.0).contains(v))
+            .ok_or_else(|| format!("invalid --fail-on '{}': use any, all, or threshold=N%", s))?;
+        Ok(FailOn::Threshold(pct))
+    }
+}
+
+/// Maps accession results to an exit code under the given `--fail-on` policy.
+///
+/// The code reflects the outcome (0 success, 2 partial, 3 all failed); the policy
+/// only decides whether a non-success outcome is reported or downgraded to 0.
+pub fn exit_code(results: &[ProcessResult], policy: &FailOn) -> u8 {
+    let total = results.len();
+    let ok = results.iter().filter(|r| r.status == "Success").count();
+    let failed = results
+        .iter()
+        .filter(|r| r.status == "Failed" || r.status == STATUS_NOT_ATTEMPTED)
+        .count();
+    if ok == total {
+        return EXIT_SUCCESS;
+    }
+
+    let code = if failed == total {
+        EXIT_ALL_FAILED
+    } else {
+        EXIT_PARTIAL
+    };
+    let should_fail = match policy {
+        FailOn::Any => true,
+        FailOn::All => code == EXIT_ALL_FAILED,
+        FailOn::Threshold(pct) => (total - ok) as f64 * 100.0 / total as f64 >= *pct,
+    };
+    if should_fail {
+        code
+    } else {
+        EXIT_SUCCESS
+    }
+}
+
+/// Writes the CSV (per accession or per series, see [`ReportDetail`]) and the JSON report;
+/// the JSON always carries the per-series list.
+///
+/// `mode` replaces the files, writes timestamped names, or appends rows tagged with a run ID
+/// (see [`crate::reportfile`]); both files are locked while written. Returns the paths used.
 pub fn write_reports(
-    csv_path: &PathBuf,
-    json_path: &PathBuf,
+    csv_path: &Path,
+    json_path: &Path,
     results: &[ProcessResult],
-) -> Result<()> {
-    write_csv_report(csv_path, results)?;
-    write_json_report(json_path, results)?;
-    Ok(())
+    detail: ReportDetail,
+    mode: ReportMode,
+) -> Result<(PathBuf, PathBuf)> {
+    let now = Utc::now();
+    let (csv_path, json_path) = match mode {
+        ReportMode::Timestamped => (
+            timestamped_path(csv_path, now),
+            timestamped_path(json_path, now),
+        ),
+        _ => (csv_path.to_path_buf(), json_path.to_path_buf()),
+    };
+    let _csv_lock = ReportLock::acquire(&csv_path)?;
+    let _json_lock = (json_path != csv_path)
+        .then(|| ReportLock::acquire(&json_path))
+        .transpose()?;
+
+    if mode == ReportMode::Append {
+        let run = run_id(now);
+        let mut rendered = Vec::new();
+        write_csv_detail(&mut rendered, results, detail)?;
+        append_csv(&csv_path, &rendered, &run)?;
+        append_json(&json_path, serde_json::to_value(results)?, &run)?;
+    } else {
+        write_atomic(&csv_path, |w| write_csv_detail(w, results, detail))?;
+        write_json_report(&json_path, results)?;
+    }
+    Ok((csv_path, json_path))
 }
 
-fn write_json_report(path: &PathBuf, results: &[ProcessResult]) -> Result<()> {
-    let file = File::create(path)?;
-    serde_json::to_writer_pretty(file, results)?;
-    Ok(())
+fn write_json_report(path: &Path, results: &[ProcessResult]) -> Result<()> {
+    write_atomic(path, |w| Ok(serde_json::to_writer_pretty(w, results)?))
+}
+
+fn write_csv_detail(
+    w: &mut impl std::io::Write,
+    results: &[ProcessResult],
+    detail: ReportDetail,
+) -> Result<()> {
+    match detail {
+        ReportDetail::Accession => write_csv_rows(w, results),
+        ReportDetail::Series => write_series_csv_rows(w, results),
+    }
 }
 
-fn write_csv_report(path: &PathBuf, results: &[ProcessResult]) -> Result<()> {
-    let mut wtr = csv::Writer::from_path(path)?;
-    wtr.write_record(&[
+fn write_csv_rows(w: &mut impl std::io::Write, results: &[ProcessResult]) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer(w);
+    wtr.write_record([
         "AccessionNumber",
         "Status",
         "Reason",
@@ -185,9 +665,15 @@ fn write_csv_report(path: &PathBuf, results: &[ProcessResult]) -> Result<()> {
         "ConvertedCount",
         "ConversionFailedCount",
         "Timestamp",
+        "Notes",
+        "QcIssues",
+        "ValidationFailures",
+        "DurationMs",
+        "BytesDownloaded",
+        "ThroughputBytesPerSec",
     ])?;
     for r in results {
-        wtr.write_record(&[
+        wtr.write_record([
             &r.accession,
             &r.status,
             &r.reason.join("; "),
@@ -197,8 +683,178 @@ fn write_csv_report(path: &PathBuf, results: &[ProcessResult]) -> Result<()> {
             &r.converted_series.len().to_string(),
             &r.conversion_failed.len().to_string(),
             &r.timestamp.to_rfc3339(),
+            &r.notes.join("; "),
+            &r.qc_issues.join("; "),
+            &r.validation_failures.join("; "),
+            &r.duration_ms.to_string(),
+            &r.bytes_downloaded.to_string(),
+            &format!("{:.0}", r.throughput_bps),
         ])?;
     }
     wtr.flush()?;
     Ok(())
 }
+
+fn write_series_csv_rows(w: &mut impl std::io::Write, results: &[ProcessResult]) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer(w);
+    wtr.write_record([
+        "AccessionNumber",
+        "AccessionStatus",
+        "StudyFolder",
+        "SeriesFolder",
+        "Status",
+        "Conversion",
+        "ExpectedInstances",
+        "DownloadedInstances",
+        "SkippedInstances",
+        "FailedInstances",
+        "Bytes",
+        "DurationMs",
+        "Error",
+    ])?;
+    for r in results {
+        // Accessions that failed before any series was attempted still get a row
+        if r.series.is_empty() {
+            wtr.write_record([
+                r.accession.as_str(),
+                &r.status,
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                "",
+                &r.reason.join("; "),
+            ])?;
+        }
+        for s in &r.series {
+            wtr.write_record([
+                r.accession.as_str(),
+                &r.status,
+                &s.study_folder,
+                &s.series_folder,
+                &s.status,
+                &s.conversion,
+                &s.expected_instances.to_string(),
+                &s.downloaded_instances.to_string(),
+                &s.skipped_instances.to_string(),
+                &s.failed_instances.to_string(),
+                &s.bytes.to_string(),
+                &s.duration_ms.to_string(),
+                &s.error,
+            ])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(status: &str) -> ProcessResult {
+        ProcessResult {
+            status: status.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fail_on_parse() {
+        assert_eq!("any".parse::<FailOn>().unwrap(), FailOn::Any);
+        assert_eq!("ALL".parse::<FailOn>().unwrap(), FailOn::All);
+        assert_eq!(
+            "threshold=25%".parse::<FailOn>().unwrap(),
+            FailOn::Threshold(25.0)
+        );
+        assert!("threshold=150".parse::<FailOn>().is_err());
+        assert!("sometimes".parse::<FailOn>().is_err());
+    }
+
+    #[test]
+    fn test_match_stats_total() {
+        let mut a = result("Success");
+        a.match_stats.record(MatchKind::Whitelist, Some("T1"));
+        a.match_stats.record(MatchKind::Excluded, Some("SWI"));
+        let mut b = result("Success");
+        b.match_stats.record(MatchKind::DirectKeyword, None);
+        b.match_stats.record(MatchKind::Excluded, Some("SWI"));
+        b.match_stats.record(MatchKind::Excluded, None);
+        b.match_stats.record(MatchKind::Filtered, None);
+
+        let total = MatchStats::total(&[a, b]);
+        assert_eq!(
+            (
+                total.direct_keyword,
+                total.whitelist,
+                total.excluded,
+                total.unclassified
+            ),
+            (1, 1, 2, 1)
+        );
+        assert_eq!(total.excluded_types.get("SWI"), Some(&2));
+        assert_eq!(total.filtered, 1);
+        assert!(total.summary_lines()[0].ends_with(", 1 filtered"));
+        assert_eq!(total.summary_lines()[2], "  Excluded types: SWI x2");
+    }
+
+    #[test]
+    fn test_check_target_aet() {
+        let modalities = vec![("PACS".to_string(), "INFINTT-SERVER".to_string())];
+        assert!(check_target_aet("RADAX", Some("RADAX"), &modalities).is_ok());
+        assert!(check_target_aet("INFINTT-SERVER", None, &modalities).is_ok());
+
+        let err = check_target_aet("radax", Some("RADAX"), &modalities).unwrap_err();
+        assert!(err.to_string().contains("did you mean 'RADAX'"));
+        let err = check_target_aet("RADEX", Some("RADAX"), &modalities).unwrap_err();
+        assert!(err.to_string().contains("known modalities: PACS"));
+    }
+
+    #[test]
+    fn test_batch_summary_line() {
+        let mut a = result("Success");
+        a.accession = "A1".into();
+        a.bytes_downloaded = 10 * 1024 * 1024;
+        a.finish_timing(Duration::from_secs(4));
+        assert_eq!(a.throughput_bps, 2.5 * 1024.0 * 1024.0);
+        let mut b = result("Failed");
+        b.accession = "B2".into();
+        b.finish_timing(Duration::ZERO);
+        assert_eq!(b.throughput_bps, 0.0);
+
+        assert_eq!(
+            batch_summary_line(&[a, b], Duration::from_secs(5)),
+            "Batch time: 0h00m05s; 10.0 MiB downloaded, 2.0 MiB/s average; \
+             per accession 0h00m02s mean, slowest A1 (0h00m04s)"
+        );
+        assert_eq!(
+            batch_summary_line(&[], Duration::from_secs(1)),
+            "Batch time: 0h00m01s; 0.0 B downloaded, 0.0 B/s average"
+        );
+    }
+
+    #[test]
+    fn test_exit_code_policies() {
+        let mixed = [
+            result("Success"),
+            result("Failed"),
+            result("Partial"),
+            result("Success"),
+        ];
+        assert_eq!(exit_code(&mixed, &FailOn::Any), EXIT_PARTIAL);
+        assert_eq!(exit_code(&mixed, &FailOn::All), EXIT_SUCCESS);
+        assert_eq!(exit_code(&mixed, &FailOn::Threshold(50.0)), EXIT_PARTIAL);
+        assert_eq!(exit_code(&mixed, &FailOn::Threshold(60.0)), EXIT_SUCCESS);
+
+        let failed = [result("Failed"), result("Failed")];
+        assert_eq!(exit_code(&failed, &FailOn::All), EXIT_ALL_FAILED);
+        let stopped = [result("Failed"), not_attempted("A2")];
+        assert_eq!(exit_code(&stopped, &FailOn::All), EXIT_ALL_FAILED);
+        assert_eq!(exit_code(&[], &FailOn::Any), EXIT_SUCCESS);
+    }
+}
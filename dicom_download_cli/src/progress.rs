@@ -0,0 +1,180 @@
+//! Terminal progress layout helpers.
+//!
+//! indicatif redraws every bar on each tick; once there are more bars than terminal rows
+//! the output scrolls and becomes garbled. These helpers decide when to collapse to a
+//! single aggregate bar and route per-item completion lines to a log file instead.
+
+use chrono::Local;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Set by `-q` / `--events`: no progress bars at all.
+static BARS_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Default log file used when bars are collapsed and no `--log-file` was given.
+pub const DEFAULT_PROGRESS_LOG: &str = "dicom_download_cli.log";
+
+/// Rows kept free for summary lines printed around the bars.
+const RESERVED_ROWS: usize = 2;
+
+/// Returns the height of the terminal attached to stderr, if any.
+pub fn terminal_rows() -> Option<usize> {
+    let term = console::Term::stderr();
+    if !term.is_term() {
+        return None;
+    }
+    term.size_checked().map(|(rows, _)| rows as usize)
+}
+
+/// Returns true when `bars` progress bars would not fit into `rows` terminal rows.
+///
+/// Without a terminal (`rows == None`) indicatif draws nothing, so nothing collapses.
+pub fn should_collapse(bars: usize, rows: Option<usize>) -> bool {
+    rows.map(|r| bars + RESERVED_ROWS > r).unwrap_or(false)
+}
+
+/// Append-only, timestamped log of lines that would otherwise go to progress bars.
+///
+/// The file is only created on the first written line, so runs that never collapse
+/// leave nothing behind.
+pub struct ProgressLog {
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+}
+
+impl ProgressLog {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes one timestamped line; IO errors are ignored so logging never fails a run.
+    pub fn line(&self, msg: &str) {
+        let Ok(mut guard) = self.file.lock() else {
+            return;
+        };
+        if guard.is_none() {
+            *guard = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .ok();
+        }
+        // 單次寫入整行，與 --log-file 的 tracing 輸出共用檔案時不會交錯
+        if let Some(f) = guard.as_mut() {
+            let line = format!("{} {}\n", Local::now().format("%Y-%m-%d %H:%M:%S"), msg);
+            let _ = f.write_all(line.as_bytes());
+        }
+    }
+}
+
+/// Turns progress bars off for the rest of the process (`-q`, `--events`).
+pub fn disable_bars() {
+    BARS_DISABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn bars_disabled() -> bool {
+    BARS_DISABLED.load(Ordering::Relaxed)
+}
+
+/// Returns a `MultiProgress` whose bars are never drawn (used when collapsed).
+pub fn hidden_multi_progress() -> MultiProgress {
+    MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+}
+
+/// Creates the single aggregate bar shown in collapsed mode.
+pub fn aggregate_bar(total: u64, label: &str) -> ProgressBar {
+    let pb = ProgressBar::new(total);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
+            .unwrap()
+            .progress_chars("=>-"),
+    );
+    pb.set_message(label.to_string());
+    pb
+}
+
+/// Top-level bar of `download`: accessions finished out of the whole batch, with an ETA.
+///
+/// Each accession adds its per-series bars to the same [`MultiProgress`] below this bar
+/// and retires them once the series is done, so the batch bar stays in view.
+#[derive(Clone)]
+pub struct BatchProgress {
+    mp: MultiProgress,
+    bar: ProgressBar,
+    failed: Arc<AtomicUsize>,
+}
+
+impl BatchProgress {
+    pub fn new(total: usize) -> Self {
+        let mp = MultiProgress::new();
+        let bar = mp.add(ProgressBar::new(total as u64));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "{spinner:.green} Batch [{bar:40.green/white}] {pos}/{len} accessions ({eta} left) {msg}",
+                )
+                .unwrap()
+                .progress_chars("=>-"),
+        );
+        // 單一 accession 可能跑很久，定時重繪讓 spinner 與 ETA 持續更新
+        bar.enable_steady_tick(Duration::from_millis(250));
+        Self {
+            mp,
+            bar,
+            failed: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// `MultiProgress` that per-series bars are added to.
+    pub fn multi(&self) -> &MultiProgress {
+        &self.mp
+    }
+
+    pub fn accession_finished(&self, succeeded: bool) {
+        if !succeeded {
+            let failed = self.failed.fetch_add(1, Ordering::Relaxed) + 1;
+            self.bar.set_message(format!("{} failed", failed));
+        }
+        self.bar.inc(1);
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_collapse() {
+        assert!(!should_collapse(20, None));
+        assert!(!should_collapse(10, Some(40)));
+        assert!(should_collapse(20, Some(20)));
+        assert!(should_collapse(39, Some(40)));
+    }
+
+    #[test]
+    fn test_batch_progress_counts_accessions() {
+        let batch = BatchProgress::new(3);
+        batch.accession_finished(true);
+        batch.accession_finished(false);
+        assert_eq!(batch.bar.position(), 2);
+        assert_eq!(batch.bar.length(), Some(3));
+        assert_eq!(batch.bar.message(), "1 failed");
+    }
+}
@@ -0,0 +1,185 @@
+//! `download --purge-source`: delete a study from the local Orthanc once its download is
+//! verified, for sites where Orthanc is only a staging cache.
+//!
+//! Deletion is never done on trust. A study is purged only when every series of its plan
+//! completed, every series folder still verifies against its `checksums.sha256` manifest,
+//! and the SOPInstanceUIDs of the verified files include every instance Orthanc holds for
+//! the study (a count would let extra local files hide missing ones) — a study
+//! with series left out by the whitelist, patterns, or non-image policy is kept, since
+//! deleting it would lose data that was never downloaded. `--purge-dry-run` runs the same
+//! checks and only reports what would be deleted. Deletions (and dry-run plans) are written
+//! to the audit log.
+
+use anyhow::Result;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::audit::{AuditLog, AuditOperation};
+use crate::checksum::{verify_series, VerifyReport};
+use crate::client::OrthancClient;
+use crate::sopindex::{dicom_file_names, index_sop_uids};
+
+/// `--purge-source` mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PurgeMode {
+    Delete,
+    /// `--purge-dry-run`: verify and report, delete nothing.
+    DryRun,
+}
+
+/// SOPInstanceUIDs of the files verified on disk for a study, or why the study must not be
+/// purged. Files whose header cannot be read are left out, so their instances count as
+/// missing.
+pub fn verify_study_files(series_dirs: &[PathBuf]) -> Result<HashSet<String>, String> {
+    let mut report = VerifyReport::default();
+    for dir in series_dirs {
+        verify_series(dir, &mut report)
+            .map_err(|e| format!("{} has no readable checksum manifest: {}", dir.display(), e))?;
+    }
+    match report.issues.first() {
+        Some(issue) => Err(format!(
+            "{} issue(s), e.g. {} {}",
+            report.issues.len(),
+            issue.path.display(),
+            issue.kind
+        )),
+        None => Ok(series_dirs
+            .iter()
+            .flat_map(|dir| index_sop_uids(dir, &dicom_file_names(dir)).into_keys())
+            .collect()),
+    }
+}
+
+/// Checks that every instance Orthanc stores for the study (its SOPInstanceUID, `None`
+/// when Orthanc has none on record) is among the verified files.
+pub fn check_coverage(
+    verified: &HashSet<String>,
+    orthanc_instances: &[Option<String>],
+) -> Result<(), String> {
+    let missing: Vec<&str> = orthanc_instances
+        .iter()
+        .filter(|uid| !uid.as_ref().is_some_and(|uid| verified.contains(uid)))
+        .map(|uid| uid.as_deref().unwrap_or("(no SOPInstanceUID)"))
+        .collect();
+    match missing.first() {
+        Some(first) => Err(format!(
+            "Orthanc holds {} instances and {} of them are not among the verified files, e.g. {}",
+            orthanc_instances.len(),
+            missing.len(),
+            first
+        )),
+        None => Ok(()),
+    }
+}
+
+/// SOPInstanceUIDs of every instance Orthanc stores for the study, one request per series.
+async fn orthanc_instance_uids(
+    client: &OrthancClient,
+    study_id: &str,
+) -> Result<Vec<Option<String>>> {
+    let mut uids = Vec::new();
+    for series_id in client.list_series_ids(study_id).await? {
+        let tags = client.get_series_instance_tags(&series_id).await?;
+        uids.extend(tags.into_iter().map(|t| t.sop_instance_uid));
+    }
+    Ok(uids)
+}
+
+/// Verifies a downloaded study and deletes it from Orthanc (or plans it in dry-run mode).
+/// `Ok` carries the report note; `Err` is a reason the study was kept or the delete failed.
+pub async fn purge_study(
+    client: &OrthancClient,
+    study_id: &str,
+    study_dir: &Path,
+    series_dirs: &[PathBuf],
+    mode: PurgeMode,
+    audit: Option<&AuditLog>,
+) -> Result<String, String> {
+    let dirs = series_dirs.to_vec();
+    let verified = tokio::task::spawn_blocking(move || verify_study_files(&dirs))
+        .await
+        .map_err(|e| format!("verification task failed: {}", e))??;
+    let instances = orthanc_instance_uids(client, study_id)
+        .await
+        .map_err(|e| format!("Orthanc instance query failed: {}", e))?;
+    check_coverage(&verified, &instances)?;
+    let verified = verified.len();
+
+    let rule = format!("purge_source (Orthanc study {})", study_id);
+    if mode == PurgeMode::DryRun {
+        audit_record(audit, study_dir, &rule, true, "planned")?;
+        return Ok(format!(
+            "would delete Orthanc study {} ({} verified instances; --purge-dry-run)",
+            study_id, verified
+        ));
+    }
+    let deleted = client.delete_study(study_id).await;
+    let outcome = match &deleted {
+        Ok(()) => "ok".to_string(),
+        Err(e) => format!("failed: {}", e),
+    };
+    audit_record(audit, study_dir, &rule, false, &outcome)?;
+    deleted.map_err(|e| format!("Orthanc delete failed: {}", e))?;
+    Ok(format!(
+        "deleted Orthanc study {} after verifying {} instances",
+        study_id, verified
+    ))
+}
+
+fn audit_record(
+    audit: Option<&AuditLog>,
+    study_dir: &Path,
+    rule: &str,
+    dry_run: bool,
+    result: &str,
+) -> Result<(), String> {
+    let Some(log) = audit else {
+        return Ok(());
+    };
+    log.record(
+        AuditOperation::PurgeSource,
+        study_dir,
+        None,
+        rule,
+        dry_run,
+        result,
+    )
+    .map_err(|e| format!("audit log failed, study kept: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::write_manifest;
+
+    #[test]
+    fn test_purge_verification() {
+        let root = std::env::temp_dir().join(format!("purge_test_{}", std::process::id()));
+        let series = root.join("S1").join("T1_3");
+        std::fs::create_dir_all(&series).unwrap();
+        std::fs::write(series.join("a.dcm"), b"one").unwrap();
+        std::fs::write(series.join("b.dcm"), b"two").unwrap();
+
+        let dirs = vec![series.clone()];
+        assert!(verify_study_files(&dirs).unwrap_err().contains("manifest"));
+        write_manifest(&series).unwrap();
+        // 測試檔不是 DICOM，讀不到 SOPInstanceUID，因此不算涵蓋任何 instance
+        assert_eq!(verify_study_files(&dirs), Ok(HashSet::new()));
+
+        let verified: HashSet<String> = ["1.1".to_string(), "1.2".to_string()].into();
+        let uid = |u: &str| Some(u.to_string());
+        assert!(check_coverage(&verified, &[uid("1.1"), uid("1.2")]).is_ok());
+        // 數量足夠但內容不同（多出的本機檔案不能掩蓋缺少的 instance）
+        let err = check_coverage(&verified, &[uid("1.1"), uid("1.3")]).unwrap_err();
+        assert!(err.contains("1 of them") && err.contains("1.3"), "{}", err);
+        assert!(check_coverage(&verified, &[uid("1.1"), None])
+            .unwrap_err()
+            .contains("no SOPInstanceUID"));
+
+        std::fs::write(series.join("b.dcm"), b"changed").unwrap();
+        assert!(verify_study_files(&dirs)
+            .unwrap_err()
+            .contains("checksum mismatch"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
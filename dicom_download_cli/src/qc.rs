@@ -0,0 +1,110 @@
+//! Optional post-download pixel-data QC (`download --qc`).
+//!
+//! One instance per series (the middle file) is decoded with `dicom-pixeldata` and its first
+//! frame checked for all-zero or constant images and stored values outside what BitsStored
+//! allows. Findings go into the report's QcIssues column so corrupted transfers are caught
+//! before they reach analysis. Series without pixel data (SR, encapsulated PDF) are skipped.
+
+use anyhow::Result;
+use dicom_object::{open_file, Tag};
+use dicom_pixeldata::{ConvertOptions, ModalityLutOption, PixelDecoder};
+use std::path::{Path, PathBuf};
+
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+
+/// Middle `.dcm` file of a series folder (by name), the instance QC samples.
+pub fn sample_instance(series_dir: &Path) -> Option<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(series_dir)
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case("dcm"))
+        })
+        .collect();
+    files.sort();
+    let mid = files.len() / 2;
+    files.into_iter().nth(mid)
+}
+
+/// Checks stored (pre-rescale) pixel values; returns a description of the problem, if any.
+pub fn assess_pixels(values: &[f64], bits_stored: u16) -> Option<String> {
+    if values.is_empty() {
+        return Some("no pixel values decoded".into());
+    }
+    if values.iter().any(|v| !v.is_finite()) {
+        return Some("non-finite pixel values".into());
+    }
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if min == 0.0 && max == 0.0 {
+        return Some("all-zero image".into());
+    }
+    if min == max {
+        return Some(format!("constant image (every pixel = {})", min));
+    }
+    if (1..=32).contains(&bits_stored) {
+        // Covers both unsigned [0, 2^n - 1] and signed [-2^(n-1), 2^(n-1) - 1] storage
+        let limit = 2f64.powi(bits_stored as i32);
+        if min < -limit / 2.0 || max > limit - 1.0 {
+            return Some(format!(
+                "pixel range [{}, {}] outside BitsStored {}",
+                min, max, bits_stored
+            ));
+        }
+    }
+    None
+}
+
+/// Decodes the first frame of one instance and assesses it.
+///
+/// `Ok(None)` means the instance looks fine or has no pixel data; decode failures are
+/// reported as findings rather than errors.
+pub fn check_instance(path: &Path) -> Result<Option<String>> {
+    let obj = open_file(path)?;
+    if obj.element_opt(PIXEL_DATA)?.is_none() {
+        return Ok(None);
+    }
+    let decoded = match obj.decode_pixel_data() {
+        Ok(d) => d,
+        Err(e) => return Ok(Some(format!("pixel data decode failed: {}", e))),
+    };
+    let options = ConvertOptions::new().with_modality_lut(ModalityLutOption::None);
+    match decoded.to_vec_frame_with_options::<f64>(0, &options) {
+        Ok(values) => Ok(assess_pixels(&values, decoded.bits_stored())),
+        Err(e) => Ok(Some(format!("pixel data decode failed: {}", e))),
+    }
+}
+
+/// QC for one series folder: `Some("<file>: <finding>")` when the sampled instance fails.
+pub fn check_series(series_dir: &Path) -> Option<String> {
+    let sample = sample_instance(series_dir)?;
+    let name = sample.file_name().unwrap_or_default().to_string_lossy();
+    match check_instance(&sample) {
+        Ok(None) => None,
+        Ok(Some(finding)) => Some(format!("{}: {}", name, finding)),
+        Err(e) => Some(format!("{}: unreadable ({})", name, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assess_pixels() {
+        assert_eq!(
+            assess_pixels(&[0.0; 16], 12).as_deref(),
+            Some("all-zero image")
+        );
+        assert!(assess_pixels(&[7.0; 4], 12)
+            .unwrap()
+            .starts_with("constant image"));
+        assert_eq!(assess_pixels(&[0.0, 100.0, 4095.0], 12), None);
+        assert_eq!(assess_pixels(&[-1024.0, 3071.0], 12), None);
+        assert!(assess_pixels(&[0.0, 70000.0], 16)
+            .unwrap()
+            .contains("BitsStored 16"));
+        assert!(assess_pixels(&[], 16).is_some());
+    }
+}
@@ -0,0 +1,235 @@
+//! Re-fetch one downloaded series folder from Orthanc (`redownload --series-path`).
+//!
+//! Downloaded files are named `<Orthanc instance ID>.dcm` (or carry the UIDs that ID is derived
+//! from, under `instance_naming = "instance_number"`) and embed their SeriesInstanceUID, so a
+//! folder found corrupted weeks later can be resolved back to its Orthanc series without the
+//! original accession list. Re-fetched files keep the naming the folder already uses. Only the instances belonging to that folder are re-fetched;
+//! instances stored in sibling folders (per-instance groups such as DWI0/DWI1000) are left alone.
+
+use anyhow::{anyhow, Result};
+use dicom_object::{open_file, Tag};
+use futures::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::checksum::write_manifest;
+use crate::client::OrthancClient;
+use crate::downloader::safe_dicom_filename;
+use crate::layout::instance_number_file_names;
+use crate::ordering::{is_dynamic_series, write_ordering_file};
+use crate::validate::{is_orthanc_id, local_instance_id};
+
+/// Outcome of re-fetching one series folder.
+#[derive(Debug, Default)]
+pub struct RedownloadSummary {
+    /// Orthanc series ID the folder resolved to.
+    pub series_id: String,
+    /// Instances that belong in the folder.
+    pub expected: usize,
+    pub downloaded: usize,
+    /// `(instance ID, error)` for instances that could not be re-fetched.
+    pub failed: Vec<(String, String)>,
+    /// Local `.dcm` files that do not belong to the series (left untouched).
+    pub unexpected: Vec<String>,
+}
+
+/// Returns true for `*.dcm` paths.
+fn is_dcm(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase() == "dcm")
+        .unwrap_or(false)
+}
+
+/// `.dcm` files in `dir` keyed by Orthanc instance ID.
+fn local_files(dir: &Path) -> Result<HashMap<String, PathBuf>> {
+    let mut files = HashMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if is_dcm(&path) {
+            if let Some(id) = local_instance_id(&path) {
+                files.insert(id, path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Orthanc instance IDs of the `.dcm` files in `dir`.
+pub fn instance_ids_in(dir: &Path) -> Result<HashSet<String>> {
+    Ok(local_files(dir)?.into_keys().collect())
+}
+
+/// SeriesInstanceUID (0020,000E) of the first readable DICOM file in `dir`.
+fn read_series_uid(dir: &Path) -> Option<String> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| is_dcm(p))
+        .find_map(|path| {
+            let obj = open_file(&path).ok()?;
+            let uid = obj.element(Tag(0x0020, 0x000E)).ok()?.to_str().ok()?;
+            let uid = uid.trim().trim_end_matches('\0').to_string();
+            (!uid.is_empty()).then_some(uid)
+        })
+}
+
+/// Instances of the series that belong in this folder: all of them, minus those already
+/// stored in sibling folders of the same study.
+pub fn instances_for_folder(
+    series_instances: &[String],
+    sibling_ids: &HashSet<String>,
+) -> Vec<String> {
+    series_instances
+        .iter()
+        .filter(|id| !sibling_ids.contains(*id))
+        .cloned()
+        .collect()
+}
+
+/// Instance IDs stored in the other series folders next to `series_dir`.
+fn sibling_instance_ids(series_dir: &Path) -> HashSet<String> {
+    let Some(study_dir) = series_dir.parent() else {
+        return HashSet::new();
+    };
+    let Ok(entries) = std::fs::read_dir(study_dir) else {
+        return HashSet::new();
+    };
+    entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_dir() && p.file_name() != series_dir.file_name())
+        .filter_map(|p| instance_ids_in(&p).ok())
+        .flatten()
+        .collect()
+}
+
+/// Finds the Orthanc series for a folder: first via the instance IDs in the file names,
+/// then via the SeriesInstanceUID embedded in a readable file.
+async fn resolve_series(
+    client: &OrthancClient,
+    series_dir: &Path,
+    local_ids: &HashSet<String>,
+) -> Result<String> {
+    for id in local_ids {
+        if let Some(series_id) = client.get_instance_parent_series(id).await? {
+            return Ok(series_id);
+        }
+    }
+    let uid = read_series_uid(series_dir).ok_or_else(|| {
+        anyhow!(
+            "Cannot resolve {}: no instance known to Orthanc and no readable SeriesInstanceUID",
+            series_dir.display()
+        )
+    })?;
+    client
+        .lookup_series(&uid)
+        .await?
+        .ok_or_else(|| anyhow!("Series {} not found in Orthanc", uid))
+}
+
+/// Streams one instance to `<name>.part` and renames it over `<name>`.
+async fn refetch_instance(client: &OrthancClient, instance_id: &str, dest: &Path) -> Result<()> {
+    client.download_instance_to_path(instance_id, dest).await?;
+    Ok(())
+}
+
+/// Re-fetches every instance belonging to `series_dir`, replacing existing files.
+///
+/// Dynamic (DSC/ASL) folders get their `temporal_order.csv` rewritten afterwards, and the
+/// `checksums.sha256` manifest is regenerated.
+pub async fn redownload_series(
+    client: &OrthancClient,
+    series_dir: &Path,
+    concurrency: usize,
+) -> Result<RedownloadSummary> {
+    if !series_dir.is_dir() {
+        return Err(anyhow!("{} is not a directory", series_dir.display()));
+    }
+    let local = local_files(series_dir)?;
+    let local_ids: HashSet<String> = local.keys().cloned().collect();
+    let series_id = resolve_series(client, series_dir, &local_ids).await?;
+    let meta = client.get_series_meta(&series_id).await?;
+    let targets = instances_for_folder(&meta.instances, &sibling_instance_ids(series_dir));
+
+    // 資料夾內已有非 Orthanc ID 的檔名時，缺少的檔案也用 InstanceNumber 命名
+    let numbered = local.values().any(|path| {
+        path.file_stem()
+            .is_some_and(|stem| !is_orthanc_id(&stem.to_string_lossy()))
+    });
+    let names = if numbered {
+        instance_number_file_names(&client.get_series_instance_tags(&series_id).await?)
+    } else {
+        HashMap::new()
+    };
+    let dest = |id: &str| -> PathBuf {
+        local.get(id).cloned().unwrap_or_else(|| {
+            series_dir.join(
+                names
+                    .get(id)
+                    .cloned()
+                    .unwrap_or_else(|| safe_dicom_filename(id)),
+            )
+        })
+    };
+
+    let target_set: HashSet<&String> = targets.iter().collect();
+    let mut unexpected: Vec<String> = local_ids
+        .iter()
+        .filter(|id| !target_set.contains(id))
+        .cloned()
+        .collect();
+    unexpected.sort();
+
+    let results: Vec<(String, Result<()>)> = stream::iter(targets.iter().cloned())
+        .map(|id| {
+            let dest = dest(&id);
+            async move {
+                let result = refetch_instance(client, &id, &dest).await;
+                (id, result)
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut summary = RedownloadSummary {
+        series_id,
+        expected: targets.len(),
+        unexpected,
+        ..Default::default()
+    };
+    for (id, result) in results {
+        match result {
+            Ok(()) => summary.downloaded += 1,
+            Err(e) => summary.failed.push((id, format!("{:#}", e))),
+        }
+    }
+
+    let folder = series_dir
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if is_dynamic_series(&folder, series_dir) {
+        if let Err(e) = write_ordering_file(series_dir) {
+            warn!("temporal ordering export failed: {}", e);
+        }
+    }
+    if let Err(e) = write_manifest(series_dir) {
+        warn!("checksum manifest failed: {}", e);
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instances_for_folder_excludes_siblings() {
+        let series = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let siblings: HashSet<String> = ["b".to_string()].into();
+        assert_eq!(instances_for_folder(&series, &siblings), ["a", "c"]);
+        assert_eq!(instances_for_folder(&series, &HashSet::new()).len(), 3);
+    }
+}
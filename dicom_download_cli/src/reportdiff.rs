@@ -0,0 +1,244 @@
+//! `report diff`: accession-level changes between two JSON reports.
+//!
+//! Meant for cohorts that are re-run on a schedule: accessions that went from `Success` to
+//! anything else are regressions, the reverse are recoveries, and accessions whose status
+//! stayed the same but whose downloaded series changed are listed too. Appended reports
+//! (`--report-mode append`) may list an accession once per run; the last entry wins.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// The fields of a [`crate::ProcessResult`] entry that the diff looks at.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ReportEntry {
+    pub accession: String,
+    pub status: String,
+    pub downloaded_series: Vec<String>,
+}
+
+/// Reads a JSON report as accession -> entry (last entry per accession wins).
+pub fn load_report(path: &Path) -> Result<BTreeMap<String, ReportEntry>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let entries: Vec<ReportEntry> = serde_json::from_str(&text)
+        .with_context(|| format!("{} is not a JSON report", path.display()))?;
+    Ok(entries
+        .into_iter()
+        .map(|e| (e.accession.clone(), e))
+        .collect())
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// `Success` before, anything else now.
+    NewlyFailed,
+    /// Anything else before, `Success` now.
+    NewlySucceeded,
+    /// Status changed between two non-success values (e.g. `Partial` -> `Failed`).
+    StatusChanged,
+    /// Same status, different set of downloaded series.
+    SeriesChanged,
+    /// Only in the new report.
+    Added,
+    /// Only in the old report.
+    Removed,
+}
+
+impl ChangeKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            ChangeKind::NewlyFailed => "newly failed",
+            ChangeKind::NewlySucceeded => "newly succeeded",
+            ChangeKind::StatusChanged => "status changed",
+            ChangeKind::SeriesChanged => "series changed",
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AccessionChange {
+    pub accession: String,
+    pub change: ChangeKind,
+    pub old_status: Option<String>,
+    pub new_status: Option<String>,
+    pub old_series: usize,
+    pub new_series: usize,
+    /// Downloaded now but not before.
+    pub series_added: Vec<String>,
+    /// Downloaded before but not now.
+    pub series_removed: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ReportDiff {
+    pub old_accessions: usize,
+    pub new_accessions: usize,
+    pub unchanged: usize,
+    /// Changed accessions, regressions first.
+    pub changes: Vec<AccessionChange>,
+}
+
+impl ReportDiff {
+    pub fn count(&self, kind: ChangeKind) -> usize {
+        self.changes.iter().filter(|c| c.change == kind).count()
+    }
+
+    /// Plain-text table for the terminal.
+    pub fn table(&self) -> String {
+        let mut out = format!(
+            "{:<20} {:<16} {:<14} {:<14} {:>7}  {}\n",
+            "Accession", "Change", "Old status", "New status", "Series", "Series detail"
+        );
+        for c in &self.changes {
+            let mut detail: Vec<String> = Vec::new();
+            detail.extend(c.series_added.iter().map(|s| format!("+{}", s)));
+            detail.extend(c.series_removed.iter().map(|s| format!("-{}", s)));
+            out.push_str(&format!(
+                "{:<20} {:<16} {:<14} {:<14} {:>7}  {}\n",
+                c.accession,
+                c.change.label(),
+                c.old_status.as_deref().unwrap_or("-"),
+                c.new_status.as_deref().unwrap_or("-"),
+                format!("{}->{}", c.old_series, c.new_series),
+                detail.join(" ")
+            ));
+        }
+        out
+    }
+
+    pub fn summary_line(&self) -> String {
+        format!(
+            "{} -> {} accessions: {} newly failed, {} newly succeeded, {} other status changes, \
+             {} series changes, {} added, {} removed, {} unchanged",
+            self.old_accessions,
+            self.new_accessions,
+            self.count(ChangeKind::NewlyFailed),
+            self.count(ChangeKind::NewlySucceeded),
+            self.count(ChangeKind::StatusChanged),
+            self.count(ChangeKind::SeriesChanged),
+            self.count(ChangeKind::Added),
+            self.count(ChangeKind::Removed),
+            self.unchanged
+        )
+    }
+}
+
+fn series_set(entry: Option<&ReportEntry>) -> BTreeSet<&str> {
+    entry
+        .map(|e| e.downloaded_series.iter().map(String::as_str).collect())
+        .unwrap_or_default()
+}
+
+/// Compares two reports keyed by accession.
+pub fn diff_reports(
+    old: &BTreeMap<String, ReportEntry>,
+    new: &BTreeMap<String, ReportEntry>,
+) -> ReportDiff {
+    let mut diff = ReportDiff {
+        old_accessions: old.len(),
+        new_accessions: new.len(),
+        ..Default::default()
+    };
+    let accessions: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for acc in accessions {
+        let (before, after) = (old.get(acc), new.get(acc));
+        let (old_series, new_series) = (series_set(before), series_set(after));
+        let success = |e: &ReportEntry| e.status == "Success";
+        let change = match (before, after) {
+            (None, Some(_)) => ChangeKind::Added,
+            (Some(_), None) => ChangeKind::Removed,
+            (Some(b), Some(a)) if success(b) && !success(a) => ChangeKind::NewlyFailed,
+            (Some(b), Some(a)) if !success(b) && success(a) => ChangeKind::NewlySucceeded,
+            (Some(b), Some(a)) if b.status != a.status => ChangeKind::StatusChanged,
+            _ if old_series != new_series => ChangeKind::SeriesChanged,
+            _ => {
+                diff.unchanged += 1;
+                continue;
+            }
+        };
+        diff.changes.push(AccessionChange {
+            accession: acc.clone(),
+            change,
+            old_status: before.map(|e| e.status.clone()),
+            new_status: after.map(|e| e.status.clone()),
+            old_series: old_series.len(),
+            new_series: new_series.len(),
+            series_added: new_series
+                .difference(&old_series)
+                .map(|s| s.to_string())
+                .collect(),
+            series_removed: old_series
+                .difference(&new_series)
+                .map(|s| s.to_string())
+                .collect(),
+        });
+    }
+    diff.changes
+        .sort_by(|a, b| (a.change, &a.accession).cmp(&(b.change, &b.accession)));
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(acc: &str, status: &str, series: &[&str]) -> (String, ReportEntry) {
+        let e = ReportEntry {
+            accession: acc.into(),
+            status: status.into(),
+            downloaded_series: series.iter().map(|s| s.to_string()).collect(),
+        };
+        (acc.to_string(), e)
+    }
+
+    #[test]
+    fn test_diff_reports_classifies_changes() {
+        let old: BTreeMap<_, _> = [
+            entry("A", "Success", &["T1", "T2"]),
+            entry("B", "Failed", &[]),
+            entry("C", "Success", &["T1"]),
+            entry("D", "Partial", &["T1"]),
+            entry("E", "Success", &["T1"]),
+            entry("F", "Success", &["T1"]),
+        ]
+        .into_iter()
+        .collect();
+        let new: BTreeMap<_, _> = [
+            entry("A", "Partial", &["T1"]),
+            entry("B", "Success", &["T1"]),
+            entry("C", "Success", &["T1", "FLAIR"]),
+            entry("D", "Failed", &[]),
+            entry("E", "Success", &["T1"]),
+            entry("G", "Success", &["T1"]),
+        ]
+        .into_iter()
+        .collect();
+
+        let diff = diff_reports(&old, &new);
+        let kinds: Vec<(&str, ChangeKind)> = diff
+            .changes
+            .iter()
+            .map(|c| (c.accession.as_str(), c.change))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("A", ChangeKind::NewlyFailed),
+                ("B", ChangeKind::NewlySucceeded),
+                ("D", ChangeKind::StatusChanged),
+                ("C", ChangeKind::SeriesChanged),
+                ("G", ChangeKind::Added),
+                ("F", ChangeKind::Removed),
+            ]
+        );
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(diff.changes[0].series_removed, vec!["T2"]);
+        assert_eq!(diff.changes[3].series_added, vec!["FLAIR"]);
+    }
+}
@@ -0,0 +1,248 @@
+//! Report file naming, appending, and locking.
+//!
+//! By default each run replaces `report.csv` / `report.json`. `--report-mode timestamped`
+//! writes `report_2024-06-01T12-00.csv` instead, and `--report-mode append` adds this run's
+//! rows to the existing files with a `RunId` column (`run_id` in JSON) so runs can be told
+//! apart. In every mode the report is held under a `<report>.lock` file while it is written,
+//! so two concurrent invocations pointed at the same report cannot interleave or clobber
+//! each other's temp files.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::atomic::{write_atomic, write_bytes_atomic};
+
+/// How long to wait for another run to release a report lock.
+const LOCK_WAIT: Duration = Duration::from_secs(30);
+/// Locks older than this are left over from a crashed run and are taken over.
+const STALE_LOCK: Duration = Duration::from_secs(600);
+
+/// What to do with an existing report file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ReportMode {
+    /// Replace the file (previous behaviour).
+    #[default]
+    Overwrite,
+    /// Write `<stem>_<YYYY-MM-DDTHH-MM>.<ext>` next to the configured path.
+    Timestamped,
+    /// Add this run's rows, tagged with a run ID.
+    Append,
+}
+
+impl std::str::FromStr for ReportMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "overwrite" => Ok(Self::Overwrite),
+            "timestamped" => Ok(Self::Timestamped),
+            "append" => Ok(Self::Append),
+            other => Err(anyhow!(
+                "Invalid report mode '{}': expected overwrite, timestamped, or append",
+                other
+            )),
+        }
+    }
+}
+
+/// Identifies one invocation in appended reports: start time plus process ID.
+pub fn run_id(at: DateTime<Utc>) -> String {
+    format!("{}-{}", at.format("%Y%m%dT%H%M%SZ"), std::process::id())
+}
+
+/// Whether `name` has the [`run_id`] format, `<%Y%m%dT%H%M%SZ>-<pid>`.
+pub fn is_run_id(name: &str) -> bool {
+    name.split_once('-').is_some_and(|(at, pid)| {
+        chrono::NaiveDateTime::parse_from_str(at, "%Y%m%dT%H%M%SZ").is_ok()
+            && !pid.is_empty()
+            && pid.bytes().all(|b| b.is_ascii_digit())
+    })
+}
+
+/// `report.csv` -> `report_2024-06-01T12-00.csv`; a `-2`, `-3`, ... suffix is added when a
+/// run in the same minute already took the name.
+pub fn timestamped_path(path: &Path, at: DateTime<Utc>) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let stamp = at.format("%Y-%m-%dT%H-%M");
+    let candidate = |n: usize| {
+        let suffix = if n > 1 {
+            format!("-{}", n)
+        } else {
+            String::new()
+        };
+        path.with_file_name(format!("{}_{}{}{}", stem, stamp, suffix, ext))
+    };
+    (1..)
+        .map(candidate)
+        .find(|p| !p.exists())
+        .expect("unbounded range always yields a free name")
+}
+
+/// Exclusive lock on a report, released on drop.
+#[derive(Debug)]
+pub struct ReportLock {
+    path: PathBuf,
+}
+
+impl ReportLock {
+    /// Creates `<report>.lock`, waiting for another run to finish writing first.
+    pub fn acquire(report: &Path) -> Result<Self> {
+        let mut name = report.as_os_str().to_os_string();
+        name.push(".lock");
+        let path = PathBuf::from(name);
+        let started = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let _ = writeln!(file, "{}", std::process::id());
+                    return Ok(Self { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let age = std::fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|t| t.elapsed().ok());
+                    if age.is_some_and(|a| a > STALE_LOCK) {
+                        let _ = std::fs::remove_file(&path);
+                        continue;
+                    }
+                    if started.elapsed() > LOCK_WAIT {
+                        bail!(
+                            "{} is locked by another run ({}); remove the lock file if no \
+                             other run is active",
+                            report.display(),
+                            path.display()
+                        );
+                    }
+                    std::thread::sleep(Duration::from_millis(200));
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to create {}", path.display()))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ReportLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Appends rendered CSV (header + rows) to `path` with a leading `RunId` column.
+///
+/// The existing file must have been written in append mode with the same columns, so a
+/// per-accession report is never mixed into a per-series one.
+pub fn append_csv(path: &Path, rendered: &[u8], run_id: &str) -> Result<()> {
+    let mut reader = csv::Reader::from_reader(rendered);
+    let mut header = vec!["RunId".to_string()];
+    header.extend(reader.headers()?.iter().map(str::to_string));
+
+    let existing = match std::fs::read(path) {
+        Ok(bytes) => Some(bytes),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    if let Some(bytes) = existing.as_deref().filter(|b| !b.is_empty()) {
+        let old: Vec<String> = csv::Reader::from_reader(bytes)
+            .headers()?
+            .iter()
+            .map(str::to_string)
+            .collect();
+        if old != header {
+            bail!(
+                "{} has different columns (written without append mode or with another \
+                 --report-detail); choose another report path",
+                path.display()
+            );
+        }
+    }
+
+    let mut out = existing.unwrap_or_default();
+    let write_header = out.is_empty();
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(&mut out);
+    if write_header {
+        wtr.write_record(&header)?;
+    }
+    for record in reader.records() {
+        let record = record?;
+        wtr.write_record(std::iter::once(run_id).chain(record.iter()))?;
+    }
+    wtr.flush()?;
+    drop(wtr);
+    write_bytes_atomic(path, &out)
+}
+
+/// Appends `entries` (serialized report objects) to the JSON array in `path`, adding
+/// `run_id` to each.
+pub fn append_json(path: &Path, entries: Value, run_id: &str) -> Result<()> {
+    let mut all: Vec<Value> = match std::fs::read_to_string(path) {
+        Ok(text) if !text.trim().is_empty() => serde_json::from_str(&text)
+            .with_context(|| format!("{} is not a JSON report array", path.display()))?,
+        Ok(_) => Vec::new(),
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let Value::Array(entries) = entries else {
+        bail!("report entries must serialize to an array");
+    };
+    for mut entry in entries {
+        if let Value::Object(map) = &mut entry {
+            map.insert("run_id".into(), Value::String(run_id.to_string()));
+        }
+        all.push(entry);
+    }
+    write_atomic(path, |w| Ok(serde_json::to_writer_pretty(w, &all)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_timestamped_and_append() {
+        let dir = std::env::temp_dir().join(format!("reportfile_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let at = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 30).unwrap();
+
+        let first = timestamped_path(&dir.join("report.csv"), at);
+        assert_eq!(first, dir.join("report_2024-06-01T12-00.csv"));
+        std::fs::write(&first, "").unwrap();
+        assert_eq!(
+            timestamped_path(&dir.join("report.csv"), at),
+            dir.join("report_2024-06-01T12-00-2.csv")
+        );
+
+        let path = dir.join("append.csv");
+        append_csv(&path, b"Acc,Status\nA1,Success\n", "run1").unwrap();
+        append_csv(&path, b"Acc,Status\nA2,Failed\n", "run2").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "RunId,Acc,Status\nrun1,A1,Success\nrun2,A2,Failed\n"
+        );
+        assert!(append_csv(&path, b"Acc,Series\nA3,T1\n", "run3").is_err());
+
+        let lock = ReportLock::acquire(&path).unwrap();
+        assert!(dir.join("append.csv.lock").exists());
+        drop(lock);
+        assert!(!dir.join("append.csv.lock").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+//! HTTP retry policy shared by every `OrthancClient` request.
+//!
+//! Transient failures (connect/timeout errors, 408/429/5xx responses) are retried with
+//! exponential backoff and jitter. A run-wide retry budget caps the total number of retries
+//! so an unhealthy server cannot turn one batch into hours of retry storms. A
+//! [`CircuitBreaker`] stops calling a dependency (the analysis service) altogether after
+//! repeated failures.
+
+use anyhow::Result;
+use reqwest::StatusCode;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Default retries per request.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+/// Default delay before the first retry.
+pub const DEFAULT_BASE_DELAY_MS: u64 = 500;
+/// Default upper bound for a single backoff delay.
+pub const DEFAULT_MAX_DELAY_MS: u64 = 10_000;
+
+/// Backoff settings for transient HTTP failures.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Total retries allowed across the whole run; `None` means unlimited.
+    pub budget: Option<usize>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: Duration::from_millis(DEFAULT_BASE_DELAY_MS),
+            max_delay: Duration::from_millis(DEFAULT_MAX_DELAY_MS),
+            budget: None,
+        }
+    }
+}
+
+/// Non-success status that is worth retrying (408, 429, 500, 502, 503, 504).
+#[derive(Debug)]
+pub struct TransientStatus {
+    pub status: StatusCode,
+    pub url: String,
+}
+
+impl std::fmt::Display for TransientStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP {} for {}", self.status, self.url)
+    }
+}
+
+impl std::error::Error for TransientStatus {}
+
+/// Returns true for status codes that usually clear up on their own.
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+/// Returns true when `err` is a transient network failure or a [`TransientStatus`].
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        if e.is::<TransientStatus>() {
+            return true;
+        }
+        match e.downcast_ref::<reqwest::Error>() {
+            Some(re) => re.is_timeout() || re.is_connect() || re.is_body() || re.is_request(),
+            None => false,
+        }
+    })
+}
+
+/// Delay before retry number `attempt` (0-based): exponential growth capped at
+/// `max_delay`, then "equal jitter" — half fixed, half scaled by `jitter` in `[0, 1)`.
+pub fn backoff_delay(policy: &RetryPolicy, attempt: u32, jitter: f64) -> Duration {
+    let exp = policy
+        .base_delay
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(policy.max_delay);
+    let half = exp / 2;
+    half + half.mul_f64(jitter.clamp(0.0, 1.0))
+}
+
+/// Runs operations under a [`RetryPolicy`] and tracks the shared retry budget.
+pub struct Retrier {
+    policy: RetryPolicy,
+    remaining: Option<AtomicUsize>,
+}
+
+impl Retrier {
+    pub fn new(policy: RetryPolicy) -> Self {
+        let remaining = policy.budget.map(AtomicUsize::new);
+        Self { policy, remaining }
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// Takes one retry from the budget; false once it is exhausted.
+    fn take_budget(&self) -> bool {
+        match &self.remaining {
+            None => true,
+            Some(left) => left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok(),
+        }
+    }
+
+    /// Calls `op` until it succeeds, fails permanently, or retries/budget run out.
+    pub async fn run<T, F, Fut>(&self, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Ok(v) => return Ok(v),
+                Err(e)
+                    if attempt < self.policy.max_retries
+                        && is_transient(&e)
+                        && self.take_budget() =>
+                {
+                    let delay = backoff_delay(&self.policy, attempt, rand::random());
+                    debug!(
+                        attempt = attempt + 1,
+                        ?delay,
+                        "retrying after transient error: {:#}",
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Opens after `threshold` consecutive failures; while open, calls are refused until
+/// `cooldown` has passed, then one trial call is let through per cooldown.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// A `threshold` of 0 never opens.
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// `Err(consecutive failures)` while open. Once the cooldown has passed, the first
+    /// caller gets `Ok` as the trial and the breaker stays open for everyone else.
+    pub fn check(&self, now: Instant) -> Result<(), u32> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.open_until {
+            Some(until) if now < until => Err(state.failures),
+            Some(_) => {
+                state.open_until = Some(now + self.cooldown);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Closes the breaker and resets the failure count.
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner()) = BreakerState::default();
+    }
+
+    /// Counts a failure; returns true when this failure opened a closed breaker.
+    pub fn record_failure(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.failures = state.failures.saturating_add(1);
+        if self.threshold == 0 || state.failures < self.threshold {
+            return false;
+        }
+        let opened = state.open_until.is_none();
+        state.open_until = Some(now + self.cooldown);
+        opened
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay_grows_and_caps() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            ..Default::default()
+        };
+        assert_eq!(backoff_delay(&policy, 0, 0.0), Duration::from_millis(50));
+        assert_eq!(backoff_delay(&policy, 0, 1.0), Duration::from_millis(100));
+        assert_eq!(backoff_delay(&policy, 2, 0.0), Duration::from_millis(200));
+        assert_eq!(backoff_delay(&policy, 10, 1.0), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_retryable_status(StatusCode::NOT_FOUND));
+        assert!(!is_retryable_status(StatusCode::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn test_retrier_respects_budget() {
+        let retrier = Retrier::new(RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            budget: Some(2),
+        });
+        let calls = AtomicUsize::new(0);
+        let result: Result<()> = retrier
+            .run(|| async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(TransientStatus {
+                    status: StatusCode::BAD_GATEWAY,
+                    url: "http://orthanc/series".into(),
+                }
+                .into())
+            })
+            .await;
+        assert!(result.is_err());
+        // 1 initial attempt + 2 budgeted retries
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_circuit_breaker_opens_and_allows_one_trial() {
+        let cooldown = Duration::from_secs(60);
+        let breaker = CircuitBreaker::new(2, cooldown);
+        let t0 = Instant::now();
+        assert!(!breaker.record_failure(t0));
+        assert_eq!(breaker.check(t0), Ok(()));
+        assert!(breaker.record_failure(t0));
+        assert_eq!(breaker.check(t0), Err(2));
+
+        let later = t0 + cooldown;
+        assert_eq!(breaker.check(later), Ok(()));
+        assert_eq!(breaker.check(later), Err(2));
+        assert!(!breaker.record_failure(later));
+        assert_eq!(breaker.check(later + Duration::from_secs(1)), Err(3));
+
+        breaker.record_success();
+        assert_eq!(breaker.check(later), Ok(()));
+    }
+
+    #[test]
+    fn test_circuit_breaker_zero_threshold_never_opens() {
+        let breaker = CircuitBreaker::new(0, Duration::from_secs(60));
+        let now = Instant::now();
+        for _ in 0..10 {
+            assert!(!breaker.record_failure(now));
+        }
+        assert_eq!(breaker.check(now), Ok(()));
+    }
+}
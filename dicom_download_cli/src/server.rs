@@ -0,0 +1,458 @@
+//! `serve`: HTTP API for submitting `download` jobs.
+//!
+//! Other services POST an accession list and poll the job instead of shelling out to the
+//! CLI and parsing stdout. Jobs run one at a time in submission order through the same
+//! [`download_accession_v2`] path as `download`, sharing one [`DownloadContext`] (output
+//! folder, conversion, QC, and state cache). `duplicate_studies` is applied within a job,
+//! so an accession submitted again in a later job is downloaded again. When a job ends its CSV and JSON reports are
+//! written to `<output>/jobs/<id>/`. Job status is kept in memory only; after a restart
+//! the reports on disk remain but the job list starts empty.
+//!
+//! A 401/403 from Orthanc stops the rest of that job and sets `auth_failed` in its status;
+//! the next job starts with a clean slate. When `[serve] token` is set every route except
+//! `/health` requires `Authorization: Bearer <token>`.
+//!
+//! | Method | Path                    | Response                                   |
+//! |--------|-------------------------|--------------------------------------------|
+//! | POST   | `/jobs`                 | `{"accessions": [...]}` → 202 + job status |
+//! | GET    | `/jobs`                 | status of every job, oldest first          |
+//! | GET    | `/jobs/:id`             | job status                                 |
+//! | GET    | `/jobs/:id/report`      | JSON report (409 until the job finished)   |
+//! | GET    | `/jobs/:id/report.csv`  | CSV report                                 |
+//! | GET    | `/health`               | `ok`                                       |
+
+use anyhow::{bail, Context, Result};
+use axum::extract::{Path as UrlPath, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::client::OrthancClient;
+use crate::downloader::{download_accession_v2, DownloadContext};
+use crate::duplicates::StudyClaims;
+use crate::processor::{write_reports, ProcessResult, ReportDetail};
+use crate::reportfile::{run_id, ReportMode};
+
+/// Folder under the output directory that holds one report folder per job.
+pub const JOBS_DIR: &str = "jobs";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Finished,
+}
+
+/// What `GET /jobs/:id` returns.
+#[derive(Serialize, Debug, Clone)]
+pub struct JobStatus {
+    pub id: String,
+    pub state: JobState,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub total: usize,
+    pub completed: usize,
+    pub success: usize,
+    pub failed: usize,
+    /// Accession being downloaded right now.
+    pub current: Option<String>,
+    /// Orthanc rejected the credentials; the remaining accessions were not attempted.
+    pub auth_failed: bool,
+}
+
+#[derive(Deserialize)]
+struct SubmitRequest {
+    accessions: Vec<String>,
+}
+
+struct QueuedJob {
+    id: String,
+    accessions: Vec<String>,
+}
+
+/// Job table shared by the HTTP handlers and the worker.
+pub struct JobQueue {
+    jobs: Mutex<BTreeMap<String, JobStatus>>,
+    next: AtomicUsize,
+    jobs_dir: PathBuf,
+    sender: mpsc::UnboundedSender<QueuedJob>,
+}
+
+/// Receiving end of the queue; consumed by [`JobWorker::run`].
+pub struct JobReceiver(mpsc::UnboundedReceiver<QueuedJob>);
+
+impl JobQueue {
+    pub fn new(output: &Path) -> (Arc<Self>, JobReceiver) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let queue = Self {
+            jobs: Mutex::new(BTreeMap::new()),
+            next: AtomicUsize::new(1),
+            jobs_dir: output.join(JOBS_DIR),
+            sender,
+        };
+        (Arc::new(queue), JobReceiver(receiver))
+    }
+
+    /// Queues a job; blank and repeated accessions are dropped.
+    pub fn submit(&self, accessions: Vec<String>) -> Result<JobStatus> {
+        let mut unique: Vec<String> = Vec::new();
+        for acc in accessions {
+            let acc = acc.trim().to_string();
+            if !acc.is_empty() && !unique.contains(&acc) {
+                unique.push(acc);
+            }
+        }
+        if unique.is_empty() {
+            bail!("accessions must contain at least one accession number");
+        }
+        // 序號在前面補零，BTreeMap 依 ID 排序即為送出順序
+        let id = format!(
+            "{:06}-{}",
+            self.next.fetch_add(1, Ordering::SeqCst),
+            run_id(Utc::now())
+        );
+        let status = JobStatus {
+            id: id.clone(),
+            state: JobState::Queued,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            total: unique.len(),
+            completed: 0,
+            success: 0,
+            failed: 0,
+            current: None,
+            auth_failed: false,
+        };
+        if let Ok(mut jobs) = self.jobs.lock() {
+            jobs.insert(id.clone(), status.clone());
+        }
+        self.sender
+            .send(QueuedJob {
+                id,
+                accessions: unique,
+            })
+            .context("Job worker has stopped")?;
+        Ok(status)
+    }
+
+    pub fn get(&self, id: &str) -> Option<JobStatus> {
+        self.jobs.lock().ok()?.get(id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<JobStatus> {
+        self.jobs
+            .lock()
+            .map(|jobs| jobs.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Report folder of a job.
+    pub fn job_dir(&self, id: &str) -> PathBuf {
+        self.jobs_dir.join(id)
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut JobStatus)) {
+        if let Ok(mut jobs) = self.jobs.lock() {
+            if let Some(status) = jobs.get_mut(id) {
+                f(status);
+            }
+        }
+    }
+}
+
+/// Runs queued jobs one after another.
+pub struct JobWorker {
+    pub client: Arc<OrthancClient>,
+    pub ctx: DownloadContext,
+    pub detail: ReportDetail,
+}
+
+impl JobWorker {
+    pub async fn run(self, queue: Arc<JobQueue>, mut receiver: JobReceiver) {
+        while let Some(job) = receiver.0.recv().await {
+            info!("Job {}: {} accessions", job.id, job.accessions.len());
+            // 401/403 只讓當前工作停止，下一個工作重新嘗試（憑證可能已在 Orthanc 端修正）
+            self.client.reset_auth_failed();
+            queue.update(&job.id, |s| {
+                s.state = JobState::Running;
+                s.started_at = Some(Utc::now());
+            });
+            let mut results: Vec<ProcessResult> = Vec::with_capacity(job.accessions.len());
+            // 重複 study 只在同一工作內判定；重送的 accession 不會撞到先前工作的資料夾
+            let claims = StudyClaims::default();
+            for acc in job.accessions {
+                queue.update(&job.id, |s| s.current = Some(acc.clone()));
+                let res = download_accession_v2(self.client.clone(), acc, &self.ctx, &claims).await;
+                let ok = res.status == "Success";
+                queue.update(&job.id, |s| {
+                    s.completed += 1;
+                    if ok {
+                        s.success += 1;
+                    } else {
+                        s.failed += 1;
+                    }
+                });
+                results.push(res);
+                if let Some(state) = &self.ctx.state {
+                    if let Err(e) = state.save() {
+                        warn!("{}", e);
+                    }
+                }
+            }
+
+            let dir = queue.job_dir(&job.id);
+            let written = std::fs::create_dir_all(&dir)
+                .map_err(anyhow::Error::from)
+                .and_then(|_| {
+                    write_reports(
+                        &dir.join("report.csv"),
+                        &dir.join("report.json"),
+                        &results,
+                        self.detail,
+                        ReportMode::Overwrite,
+                    )
+                });
+            if let Err(e) = written {
+                warn!("Job {}: failed to write reports: {:#}", job.id, e);
+            }
+            let auth_failed = self.client.auth_failed();
+            if auth_failed {
+                warn!("Job {}: Orthanc rejected the credentials", job.id);
+            }
+            queue.update(&job.id, |s| {
+                s.state = JobState::Finished;
+                s.finished_at = Some(Utc::now());
+                s.current = None;
+                s.auth_failed = auth_failed;
+            });
+            info!("Job {} finished", job.id);
+        }
+    }
+}
+
+fn error(status: StatusCode, message: impl std::fmt::Display) -> Response {
+    (status, Json(json!({ "error": message.to_string() }))).into_response()
+}
+
+async fn submit_job(
+    State(queue): State<Arc<JobQueue>>,
+    Json(request): Json<SubmitRequest>,
+) -> Response {
+    match queue.submit(request.accessions) {
+        Ok(status) => (StatusCode::ACCEPTED, Json(status)).into_response(),
+        Err(e) => error(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+async fn list_jobs(State(queue): State<Arc<JobQueue>>) -> Json<Vec<JobStatus>> {
+    Json(queue.list())
+}
+
+async fn job_status(State(queue): State<Arc<JobQueue>>, UrlPath(id): UrlPath<String>) -> Response {
+    match queue.get(&id) {
+        Some(status) => Json(status).into_response(),
+        None => error(StatusCode::NOT_FOUND, format!("Unknown job {}", id)),
+    }
+}
+
+/// Serves one of the report files once the job has finished.
+async fn report_file(queue: &JobQueue, id: &str, name: &str, content_type: &str) -> Response {
+    match queue.get(id) {
+        None => error(StatusCode::NOT_FOUND, format!("Unknown job {}", id)),
+        Some(status) if status.state != JobState::Finished => error(
+            StatusCode::CONFLICT,
+            format!("Job {} has not finished yet", id),
+        ),
+        Some(_) => match tokio::fs::read(queue.job_dir(id).join(name)).await {
+            Ok(body) => ([(header::CONTENT_TYPE, content_type.to_string())], body).into_response(),
+            Err(e) => error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Report {} unavailable: {}", name, e),
+            ),
+        },
+    }
+}
+
+async fn job_report(State(queue): State<Arc<JobQueue>>, UrlPath(id): UrlPath<String>) -> Response {
+    report_file(&queue, &id, "report.json", "application/json").await
+}
+
+async fn job_report_csv(
+    State(queue): State<Arc<JobQueue>>,
+    UrlPath(id): UrlPath<String>,
+) -> Response {
+    report_file(&queue, &id, "report.csv", "text/csv").await
+}
+
+/// True when `headers` carry `Authorization: Bearer <token>`.
+fn bearer_matches(headers: &HeaderMap, token: &str) -> bool {
+    let Some(sent) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    // 逐位元組比較全部內容，不因第一個不同字元提早返回
+    sent.len() == token.len()
+        && sent
+            .bytes()
+            .zip(token.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+async fn require_token(State(token): State<Arc<str>>, request: Request, next: Next) -> Response {
+    if bearer_matches(request.headers(), &token) {
+        next.run(request).await
+    } else {
+        error(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token")
+    }
+}
+
+/// Builds the API; with `token` set every route except `/health` requires it.
+pub fn router(queue: Arc<JobQueue>, token: Option<&str>) -> Router {
+    let mut jobs = Router::new()
+        .route("/jobs", get(list_jobs).post(submit_job))
+        .route("/jobs/:id", get(job_status))
+        .route("/jobs/:id/report", get(job_report))
+        .route("/jobs/:id/report.csv", get(job_report_csv));
+    if let Some(token) = token {
+        jobs = jobs.route_layer(middleware::from_fn_with_state(
+            Arc::<str>::from(token),
+            require_token,
+        ));
+    }
+    jobs.route("/health", get(|| async { "ok" }))
+        .with_state(queue)
+}
+
+/// Starts the worker and serves the API until the process is stopped.
+pub async fn serve(
+    bind: SocketAddr,
+    token: Option<&str>,
+    output: &Path,
+    worker: JobWorker,
+) -> Result<()> {
+    if token.is_none() && !bind.ip().is_loopback() {
+        warn!(
+            "Serving on non-loopback address {} without [serve] token; anyone who can reach it can queue downloads",
+            bind
+        );
+    }
+    let (queue, receiver) = JobQueue::new(output);
+    tokio::spawn(worker.run(queue.clone(), receiver));
+    let listener = tokio::net::TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("Failed to bind {}", bind))?;
+    info!("Listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, router(queue, token))
+        .await
+        .context("HTTP server failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_submit_dedupes_and_orders_jobs() {
+        let (queue, _receiver) = JobQueue::new(Path::new("out"));
+        assert!(queue.submit(vec![" ".into()]).is_err());
+
+        let first = queue
+            .submit(vec!["A1".into(), " A1 ".into(), "A2".into()])
+            .unwrap();
+        assert_eq!(first.state, JobState::Queued);
+        assert_eq!(first.total, 2);
+        let second = queue.submit(vec!["A3".into()]).unwrap();
+
+        let ids: Vec<String> = queue.list().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![first.id.clone(), second.id]);
+        assert_eq!(queue.get(&first.id).unwrap().total, 2);
+        assert!(queue.get("missing").is_none());
+        assert_eq!(
+            queue.job_dir(&first.id),
+            Path::new("out").join(JOBS_DIR).join(&first.id)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resubmitted_accession_is_downloaded_again() {
+        use crate::downloader::tests::{test_context, FakeOrthanc};
+        use crate::duplicates::DuplicatePolicy;
+
+        let orthanc = FakeOrthanc::start(&[("series-1", "T1", &["i1", "i2"])]).await;
+        orthanc.failing.lock().unwrap().insert("i2".to_string());
+        let output = std::env::temp_dir().join(format!("serve_resubmit_{}", std::process::id()));
+        let mut ctx = test_context(&output);
+        ctx.duplicate_policy = DuplicatePolicy::SkipDuplicates;
+        let worker = JobWorker {
+            client: orthanc.client(),
+            ctx,
+            detail: ReportDetail::Accession,
+        };
+        let (queue, receiver) = JobQueue::new(&output);
+        tokio::spawn(worker.run(queue.clone(), receiver));
+        let wait = |id: String| {
+            let queue = queue.clone();
+            async move {
+                for _ in 0..200 {
+                    match queue.get(&id) {
+                        Some(status) if status.state == JobState::Finished => return status,
+                        _ => tokio::time::sleep(std::time::Duration::from_millis(25)).await,
+                    }
+                }
+                panic!("job {} did not finish", id);
+            }
+        };
+
+        // 第一個工作缺一個 instance 而失敗
+        let first = wait(queue.submit(vec!["A1".into()]).unwrap().id).await;
+        assert_eq!(first.failed, 1);
+        assert_eq!(orthanc.downloads.load(Ordering::SeqCst), 1);
+
+        // 重送同一 accession：不可被當成重複 study 略過，須補抓缺少的 instance
+        orthanc.failing.lock().unwrap().clear();
+        let second = wait(queue.submit(vec!["A1".into()]).unwrap().id).await;
+        assert_eq!(second.success, 1);
+        assert_eq!(orthanc.downloads.load(Ordering::SeqCst), 2);
+        let report: serde_json::Value = serde_json::from_slice(
+            &std::fs::read(queue.job_dir(&second.id).join("report.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(report[0]["notes"], json!([]));
+        let _ = std::fs::remove_dir_all(&output);
+    }
+
+    #[test]
+    fn test_bearer_token_must_match_exactly() {
+        let mut headers = HeaderMap::new();
+        assert!(!bearer_matches(&headers, "s3cret"));
+        for (sent, ok) in [
+            ("Bearer s3cret", true),
+            ("Bearer s3cre", false),
+            ("Bearer s3cretx", false),
+            ("Basic s3cret", false),
+            ("s3cret", false),
+        ] {
+            headers.insert(header::AUTHORIZATION, sent.parse().unwrap());
+            assert_eq!(bearer_matches(&headers, "s3cret"), ok, "{}", sent);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! `series.json`: per-series metadata written next to the DICOMs during `download`.
+//!
+//! Downstream tools learn what a folder holds without re-opening DICOM headers: the
+//! SeriesInstanceUID, description, the type the analysis service assigned, modality,
+//! instance count, echo / TR / TE where the headers carry them, and the Orthanc series and
+//! instance IDs. Header values are read from one instance of the folder (up to the pixel
+//! data). The file is written once the series downloaded completely and stays in place when
+//! `delete_dicom_after_conversion` removes the DICOMs. Each NIfTI conversion of the folder
+//! is recorded in it (converter version and command line) for reproducibility audits.
+
+use anyhow::{Context, Result};
+use dicom_object::{DefaultDicomObject, OpenFileOptions, Tag};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::atomic::write_atomic;
+use crate::client::SeriesDownloadPlan;
+
+/// File name of the sidecar inside a series folder.
+pub const SERIES_SIDECAR: &str = "series.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SeriesSidecar {
+    pub series_instance_uid: Option<String>,
+    pub series_description: Option<String>,
+    pub series_number: Option<String>,
+    /// Type from the analysis service (or the header/description fallback); the folder
+    /// name is derived from it.
+    pub series_type: String,
+    pub modality: Option<String>,
+    pub instance_count: usize,
+    /// EchoNumbers (0018,0086) as written in the file.
+    pub echo_numbers: Option<String>,
+    /// EchoTime (0018,0081) in milliseconds.
+    pub echo_time: Option<f64>,
+    /// RepetitionTime (0018,0080) in milliseconds.
+    pub repetition_time: Option<f64>,
+    pub orthanc_series_id: String,
+    pub orthanc_instance_ids: Vec<String>,
+    /// NIfTI conversions of this folder, one per output name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conversions: Vec<SidecarConversion>,
+}
+
+/// One conversion recorded in `series.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SidecarConversion {
+    /// Output base name in `niix/<study>/` (e.g. `T1`, or `DWI_4D` for a merged DWI).
+    pub output: String,
+    /// Converter and version, e.g. `dcm2niix v1.0.20240202`.
+    pub converter: String,
+    pub command: String,
+}
+
+impl SeriesSidecar {
+    /// Sidecar for a downloaded plan; header fields are filled from `sample` when given.
+    pub fn new(plan: &SeriesDownloadPlan, sample: Option<&DefaultDicomObject>) -> Self {
+        let text = |name: &str| {
+            sample
+                .and_then(|obj| obj.element_by_name(name).ok())
+                .and_then(|e| e.to_str().ok())
+                .map(|s| s.trim().trim_end_matches('\0').to_string())
+                .filter(|s| !s.is_empty())
+        };
+        // DS 可能有多值（以 \ 分隔），取第一個
+        let number = |name: &str| {
+            text(name).and_then(|v| v.split('\\').next().and_then(|n| n.trim().parse().ok()))
+        };
+        Self {
+            series_instance_uid: text("SeriesInstanceUID"),
+            series_description: plan
+                .description
+                .clone()
+                .or_else(|| text("SeriesDescription")),
+            series_number: plan.series_number.clone(),
+            series_type: plan.series_type.clone(),
+            modality: text("Modality"),
+            instance_count: plan.instances.len(),
+            echo_numbers: text("EchoNumbers"),
+            echo_time: number("EchoTime"),
+            repetition_time: number("RepetitionTime"),
+            orthanc_series_id: plan.source_series.clone(),
+            orthanc_instance_ids: plan.instances.clone(),
+            conversions: Vec::new(),
+        }
+    }
+}
+
+/// Header of the first readable `.dcm` file in `dir`, read up to the pixel data.
+fn sample_header(dir: &Path) -> Option<DefaultDicomObject> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case("dcm"))
+        })
+        .collect();
+    files.sort();
+    files.iter().find_map(|path| {
+        OpenFileOptions::new()
+            .read_until(Tag(0x7FE0, 0x0010))
+            .open_file(path)
+            .ok()
+    })
+}
+
+/// Writes `<series_dir>/series.json` for a downloaded plan.
+pub fn write_series_sidecar(series_dir: &Path, plan: &SeriesDownloadPlan) -> Result<()> {
+    let sidecar = SeriesSidecar::new(plan, sample_header(series_dir).as_ref());
+    let path = series_dir.join(SERIES_SIDECAR);
+    write_atomic(&path, |w| Ok(serde_json::to_writer_pretty(w, &sidecar)?))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Records a conversion in `<series_dir>/series.json`, replacing an earlier one with the
+/// same output name. Folders without a sidecar are left alone.
+pub fn record_conversion(series_dir: &Path, conversion: SidecarConversion) -> Result<()> {
+    let path = series_dir.join(SERIES_SIDECAR);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let mut sidecar: SeriesSidecar = serde_json::from_str(&text)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    sidecar
+        .conversions
+        .retain(|c| c.output != conversion.output);
+    sidecar.conversions.push(conversion);
+    write_atomic(&path, |w| Ok(serde_json::to_writer_pretty(w, &sidecar)?))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sidecar_without_header_uses_plan() {
+        let plan = SeriesDownloadPlan {
+            source_series: "s1".into(),
+            series_folder: "DWI1000".into(),
+            series_type: "DWI1000".into(),
+            description: Some("ep2d_diff".into()),
+            series_number: Some("5".into()),
+            instances: vec!["i1".into(), "i2".into()],
+            analyzer_note: None,
+            non_image: false,
+            prefetched: None,
+        };
+        let dir = std::env::temp_dir().join(format!("sidecar_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        write_series_sidecar(&dir, &plan).unwrap();
+
+        let text = std::fs::read_to_string(dir.join(SERIES_SIDECAR)).unwrap();
+        let sidecar: SeriesSidecar = serde_json::from_str(&text).unwrap();
+        assert_eq!(sidecar.series_type, "DWI1000");
+        assert_eq!(sidecar.series_description.as_deref(), Some("ep2d_diff"));
+        assert_eq!(sidecar.instance_count, 2);
+        assert_eq!(sidecar.orthanc_instance_ids, ["i1", "i2"]);
+        assert_eq!(sidecar.echo_time, None);
+        assert_eq!(sidecar.series_instance_uid, None);
+        assert!(!text.contains("conversions"));
+
+        for command in ["dcm2niix -f DWI1000 a", "dcm2niix -f DWI1000 b"] {
+            let conversion = SidecarConversion {
+                output: "DWI1000".into(),
+                converter: "dcm2niix v1.0.20240202".into(),
+                command: command.into(),
+            };
+            record_conversion(&dir, conversion).unwrap();
+        }
+        let text = std::fs::read_to_string(dir.join(SERIES_SIDECAR)).unwrap();
+        let sidecar: SeriesSidecar = serde_json::from_str(&text).unwrap();
+        assert_eq!(sidecar.conversions.len(), 1);
+        assert_eq!(sidecar.conversions[0].command, "dcm2niix -f DWI1000 b");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
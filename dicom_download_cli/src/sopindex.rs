@@ -0,0 +1,80 @@
+//! Cross-run skip by SOPInstanceUID.
+//!
+//! Downloaded files are named after the Orthanc instance ID (or InstanceNumber), and a rerun
+//! skips instances whose file already exists. Orthanc IDs are derived from PatientID and the
+//! UIDs, so a re-populated or re-anonymized Orthanc can hand out new IDs for the very same
+//! instances, and every file would be fetched again beside the old copy. Before a series is
+//! downloaded, files in its folder that no longer match any planned name are indexed by
+//! their SOPInstanceUID (header only); planned instances with the same SOPInstanceUID take
+//! over those files under the new name instead of being downloaded.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::validate::local_sop_instance_uid;
+
+/// Names of the `.dcm` files directly in `dir`; empty when it does not exist.
+pub fn dicom_file_names(dir: &Path) -> HashSet<String> {
+    std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .filter(|name| name.to_ascii_lowercase().ends_with(".dcm"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// SOPInstanceUID → path for the given files of `dir`; unreadable files are left out.
+pub fn index_sop_uids<'a>(
+    dir: &Path,
+    names: impl IntoIterator<Item = &'a String>,
+) -> HashMap<String, PathBuf> {
+    names
+        .into_iter()
+        .map(|name| dir.join(name))
+        .filter_map(|path| local_sop_instance_uid(&path).map(|uid| (uid, path)))
+        .collect()
+}
+
+/// Renames to perform: each missing `(file name, SOPInstanceUID)` whose UID is in `index`
+/// takes over that file. Every indexed file is used at most once.
+pub fn adoptions(
+    dir: &Path,
+    missing: &[(String, String)],
+    index: &HashMap<String, PathBuf>,
+) -> Vec<(PathBuf, PathBuf)> {
+    let mut used = HashSet::new();
+    missing
+        .iter()
+        .filter_map(|(name, sop_uid)| {
+            let from = index.get(sop_uid)?;
+            used.insert(from).then(|| (from.clone(), dir.join(name)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adoptions_match_on_sop_uid() {
+        let dir = Path::new("/out/dicom/S1/T1_3");
+        let index = HashMap::from([
+            ("1.2.3.1".to_string(), dir.join("old-a.dcm")),
+            ("1.2.3.2".to_string(), dir.join("old-b.dcm")),
+        ]);
+        let missing = vec![
+            ("new-a.dcm".to_string(), "1.2.3.1".to_string()),
+            ("new-c.dcm".to_string(), "1.2.3.9".to_string()),
+            ("dup-a.dcm".to_string(), "1.2.3.1".to_string()),
+        ];
+        assert_eq!(
+            adoptions(dir, &missing, &index),
+            [(dir.join("old-a.dcm"), dir.join("new-a.dcm"))]
+        );
+        assert!(dicom_file_names(Path::new("/nonexistent/series")).is_empty());
+    }
+}
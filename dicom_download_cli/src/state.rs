@@ -0,0 +1,187 @@
+//! Persistent run state shared across invocations.
+//!
+//! A small JSON document stored next to the downloaded data that caches facts which are
+//! expensive to rediscover from Orthanc (e.g. the tags that determine study folder names)
+//! or from the analysis service (series types by SOPInstanceUID).
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+use crate::atomic::write_bytes_atomic;
+use crate::client::DicomStudyInfo;
+
+/// Directory (inside the output root) that holds CLI state files.
+pub const STATE_DIR: &str = ".dicom_download_cli";
+/// File name of the state document inside [`STATE_DIR`].
+pub const STATE_FILE: &str = "state.json";
+
+/// Serialized content of the state store.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateData {
+    /// Parsed study tags keyed by StudyInstanceUID.
+    #[serde(default)]
+    pub study_info: HashMap<String, DicomStudyInfo>,
+    /// Analysis-service series types keyed by SOPInstanceUID.
+    #[serde(default)]
+    pub analysis: HashMap<String, String>,
+}
+
+/// `<state>.corrupt`, where [`StateStore::open`] keeps a state file it could not parse.
+fn corrupt_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".corrupt");
+    path.with_file_name(name)
+}
+
+/// Thread-safe handle to the on-disk state document.
+pub struct StateStore {
+    path: PathBuf,
+    data: Mutex<StateData>,
+}
+
+impl StateStore {
+    /// Default state path for an output directory.
+    pub fn default_path(output_root: &Path) -> PathBuf {
+        output_root.join(STATE_DIR).join(STATE_FILE)
+    }
+
+    /// Loads the state file, starting empty when it does not exist yet.
+    ///
+    /// The state is only a cache, so a file that does not parse (e.g. truncated by a full
+    /// disk) is moved aside to `<state>.corrupt` with a warning and the run starts empty.
+    pub fn open(path: &Path) -> Result<Self> {
+        let data = if path.exists() {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read state file {}", path.display()))?;
+            match serde_json::from_str(&content) {
+                Ok(data) => data,
+                Err(e) => {
+                    let aside = corrupt_path(path);
+                    std::fs::rename(path, &aside).with_context(|| {
+                        format!("Failed to move corrupt state file {}", path.display())
+                    })?;
+                    warn!(
+                        "State file {} is corrupt ({}); moved it to {} and starting empty",
+                        path.display(),
+                        e,
+                        aside.display()
+                    );
+                    StateData::default()
+                }
+            }
+        } else {
+            StateData::default()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            data: Mutex::new(data),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the cached study tags for a StudyInstanceUID.
+    pub fn study_info(&self, study_uid: &str) -> Option<DicomStudyInfo> {
+        self.data.lock().ok()?.study_info.get(study_uid).cloned()
+    }
+
+    /// Caches study tags for a StudyInstanceUID (persisted on the next [`save`](Self::save)).
+    pub fn put_study_info(&self, study_uid: &str, info: DicomStudyInfo) {
+        if let Ok(mut data) = self.data.lock() {
+            data.study_info.insert(study_uid.to_string(), info);
+        }
+    }
+
+    /// Returns the cached analysis result for a SOPInstanceUID.
+    pub fn analysis_type(&self, sop_uid: &str) -> Option<String> {
+        self.data.lock().ok()?.analysis.get(sop_uid).cloned()
+    }
+
+    /// Caches an analysis result for a SOPInstanceUID (persisted on the next
+    /// [`save`](Self::save)).
+    pub fn put_analysis_type(&self, sop_uid: &str, series_type: &str) {
+        if let Ok(mut data) = self.data.lock() {
+            data.analysis
+                .insert(sop_uid.to_string(), series_type.to_string());
+        }
+    }
+
+    /// Writes the state to a temp file, fsyncs it, and renames it over the old one.
+    pub fn save(&self) -> Result<()> {
+        let json = {
+            let data = self
+                .data
+                .lock()
+                .map_err(|_| anyhow::anyhow!("State store lock poisoned"))?;
+            serde_json::to_string_pretty(&*data)?
+        };
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        write_bytes_atomic(&self.path, json.as_bytes())
+            .with_context(|| format!("Failed to write state file {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analysis_types_survive_reopen() {
+        let dir = std::env::temp_dir().join(format!("state_test_{}", std::process::id()));
+        let path = StateStore::default_path(&dir);
+
+        let store = StateStore::open(&path).unwrap();
+        assert_eq!(store.analysis_type("1.2.3"), None);
+        store.put_analysis_type("1.2.3", "T1");
+        store.save().unwrap();
+
+        let reopened = StateStore::open(&path).unwrap();
+        assert_eq!(reopened.analysis_type("1.2.3").as_deref(), Some("T1"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_state_is_moved_aside() {
+        let dir = std::env::temp_dir().join(format!("state_corrupt_{}", std::process::id()));
+        let path = StateStore::default_path(&dir);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        // 磁碟寫滿時可能只留下截斷的 JSON
+        std::fs::write(&path, r#"{"study_info": {"#).unwrap();
+
+        let store = StateStore::open(&path).unwrap();
+        assert_eq!(store.analysis_type("1.2.3"), None);
+        assert!(!path.exists());
+        let aside = path.with_file_name("state.json.corrupt");
+        assert_eq!(
+            std::fs::read_to_string(&aside).unwrap(),
+            r#"{"study_info": {"#
+        );
+
+        store.put_analysis_type("1.2.3", "T1");
+        store.save().unwrap();
+        assert_eq!(
+            StateStore::open(&path)
+                .unwrap()
+                .analysis_type("1.2.3")
+                .as_deref(),
+            Some("T1")
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_state_without_analysis_section_parses() {
+        let data: StateData = serde_json::from_str(r#"{"study_info": {}}"#).unwrap();
+        assert!(data.analysis.is_empty());
+    }
+}
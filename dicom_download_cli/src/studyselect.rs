@@ -0,0 +1,333 @@
+//! Choosing among several stored studies that share one accession number
+//! (`download --study-select`, `--study-date`).
+//!
+//! Orthanc can hold more than one study for an accession (re-sent exams, merged orders, a
+//! reused number). `all` (default) still downloads every one of them, but the result notes
+//! now say how many matched. `--study-date` keeps only studies whose StudyDate falls in a
+//! DICOM-style range (`20240101-20240630`, `20240101-`, `-20240630`, or a single day), and
+//! `newest` / `oldest` keep the latest or earliest remaining study by StudyDate and
+//! StudyTime. `interactive` lists the studies on the terminal and asks which to download; it
+//! needs a TTY and cannot be combined with `--tui`.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::NaiveDate;
+use std::io::{BufRead, Write};
+
+use crate::client::StudySummary;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StudySelect {
+    #[default]
+    All,
+    Newest,
+    Oldest,
+    Interactive,
+}
+
+impl std::str::FromStr for StudySelect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "all" => Ok(Self::All),
+            "newest" => Ok(Self::Newest),
+            "oldest" => Ok(Self::Oldest),
+            "interactive" => Ok(Self::Interactive),
+            other => Err(anyhow!(
+                "Invalid study selection '{}': expected all, newest, oldest, or interactive",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for StudySelect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::All => "all",
+            Self::Newest => "newest",
+            Self::Oldest => "oldest",
+            Self::Interactive => "interactive",
+        })
+    }
+}
+
+/// Inclusive StudyDate range; either end may be open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateRange {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+fn parse_dicom_date(s: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(s.trim(), "%Y%m%d").ok()
+}
+
+impl std::str::FromStr for DateRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let end = |part: &str| -> Result<Option<NaiveDate>> {
+            if part.trim().is_empty() {
+                return Ok(None);
+            }
+            parse_dicom_date(part)
+                .map(Some)
+                .ok_or_else(|| anyhow!("Invalid study date '{}': expected YYYYMMDD", part.trim()))
+        };
+        let (from, to) = match s.split_once('-') {
+            Some((from, to)) => (end(from)?, end(to)?),
+            None => {
+                let day = end(s)?;
+                (day, day)
+            }
+        };
+        match (from, to) {
+            (None, None) => bail!("Empty study date range '{}'", s),
+            (Some(f), Some(t)) if f > t => bail!("Study date range '{}' ends before it starts", s),
+            _ => Ok(Self { from, to }),
+        }
+    }
+}
+
+impl std::fmt::Display for DateRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let date = |d: Option<NaiveDate>| d.map(|d| d.format("%Y%m%d").to_string());
+        if self.from == self.to {
+            return f.write_str(&date(self.from).unwrap_or_default());
+        }
+        write!(
+            f,
+            "{}-{}",
+            date(self.from).unwrap_or_default(),
+            date(self.to).unwrap_or_default()
+        )
+    }
+}
+
+impl DateRange {
+    /// Whether a StudyDate lies in the range; studies without a readable date never do.
+    pub fn contains(&self, study_date: &str) -> bool {
+        parse_dicom_date(study_date)
+            .is_some_and(|d| self.from.is_none_or(|f| d >= f) && self.to.is_none_or(|t| d <= t))
+    }
+}
+
+/// `--study-select` and `--study-date` together.
+#[derive(Debug, Clone, Default)]
+pub struct StudySelection {
+    pub policy: StudySelect,
+    pub date_range: Option<DateRange>,
+}
+
+impl StudySelection {
+    /// Whether study tags must be fetched to choose among `found` studies.
+    pub fn needs_summaries(&self, found: usize) -> bool {
+        self.date_range.is_some() || (found > 1 && self.policy != StudySelect::All)
+    }
+
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(range) = &self.date_range {
+            parts.push(format!("--study-date {}", range));
+        }
+        if self.policy != StudySelect::All {
+            parts.push(format!("--study-select {}", self.policy));
+        }
+        parts.join(", ")
+    }
+
+    /// Applies the date range, then the policy; `choose` asks the user for `interactive` and
+    /// returns indexes into the studies it is given. Returns the kept study IDs, oldest
+    /// first, and a note for the result when more than one study matched.
+    pub fn select<F>(
+        &self,
+        accession: &str,
+        mut studies: Vec<StudySummary>,
+        choose: F,
+    ) -> Result<(Vec<String>, Option<String>)>
+    where
+        F: FnOnce(&str, &[StudySummary]) -> Result<Vec<usize>>,
+    {
+        let found = studies.len();
+        if let Some(range) = &self.date_range {
+            studies.retain(|s| range.contains(&s.study_date));
+        }
+        studies.sort_by(|a, b| {
+            (a.study_date.as_str(), a.study_time.as_str())
+                .cmp(&(b.study_date.as_str(), b.study_time.as_str()))
+        });
+        let kept: Vec<StudySummary> = match self.policy {
+            _ if studies.len() <= 1 => studies,
+            StudySelect::All => studies,
+            StudySelect::Newest => studies.pop().into_iter().collect(),
+            StudySelect::Oldest => studies.into_iter().take(1).collect(),
+            StudySelect::Interactive => {
+                let picks = choose(accession, &studies)?;
+                studies
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| picks.contains(i))
+                    .map(|(_, s)| s)
+                    .collect()
+            }
+        };
+
+        let note = if kept.len() < found {
+            let dates: Vec<&str> = kept.iter().map(|s| s.study_date.as_str()).collect();
+            Some(format!(
+                "{} of {} studies selected ({}){}",
+                kept.len(),
+                found,
+                self.describe(),
+                if dates.is_empty() {
+                    String::new()
+                } else {
+                    format!(": StudyDate {}", dates.join(", "))
+                }
+            ))
+        } else if found > 1 {
+            Some(format!(
+                "{} studies match this accession; all downloaded (see --study-select)",
+                found
+            ))
+        } else {
+            None
+        };
+        Ok((kept.into_iter().map(|s| s.id).collect(), note))
+    }
+}
+
+/// Parses the answer to the interactive prompt: empty or `all` for every study, otherwise
+/// 1-based numbers separated by commas or spaces.
+fn parse_choice(line: &str, count: usize) -> Result<Vec<usize>> {
+    let line = line.trim();
+    if line.is_empty() || line.eq_ignore_ascii_case("all") {
+        return Ok((0..count).collect());
+    }
+    line.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| match part.parse::<usize>() {
+            Ok(n) if (1..=count).contains(&n) => Ok(n - 1),
+            _ => Err(anyhow!(
+                "'{}' is not a study number between 1 and {}",
+                part,
+                count
+            )),
+        })
+        .collect()
+}
+
+/// Lists `studies` on stderr and reads the choice from stdin, asking again on invalid input.
+pub fn prompt_study_choice(accession: &str, studies: &[StudySummary]) -> Result<Vec<usize>> {
+    let mut err = std::io::stderr().lock();
+    writeln!(
+        err,
+        "Accession {} matches {} studies:",
+        accession,
+        studies.len()
+    )?;
+    for (i, s) in studies.iter().enumerate() {
+        writeln!(
+            err,
+            "  [{}] {} {}  {} series  {}",
+            i + 1,
+            if s.study_date.is_empty() {
+                "--------"
+            } else {
+                &s.study_date
+            },
+            s.study_time.get(..6).unwrap_or(&s.study_time),
+            s.series_count,
+            s.description.as_deref().unwrap_or_default()
+        )?;
+    }
+    let stdin = std::io::stdin();
+    loop {
+        write!(err, "Download which? (e.g. 1,3; empty for all): ")?;
+        err.flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            bail!("No study selected for {} (stdin closed)", accession);
+        }
+        match parse_choice(&line, studies.len()) {
+            Ok(picks) => return Ok(picks),
+            Err(e) => writeln!(err, "{}", e)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn study(id: &str, date: &str, time: &str) -> StudySummary {
+        StudySummary {
+            id: id.into(),
+            study_date: date.into(),
+            study_time: time.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_study_selection_by_date_and_policy() {
+        let studies = vec![
+            study("b", "20240301", "080000"),
+            study("a", "20240301", "070000"),
+            study("c", "20230115", "120000"),
+            study("d", "", ""),
+        ];
+        let never = |_: &str, _: &[StudySummary]| -> Result<Vec<usize>> { unreachable!() };
+
+        let all = StudySelection::default();
+        let (ids, note) = all.select("A1", studies.clone(), never).unwrap();
+        assert_eq!(ids, ["d", "c", "a", "b"]);
+        assert!(note.unwrap().starts_with("4 studies match"));
+
+        let newest = StudySelection {
+            policy: "newest".parse().unwrap(),
+            date_range: None,
+        };
+        let (ids, note) = newest.select("A1", studies.clone(), never).unwrap();
+        assert_eq!(ids, ["b"]);
+        assert_eq!(
+            note.as_deref(),
+            Some("1 of 4 studies selected (--study-select newest): StudyDate 20240301")
+        );
+
+        let ranged = StudySelection {
+            policy: StudySelect::Oldest,
+            date_range: Some("20240101-".parse().unwrap()),
+        };
+        let (ids, _) = ranged.select("A1", studies.clone(), never).unwrap();
+        assert_eq!(ids, ["a"]);
+
+        let interactive = StudySelection {
+            policy: StudySelect::Interactive,
+            date_range: Some("-20231231".parse().unwrap()),
+        };
+        // 只剩一個 study 時不詢問
+        let (ids, _) = interactive.select("A1", studies.clone(), never).unwrap();
+        assert_eq!(ids, ["c"]);
+
+        let picked = StudySelection {
+            policy: StudySelect::Interactive,
+            date_range: None,
+        };
+        let (ids, _) = picked
+            .select("A1", studies, |_, s| parse_choice("2, 4", s.len()))
+            .unwrap();
+        assert_eq!(ids, ["c", "b"]);
+
+        assert_eq!(
+            "20240101".parse::<DateRange>().unwrap().to_string(),
+            "20240101"
+        );
+        assert!("20240231-".parse::<DateRange>().is_err());
+        assert!("20240301-20240101".parse::<DateRange>().is_err());
+        assert!("-".parse::<DateRange>().is_err());
+        assert!(parse_choice("5", 4).is_err());
+        assert_eq!(parse_choice("", 2).unwrap(), [0, 1]);
+    }
+}
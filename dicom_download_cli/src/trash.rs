@@ -0,0 +1,201 @@
+//! Trash folder for DICOMs removed after conversion (`[conversion] trash_dir`).
+//!
+//! Instead of deleting a converted series' `.dcm` files, `download` moves them to
+//! `<trash_dir>/<run id>/<study>/<series>/`, so a bad conversion noticed later can still be
+//! redone from the originals. Run folders older than `trash_retention_days` are removed at
+//! the start of the next run; only folders named like a run ID are, so other folders in
+//! `trash_dir` are never touched. Every move and removal is written to the audit log.
+//!
+//! `check --quarantine` (`[check] quarantine_dir`) uses the same run folders for files the
+//! checker would delete, mirroring their path under `dicom/`; `quarantine purge` removes
+//! expired runs on demand.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::audit::{AuditLog, AuditOperation};
+use crate::reportfile::is_run_id;
+
+/// Default days a run folder stays in the trash.
+pub const DEFAULT_TRASH_RETENTION_DAYS: u64 = 30;
+
+/// Audit rule of moves and deletions after conversion.
+const RULE: &str = "delete_dicom_after_conversion";
+
+/// The trash of one run.
+#[derive(Debug, Clone)]
+pub struct DicomTrash {
+    root: PathBuf,
+    run_dir: PathBuf,
+    retention: Duration,
+    rule: &'static str,
+}
+
+impl DicomTrash {
+    /// Trash under `root`; this run's files go to `root/<run_id>`.
+    pub fn new(root: &Path, run_id: &str, retention_days: u64) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            run_dir: root.join(run_id),
+            retention: Duration::from_secs(retention_days * 24 * 60 * 60),
+            rule: RULE,
+        }
+    }
+
+    /// Audit rule recorded for expired-run removals (and moves) of this trash.
+    pub fn with_rule(mut self, rule: &'static str) -> Self {
+        self.rule = rule;
+        self
+    }
+
+    pub fn run_dir(&self) -> &Path {
+        &self.run_dir
+    }
+
+    /// Removes run folders whose modification time is older than the retention period.
+    /// Folders not named like a run ID ([`is_run_id`]) were not created by the trash and are
+    /// kept. Returns how many were removed; a missing trash folder is not an error.
+    pub fn purge_expired(&self, audit: Option<&AuditLog>) -> Result<usize> {
+        let entries = match std::fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.root.display()))
+            }
+        };
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if !is_run_id(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let expired = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|age| age > self.retention);
+            if !expired || !path.is_dir() || path == self.run_dir {
+                continue;
+            }
+            let outcome = std::fs::remove_dir_all(&path);
+            if let Some(audit) = audit {
+                audit.record_outcome(
+                    AuditOperation::RemoveDir,
+                    &path,
+                    None,
+                    self.rule,
+                    &outcome,
+                )?;
+            }
+            match outcome {
+                Ok(()) => removed += 1,
+                Err(e) => warn!("Failed to remove {}: {}", path.display(), e),
+            }
+        }
+        if removed > 0 {
+            info!(
+                "Removed {} expired run folder(s) from {}",
+                removed,
+                self.root.display()
+            );
+        }
+        Ok(removed)
+    }
+
+    /// Moves the `.dcm` files of `series_dir` to `<run_dir>/<relative>`; files on another
+    /// filesystem are copied, then deleted. Each move is written to the audit log when one
+    /// is given; if the log cannot be written, moving stops with an error.
+    pub async fn move_dicom_files(
+        &self,
+        series_dir: &Path,
+        relative: &Path,
+        audit: Option<&AuditLog>,
+    ) -> Result<usize> {
+        let target_dir = self.run_dir.join(relative);
+        tokio::fs::create_dir_all(&target_dir)
+            .await
+            .with_context(|| format!("Failed to create {}", target_dir.display()))?;
+        let mut moved = 0;
+        let mut entries = tokio::fs::read_dir(series_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let is_dicom = path
+                .extension()
+                .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case("dcm"));
+            if !is_dicom {
+                continue;
+            }
+            let target = target_dir.join(entry.file_name());
+            let outcome = move_file(&path, &target).await;
+            if let Some(audit) = audit {
+                audit.record_outcome(
+                    AuditOperation::Move,
+                    &path,
+                    Some(&target),
+                    self.rule,
+                    &outcome,
+                )?;
+            }
+            match outcome {
+                Ok(()) => moved += 1,
+                Err(e) => warn!("Failed to move {}: {}", path.display(), e),
+            }
+        }
+        Ok(moved)
+    }
+}
+
+/// Renames `from` to `to`, falling back to copy and delete across filesystems.
+pub(crate) async fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if tokio::fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+    tokio::fs::copy(from, to).await?;
+    tokio::fs::remove_file(from).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_move_dicom_files_and_purge() {
+        let dir = std::env::temp_dir().join(format!("trash_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let series = dir.join("dicom/S1/T1");
+        std::fs::create_dir_all(&series).unwrap();
+        std::fs::write(series.join("a.dcm"), b"a").unwrap();
+        std::fs::write(series.join("series.json"), b"{}").unwrap();
+        let stale = dir.join("trash/20200101T000000Z-1");
+        std::fs::create_dir_all(&stale).unwrap();
+        // 非本程式建立的資料夾即使過期也保留
+        let foreign = dir.join("trash/scratch");
+        std::fs::create_dir_all(&foreign).unwrap();
+
+        let audit = AuditLog::new(&dir.join("audit.jsonl"), "download");
+        let trash = DicomTrash::new(&dir.join("trash"), "run", 0);
+        let moved = trash
+            .move_dicom_files(&series, Path::new("S1/T1"), Some(&audit))
+            .await
+            .unwrap();
+        assert_eq!(moved, 1);
+        assert!(!series.join("a.dcm").exists());
+        assert!(series.join("series.json").exists());
+        assert!(dir.join("trash/run/S1/T1/a.dcm").exists());
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(trash.purge_expired(Some(&audit)).unwrap(), 1);
+        assert!(!stale.exists());
+        assert!(foreign.exists());
+        assert!(trash.run_dir().exists());
+
+        let entries = crate::audit::read_audit_log(audit.path()).unwrap();
+        assert_eq!(entries[0].operation, AuditOperation::Move);
+        assert_eq!(entries[1].operation, AuditOperation::RemoveDir);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,422 @@
+//! `--tui`: full-screen dashboard for large `remote`/`download` batches.
+//!
+//! With thousands of accessions the per-accession spinners scroll past faster than they
+//! can be read. The dashboard replaces them with an aggregate progress gauge, the
+//! accessions in flight, a live list of failures, the throughput of the most recently
+//! finished series, and the latest log lines (console logging is captured while it is
+//! shown). `p` or space pauses scheduling: accessions already running finish, but no new
+//! one starts until scheduling is resumed. Ctrl-C restores the terminal and aborts the run.
+
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Cell, Gauge, List, ListItem, Paragraph, Row, Table};
+use ratatui::Frame;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::estimate::{format_bytes, format_duration};
+use crate::logging::{capture_console, CaptureGuard};
+use crate::processor::{throughput_bps, ProcessResult};
+
+/// Rows kept for the failure, series, and log panes.
+const KEEP_ROWS: usize = 200;
+const REDRAW: Duration = Duration::from_millis(250);
+
+struct Failure {
+    accession: String,
+    status: String,
+    reason: String,
+}
+
+struct SeriesRate {
+    name: String,
+    bytes: u64,
+    duration_ms: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    finished: usize,
+    success: usize,
+    failed: usize,
+    bytes: u64,
+}
+
+struct State {
+    running: Vec<(String, Instant)>,
+    counters: Counters,
+    failures: VecDeque<Failure>,
+    series: VecDeque<SeriesRate>,
+    log: VecDeque<String>,
+}
+
+/// Live batch state shared by the workers and the drawing thread.
+pub struct Dashboard {
+    title: String,
+    total: usize,
+    started: Instant,
+    state: Mutex<State>,
+    paused: watch::Sender<bool>,
+}
+
+fn push_capped<T>(rows: &mut VecDeque<T>, row: T) {
+    rows.push_front(row);
+    rows.truncate(KEEP_ROWS);
+}
+
+/// Drops ANSI colour sequences from captured log lines.
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // CSI: ESC [ ... final byte in @..~
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) && c != '[' {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+impl Dashboard {
+    pub fn new(title: &str, total: usize) -> Arc<Self> {
+        let (paused, _) = watch::channel(false);
+        Arc::new(Self {
+            title: title.to_string(),
+            total,
+            started: Instant::now(),
+            state: Mutex::new(State {
+                running: Vec::new(),
+                counters: Counters::default(),
+                failures: VecDeque::new(),
+                series: VecDeque::new(),
+                log: VecDeque::new(),
+            }),
+            paused,
+        })
+    }
+
+    fn with_state(&self, f: impl FnOnce(&mut State)) {
+        if let Ok(mut state) = self.state.lock() {
+            f(&mut state);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    pub fn toggle_pause(&self) {
+        self.paused.send_modify(|p| *p = !*p);
+    }
+
+    /// Returns once scheduling is not paused; call before starting an accession.
+    pub async fn wait_if_paused(&self) {
+        let mut rx = self.paused.subscribe();
+        let _ = rx.wait_for(|paused| !*paused).await;
+    }
+
+    pub fn accession_started(&self, acc: &str) {
+        self.with_state(|s| s.running.push((acc.to_string(), Instant::now())));
+    }
+
+    pub fn accession_finished(&self, res: &ProcessResult) {
+        self.with_state(|s| {
+            s.running.retain(|(acc, _)| acc != &res.accession);
+            s.counters.finished += 1;
+            s.counters.bytes += res.bytes_downloaded;
+            if res.status == "Success" {
+                s.counters.success += 1;
+            } else {
+                s.counters.failed += 1;
+                push_capped(
+                    &mut s.failures,
+                    Failure {
+                        accession: res.accession.clone(),
+                        status: res.status.clone(),
+                        reason: res.reason.join("; "),
+                    },
+                );
+            }
+            for series in &res.series {
+                push_capped(
+                    &mut s.series,
+                    SeriesRate {
+                        name: format!("{}/{}", res.accession, series.series_folder),
+                        bytes: series.bytes,
+                        duration_ms: series.duration_ms,
+                    },
+                );
+            }
+        });
+    }
+
+    pub fn log_line(&self, line: &str) {
+        let line = strip_ansi(line);
+        self.with_state(|s| push_capped(&mut s.log, line));
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let Ok(s) = self.state.lock() else {
+            return;
+        };
+        let [top, middle, running, log, help] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(6),
+            Constraint::Length(6),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [failures, series] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(middle);
+
+        let elapsed = self.started.elapsed();
+        let c = &s.counters;
+        let mut summary = format!(
+            "{}/{} accessions · {} running · {} ok · {} failed · {} · {}/s",
+            c.finished,
+            self.total,
+            s.running.len(),
+            c.success,
+            c.failed,
+            format_bytes(c.bytes),
+            format_bytes(throughput_bps(c.bytes, elapsed) as u64)
+        );
+        if c.finished > 0 && c.finished < self.total {
+            let eta = elapsed.as_secs_f64() / c.finished as f64 * (self.total - c.finished) as f64;
+            summary.push_str(&format!(" · ETA {}", format_duration(eta)));
+        }
+        let title = if self.is_paused() {
+            format!(" {} — PAUSED (no new accessions) ", self.title)
+        } else {
+            format!(" {} ", self.title)
+        };
+        let ratio = if self.total == 0 {
+            1.0
+        } else {
+            c.finished as f64 / self.total as f64
+        };
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .gauge_style(Style::default().fg(if c.failed > 0 {
+                    Color::Yellow
+                } else {
+                    Color::Green
+                }))
+                .ratio(ratio.clamp(0.0, 1.0))
+                .label(summary),
+            top,
+        );
+
+        let items: Vec<ListItem> = s
+            .failures
+            .iter()
+            .map(|f| {
+                ListItem::new(Line::from(format!(
+                    "{} [{}] {}",
+                    f.accession, f.status, f.reason
+                )))
+            })
+            .collect();
+        frame.render_widget(
+            List::new(items)
+                .style(Style::default().fg(Color::Red))
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(format!(" Failures ({}) ", c.failed)),
+                ),
+            failures,
+        );
+
+        let rows: Vec<Row> = s
+            .series
+            .iter()
+            .map(|r| {
+                Row::new(vec![
+                    Cell::from(r.name.clone()),
+                    Cell::from(format_bytes(r.bytes)),
+                    Cell::from(format_duration(r.duration_ms as f64 / 1000.0)),
+                    Cell::from(format!(
+                        "{}/s",
+                        format_bytes(
+                            throughput_bps(r.bytes, Duration::from_millis(r.duration_ms)) as u64
+                        )
+                    )),
+                ])
+            })
+            .collect();
+        frame.render_widget(
+            Table::new(
+                rows,
+                [
+                    Constraint::Min(20),
+                    Constraint::Length(11),
+                    Constraint::Length(9),
+                    Constraint::Length(13),
+                ],
+            )
+            .header(
+                Row::new(vec!["Series", "Size", "Time", "Rate"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(" Series throughput "),
+            ),
+            series,
+        );
+
+        let in_flight: Vec<ListItem> = s
+            .running
+            .iter()
+            .map(|(acc, since)| {
+                ListItem::new(format!(
+                    "{} ({})",
+                    acc,
+                    format_duration(since.elapsed().as_secs_f64())
+                ))
+            })
+            .collect();
+        frame.render_widget(
+            List::new(in_flight).block(Block::default().borders(Borders::ALL).title(" Running ")),
+            running,
+        );
+
+        let lines: Vec<ListItem> = s
+            .log
+            .iter()
+            .take(log.height.saturating_sub(2) as usize)
+            .rev()
+            .map(|l| ListItem::new(l.as_str()))
+            .collect();
+        frame.render_widget(
+            List::new(lines).block(Block::default().borders(Borders::ALL).title(" Log ")),
+            log,
+        );
+        frame.render_widget(
+            Paragraph::new("p/space: pause/resume scheduling · Ctrl-C: abort")
+                .style(Style::default().fg(Color::DarkGray)),
+            help,
+        );
+    }
+}
+
+/// The running dashboard; [`TuiHandle::finish`] restores the terminal.
+pub struct TuiHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    _capture: CaptureGuard,
+}
+
+/// Takes over the terminal and draws `dashboard` until the handle is finished.
+pub fn start(dashboard: Arc<Dashboard>) -> Result<TuiHandle> {
+    let sink = dashboard.clone();
+    let capture = capture_console(Arc::new(move |line: &str| sink.log_line(line)));
+    let mut terminal = ratatui::try_init()?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let thread = std::thread::spawn(move || {
+        while !stopped.load(Ordering::SeqCst) {
+            let _ = terminal.draw(|frame| dashboard.render(frame));
+            if !event::poll(REDRAW).unwrap_or(false) {
+                continue;
+            }
+            let Ok(Event::Key(key)) = event::read() else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    // raw mode 下 Ctrl-C 不會送出 SIGINT，自行還原終端機後結束
+                    ratatui::restore();
+                    std::process::exit(130);
+                }
+                KeyCode::Char('p') | KeyCode::Char(' ') => dashboard.toggle_pause(),
+                _ => {}
+            }
+        }
+        let _ = terminal.draw(|frame| dashboard.render(frame));
+        ratatui::restore();
+    });
+    Ok(TuiHandle {
+        stop,
+        thread: Some(thread),
+        _capture: capture,
+    })
+}
+
+impl TuiHandle {
+    /// Stops drawing and restores the terminal (also done on drop).
+    pub fn finish(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for TuiHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    #[test]
+    fn test_dashboard_renders_failures_and_pause() {
+        let dashboard = Dashboard::new("download", 3);
+        dashboard.accession_started("A1");
+        dashboard.accession_started("A2");
+        dashboard.accession_finished(&ProcessResult {
+            accession: "A1".into(),
+            status: "Failed".into(),
+            reason: vec!["No studies found".into()],
+            ..Default::default()
+        });
+        dashboard.toggle_pause();
+        dashboard.log_line("\u{1b}[33m WARN\u{1b}[0m disk almost full");
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| dashboard.render(frame)).unwrap();
+        let text: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(text.contains("PAUSED"));
+        assert!(text.contains("1/3 accessions · 1 running · 0 ok · 1 failed"));
+        assert!(text.contains("A1 [Failed] No studies found"));
+        assert!(text.contains("WARN disk almost full"));
+        assert!(dashboard.is_paused());
+    }
+}
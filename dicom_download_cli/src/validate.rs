@@ -0,0 +1,130 @@
+//! Post-write validation of downloaded instances (`download --validate`).
+//!
+//! Each file is re-opened with `dicom-object` right after it is written, so truncation by
+//! flaky storage or a proxy shows up as a parse error instead of a broken series days later.
+//! The identifying UIDs must be present, and Orthanc's instance ID is recomputed from them
+//! (SHA-1 of `PatientID|StudyInstanceUID|SeriesInstanceUID|SOPInstanceUID`) and compared
+//! with the ID the file was requested as, which confirms the SOPInstanceUID without an
+//! extra HTTP round trip.
+
+use dicom_object::{open_file, DefaultDicomObject, OpenFileOptions, Tag};
+use sha1::{Digest, Sha1};
+use std::path::Path;
+
+/// Tags every stored instance must carry (PatientID may legitimately be empty).
+pub const REQUIRED_TAGS: [&str; 4] = [
+    "SOPClassUID",
+    "SOPInstanceUID",
+    "StudyInstanceUID",
+    "SeriesInstanceUID",
+];
+
+/// Orthanc's public instance ID: SHA-1 of the pipe-joined UIDs, as five dash-separated
+/// groups of eight hex digits.
+pub fn orthanc_instance_id(
+    patient_id: &str,
+    study_uid: &str,
+    series_uid: &str,
+    sop_uid: &str,
+) -> String {
+    let digest = Sha1::digest(format!(
+        "{}|{}|{}|{}",
+        patient_id, study_uid, series_uid, sop_uid
+    ));
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    hex.as_bytes()
+        .chunks(8)
+        .map(|c| String::from_utf8_lossy(c).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Orthanc instance ID recomputed from a parsed file's UIDs, with its SOPInstanceUID; the
+/// error says what is missing.
+fn object_instance_id(obj: &DefaultDicomObject) -> Result<(String, String), String> {
+    let text = |name: &str| {
+        obj.element_by_name(name)
+            .ok()
+            .and_then(|e| e.to_str().ok().map(|s| s.trim().to_string()))
+    };
+    let mut uids = Vec::with_capacity(REQUIRED_TAGS.len());
+    for name in REQUIRED_TAGS {
+        match text(name).filter(|v| !v.is_empty()) {
+            Some(v) => uids.push(v),
+            None => return Err(format!("missing or unparsable {}", name)),
+        }
+    }
+    let patient_id = text("PatientID").unwrap_or_default();
+    let id = orthanc_instance_id(&patient_id, &uids[2], &uids[3], &uids[1]);
+    Ok((id, uids.swap_remove(1)))
+}
+
+/// Parses `path` and checks it is the instance `expected_id`; the error says what is wrong.
+pub fn validate_instance(path: &Path, expected_id: &str) -> Result<(), String> {
+    let obj = open_file(path).map_err(|e| format!("unreadable: {}", e))?;
+    let (actual, sop_uid) = object_instance_id(&obj)?;
+    if !actual.eq_ignore_ascii_case(expected_id) {
+        return Err(format!(
+            "SOPInstanceUID {} does not belong to Orthanc instance {}",
+            sop_uid, expected_id
+        ));
+    }
+    Ok(())
+}
+
+/// True for names shaped like an Orthanc ID (five dash-separated groups of eight hex digits).
+pub fn is_orthanc_id(name: &str) -> bool {
+    let groups: Vec<&str> = name.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .all(|g| g.len() == 8 && g.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Orthanc instance ID of a stored `.dcm` file: the file stem under the default naming,
+/// otherwise (e.g. `instance_naming = "instance_number"`) recomputed from the file's UIDs.
+pub fn local_instance_id(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_string_lossy().to_string();
+    if is_orthanc_id(&stem) {
+        return Some(stem);
+    }
+    // 只讀到 PixelData 之前，掃描大量檔案時不必載入影像
+    let obj = OpenFileOptions::new()
+        .read_until(Tag(0x7FE0, 0x0010))
+        .open_file(path)
+        .ok()?;
+    object_instance_id(&obj).ok().map(|(id, _)| id)
+}
+
+/// SOPInstanceUID of a stored file, read from its header only.
+pub fn local_sop_instance_uid(path: &Path) -> Option<String> {
+    let obj = OpenFileOptions::new()
+        .read_until(Tag(0x7FE0, 0x0010))
+        .open_file(path)
+        .ok()?;
+    obj.element_by_name("SOPInstanceUID")
+        .ok()
+        .and_then(|e| e.to_str().ok().map(|s| s.trim().to_string()))
+        .filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orthanc_instance_id_format() {
+        let id = orthanc_instance_id("P1", "1.2.3", "1.2.3.4", "1.2.3.4.5");
+        assert!(is_orthanc_id(&id));
+        assert!(!is_orthanc_id("0007"));
+        assert_ne!(
+            id,
+            orthanc_instance_id("P1", "1.2.3", "1.2.3.4", "1.2.3.4.6")
+        );
+        assert_eq!(
+            orthanc_instance_id("", "", "", ""),
+            // SHA-1 of "|||"
+            "98c4b7d3-7a4c63c3-f69f7a0f-794fb8a9-187549ef"
+        );
+    }
+}
@@ -0,0 +1,319 @@
+//! `verify -i <accessions>`: compares the local download tree with what Orthanc holds.
+//!
+//! For every accession the expected series and instance IDs are re-queried from Orthanc and
+//! matched against the `.dcm` files on disk, wherever they ended up (per-instance
+//! groups split one Orthanc series across several folders). Each series gets a row with its
+//! expected/found counts and the missing instance IDs; `.dcm` files in the accession's study
+//! folders that belong to no expected series are reported as extra.
+
+use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::write_atomic;
+use crate::client::OrthancClient;
+use crate::validate::local_instance_id;
+
+/// Every local `.dcm` file keyed by Orthanc instance ID (its file stem, or the ID recomputed
+/// from its UIDs when files are named by InstanceNumber), with the folder relative to the
+/// scanned root.
+#[derive(Debug, Default)]
+pub struct LocalIndex {
+    pub instances: HashMap<String, String>,
+    /// Folder → instance IDs stored in it.
+    pub folders: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl LocalIndex {
+    pub fn scan(root: &Path) -> Result<Self> {
+        let mut index = LocalIndex::default();
+        let mut stack = vec![root.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    stack.push(path);
+                    continue;
+                }
+                let is_dcm = path
+                    .extension()
+                    .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case("dcm"));
+                if !is_dcm {
+                    continue;
+                }
+                let folder = relative_folder(root, &dir);
+                let id = local_instance_id(&path).unwrap_or_else(|| {
+                    path.file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string()
+                });
+                index
+                    .folders
+                    .entry(folder.clone())
+                    .or_default()
+                    .insert(id.clone());
+                index.instances.insert(id, folder);
+            }
+        }
+        Ok(index)
+    }
+}
+
+fn relative_folder(root: &Path, dir: &Path) -> String {
+    dir.strip_prefix(root)
+        .unwrap_or(dir)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// One line of the content verification report.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ContentRow {
+    pub accession: String,
+    /// `OK`, `Missing`, `NotDownloaded`, `Extra`, `NotInOrthanc`, or `Error`.
+    pub status: String,
+    /// Orthanc series ID; empty for extra-file and accession-level rows.
+    pub series_id: String,
+    pub description: String,
+    /// Local folders holding this series' files.
+    pub folders: Vec<String>,
+    pub expected: usize,
+    pub found: usize,
+    pub missing: Vec<String>,
+    /// `.dcm` files (instance IDs) not belonging to any expected series.
+    pub extra: Vec<String>,
+    pub detail: String,
+}
+
+/// Builds the row for one Orthanc series from its expected instance IDs.
+pub fn series_row(
+    accession: &str,
+    series_id: &str,
+    description: &str,
+    expected: &[String],
+    local: &LocalIndex,
+) -> ContentRow {
+    let mut folders = BTreeSet::new();
+    let mut missing = Vec::new();
+    for id in expected {
+        match local.instances.get(id) {
+            Some(folder) => {
+                folders.insert(folder.clone());
+            }
+            None => missing.push(id.clone()),
+        }
+    }
+    let found = expected.len() - missing.len();
+    let status = if missing.is_empty() {
+        "OK"
+    } else if found == 0 {
+        "NotDownloaded"
+    } else {
+        "Missing"
+    };
+    ContentRow {
+        accession: accession.to_string(),
+        status: status.into(),
+        series_id: series_id.to_string(),
+        description: description.to_string(),
+        folders: folders.into_iter().collect(),
+        expected: expected.len(),
+        found,
+        missing,
+        ..Default::default()
+    }
+}
+
+/// Rows for local files in the accession's study folders that Orthanc does not list.
+pub fn extra_rows(
+    accession: &str,
+    rows: &[ContentRow],
+    expected: &BTreeSet<String>,
+    local: &LocalIndex,
+) -> Vec<ContentRow> {
+    // Study folders = parents of every folder that holds an expected instance
+    let studies: BTreeSet<&str> = rows
+        .iter()
+        .flat_map(|r| &r.folders)
+        .map(|f| f.rsplit_once('/').map(|(study, _)| study).unwrap_or(""))
+        .collect();
+    local
+        .folders
+        .iter()
+        .filter(|(folder, _)| {
+            let study = folder.rsplit_once('/').map(|(s, _)| s).unwrap_or("");
+            studies.contains(study)
+        })
+        .filter_map(|(folder, ids)| {
+            let extra: Vec<String> = ids.difference(expected).cloned().collect();
+            (!extra.is_empty()).then(|| ContentRow {
+                accession: accession.to_string(),
+                status: "Extra".into(),
+                folders: vec![folder.clone()],
+                found: extra.len(),
+                extra,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+async fn verify_accession(
+    client: &OrthancClient,
+    accession: &str,
+    local: &LocalIndex,
+) -> Result<Vec<ContentRow>> {
+    let study_ids = client.find_study_ids_by_accession(accession).await?;
+    if study_ids.is_empty() {
+        return Ok(vec![ContentRow {
+            accession: accession.to_string(),
+            status: "NotInOrthanc".into(),
+            detail: "no study with this accession on Orthanc".into(),
+            ..Default::default()
+        }]);
+    }
+    let mut rows = Vec::new();
+    let mut expected_all = BTreeSet::new();
+    for study_id in &study_ids {
+        for (series_id, meta) in client.list_series_meta(study_id).await? {
+            expected_all.extend(meta.instances.iter().cloned());
+            rows.push(series_row(
+                accession,
+                &series_id,
+                meta.description.as_deref().unwrap_or_default(),
+                &meta.instances,
+                local,
+            ));
+        }
+    }
+    let extras = extra_rows(accession, &rows, &expected_all, local);
+    rows.extend(extras);
+    Ok(rows)
+}
+
+/// Verifies every accession against the tree at `root`; query errors become `Error` rows.
+pub async fn verify_contents(
+    client: &OrthancClient,
+    root: &Path,
+    accessions: Vec<String>,
+    concurrency: usize,
+) -> Result<Vec<ContentRow>> {
+    let root = root.to_path_buf();
+    let local = tokio::task::spawn_blocking(move || LocalIndex::scan(&root)).await??;
+    let local = &local;
+    let mut results: Vec<(usize, Vec<ContentRow>)> =
+        stream::iter(accessions.into_iter().enumerate())
+            .map(|(i, acc)| async move {
+                let rows = verify_accession(client, &acc, local)
+                    .await
+                    .unwrap_or_else(|e| {
+                        vec![ContentRow {
+                            accession: acc.clone(),
+                            status: "Error".into(),
+                            detail: format!("{:#}", e),
+                            ..Default::default()
+                        }]
+                    });
+                (i, rows)
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await;
+    results.sort_by_key(|(i, _)| *i);
+    Ok(results.into_iter().flat_map(|(_, rows)| rows).collect())
+}
+
+/// Writes the rows as CSV (ID lists joined with `;`) and JSON.
+pub fn write_content_reports(csv_path: &Path, json_path: &Path, rows: &[ContentRow]) -> Result<()> {
+    write_atomic(csv_path, |w| {
+        let mut wtr = csv::Writer::from_writer(w);
+        wtr.write_record([
+            "AccessionNumber",
+            "Status",
+            "OrthancSeries",
+            "Description",
+            "Folders",
+            "Expected",
+            "Found",
+            "MissingCount",
+            "ExtraCount",
+            "Missing",
+            "Extra",
+            "Detail",
+        ])?;
+        for r in rows {
+            wtr.write_record([
+                r.accession.as_str(),
+                &r.status,
+                &r.series_id,
+                &r.description,
+                &r.folders.join(";"),
+                &r.expected.to_string(),
+                &r.found.to_string(),
+                &r.missing.len().to_string(),
+                &r.extra.len().to_string(),
+                &r.missing.join(";"),
+                &r.extra.join(";"),
+                &r.detail,
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    })?;
+    write_atomic(json_path, |w| Ok(serde_json::to_writer_pretty(w, rows)?))
+}
+
+/// Default report paths when `--report-csv/--report-json` are not given.
+pub fn default_report_paths() -> (PathBuf, PathBuf) {
+    (
+        PathBuf::from("verify_report.csv"),
+        PathBuf::from("verify_report.json"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(files: &[(&str, &str)]) -> LocalIndex {
+        let mut index = LocalIndex::default();
+        for (folder, id) in files {
+            index.instances.insert(id.to_string(), folder.to_string());
+            index
+                .folders
+                .entry(folder.to_string())
+                .or_default()
+                .insert(id.to_string());
+        }
+        index
+    }
+
+    #[test]
+    fn test_series_row_and_extras() {
+        let local = index(&[
+            ("S1/DWI0", "a"),
+            ("S1/DWI1000", "b"),
+            ("S1/T1", "c"),
+            ("S1/T1", "stray"),
+            ("S2/T1", "other"),
+        ]);
+        let dwi = vec!["a".to_string(), "b".to_string()];
+        let t1 = vec!["c".to_string(), "d".to_string()];
+
+        let row = series_row("ACC", "dwi", "DWI", &dwi, &local);
+        assert_eq!((row.status.as_str(), row.found), ("OK", 2));
+        assert_eq!(row.folders, ["S1/DWI0", "S1/DWI1000"]);
+
+        let row_t1 = series_row("ACC", "t1", "T1", &t1, &local);
+        assert_eq!(row_t1.status, "Missing");
+        assert_eq!(row_t1.missing, ["d"]);
+
+        let expected: BTreeSet<String> = dwi.iter().chain(&t1).cloned().collect();
+        let extras = extra_rows("ACC", &[row, row_t1], &expected, &local);
+        assert_eq!(extras.len(), 1);
+        assert_eq!(extras[0].extra, ["stray"]);
+    }
+}
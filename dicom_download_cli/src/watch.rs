@@ -0,0 +1,144 @@
+//! `watch`: drop folder for accession lists.
+//!
+//! New `.csv`/`.json` files placed directly in the watched folder are picked up once their
+//! size and modification time stayed the same across two polls (so a file still being
+//! copied is not read half-written). After processing, each file is moved to `done/` — or
+//! to `failed/` when it could not be parsed or the run fails under `--fail-on` — and its
+//! reports are written next to it as `<name>_report.csv` / `<name>_report.json`. Hidden
+//! files and editor temporaries (`.name`, `~name`) are ignored.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Processed lists whose run succeeded.
+pub const DONE_DIR: &str = "done";
+/// Lists that could not be parsed or whose run failed.
+pub const FAILED_DIR: &str = "failed";
+
+/// Accession list candidates: regular `.csv`/`.json` files that are not hidden.
+pub fn is_accession_list(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    !name.starts_with('.') && !name.starts_with('~') && (ext == "csv" || ext == "json")
+}
+
+/// Polls a drop folder and archives processed files.
+pub struct DropFolder {
+    dir: PathBuf,
+    /// Size and mtime of each candidate at the previous poll.
+    seen: HashMap<PathBuf, (u64, Option<SystemTime>)>,
+}
+
+impl DropFolder {
+    /// Creates `done/` and `failed/` inside `dir`.
+    pub fn new(dir: &Path) -> Result<Self> {
+        for sub in [DONE_DIR, FAILED_DIR] {
+            std::fs::create_dir_all(dir.join(sub))
+                .with_context(|| format!("Failed to create {}", dir.join(sub).display()))?;
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            seen: HashMap::new(),
+        })
+    }
+
+    /// Files that did not change since the previous poll, sorted by name.
+    pub fn poll(&mut self) -> Result<Vec<PathBuf>> {
+        let mut current = HashMap::new();
+        let entries = std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read {}", self.dir.display()))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.is_file() && is_accession_list(&path) {
+                current.insert(path, (meta.len(), meta.modified().ok()));
+            }
+        }
+        let mut ready: Vec<PathBuf> = current
+            .iter()
+            .filter(|(path, stamp)| self.seen.get(*path) == Some(stamp))
+            .map(|(path, _)| path.clone())
+            .collect();
+        ready.sort();
+        self.seen = current;
+        Ok(ready)
+    }
+
+    /// Moves a processed file into `done/` or `failed/`; a numeric suffix avoids
+    /// overwriting an earlier file with the same name.
+    pub fn archive(&mut self, file: &Path, succeeded: bool) -> Result<PathBuf> {
+        let target_dir = self.dir.join(if succeeded { DONE_DIR } else { FAILED_DIR });
+        let name = file.file_name().context("Drop file has no name")?;
+        let mut target = target_dir.join(name);
+        let stem = file.file_stem().unwrap_or(name).to_string_lossy();
+        let ext = file
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        let mut n = 2;
+        while target.exists() {
+            target = target_dir.join(format!("{}-{}{}", stem, n, ext));
+            n += 1;
+        }
+        std::fs::rename(file, &target).with_context(|| {
+            format!("Failed to move {} to {}", file.display(), target.display())
+        })?;
+        self.seen.remove(file);
+        Ok(target)
+    }
+}
+
+/// CSV and JSON report paths for an archived accession list.
+pub fn report_paths(archived: &Path) -> (PathBuf, PathBuf) {
+    let stem = archived
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let dir = archived.parent().unwrap_or(Path::new("."));
+    (
+        dir.join(format!("{}_report.csv", stem)),
+        dir.join(format!("{}_report.json", stem)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_waits_for_stable_files_and_archive_renames() {
+        let dir = std::env::temp_dir().join(format!("watch_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut folder = DropFolder::new(&dir).unwrap();
+
+        let list = dir.join("batch.csv");
+        std::fs::write(&list, "AccessionNumber\nA1\n").unwrap();
+        std::fs::write(dir.join(".batch.csv.swp"), "x").unwrap();
+        std::fs::write(dir.join("notes.txt"), "x").unwrap();
+        // first sighting only records the file
+        assert!(folder.poll().unwrap().is_empty());
+        assert_eq!(folder.poll().unwrap(), vec![list.clone()]);
+
+        let archived = folder.archive(&list, true).unwrap();
+        assert_eq!(archived, dir.join(DONE_DIR).join("batch.csv"));
+        std::fs::write(&list, "AccessionNumber\nA2\n").unwrap();
+        let second = folder.archive(&list, true).unwrap();
+        assert_eq!(second, dir.join(DONE_DIR).join("batch-2.csv"));
+        assert_eq!(
+            report_paths(&second).0,
+            dir.join(DONE_DIR).join("batch-2_report.csv")
+        );
+        assert!(folder.poll().unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}